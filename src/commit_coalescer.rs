@@ -0,0 +1,255 @@
+//! Coalescing of frequent var changes into fewer commits
+//!
+//! With `[tcl.commit_coalescing]` on, an eval whose only changes are to
+//! coalesced vars isn't committed straight away. Its vars are held per author
+//! and committed later in one squashed commit: once each var has gone the
+//! interval without changing (debounce), or every interval (batch). Evals that
+//! change anything else are committed immediately and take their author's
+//! pending vars along. Values are read from the interpreter at commit time, so
+//! a held var is committed as it is then, not as it was when it changed.
+
+use crate::config::{CoalesceConfig, CoalesceMode};
+use crate::glob_pattern::GlobPattern;
+use crate::state::{StateChanges, UserInfo};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Var changes held back for one author
+struct PendingVars {
+    user_info: UserInfo,
+    /// When the oldest pending change was made
+    since: Instant,
+    /// Pending vars: when each last changed, and whether it was deleted
+    vars: BTreeMap<String, (Instant, bool)>,
+    /// Number of evals folded in
+    evals: usize,
+}
+
+/// Coalesced var changes of one author, ready to commit
+#[derive(Debug)]
+pub struct CoalescedCommit {
+    pub user_info: UserInfo,
+    pub changes: StateChanges,
+    pub evals: usize,
+}
+
+/// Holds back var-only changes and hands them out as commits when due
+pub struct CommitCoalescer {
+    config: CoalesceConfig,
+    /// `config.vars`, compiled
+    vars: Vec<GlobPattern>,
+    /// Pending changes by author (nick and host)
+    pending: BTreeMap<(String, String), PendingVars>,
+}
+
+impl CommitCoalescer {
+    pub fn new(config: CoalesceConfig) -> Self {
+        let vars = config.vars.iter().map(|pattern| GlobPattern::new(pattern)).collect();
+        Self {
+            config,
+            vars,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(self.config.interval_secs)
+    }
+
+    fn coalesces(&self, var_name: &str) -> bool {
+        self.vars.iter().any(|pattern| pattern.matches(var_name))
+    }
+
+    /// Take an eval's changes. Returns None if they were all held back,
+    /// otherwise what to commit now: the changes plus the author's pending
+    /// vars
+    pub fn submit(&mut self, mut changes: StateChanges, user_info: &UserInfo, now: Instant) -> Option<StateChanges> {
+        if self.config.mode == CoalesceMode::Off {
+            return Some(changes);
+        }
+
+        let key = (user_info.nick.clone(), user_info.host.clone());
+        let var_only = changes.new_procs.is_empty()
+            && changes.deleted_procs.is_empty()
+            && !changes.has_namespace_changes()
+            && changes.new_vars.iter().chain(&changes.deleted_vars).all(|name| self.coalesces(name));
+
+        if !var_only {
+            // The eval's own view of a var wins over what was pending
+            if let Some(pending) = self.pending.remove(&key) {
+                let mut held = pending_changes(&pending.vars);
+                held.exclude(&changes);
+                changes.merge(held);
+            }
+            self.forget(&changes);
+            return Some(changes);
+        }
+
+        let pending = self.pending.entry(key).or_insert_with(|| PendingVars {
+            user_info: user_info.clone(),
+            since: now,
+            vars: BTreeMap::new(),
+            evals: 0,
+        });
+        pending.evals += 1;
+        for name in &changes.new_vars {
+            pending.vars.insert(name.clone(), (now, false));
+        }
+        for name in &changes.deleted_vars {
+            pending.vars.insert(name.clone(), (now, true));
+        }
+        None
+    }
+
+    /// Coalesced commits due at `now`, or everything pending with `force`
+    /// (at shutdown)
+    pub fn due(&mut self, now: Instant, force: bool) -> Vec<CoalescedCommit> {
+        let interval = self.interval();
+        let mode = self.config.mode;
+        let mut commits = Vec::new();
+
+        self.pending.retain(|_, pending| {
+            let quiet = |changed: &Instant| force || now.duration_since(*changed) >= interval;
+            let taken: BTreeMap<String, (Instant, bool)> = match mode {
+                CoalesceMode::Batch if quiet(&pending.since) => std::mem::take(&mut pending.vars),
+                CoalesceMode::Debounce => {
+                    let (ready, waiting) = std::mem::take(&mut pending.vars)
+                        .into_iter()
+                        .partition(|(_, (changed, _))| quiet(changed));
+                    pending.vars = waiting;
+                    ready
+                }
+                _ if force => std::mem::take(&mut pending.vars),
+                _ => BTreeMap::new(),
+            };
+
+            if !taken.is_empty() {
+                commits.push(CoalescedCommit {
+                    user_info: pending.user_info.clone(),
+                    changes: pending_changes(&taken),
+                    evals: pending.evals,
+                });
+                pending.evals = 0;
+                pending.since = now;
+            }
+            !pending.vars.is_empty()
+        });
+
+        // Someone else may have committed a var since; only the first commit keeps it
+        for i in 0..commits.len() {
+            let (earlier, later) = commits.split_at_mut(i + 1);
+            for commit in later {
+                commit.changes.exclude(&earlier[i].changes);
+            }
+        }
+        commits.retain(|commit| commit.changes.has_changes());
+        commits
+    }
+
+    /// When the next coalesced commit falls due, if anything is pending
+    pub fn next_due(&self) -> Option<Instant> {
+        let interval = self.interval();
+        self.pending
+            .values()
+            .filter_map(|pending| match self.config.mode {
+                CoalesceMode::Debounce => pending.vars.values().map(|(changed, _)| *changed).min(),
+                _ => Some(pending.since),
+            })
+            .min()
+            .map(|earliest| earliest + interval)
+    }
+
+    /// Drop pending vars that were committed or reloaded some other way
+    pub fn forget(&mut self, changes: &StateChanges) {
+        self.pending.retain(|_, pending| {
+            for name in changes.new_vars.iter().chain(&changes.deleted_vars) {
+                pending.vars.remove(name);
+            }
+            !pending.vars.is_empty()
+        });
+    }
+}
+
+fn pending_changes(vars: &BTreeMap<String, (Instant, bool)>) -> StateChanges {
+    let mut changes = StateChanges::default();
+    for (name, (_, deleted)) in vars {
+        if *deleted {
+            changes.deleted_vars.push(name.clone());
+        } else {
+            changes.new_vars.push(name.clone());
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coalescer(mode: CoalesceMode) -> CommitCoalescer {
+        CommitCoalescer::new(CoalesceConfig {
+            mode,
+            interval_secs: 60,
+            vars: vec!["score*".to_string()],
+        })
+    }
+
+    fn var_change(name: &str) -> StateChanges {
+        StateChanges {
+            new_vars: vec![name.to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_batch_holds_var_changes_per_author() {
+        let mut coalescer = coalescer(CoalesceMode::Batch);
+        let alice = UserInfo::new("alice".to_string(), "host".to_string());
+        let start = Instant::now();
+
+        assert!(coalescer.submit(var_change("scores"), &alice, start).is_none());
+        assert!(coalescer.submit(var_change("score_board"), &alice, start + Duration::from_secs(30)).is_none());
+        // Not a coalesced var: committed straight away
+        assert!(coalescer.submit(var_change("config"), &alice, start).is_some());
+
+        assert!(coalescer.due(start + Duration::from_secs(59), false).is_empty());
+        let commits = coalescer.due(start + Duration::from_secs(60), false);
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].changes.new_vars, vec!["score_board", "scores"]);
+        assert_eq!(commits[0].evals, 2);
+        assert!(coalescer.next_due().is_none());
+    }
+
+    #[test]
+    fn test_debounce_waits_for_quiet_vars() {
+        let mut coalescer = coalescer(CoalesceMode::Debounce);
+        let alice = UserInfo::new("alice".to_string(), "host".to_string());
+        let start = Instant::now();
+
+        coalescer.submit(var_change("scores"), &alice, start);
+        coalescer.submit(var_change("score_board"), &alice, start);
+        coalescer.submit(var_change("scores"), &alice, start + Duration::from_secs(50));
+
+        let commits = coalescer.due(start + Duration::from_secs(60), false);
+        assert_eq!(commits[0].changes.new_vars, vec!["score_board"]);
+        assert_eq!(coalescer.next_due(), Some(start + Duration::from_secs(110)));
+        assert_eq!(coalescer.due(start + Duration::from_secs(60), true)[0].changes.new_vars, vec!["scores"]);
+    }
+
+    #[test]
+    fn test_proc_change_takes_pending_vars_along() {
+        let mut coalescer = coalescer(CoalesceMode::Batch);
+        let alice = UserInfo::new("alice".to_string(), "host".to_string());
+        let start = Instant::now();
+
+        coalescer.submit(var_change("scores"), &alice, start);
+        let changes = StateChanges {
+            new_procs: vec!["greet".to_string()],
+            ..Default::default()
+        };
+        let committed = coalescer.submit(changes, &alice, start).unwrap();
+        assert_eq!(committed.new_procs, vec!["greet"]);
+        assert_eq!(committed.new_vars, vec!["scores"]);
+        assert!(coalescer.due(start + Duration::from_secs(60), true).is_empty());
+    }
+}
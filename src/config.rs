@@ -25,16 +25,32 @@ pub struct SecurityConfig {
     #[serde(default)]
     pub blacklisted_users: Vec<String>,
     pub eval_timeout_ms: u64,
-    /// Memory limit per evaluation in megabytes (Unix only, 0 = no limit)
-    /// Note: Uses RLIMIT_AS which limits entire process address space.
-    /// Set to 0 (disabled) by default as small values cause crashes.
-    /// If enabling, use values >= 1024 MB to account for process overhead.
+    /// Memory limit for the TCL worker process in megabytes (Unix only, 0 = no limit)
+    /// Note: Uses RLIMIT_AS, which can't be scoped to a thread, so it only
+    /// applies with `worker_process = true` and is ignored in thread mode.
     #[serde(default = "default_memory_limit")]
     pub memory_limit_mb: u64,
     /// Maximum recursion depth for TCL procedures (0 = no limit)
     /// Default: 1000
     #[serde(default = "default_recursion_limit")]
     pub max_recursion_depth: u32,
+    /// Maximum number of TCL commands a single evaluation may execute (0 = no limit)
+    /// Enforced by the interpreter alongside eval_timeout_ms
+    /// Default: 0 (only the time limit applies)
+    #[serde(default)]
+    pub max_eval_commands: u64,
+    /// Run the TCL interpreter in a supervised child process instead of a thread (Unix only)
+    /// The child gets its own rlimits and is killed and respawned when it hangs or dies
+    /// Default: false
+    #[serde(default)]
+    pub worker_process: bool,
+    /// CPU time limit for the worker process in seconds (0 = no limit, worker_process only)
+    /// Cumulative over the worker's lifetime; exceeding it kills and respawns the worker
+    #[serde(default)]
+    pub worker_cpu_limit_secs: u64,
+    /// Maximum open file descriptors for the worker process (0 = no limit, worker_process only)
+    #[serde(default)]
+    pub worker_max_open_files: u64,
     /// Send commit notifications to the admin who made the change
     /// Default: false (only notify other admins)
     #[serde(default)]
@@ -61,6 +77,95 @@ pub struct TclConfig {
     /// Required if using SSH URLs (git@github.com:user/repo.git)
     /// Example: "/home/user/.ssh/id_rsa"
     pub ssh_key: Option<PathBuf>,
+    /// Branch of `state_repo` to clone, push to and merge from
+    /// Default: the branch checked out in `state_path`
+    #[serde(default)]
+    pub state_branch: Option<String>,
+    /// Username for an HTTPS `state_repo`
+    /// Default: the user in the URL, or "git" (fine for GitHub tokens)
+    #[serde(default)]
+    pub state_username: Option<String>,
+    /// File holding an access token to use as the password of an HTTPS
+    /// `state_repo`, so the token stays out of the config
+    #[serde(default)]
+    pub state_token_file: Option<PathBuf>,
+    /// Environment variable holding the access token (used if
+    /// `state_token_file` isn't set)
+    #[serde(default)]
+    pub state_token_env: Option<String>,
+    /// Ask git's configured credential helpers for HTTPS credentials when no
+    /// token is configured (or it was rejected)
+    /// Default: false
+    #[serde(default)]
+    pub state_credential_helper: bool,
+    /// Roll back the procs and vars changed by an eval that errors or times out,
+    /// so nothing from a failed eval is kept or committed
+    /// Scripts can opt in individually with `transaction on`
+    /// Default: false
+    #[serde(default)]
+    pub transactional_evals: bool,
+    /// Commit frequently changing vars in periodic batches instead of once
+    /// per eval (see `[tcl.commit_coalescing]`)
+    /// Default: off
+    #[serde(default)]
+    pub commit_coalescing: CoalesceConfig,
+    /// How often to fetch `state_repo` and merge what others pushed to it,
+    /// in seconds (0 disables it; `sync` still works on demand)
+    /// Default: 300
+    #[serde(default = "default_sync_interval")]
+    pub sync_interval_secs: u64,
+}
+
+/// How var changes are grouped into commits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoalesceMode {
+    /// Every eval that changes state is committed straight away
+    #[default]
+    Off,
+    /// A var is committed once it has gone `interval_secs` without changing
+    Debounce,
+    /// Each author's pending vars are committed together every `interval_secs`
+    Batch,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CoalesceConfig {
+    /// Default: off
+    #[serde(default)]
+    pub mode: CoalesceMode,
+    /// Debounce delay or batch period in seconds
+    /// Default: 60
+    #[serde(default = "default_coalesce_interval")]
+    pub interval_secs: u64,
+    /// Vars whose changes are coalesced, as `string match` glob patterns
+    /// Evals that also change procs, namespaces or other vars are committed
+    /// immediately, together with their author's pending vars
+    /// Default: ["*"] (every var)
+    #[serde(default = "default_coalesce_vars")]
+    pub vars: Vec<String>,
+}
+
+impl Default for CoalesceConfig {
+    fn default() -> Self {
+        Self {
+            mode: CoalesceMode::Off,
+            interval_secs: default_coalesce_interval(),
+            vars: default_coalesce_vars(),
+        }
+    }
+}
+
+fn default_sync_interval() -> u64 {
+    300
+}
+
+fn default_coalesce_interval() -> u64 {
+    60
+}
+
+fn default_coalesce_vars() -> Vec<String> {
+    vec!["*".to_string()]
 }
 
 impl Config {
@@ -0,0 +1,131 @@
+//! Interpreter resource limits for user evaluations
+//!
+//! Uses Tcl's native resource limits (`interp limit`) on the safe sandbox
+//! interpreter, so a runaway script like `while 1 {}` is cancelled by the
+//! interpreter itself. The worker thread stays alive and no state reload is
+//! needed afterwards.
+//!
+//! Limits are set from the master interpreter, which user code can't reach,
+//! and limit errors cannot be caught with `catch` inside the sandbox.
+//! Scripts blocked inside a C call (e.g. a socket read) are not interrupted;
+//! TclThreadHandle keeps the thread restart as a fallback for that case.
+
+use crate::tcl_wrapper::{TclSandbox, SANDBOX_NAME};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// Which limit stopped an evaluation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    Time,
+    Commands,
+}
+
+/// Limits applied to a single evaluation
+#[derive(Debug, Clone, Copy)]
+pub struct EvalLimits {
+    /// Wall-clock time the script may run for
+    pub timeout: Duration,
+    /// Maximum number of TCL commands (0 = no limit)
+    pub max_commands: u64,
+}
+
+impl EvalLimits {
+    pub fn new(timeout: Duration, max_commands: u64) -> Self {
+        Self {
+            timeout,
+            max_commands,
+        }
+    }
+}
+
+/// Limits currently enforced on the sandbox, returned by `arm`
+#[derive(Debug)]
+pub struct ArmedLimits {
+    limits: EvalLimits,
+    started: Instant,
+    /// Absolute `info cmdcount` value the command limit fires at
+    command_limit: Option<u64>,
+}
+
+/// Get the sandbox's running command counter (`info cmdcount`)
+pub fn command_count(sandbox: &TclSandbox) -> u64 {
+    sandbox
+        .eval("info cmdcount")
+        .ok()
+        .and_then(|obj| obj.get_string().parse::<u64>().ok())
+        .unwrap_or(0)
+}
+
+/// Enable limits on the sandbox before running user code
+pub fn arm(sandbox: &TclSandbox, limits: &EvalLimits) -> ArmedLimits {
+    // Time limits are absolute, so compute the deadline from now
+    let deadline = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        + limits.timeout;
+    let time_cmd = format!(
+        "interp limit {} time -seconds {} -milliseconds {}",
+        SANDBOX_NAME,
+        deadline.as_secs(),
+        deadline.subsec_millis()
+    );
+    if let Err(e) = sandbox.master().eval(time_cmd.as_str()) {
+        warn!("Failed to set sandbox time limit: {:?}", e);
+    }
+
+    // Command limits are also absolute, relative to the sandbox's counter
+    let mut command_limit = None;
+    if limits.max_commands > 0 {
+        let limit = command_count(sandbox)
+            .saturating_add(limits.max_commands)
+            .min(i32::MAX as u64);
+        let commands_cmd = format!("interp limit {} commands -value {}", SANDBOX_NAME, limit);
+        match sandbox.master().eval(commands_cmd.as_str()) {
+            Ok(_) => command_limit = Some(limit),
+            Err(e) => warn!("Failed to set sandbox command limit: {:?}", e),
+        }
+    }
+
+    debug!(
+        "Armed eval limits: {}ms, {} commands",
+        limits.timeout.as_millis(),
+        limits.max_commands
+    );
+
+    ArmedLimits {
+        limits: *limits,
+        started: Instant::now(),
+        command_limit,
+    }
+}
+
+/// Disable limits after user code returned
+/// Returns which limit (if any) stopped the evaluation, given its error message
+pub fn disarm(sandbox: &TclSandbox, armed: ArmedLimits, error: Option<&str>) -> Option<LimitExceeded> {
+    // An empty value removes the limit and clears its exceeded flag, so the
+    // next evaluation (and our own bookkeeping evals) run unrestricted
+    let reset_cmd = format!(
+        "interp limit {name} time -seconds {{}} -milliseconds {{}}; interp limit {name} commands -value {{}}",
+        name = SANDBOX_NAME
+    );
+    if let Err(e) = sandbox.master().eval(reset_cmd.as_str()) {
+        warn!("Failed to reset sandbox limits: {:?}", e);
+    }
+
+    // Successful evaluations can't have hit a limit
+    let error = error?;
+
+    // User code can raise an error with the same text, so only trust the
+    // limit messages once the deadline has passed or the counter (readable
+    // again now that the limits are reset) has actually reached the limit
+    if error.contains("time limit exceeded") && armed.started.elapsed() >= armed.limits.timeout {
+        Some(LimitExceeded::Time)
+    } else if error.contains("command count limit exceeded")
+        && armed.command_limit.is_some_and(|limit| command_count(sandbox) >= limit)
+    {
+        Some(LimitExceeded::Commands)
+    } else {
+        None
+    }
+}
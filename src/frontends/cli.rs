@@ -4,7 +4,7 @@
 
 use crate::config::{SecurityConfig, TclConfig};
 use crate::frontend::Frontend;
-use crate::tcl_service::{EvalContext, TclService};
+use crate::tcl_service::{EvalContext, SharedTclService, TclService};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use rustyline::error::ReadlineError;
@@ -43,7 +43,9 @@ pub struct CliFrontend {
     #[allow(dead_code)]
     name: String,
     config: CliConfig,
-    tcl_service: TclService,
+    tcl_service: SharedTclService,
+    /// Whether this frontend created the service (and should shut it down on stop)
+    owns_service: bool,
     running: bool,
 }
 
@@ -55,16 +57,28 @@ impl CliFrontend {
         tcl_config: TclConfig,
     ) -> Result<Self> {
         let channel_members = Arc::new(RwLock::new(HashMap::new()));
-        let tcl_service = TclService::new(security_config, tcl_config, channel_members)?;
+        let tcl_service = TclService::new_shared(security_config, tcl_config, channel_members)?;
 
         Ok(Self {
             name: "CLI".to_string(),
             config,
             tcl_service,
+            owns_service: true,
             running: false,
         })
     }
 
+    /// Create a CLI frontend that uses an existing shared TCL service
+    pub fn with_service(config: CliConfig, tcl_service: SharedTclService) -> Self {
+        Self {
+            name: "CLI".to_string(),
+            config,
+            tcl_service,
+            owns_service: false,
+            running: false,
+        }
+    }
+
     /// Run the REPL loop
     async fn run_repl(&mut self) -> Result<()> {
         let mut rl = DefaultEditor::new().context("Failed to create readline editor")?;
@@ -105,7 +119,8 @@ impl CliFrontend {
                     let ctx = EvalContext::new(self.config.username.clone(), "local".to_string())
                         .with_admin(self.config.is_admin);
 
-                    match self.tcl_service.eval(line, ctx).await {
+                    let result = self.tcl_service.lock().await.eval(line, ctx).await;
+                    match result {
                         Ok(response) => {
                             for line in &response.output {
                                 println!("{}", line);
@@ -124,6 +139,14 @@ impl CliFrontend {
                                     commit_info.deletions
                                 );
                             }
+
+                            if let Some(conflicts) = response.sync_conflicts {
+                                println!("[Sync] {}", conflicts);
+                            }
+
+                            if let Some(stats) = response.stats {
+                                println!("[{}]", stats.summary());
+                            }
                         }
                         Err(e) => {
                             eprintln!("Error: {}", e);
@@ -166,6 +189,8 @@ impl CliFrontend {
                 println!("  .quit / .exit   - Exit the REPL");
                 println!("  .history [N]    - Show git commit history (last N commits)");
                 println!("  .rollback <hash> - Rollback to a specific commit");
+                println!("  .revert <hash>  - Undo a single commit, keeping later history");
+                println!("  .dryrun <code>  - Show what <code> would commit, without keeping it");
                 println!("  .more           - Show more paginated output");
             }
             ".quit" | ".exit" => {
@@ -178,7 +203,8 @@ impl CliFrontend {
                     10
                 };
 
-                match self.tcl_service.history(limit).await {
+                let result = self.tcl_service.lock().await.history(limit).await;
+                match result {
                     Ok(commits) => {
                         if commits.is_empty() {
                             println!("No commit history available");
@@ -209,7 +235,8 @@ impl CliFrontend {
                 }
 
                 let commit_hash = parts[1];
-                match self.tcl_service.rollback(commit_hash).await {
+                let result = self.tcl_service.lock().await.rollback(commit_hash).await;
+                match result {
                     Ok(message) => {
                         println!("{}", message);
                     }
@@ -218,11 +245,58 @@ impl CliFrontend {
                     }
                 }
             }
+            ".revert" => {
+                if parts.len() < 2 {
+                    eprintln!("Usage: .revert <commit-hash>");
+                    return Ok(());
+                }
+
+                let ctx = EvalContext::new(self.config.username.clone(), "local".to_string())
+                    .with_admin(self.config.is_admin);
+
+                let commit_hash = parts[1];
+                let result = self.tcl_service.lock().await.revert(commit_hash, &ctx).await;
+                match result {
+                    Ok(message) => {
+                        println!("{}", message);
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to revert: {}", e);
+                    }
+                }
+            }
+            ".dryrun" => {
+                let code = command[cmd.len()..].trim();
+                if code.is_empty() {
+                    eprintln!("Usage: .dryrun <tcl code>");
+                    return Ok(());
+                }
+
+                let ctx = EvalContext::new(self.config.username.clone(), "local".to_string())
+                    .with_admin(self.config.is_admin);
+
+                let result = self.tcl_service.lock().await.dry_run(code, ctx).await;
+                match result {
+                    Ok(response) => {
+                        for line in &response.output {
+                            println!("{}", line);
+                        }
+
+                        if response.more_available {
+                            println!("... (more lines available - type '.more' to continue)");
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                    }
+                }
+            }
             ".more" => {
                 let ctx = EvalContext::new(self.config.username.clone(), "local".to_string())
                     .with_admin(self.config.is_admin);
 
-                match self.tcl_service.more(ctx).await {
+                let result = self.tcl_service.lock().await.more(ctx).await;
+                match result {
                     Ok(response) => {
                         for line in &response.output {
                             println!("{}", line);
@@ -262,7 +336,9 @@ impl Frontend for CliFrontend {
     async fn stop(&mut self) -> Result<()> {
         info!("Stopping CLI frontend");
         self.running = false;
-        self.tcl_service.shutdown();
+        if self.owns_service {
+            self.tcl_service.lock().await.shutdown();
+        }
         Ok(())
     }
 
@@ -4,7 +4,7 @@
 
 use crate::config::{SecurityConfig, TclConfig};
 use crate::frontend::Frontend;
-use crate::tcl_service::{EvalContext, TclService};
+use crate::tcl_service::{EvalContext, SharedTclService, TclService};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use crossterm::{
@@ -81,7 +81,9 @@ pub struct TuiFrontend {
     #[allow(dead_code)]
     name: String,
     config: TuiConfig,
-    tcl_service: TclService,
+    tcl_service: SharedTclService,
+    /// Whether this frontend created the service (and should shut it down on stop)
+    owns_service: bool,
     running: bool,
 }
 
@@ -93,16 +95,28 @@ impl TuiFrontend {
         tcl_config: TclConfig,
     ) -> Result<Self> {
         let channel_members = Arc::new(RwLock::new(HashMap::new()));
-        let tcl_service = TclService::new(security_config, tcl_config, channel_members)?;
+        let tcl_service = TclService::new_shared(security_config, tcl_config, channel_members)?;
 
         Ok(Self {
             name: "TUI".to_string(),
             config,
             tcl_service,
+            owns_service: true,
             running: false,
         })
     }
 
+    /// Create a TUI frontend that uses an existing shared TCL service
+    pub fn with_service(config: TuiConfig, tcl_service: SharedTclService) -> Self {
+        Self {
+            name: "TUI".to_string(),
+            config,
+            tcl_service,
+            owns_service: false,
+            running: false,
+        }
+    }
+
     /// Run the TUI application
     async fn run_app(&mut self) -> Result<()> {
         // Setup terminal
@@ -288,7 +302,8 @@ impl TuiFrontend {
         let ctx = EvalContext::new(self.config.username.clone(), "local".to_string())
             .with_admin(self.config.is_admin);
 
-        match self.tcl_service.eval(&code, ctx).await {
+        let result = self.tcl_service.lock().await.eval(&code, ctx).await;
+        match result {
             Ok(response) => {
                 // Add input to output
                 state.output_lines.push(format!("> {}", code));
@@ -313,7 +328,14 @@ impl TuiFrontend {
                     self.update_history(state).await;
                 }
 
-                state.status = "Evaluation complete".to_string();
+                if let Some(conflicts) = response.sync_conflicts {
+                    state.output_lines.push(format!("[Sync] {}", conflicts));
+                }
+
+                state.status = match response.stats {
+                    Some(stats) => format!("Evaluation complete ({})", stats.summary()),
+                    None => "Evaluation complete".to_string(),
+                };
             }
             Err(e) => {
                 state.output_lines.push(format!("Error: {}", e));
@@ -338,7 +360,8 @@ impl TuiFrontend {
         let ctx = EvalContext::new(self.config.username.clone(), "local".to_string())
             .with_admin(self.config.is_admin);
 
-        match self.tcl_service.more(ctx).await {
+        let result = self.tcl_service.lock().await.more(ctx).await;
+        match result {
             Ok(response) => {
                 for line in &response.output {
                     state.output_lines.push(line.clone());
@@ -356,7 +379,8 @@ impl TuiFrontend {
 
     /// Update git history display
     async fn update_history(&mut self, state: &mut AppState) {
-        match self.tcl_service.history(10).await {
+        let result = self.tcl_service.lock().await.history(10).await;
+        match result {
             Ok(commits) => {
                 state.history = commits
                     .into_iter()
@@ -393,7 +417,9 @@ impl Frontend for TuiFrontend {
     async fn stop(&mut self) -> Result<()> {
         info!("Stopping TUI frontend");
         self.running = false;
-        self.tcl_service.shutdown();
+        if self.owns_service {
+            self.tcl_service.lock().await.shutdown();
+        }
         Ok(())
     }
 
@@ -4,12 +4,15 @@
 
 use crate::config::{SecurityConfig, TclConfig};
 use crate::frontend::Frontend;
-use crate::state::CommitInfo;
-use crate::tcl_service::{EvalContext, EvalResponse, TclService};
+use crate::review_queue::ReviewInfo;
+use crate::state::{CommitInfo, DryRunReport, ItemVersion};
+use crate::state_bundle::{BundleFormat, StateBundle};
+use crate::tcl_service::{EvalContext, EvalResponse, SharedTclService, TclService};
+use crate::tcl_thread::EvalStats;
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use axum::{
-    extract::{Query, Request, State as AxumState},
+    extract::{Path, Query, Request, State as AxumState},
     http::{Method, StatusCode, header},
     middleware::{self, Next},
     response::{Html, IntoResponse, Response},
@@ -20,7 +23,6 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
-use tokio::sync::Mutex;
 use tower_http::cors::{Any, CorsLayer};
 use tracing::{error, info};
 
@@ -51,7 +53,7 @@ impl Default for WebConfig {
 /// Shared application state
 #[derive(Clone)]
 pub struct AppState {
-    pub tcl_service: Arc<Mutex<TclService>>,
+    pub tcl_service: SharedTclService,
     pub config: WebConfig,
 }
 
@@ -72,6 +74,16 @@ struct EvalResponseDto {
     is_error: bool,
     commit_info: Option<CommitInfo>,
     more_available: bool,
+    return_value: String,
+    captured_output: String,
+    error_code: Option<String>,
+    error_info: Option<String>,
+    stats: Option<EvalStats>,
+    restarted: bool,
+    rolled_back: bool,
+    dry_run: Option<DryRunReport>,
+    review: Option<ReviewInfo>,
+    sync_conflicts: Option<String>,
 }
 
 impl From<EvalResponse> for EvalResponseDto {
@@ -81,6 +93,16 @@ impl From<EvalResponse> for EvalResponseDto {
             is_error: r.is_error,
             commit_info: r.commit_info,
             more_available: r.more_available,
+            return_value: r.return_value,
+            captured_output: r.captured_output,
+            error_code: r.error_code,
+            error_info: r.error_info,
+            stats: r.stats,
+            restarted: r.restarted,
+            rolled_back: r.rolled_back,
+            dry_run: r.dry_run,
+            review: r.review,
+            sync_conflicts: r.sync_conflicts,
         }
     }
 }
@@ -92,12 +114,50 @@ struct MoreRequest {
     user: Option<String>,
 }
 
+/// Query for per-proc history
+#[derive(Debug, Deserialize)]
+struct ProcHistoryQuery {
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
 /// Rollback request
 #[derive(Debug, Deserialize)]
 struct RollbackRequest {
     commit_hash: String,
 }
 
+/// Revert request
+#[derive(Debug, Deserialize)]
+struct RevertRequest {
+    commit_hash: String,
+    #[serde(default)]
+    user: Option<String>,
+}
+
+/// Query for state export
+#[derive(Debug, Deserialize)]
+struct ExportQuery {
+    /// `tcl` (default) or `json`
+    #[serde(default)]
+    format: Option<String>,
+}
+
+/// State import request
+#[derive(Debug, Deserialize)]
+struct ImportRequest {
+    /// Bundle text, as written by the export endpoint or `slopdrop --export`
+    bundle: String,
+    /// `tcl` (default) or `json`
+    #[serde(default)]
+    format: Option<String>,
+    /// Delete procs and vars the bundle doesn't contain
+    #[serde(default)]
+    replace: bool,
+    #[serde(default)]
+    user: Option<String>,
+}
+
 /// Generic response
 #[derive(Debug, Serialize)]
 struct GenericResponse {
@@ -111,7 +171,9 @@ pub struct WebFrontend {
     #[allow(dead_code)]
     name: String,
     config: WebConfig,
-    tcl_service: Arc<Mutex<TclService>>,
+    tcl_service: SharedTclService,
+    /// Whether this frontend created the service (and should shut it down on stop)
+    owns_service: bool,
     running: Arc<RwLock<bool>>,
 }
 
@@ -123,16 +185,28 @@ impl WebFrontend {
         tcl_config: TclConfig,
     ) -> Result<Self> {
         let channel_members = Arc::new(RwLock::new(HashMap::new()));
-        let tcl_service = TclService::new(security_config, tcl_config, channel_members)?;
+        let tcl_service = TclService::new_shared(security_config, tcl_config, channel_members)?;
 
         Ok(Self {
             name: "Web".to_string(),
             config,
-            tcl_service: Arc::new(Mutex::new(tcl_service)),
+            tcl_service,
+            owns_service: true,
             running: Arc::new(RwLock::new(false)),
         })
     }
 
+    /// Create a web frontend that uses an existing shared TCL service
+    pub fn with_service(config: WebConfig, tcl_service: SharedTclService) -> Self {
+        Self {
+            name: "Web".to_string(),
+            config,
+            tcl_service,
+            owns_service: false,
+            running: Arc::new(RwLock::new(false)),
+        }
+    }
+
     /// Build the axum router
     pub fn build_router(state: AppState) -> Router {
         // CORS configuration
@@ -144,9 +218,14 @@ impl WebFrontend {
         let mut router = Router::new()
             .route("/", get(serve_index))
             .route("/api/eval", post(handle_eval))
+            .route("/api/dryrun", post(handle_dry_run))
             .route("/api/more", get(handle_more))
             .route("/api/history", get(handle_history))
+            .route("/api/procs/:name/history", get(handle_proc_history))
             .route("/api/rollback", post(handle_rollback))
+            .route("/api/revert", post(handle_revert))
+            .route("/api/state/export", get(handle_export))
+            .route("/api/state/import", post(handle_import))
             .route("/api/health", get(handle_health));
 
         // Add authentication middleware if enabled
@@ -204,8 +283,10 @@ impl Frontend for WebFrontend {
     async fn stop(&mut self) -> Result<()> {
         info!("Stopping Web frontend");
         *self.running.write().unwrap() = false;
-        let mut service = self.tcl_service.lock().await;
-        service.shutdown();
+        if self.owns_service {
+            let mut service = self.tcl_service.lock().await;
+            service.shutdown();
+        }
         Ok(())
     }
 
@@ -279,6 +360,25 @@ async fn handle_eval(
     }
 }
 
+/// Handle dry-run request: evaluate, report the would-be commit, keep nothing
+async fn handle_dry_run(
+    AxumState(state): AxumState<AppState>,
+    Json(req): Json<EvalRequest>,
+) -> Result<Json<EvalResponseDto>, StatusCode> {
+    let user = req.user.unwrap_or_else(|| "web".to_string());
+    let ctx = EvalContext::new(user, "web".to_string()).with_admin(req.is_admin);
+
+    let mut service = state.tcl_service.lock().await;
+
+    match service.dry_run(&req.code, ctx).await {
+        Ok(response) => Ok(Json(response.into())),
+        Err(e) => {
+            error!("Dry run error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
 /// Handle more request
 async fn handle_more(
     AxumState(state): AxumState<AppState>,
@@ -313,6 +413,23 @@ async fn handle_history(
     }
 }
 
+/// Handle per-proc history request
+async fn handle_proc_history(
+    AxumState(state): AxumState<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<ProcHistoryQuery>,
+) -> Result<Json<Vec<ItemVersion>>, StatusCode> {
+    let service = state.tcl_service.lock().await;
+
+    match service.proc_history(&name, query.limit.unwrap_or(20)).await {
+        Ok(history) => Ok(Json(history)),
+        Err(e) => {
+            error!("Proc history error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
 /// Handle rollback request
 async fn handle_rollback(
     AxumState(state): AxumState<AppState>,
@@ -335,6 +452,94 @@ async fn handle_rollback(
     }
 }
 
+/// Handle revert request
+async fn handle_revert(
+    AxumState(state): AxumState<AppState>,
+    Json(req): Json<RevertRequest>,
+) -> Result<Json<GenericResponse>, StatusCode> {
+    let user = req.user.unwrap_or_else(|| "web".to_string());
+    let ctx = EvalContext::new(user, "web".to_string());
+
+    let mut service = state.tcl_service.lock().await;
+
+    match service.revert(&req.commit_hash, &ctx).await {
+        Ok(message) => Ok(Json(GenericResponse {
+            success: true,
+            message,
+        })),
+        Err(e) => {
+            error!("Revert error: {}", e);
+            Ok(Json(GenericResponse {
+                success: false,
+                message: format!("Revert failed: {}", e),
+            }))
+        }
+    }
+}
+
+/// Handle state export: the whole persisted state as one bundle
+async fn handle_export(
+    AxumState(state): AxumState<AppState>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, StatusCode> {
+    let format = BundleFormat::from_name(query.format.as_deref().unwrap_or("tcl"))
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let service = state.tcl_service.lock().await;
+
+    let text = service
+        .export_state()
+        .await
+        .and_then(|bundle| bundle.render(format))
+        .map_err(|e| {
+            error!("Export error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let content_type = match format {
+        BundleFormat::Tcl => "text/plain; charset=utf-8",
+        BundleFormat::Json => "application/json",
+    };
+    Ok(([(header::CONTENT_TYPE, content_type)], text).into_response())
+}
+
+/// Handle state import, committed as one change by the requesting user
+async fn handle_import(
+    AxumState(state): AxumState<AppState>,
+    Json(req): Json<ImportRequest>,
+) -> Result<Json<GenericResponse>, StatusCode> {
+    let user = req.user.unwrap_or_else(|| "web".to_string());
+    let ctx = EvalContext::new(user, "web".to_string());
+
+    let bundle = match BundleFormat::from_name(req.format.as_deref().unwrap_or("tcl"))
+        .and_then(|format| StateBundle::parse(&req.bundle, format))
+    {
+        Ok(bundle) => bundle,
+        Err(e) => {
+            return Ok(Json(GenericResponse {
+                success: false,
+                message: format!("Import failed: {}", e),
+            }))
+        }
+    };
+
+    let mut service = state.tcl_service.lock().await;
+
+    match service.import_state(&bundle, req.replace, &ctx).await {
+        Ok(message) => Ok(Json(GenericResponse {
+            success: true,
+            message,
+        })),
+        Err(e) => {
+            error!("Import error: {}", e);
+            Ok(Json(GenericResponse {
+                success: false,
+                message: format!("Import failed: {}", e),
+            }))
+        }
+    }
+}
+
 /// Health check endpoint
 async fn handle_health() -> Json<GenericResponse> {
     Json(GenericResponse {
@@ -591,7 +796,12 @@ const INDEX_HTML: &str = r#"
                 outputContent.textContent += '\n';
                 outputContent.scrollTop = outputContent.scrollHeight;
 
-                showStatus('Evaluation complete', 'success');
+                if (result.stats) {
+                    const ms = result.stats.wall_time_us / 1000;
+                    showStatus(`Evaluation complete (took ${ms.toFixed(ms < 10 ? 1 : 0)}ms, ${result.stats.commands} cmds)`, 'success');
+                } else {
+                    showStatus('Evaluation complete', 'success');
+                }
 
                 // Clear editor
                 codeEditor.value = '';
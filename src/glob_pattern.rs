@@ -0,0 +1,171 @@
+//! Glob patterns over TCL names, with the semantics of TCL's `string match`
+//!
+//! - `*` matches any sequence of characters (including empty)
+//! - `?` matches exactly one character
+//! - `[chars]` matches one of the chars; `a-z` is a range
+//! - `\x` matches `x` literally
+//!
+//! Patterns are parsed once, when the rules using them are loaded, so
+//! matching a name doesn't build anything.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Any,
+    One,
+    Char(char),
+    /// Inclusive character ranges; a single char is a range of one
+    Set(Vec<(char, char)>),
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Any | Token::One => true,
+            Token::Char(expected) => c == *expected,
+            Token::Set(ranges) => ranges.iter().any(|&(low, high)| {
+                // `string match` accepts ranges in either order
+                let (low, high) = if low <= high { (low, high) } else { (high, low) };
+                (low..=high).contains(&c)
+            }),
+        }
+    }
+}
+
+/// A compiled `string match` pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobPattern {
+    tokens: Vec<Token>,
+}
+
+impl GlobPattern {
+    pub fn new(pattern: &str) -> Self {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '*' => {
+                    // Runs of stars match the same as one
+                    if tokens.last() == Some(&Token::Any) {
+                        continue;
+                    }
+                    Token::Any
+                }
+                '?' => Token::One,
+                '\\' => Token::Char(chars.next().unwrap_or('\\')),
+                '[' => {
+                    // Like `string match`, an unclosed set runs to the end of the pattern
+                    let mut ranges = Vec::new();
+                    while let Some(c) = chars.next() {
+                        let low = match c {
+                            ']' => break,
+                            '\\' => chars.next().unwrap_or('\\'),
+                            c => c,
+                        };
+                        let high = if chars.peek() == Some(&'-') {
+                            chars.next();
+                            match chars.next() {
+                                Some(']') | None => {
+                                    ranges.push((low, low));
+                                    break;
+                                }
+                                Some('\\') => chars.next().unwrap_or('\\'),
+                                Some(c) => c,
+                            }
+                        } else {
+                            low
+                        };
+                        ranges.push((low, high));
+                    }
+                    Token::Set(ranges)
+                }
+                c => Token::Char(c),
+            };
+            tokens.push(token);
+        }
+
+        Self { tokens }
+    }
+
+    /// Whether the whole of `name` matches
+    pub fn matches(&self, name: &str) -> bool {
+        let name: Vec<char> = name.chars().collect();
+        let (mut t, mut n) = (0, 0);
+        // Where to resume after the last `*`: the token after it, and the
+        // name position it has swallowed up to
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while n < name.len() {
+            match self.tokens.get(t) {
+                Some(Token::Any) => {
+                    backtrack = Some((t + 1, n));
+                    t += 1;
+                }
+                Some(token) if token.matches(name[n]) => {
+                    t += 1;
+                    n += 1;
+                }
+                _ => match backtrack {
+                    Some((resume, swallowed)) => {
+                        backtrack = Some((resume, swallowed + 1));
+                        t = resume;
+                        n = swallowed + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+
+        self.tokens[t..].iter().all(|token| *token == Token::Any)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(name: &str, pattern: &str) -> bool {
+        GlobPattern::new(pattern).matches(name)
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches("timtom_money", "timtom*"));
+        assert!(matches("timtom", "timtom*"));
+        assert!(!matches("my_timtom", "timtom*"));
+        assert!(matches("counter", "counter"));
+        assert!(!matches("counter2", "counter"));
+        assert!(matches("a1b", "a?b"));
+        assert!(!matches("ab", "a?b"));
+        assert!(matches("anything", "*"));
+        assert!(matches("", "*"));
+        assert!(matches("abcbd", "*b*d"));
+        assert!(!matches("abcbe", "*b*d"));
+    }
+
+    #[test]
+    fn test_sets() {
+        assert!(matches("tmp1", "tmp[0-9]"));
+        assert!(!matches("tmpx", "tmp[0-9]"));
+        assert!(matches("tmpx", "tmp[xyz]"));
+        assert!(matches("tmp5", "tmp[9-0]"));
+        assert!(matches("c", "[abc"));
+    }
+
+    #[test]
+    fn test_regex_characters_are_literal() {
+        assert!(matches("a.b", "a.b"));
+        assert!(!matches("axb", "a.b"));
+        assert!(matches("cache(key)", "cache(*)"));
+        assert!(matches("x+y", "x+y"));
+        assert!(matches("ns::var", "ns::*"));
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert!(matches("a*b", "a\\*b"));
+        assert!(!matches("axb", "a\\*b"));
+        assert!(matches("what?", "what\\?"));
+        assert!(matches("[x]", "\\[x\\]"));
+    }
+}
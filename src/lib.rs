@@ -1,16 +1,28 @@
 // Library interface for integration tests
 
+pub mod commit_coalescer;
 pub mod config;
+pub mod eval_limits;
 pub mod file_watcher;
+pub mod glob_pattern;
 pub mod hostmask;
 pub mod http_commands;
 pub mod http_tcl_commands;
 pub mod irc_client;
 pub mod irc_formatting;
+pub mod native_commands;
+pub mod proc_owners;
+pub mod push_queue;
+pub mod remote_sync;
+pub mod review_queue;
 pub mod smeggdrop_commands;
 pub mod state;
+pub mod state_bundle;
+pub mod state_layout;
 pub mod stock_commands;
 pub mod tcl_plugin;
+#[cfg(unix)]
+pub mod tcl_process;
 pub mod tcl_thread;
 pub mod tcl_wrapper;
 pub mod types;
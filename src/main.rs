@@ -2,17 +2,29 @@
 //!
 //! Supports running multiple frontends (IRC, CLI, TUI, Web) simultaneously
 
+mod commit_coalescer;
 mod config;
+mod eval_limits;
 mod file_watcher;
+mod glob_pattern;
 mod hostmask;
 mod http_commands;
 mod http_tcl_commands;
 mod irc_client;
 mod irc_formatting;
+mod native_commands;
+mod proc_owners;
+mod push_queue;
+mod remote_sync;
+mod review_queue;
 mod smeggdrop_commands;
 mod state;
+mod state_bundle;
+mod state_layout;
 mod stock_commands;
 mod tcl_plugin;
+#[cfg(unix)]
+mod tcl_process;
 mod tcl_thread;
 mod tcl_wrapper;
 mod types;
@@ -74,6 +86,45 @@ async fn main() -> Result<()> {
     // Initialize logging
     tracing_subscriber::fmt::init();
 
+    // Spawned by TclProcessHandle to host the interpreter in a child process
+    #[cfg(unix)]
+    {
+        let args: Vec<String> = std::env::args().collect();
+        if let Some(pos) = args.iter().position(|a| a == tcl_process::WORKER_FLAG) {
+            let socket_path = args
+                .get(pos + 1)
+                .map(std::path::PathBuf::from)
+                .ok_or_else(|| anyhow::anyhow!("{} requires a socket path", tcl_process::WORKER_FLAG))?;
+
+            // The worker blocks on its socket, so keep it off the async runtime
+            return std::thread::spawn(move || tcl_process::run_worker_process(&socket_path))
+                .join()
+                .map_err(|_| anyhow::anyhow!("TCL worker process panicked"))?;
+        }
+    }
+
+    // One-off conversion of the state repository to the v2 layout
+    if std::env::args().any(|arg| arg == "--migrate-state") {
+        let config = Config::from_file(&config_path())?;
+        return migrate_state(&config.tcl);
+    }
+
+    // State integrity check, optionally committing fixes
+    if std::env::args().any(|arg| arg == "--fsck") {
+        let config = Config::from_file(&config_path())?;
+        return fsck_state(&config.tcl, std::env::args().any(|arg| arg == "--repair"));
+    }
+
+    // Move the whole state in or out as one portable bundle
+    if let Some(path) = flag_value("--export")? {
+        let config = Config::from_file(&config_path())?;
+        return export_state(&config.tcl, &path);
+    }
+    if let Some(path) = flag_value("--import")? {
+        let config = Config::from_file(&config_path())?;
+        return import_state(&config.tcl, &path, std::env::args().any(|arg| arg == "--replace"));
+    }
+
     info!("Slopdrop TCL evalbot starting");
 
     // Parse frontend flags
@@ -90,6 +141,12 @@ async fn main() -> Result<()> {
         println!("  --tui    Full-screen terminal UI");
         println!("  --web    Web server with HTTP API");
         println!();
+        println!("Maintenance:");
+        println!("  --migrate-state  Convert the state repository to the v2 layout, then exit");
+        println!("  --fsck [--repair]  Check the state repository (and commit fixes), then exit");
+        println!("  --export <file>  Write every proc and var to a .tcl or .json bundle, then exit");
+        println!("  --import <file> [--replace]  Merge (or replace) the state from a bundle, then exit");
+        println!();
         println!("Examples:");
         println!("  slopdrop                    # IRC bot (default)");
         println!("  slopdrop --cli              # CLI REPL only");
@@ -101,9 +158,7 @@ async fn main() -> Result<()> {
     }
 
     // Find config file path
-    let config_path = std::env::args()
-        .find(|arg| !arg.starts_with("--") && arg.ends_with(".toml"))
-        .unwrap_or_else(|| "config.toml".to_string());
+    let config_path = config_path();
 
     // Load configuration
     let config = match Config::from_file(&config_path) {
@@ -117,6 +172,23 @@ async fn main() -> Result<()> {
 
     info!("Configuration loaded from {}", config_path);
 
+    // Shared channel members tracking (populated by the IRC client, empty otherwise)
+    let channel_members: types::ChannelMembers = Arc::new(RwLock::new(HashMap::new()));
+
+    // One TCL service for the whole process: every frontend evaluates against the
+    // same interpreter and commits through the same state repository
+    let tcl_service = match tcl_service::TclService::new_shared(
+        config.security.clone(),
+        config.tcl.clone(),
+        channel_members.clone(),
+    ) {
+        Ok(service) => service,
+        Err(e) => {
+            error!("Failed to create TCL service: {}", e);
+            return Err(e);
+        }
+    };
+
     // Start requested frontends
     let mut tasks = vec![];
 
@@ -124,23 +196,16 @@ async fn main() -> Result<()> {
     #[cfg(feature = "frontend-cli")]
     if flags.cli {
         info!("Starting CLI frontend");
-        let security_config = config.security.clone();
-        let tcl_config = config.tcl.clone();
+        let tcl_service = tcl_service.clone();
 
         let task = tokio::spawn(async move {
             use crate::frontend::Frontend;
             use crate::frontends::cli::{CliConfig, CliFrontend};
 
             let cli_config = CliConfig::default();
-            match CliFrontend::new(cli_config, security_config, tcl_config) {
-                Ok(mut frontend) => {
-                    if let Err(e) = frontend.start().await {
-                        error!("CLI frontend error: {}", e);
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to create CLI frontend: {}", e);
-                }
+            let mut frontend = CliFrontend::with_service(cli_config, tcl_service);
+            if let Err(e) = frontend.start().await {
+                error!("CLI frontend error: {}", e);
             }
         });
         tasks.push(task);
@@ -154,23 +219,16 @@ async fn main() -> Result<()> {
     #[cfg(feature = "frontend-tui")]
     if flags.tui {
         info!("Starting TUI frontend");
-        let security_config = config.security.clone();
-        let tcl_config = config.tcl.clone();
+        let tcl_service = tcl_service.clone();
 
         let task = tokio::spawn(async move {
             use crate::frontend::Frontend;
             use crate::frontends::tui::{TuiConfig, TuiFrontend};
 
             let tui_config = TuiConfig::default();
-            match TuiFrontend::new(tui_config, security_config, tcl_config) {
-                Ok(mut frontend) => {
-                    if let Err(e) = frontend.start().await {
-                        error!("TUI frontend error: {}", e);
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to create TUI frontend: {}", e);
-                }
+            let mut frontend = TuiFrontend::with_service(tui_config, tcl_service);
+            if let Err(e) = frontend.start().await {
+                error!("TUI frontend error: {}", e);
             }
         });
         tasks.push(task);
@@ -184,23 +242,16 @@ async fn main() -> Result<()> {
     #[cfg(feature = "frontend-web")]
     if flags.web {
         info!("Starting Web frontend");
-        let security_config = config.security.clone();
-        let tcl_config = config.tcl.clone();
+        let tcl_service = tcl_service.clone();
 
         let task = tokio::spawn(async move {
             use crate::frontend::Frontend;
             use crate::frontends::web::{WebConfig, WebFrontend};
 
             let web_config = WebConfig::default();
-            match WebFrontend::new(web_config, security_config, tcl_config) {
-                Ok(mut frontend) => {
-                    if let Err(e) = frontend.start().await {
-                        error!("Web frontend error: {}", e);
-                    }
-                }
-                Err(e) => {
-                    error!("Failed to create Web frontend: {}", e);
-                }
+            let mut frontend = WebFrontend::with_service(web_config, tcl_service);
+            if let Err(e) = frontend.start().await {
+                error!("Web frontend error: {}", e);
             }
         });
         tasks.push(task);
@@ -214,9 +265,6 @@ async fn main() -> Result<()> {
     if flags.irc {
         info!("Starting IRC frontend");
 
-        // Create shared channel members tracking
-        let channel_members = Arc::new(RwLock::new(HashMap::new()));
-
         // Create communication channels
         let (tcl_command_tx, tcl_command_rx) = mpsc::channel(100);
         // Large buffer for responses to handle commands with huge output (e.g., crash)
@@ -243,27 +291,21 @@ async fn main() -> Result<()> {
             }
         };
 
-        // Spawn TCL plugin task
+        // Spawn TCL plugin task (evaluates through the shared TCL service)
         let tcl_handle = {
             let security_config = config.security.clone();
             let tcl_config = config.tcl.clone();
             let server_config = config.server.clone();
             let config_path_clone = std::path::PathBuf::from(&config_path);
-            let channel_members_clone = channel_members.clone();
+            let tcl_service = tcl_service.clone();
             tokio::task::spawn_blocking(move || {
-                let mut tcl_plugin = match tcl_plugin::TclPlugin::new(
+                let mut tcl_plugin = tcl_plugin::TclPlugin::with_service(
                     security_config,
                     tcl_config,
                     server_config,
                     config_path_clone,
-                    channel_members_clone,
-                ) {
-                    Ok(plugin) => plugin,
-                    Err(e) => {
-                        error!("Failed to create TCL plugin: {}", e);
-                        return;
-                    }
-                };
+                    tcl_service,
+                );
 
                 let rt = tokio::runtime::Handle::current();
                 rt.block_on(async {
@@ -302,6 +344,15 @@ async fn main() -> Result<()> {
         }
     }
 
+    tcl_service.lock().await.shutdown();
+    remote_sync::stop_all();
+
+    // The worker flushes its pushes as it shuts down; in thread mode its
+    // queue lives in this process, so make sure it got out before exiting
+    if let Err(e) = push_queue::flush_all(push_queue::FLUSH_TIMEOUT) {
+        warn!("Shutting down with unpushed state: {}", e);
+    }
+
     info!("Slopdrop shut down successfully");
     Ok(())
 }
@@ -314,3 +365,103 @@ async fn wait_for_tasks(tasks: Vec<tokio::task::JoinHandle<()>>) {
         }
     }
 }
+
+/// Config file path: the first `.toml` argument, or config.toml
+fn config_path() -> String {
+    std::env::args()
+        .find(|arg| !arg.starts_with("--") && arg.ends_with(".toml"))
+        .unwrap_or_else(|| "config.toml".to_string())
+}
+
+/// The argument following `flag`, if the flag was given
+fn flag_value(flag: &str) -> Result<Option<String>> {
+    let mut args = std::env::args().skip_while(|arg| arg != flag);
+    if args.next().is_none() {
+        return Ok(None);
+    }
+    args.next()
+        .map(Some)
+        .ok_or_else(|| anyhow::anyhow!("{} requires a file name", flag))
+}
+
+/// `slopdrop --migrate-state`: convert the state repository to the v2 layout
+/// in one commit. Run it while the bot is stopped.
+fn migrate_state(tcl_config: &config::TclConfig) -> Result<()> {
+    let commit = state_persistence(tcl_config).migrate_layout(&state::scratch_interp()?)?;
+    println!(
+        "Migrated {} to the v2 layout in commit {}",
+        tcl_config.state_path.display(),
+        &commit.commit_id[..8]
+    );
+    push_commits();
+    Ok(())
+}
+
+/// `slopdrop --fsck [--repair]`: report problems with the state repository
+/// and, with --repair, commit fixes. Fails if problems are left unfixed.
+fn fsck_state(tcl_config: &config::TclConfig, repair: bool) -> Result<()> {
+    let persistence = state_persistence(tcl_config);
+    let report = persistence.fsck(&state::scratch_interp()?)?;
+    println!("{}", report.render());
+
+    if report.is_clean() {
+        return Ok(());
+    }
+    if !repair {
+        return Err(anyhow::anyhow!("{} problems found; rerun with --repair to fix them", report.issues.len()));
+    }
+
+    let user_info = state::UserInfo::new("slopdrop".to_string(), "localhost".to_string());
+    if let Some(commit) = persistence.fsck_repair(&report, &user_info)? {
+        println!("Repaired in commit {}", &commit.commit_id[..8]);
+        push_commits();
+    }
+    Ok(())
+}
+
+fn state_persistence(tcl_config: &config::TclConfig) -> state::StatePersistence {
+    state::StatePersistence::from_config(tcl_config)
+}
+
+/// `slopdrop --export <file>`: write every persisted proc, var and namespace
+/// to one bundle, as TCL or (for `.json` files) JSON
+fn export_state(tcl_config: &config::TclConfig, path: &str) -> Result<()> {
+    let path = std::path::Path::new(path);
+    let bundle = state_persistence(tcl_config).export_bundle()?;
+    std::fs::write(path, bundle.render(state_bundle::BundleFormat::from_path(path))?)?;
+    println!(
+        "Exported {} procs and {} vars to {}",
+        bundle.procs.len(),
+        bundle.vars.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// `slopdrop --import <file> [--replace]`: merge a bundle into the state, or
+/// replace the state with it, as one commit. Run it while the bot is stopped.
+fn import_state(tcl_config: &config::TclConfig, path: &str, replace: bool) -> Result<()> {
+    let path = std::path::Path::new(path);
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let bundle = state_bundle::StateBundle::parse(&text, state_bundle::BundleFormat::from_path(path))?;
+
+    let user_info = state::UserInfo::new(whoami::username(), "localhost".to_string());
+    let (commit, changes) = state_persistence(tcl_config).import_bundle(&bundle, replace, &user_info)?;
+    match commit {
+        Some(commit) => {
+            println!("Imported {} in commit {}: {}", path.display(), &commit.commit_id[..8], changes.summary());
+            push_commits();
+        }
+        None => println!("Nothing to import: the state already matches {}", path.display()),
+    }
+    Ok(())
+}
+
+/// Push the commits a one-off command made before exiting; a failed push
+/// leaves them for the bot to push on its next commit
+fn push_commits() {
+    if let Err(e) = push_queue::flush_all(push_queue::FLUSH_TIMEOUT) {
+        eprintln!("Warning: failed to push to the state remote: {}", e);
+    }
+}
@@ -0,0 +1,707 @@
+//! Rust-backed TCL commands
+//!
+//! These are registered as real commands on the master interpreter and aliased
+//! into the sandbox, so they compose with the rest of the language:
+//! `set p [stock::price AAPL]`, `foreach n [chanlist #foo] {...}` or a proc
+//! that calls `history` all work.
+//!
+//! Native callbacks reply with a `{ok value}` or `{error message}` pair; small
+//! master-side wrapper procs turn that into a normal TCL return or error.
+
+use crate::config::TclConfig;
+use crate::push_queue;
+use crate::review_queue::HeldChanges;
+use crate::state::{scratch_interp, ConflictResolution, StateChanges, StatePersistence, UserInfo};
+use crate::tcl_thread::StateOperation;
+use crate::tcl_wrapper::{tcl_list, TclSandbox};
+use crate::types::ChannelMembers;
+use anyhow::{anyhow, Result};
+use std::sync::{Arc, Mutex, RwLock};
+use tcl::{tclosure, TclResult};
+use tracing::info;
+
+/// The user the current evaluation runs as
+/// Updated by the TCL worker before every eval
+#[derive(Debug, Clone, Default)]
+pub struct NativeContext {
+    pub nick: String,
+    pub host: String,
+    pub is_admin: bool,
+    /// The eval is a dry run, so nothing may touch the repository
+    pub dry_run: bool,
+    /// Procs/vars an approved review changed on disk; the worker reloads them
+    /// into the interpreter once the eval returns
+    pub pending_reload: Option<StateChanges>,
+    /// A `rollback`, `revert`, `undo` or `sync` asked for during the eval; the
+    /// worker runs it once the eval returns, after committing held back var
+    /// changes, and its reply becomes the eval's result
+    pub pending_operation: Option<StateOperation>,
+    /// Changes to protected procs `proc` and `rename` refused during the eval;
+    /// the worker queues them for review once the eval returns
+    pub pending_review: Option<HeldChanges>,
+}
+
+pub type SharedNativeContext = Arc<Mutex<NativeContext>>;
+
+/// The worker's current TCL config, replaced when the config is reloaded
+/// Native commands read it on every call, so they follow the reload
+pub type SharedTclConfig = Arc<RwLock<TclConfig>>;
+
+/// Snapshot of the current config for one native call
+fn current_config(config: &SharedTclConfig) -> TclConfig {
+    match config.read() {
+        Ok(config) => config.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    }
+}
+
+/// Master-side wrappers around the native callbacks, and their sandbox aliases
+const NATIVE_WRAPPERS: &str = r#"
+namespace eval ::slopdrop::cmd::stock {}
+
+proc ::slopdrop::native_result {result} {
+    lassign $result status value
+    if {$status ne "ok"} {
+        return -code error $value
+    }
+    return $value
+}
+
+proc ::slopdrop::cmd::history {args} {
+    if {[lindex $args 0] in {proc var}} {
+        if {[llength $args] ni {2 3}} {
+            return -code error "usage: history proc|var <name> ?count?"
+        }
+        lassign $args kind name count
+        if {$count eq ""} {
+            set count 10
+        }
+        return [::slopdrop::native_result [::slopdrop::native::history $kind $name $count]]
+    }
+    if {[llength $args] > 1} {
+        return -code error "usage: history ?count? | history proc|var <name> ?count?"
+    }
+    set count [expr {[llength $args] ? [lindex $args 0] : 10}]
+    ::slopdrop::native_result [::slopdrop::native::history {} {} $count]
+}
+
+proc ::slopdrop::cmd::diff {name {rev1 ""} {rev2 ""}} {
+    ::slopdrop::native_result [::slopdrop::native::diff $name $rev1 $rev2]
+}
+
+proc ::slopdrop::cmd::blame {name} {
+    ::slopdrop::native_result [::slopdrop::native::blame $name]
+}
+
+proc ::slopdrop::cmd::rollback {hash} {
+    ::slopdrop::native_result [::slopdrop::native::rollback $hash]
+}
+
+proc ::slopdrop::cmd::revert {hash} {
+    ::slopdrop::native_result [::slopdrop::native::revert $hash]
+}
+
+proc ::slopdrop::cmd::undo {{nick ""}} {
+    ::slopdrop::native_result [::slopdrop::native::undo $nick]
+}
+
+proc ::slopdrop::cmd::fsck {args} {
+    if {$args ni {{} -repair}} {
+        return -code error "usage: fsck ?-repair?"
+    }
+    ::slopdrop::native_result [::slopdrop::native::fsck [expr {$args eq "-repair"}]]
+}
+
+proc ::slopdrop::cmd::pushstatus {args} {
+    if {$args ni {{} -retry}} {
+        return -code error "usage: pushstatus ?-retry?"
+    }
+    ::slopdrop::native_result [::slopdrop::native::pushstatus [expr {$args eq "-retry"}]]
+}
+
+proc ::slopdrop::cmd::sync {args} {
+    if {$args ni {{} -ours -theirs}} {
+        return -code error "usage: sync ?-ours|-theirs?"
+    }
+    ::slopdrop::native_result [::slopdrop::native::sync [string trimleft $args -]]
+}
+
+proc ::slopdrop::cmd::protect {name} {
+    ::slopdrop::native_result [::slopdrop::native::protect $name 1]
+}
+
+proc ::slopdrop::cmd::unprotect {name} {
+    ::slopdrop::native_result [::slopdrop::native::protect $name 0]
+}
+
+proc ::slopdrop::cmd::owner {name} {
+    ::slopdrop::native_result [::slopdrop::native::owner $name]
+}
+
+proc ::slopdrop::cmd::review {args} {
+    lassign $args sub id
+    if {!(($sub eq "list" && [llength $args] == 1) || ($sub in {diff approve reject} && [llength $args] == 2))} {
+        return -code error "usage: review list | review diff|approve|reject <id>"
+    }
+    ::slopdrop::native_result [::slopdrop::native::review $sub $id]
+}
+
+proc ::slopdrop::cmd::chanlist {channel} {
+    ::slopdrop::native_result [::slopdrop::native::chanlist $channel]
+}
+
+proc ::slopdrop::cmd::stock::quote {symbol} {
+    ::slopdrop::native_result [::slopdrop::native::stock quote $symbol {} {}]
+}
+
+proc ::slopdrop::cmd::stock::price {symbol} {
+    ::slopdrop::native_result [::slopdrop::native::stock price $symbol {} {}]
+}
+
+proc ::slopdrop::cmd::stock::detail {symbol} {
+    ::slopdrop::native_result [::slopdrop::native::stock detail $symbol {} {}]
+}
+
+proc ::slopdrop::cmd::stock::history {symbol {days 7} {interval ""}} {
+    ::slopdrop::native_result [::slopdrop::native::stock history $symbol $days $interval]
+}
+
+::slopdrop::expose history ::slopdrop::cmd::history
+::slopdrop::expose diff ::slopdrop::cmd::diff
+::slopdrop::expose blame ::slopdrop::cmd::blame
+::slopdrop::expose rollback ::slopdrop::cmd::rollback
+::slopdrop::expose revert ::slopdrop::cmd::revert
+::slopdrop::expose undo ::slopdrop::cmd::undo
+::slopdrop::expose fsck ::slopdrop::cmd::fsck
+::slopdrop::expose pushstatus ::slopdrop::cmd::pushstatus
+::slopdrop::expose sync ::slopdrop::cmd::sync
+::slopdrop::expose protect ::slopdrop::cmd::protect
+::slopdrop::expose unprotect ::slopdrop::cmd::unprotect
+::slopdrop::expose owner ::slopdrop::cmd::owner
+::slopdrop::expose review ::slopdrop::cmd::review
+::slopdrop::expose chanlist ::slopdrop::cmd::chanlist
+foreach sub {quote price detail history} {
+    ::slopdrop::expose ::stock::$sub ::slopdrop::cmd::stock::$sub
+}
+"#;
+
+/// Turn a native result into the `{ok value}` / `{error message}` reply
+fn reply(result: Result<String>) -> String {
+    match result {
+        Ok(value) => tcl_list(&["ok".to_string(), value]),
+        Err(e) => tcl_list(&["error".to_string(), e.to_string()]),
+    }
+}
+
+/// Register the native commands and expose them to the sandbox
+pub fn register(
+    sandbox: &TclSandbox,
+    tcl_config: SharedTclConfig,
+    channel_members: ChannelMembers,
+    context: SharedNativeContext,
+) -> Result<()> {
+    let master = sandbox.master();
+
+    let config = tcl_config.clone();
+    tclosure!(master, cmd: "::slopdrop::native::history", move |kind: String, name: String, count: String| -> TclResult<String> {
+        Ok(reply(history(&current_config(&config), &kind, &name, &count)))
+    });
+
+    let config = tcl_config.clone();
+    tclosure!(master, cmd: "::slopdrop::native::diff", move |name: String, rev1: String, rev2: String| -> TclResult<String> {
+        Ok(reply(diff(&current_config(&config), &name, &rev1, &rev2)))
+    });
+
+    let config = tcl_config.clone();
+    tclosure!(master, cmd: "::slopdrop::native::blame", move |name: String| -> TclResult<String> {
+        Ok(reply(blame(&current_config(&config), &name)))
+    });
+
+    let rollback_context = context.clone();
+    tclosure!(master, cmd: "::slopdrop::native::rollback", move |hash: String| -> TclResult<String> {
+        Ok(reply(rollback(&rollback_context, &hash)))
+    });
+
+    let revert_context = context.clone();
+    tclosure!(master, cmd: "::slopdrop::native::revert", move |hash: String| -> TclResult<String> {
+        Ok(reply(revert(&revert_context, &hash)))
+    });
+
+    let undo_context = context.clone();
+    tclosure!(master, cmd: "::slopdrop::native::undo", move |nick: String| -> TclResult<String> {
+        Ok(reply(undo(&undo_context, &nick)))
+    });
+
+    let config = tcl_config.clone();
+    let fsck_context = context.clone();
+    tclosure!(master, cmd: "::slopdrop::native::fsck", move |repair: String| -> TclResult<String> {
+        Ok(reply(fsck(&current_config(&config), &fsck_context, &repair)))
+    });
+
+    let config = tcl_config.clone();
+    let pushstatus_context = context.clone();
+    tclosure!(master, cmd: "::slopdrop::native::pushstatus", move |retry: String| -> TclResult<String> {
+        Ok(reply(pushstatus(&current_config(&config), &pushstatus_context, &retry)))
+    });
+
+    let config = tcl_config.clone();
+    let sync_context = context.clone();
+    tclosure!(master, cmd: "::slopdrop::native::sync", move |side: String| -> TclResult<String> {
+        Ok(reply(sync(&current_config(&config), &sync_context, &side)))
+    });
+
+    let config = tcl_config.clone();
+    let protect_context = context.clone();
+    tclosure!(master, cmd: "::slopdrop::native::protect", move |name: String, protected: String| -> TclResult<String> {
+        Ok(reply(protect(&current_config(&config), &protect_context, &name, protected == "1")))
+    });
+
+    let config = tcl_config.clone();
+    tclosure!(master, cmd: "::slopdrop::native::owner", move |name: String| -> TclResult<String> {
+        Ok(reply(owner(&current_config(&config), &name)))
+    });
+
+    let config = tcl_config.clone();
+    let guard_context = context.clone();
+    tclosure!(master, cmd: "::slopdrop::native::proc_allowed", move |name: String, defined: String, arglist: String, body: String| -> TclResult<String> {
+        let definition = (defined == "1").then_some((arglist.as_str(), body.as_str()));
+        Ok(reply(proc_allowed(&current_config(&config), &guard_context, &name, definition)))
+    });
+
+    let config = tcl_config;
+    let review_context = context.clone();
+    tclosure!(master, cmd: "::slopdrop::native::review", move |sub: String, id: String| -> TclResult<String> {
+        Ok(reply(review(&current_config(&config), &review_context, &sub, &id)))
+    });
+
+    tclosure!(master, cmd: "::slopdrop::native::chanlist", move |channel: String| -> TclResult<String> {
+        Ok(reply(chanlist(&channel_members, &channel)))
+    });
+
+    tclosure!(master, cmd: "::slopdrop::native::stock", move |sub: String, symbol: String, days: String, interval: String| -> TclResult<String> {
+        Ok(reply(stock(&sub, &symbol, &days, &interval)))
+    });
+
+    master
+        .eval(NATIVE_WRAPPERS)
+        .map_err(|e| anyhow!("Failed to register native commands: {:?}", e))?;
+
+    Ok(())
+}
+
+/// `history ?count?` - recent state commits as a list of {hash date author message}
+/// `history proc|var <name> ?count?` - only the commits that changed that proc/var
+fn history(tcl_config: &TclConfig, kind: &str, name: &str, count: &str) -> Result<String> {
+    let count = count
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| anyhow!("usage: history ?count? | history proc|var <name> ?count?"))?;
+
+    let persistence = state_persistence(tcl_config);
+    let commits: Vec<(String, i64, String, String)> = match kind {
+        "proc" | "var" => {
+            let versions = if kind == "proc" {
+                persistence.proc_history(name, count)?
+            } else {
+                persistence.var_history(name, count)?
+            };
+            versions
+                .into_iter()
+                .map(|v| (v.commit_id, v.timestamp, v.author, v.message))
+                .collect()
+        }
+        _ => persistence.get_history(count)?,
+    };
+
+    let entries: Vec<String> = commits
+        .into_iter()
+        .map(|(hash, timestamp, author, message)| {
+            let date = chrono::DateTime::from_timestamp(timestamp, 0)
+                .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| timestamp.to_string());
+            tcl_list(&[short_hash(&hash), date, author, message])
+        })
+        .collect();
+
+    Ok(tcl_list(&entries))
+}
+
+/// `diff <proc> ?rev1? ?rev2?` - unified diff of a proc between two versions
+/// Defaults to the proc's most recent change
+fn diff(tcl_config: &TclConfig, name: &str, rev1: &str, rev2: &str) -> Result<String> {
+    let rev = |rev: &str| Some(rev.trim()).filter(|rev| !rev.is_empty());
+    state_persistence(tcl_config).diff_proc(name, rev(rev1), rev(rev2))
+}
+
+/// `blame <proc>` - each line of a proc as a list of {hash author line}
+fn blame(tcl_config: &TclConfig, name: &str) -> Result<String> {
+    let lines: Vec<String> = state_persistence(tcl_config)
+        .blame_proc(name)?
+        .into_iter()
+        .map(|line| tcl_list(&[short_hash(&line.commit_id), line.author, line.line]))
+        .collect();
+
+    Ok(tcl_list(&lines))
+}
+
+/// Handle on the configured state repository
+fn state_persistence(tcl_config: &TclConfig) -> StatePersistence {
+    StatePersistence::from_config(tcl_config)
+}
+
+fn short_hash(hash: &str) -> String {
+    hash.get(..8).unwrap_or(hash).to_string()
+}
+
+/// `rollback <hash>` - reset the state repository (admin only)
+/// Runs on the worker once the eval returns
+fn rollback(context: &SharedNativeContext, hash: &str) -> Result<String> {
+    let mut context = context
+        .lock()
+        .map_err(|e| anyhow!("failed to read eval context: {}", e))?;
+    if !context.is_admin {
+        return Err(anyhow!("rollback requires admin privileges (use tclAdmin)"));
+    }
+    if context.dry_run {
+        return Err(anyhow!("rollback isn't available in a dry run"));
+    }
+
+    let hash = hash.trim();
+    if hash.is_empty() {
+        return Err(anyhow!("usage: rollback <commit-hash>"));
+    }
+
+    schedule(&mut context, StateOperation::Rollback { commit_hash: hash.to_string() })?;
+    Ok(format!("Rolling back to commit {} after the eval", hash))
+}
+
+/// `revert <hash>` - undo one commit on top of HEAD, keeping later history (admin only)
+/// The revert is committed as the caller on the worker once the eval returns
+fn revert(context: &SharedNativeContext, hash: &str) -> Result<String> {
+    let mut context = context
+        .lock()
+        .map_err(|e| anyhow!("failed to read eval context: {}", e))?;
+    if !context.is_admin {
+        return Err(anyhow!("revert requires admin privileges (use tclAdmin)"));
+    }
+    if context.dry_run {
+        return Err(anyhow!("revert isn't available in a dry run"));
+    }
+
+    let hash = hash.trim();
+    if hash.is_empty() {
+        return Err(anyhow!("usage: revert <commit-hash>"));
+    }
+
+    let operation = StateOperation::Revert {
+        commit_hash: hash.to_string(),
+        nick: context.nick.clone(),
+        host: context.host.clone(),
+    };
+    schedule(&mut context, operation)?;
+    Ok(format!("Reverting {} after the eval", hash))
+}
+
+/// `undo ?nick?` - revert the caller's most recent commit
+/// Undoing someone else's commit (`undo <nick>`) is admin only
+fn undo(context: &SharedNativeContext, nick: &str) -> Result<String> {
+    let mut context = context
+        .lock()
+        .map_err(|e| anyhow!("failed to read eval context: {}", e))?;
+    if context.dry_run {
+        return Err(anyhow!("undo isn't available in a dry run"));
+    }
+
+    let nick = nick.trim();
+    let author = if nick.is_empty() || nick == context.nick {
+        None
+    } else if context.is_admin {
+        Some(nick.to_string())
+    } else {
+        return Err(anyhow!("undoing someone else's change requires admin privileges (use tclAdmin)"));
+    };
+
+    let operation = StateOperation::Undo {
+        nick: context.nick.clone(),
+        host: context.host.clone(),
+        author,
+    };
+    schedule(&mut context, operation)?;
+    Ok("Undoing after the eval".to_string())
+}
+
+/// Leave a repository rewrite to the worker, which commits held back var
+/// changes before it so none of them are lost or reverted out of order
+fn schedule(context: &mut NativeContext, operation: StateOperation) -> Result<()> {
+    if context.pending_operation.is_some() {
+        return Err(anyhow!("only one rollback, revert, undo or sync per eval"));
+    }
+    context.pending_operation = Some(operation);
+    Ok(())
+}
+
+/// `fsck ?-repair?` - check the state repository for missing, orphaned,
+/// duplicate and broken procs/vars; `-repair` commits fixes as the caller (admin only)
+fn fsck(tcl_config: &TclConfig, context: &SharedNativeContext, repair: &str) -> Result<String> {
+    let context = context
+        .lock()
+        .map_err(|e| anyhow!("failed to read eval context: {}", e))?;
+    if !context.is_admin {
+        return Err(anyhow!("fsck requires admin privileges (use tclAdmin)"));
+    }
+    let repair = repair.trim() == "1";
+    if repair && context.dry_run {
+        return Err(anyhow!("fsck -repair isn't available in a dry run"));
+    }
+
+    let persistence = state_persistence(tcl_config);
+    let report = persistence.fsck(&scratch_interp()?)?;
+    let mut message = report.render();
+    if repair {
+        let user_info = UserInfo::new(context.nick.clone(), context.host.clone());
+        if let Some(commit_info) = persistence.fsck_repair(&report, &user_info)? {
+            info!("{} repaired state in {}", context.nick, commit_info.commit_id);
+            message.push_str(&format!("\nRepaired in {}", short_hash(&commit_info.commit_id)));
+        }
+    }
+    Ok(message)
+}
+
+/// `pushstatus ?-retry?` - how many commits wait to be pushed to the state
+/// remote and why the last push failed; `-retry` pushes right away
+fn pushstatus(tcl_config: &TclConfig, context: &SharedNativeContext, retry: &str) -> Result<String> {
+    let context = context
+        .lock()
+        .map_err(|e| anyhow!("failed to read eval context: {}", e))?;
+    if !context.is_admin {
+        return Err(anyhow!("pushstatus requires admin privileges (use tclAdmin)"));
+    }
+    if tcl_config.state_repo.is_none() {
+        return Ok("No state_repo configured".to_string());
+    }
+
+    let queue = push_queue::queue(&state_persistence(tcl_config));
+    if retry.trim() == "1" {
+        queue.retry();
+    }
+    Ok(queue.status().render())
+}
+
+/// `sync ?-ours|-theirs?` - fetch the state remote and merge it (admin only)
+/// Conflicts are reported unless a side is given to win them; runs on the
+/// worker once the eval returns
+fn sync(tcl_config: &TclConfig, context: &SharedNativeContext, side: &str) -> Result<String> {
+    let mut context = context
+        .lock()
+        .map_err(|e| anyhow!("failed to read eval context: {}", e))?;
+    if !context.is_admin {
+        return Err(anyhow!("sync requires admin privileges (use tclAdmin)"));
+    }
+    if context.dry_run {
+        return Err(anyhow!("sync isn't available in a dry run"));
+    }
+    if tcl_config.state_repo.is_none() {
+        return Err(anyhow!("No state_repo configured"));
+    }
+
+    let resolution = match side.trim() {
+        "" => ConflictResolution::Report,
+        "ours" => ConflictResolution::Ours,
+        "theirs" => ConflictResolution::Theirs,
+        other => return Err(anyhow!("unknown side: {}", other)),
+    };
+    schedule(&mut context, StateOperation::Sync { resolution })?;
+    Ok("Syncing after the eval".to_string())
+}
+
+/// `protect <proc>` / `unprotect <proc>` - allow only the proc's owner and
+/// admins to change it, or lift that again (owner or admin only)
+fn protect(tcl_config: &TclConfig, context: &SharedNativeContext, name: &str, protected: bool) -> Result<String> {
+    let context = context
+        .lock()
+        .map_err(|e| anyhow!("failed to read eval context: {}", e))?;
+    let command = if protected { "protect" } else { "unprotect" };
+    if context.dry_run {
+        return Err(anyhow!("{} isn't available in a dry run", command));
+    }
+
+    let name = name.trim().trim_start_matches("::");
+    if name.is_empty() {
+        return Err(anyhow!("usage: {} <proc>", command));
+    }
+
+    let user_info = UserInfo::new(context.nick.clone(), context.host.clone());
+    let commit_info = state_persistence(tcl_config).set_proc_protected(name, protected, &user_info, context.is_admin)?;
+    info!("{} {}ed proc {}", context.nick, command, name);
+
+    Ok(format!(
+        "{} {} in {}",
+        if protected { "Protected" } else { "Unprotected" },
+        name,
+        short_hash(&commit_info.commit_id)
+    ))
+}
+
+/// `owner <proc>` - who owns a proc, and whether it is protected
+fn owner(tcl_config: &TclConfig, name: &str) -> Result<String> {
+    let name = name.trim().trim_start_matches("::");
+    let owners = state_persistence(tcl_config).proc_owners();
+    Ok(match owners.get(name) {
+        Some(owner) if owner.protected => format!("{} (protected)", owner.nick),
+        Some(owner) => owner.nick.clone(),
+        None => format!("{} has no recorded owner", name),
+    })
+}
+
+/// Refuse to let the caller redefine (to `definition`, an arglist and body)
+/// or delete a proc protected by someone else; proc_guard.tcl asks before
+/// the sandbox changes any proc. The refused change is held for review once
+/// the eval returns, except in dry runs and system evals. Outside an eval
+/// the bot itself is loading procs, which is never refused
+fn proc_allowed(
+    tcl_config: &TclConfig,
+    context: &SharedNativeContext,
+    name: &str,
+    definition: Option<(&str, &str)>,
+) -> Result<String> {
+    let mut context = context
+        .lock()
+        .map_err(|e| anyhow!("failed to read eval context: {}", e))?;
+    if context.nick.is_empty() {
+        return Ok(String::new());
+    }
+
+    let user_info = UserInfo::new(context.nick.clone(), context.host.clone());
+    let reason = match state_persistence(tcl_config).proc_owners().denied(name, &user_info, context.is_admin) {
+        Some(reason) => reason,
+        None => return Ok(String::new()),
+    };
+    if context.dry_run || context.nick == "system" {
+        return Err(anyhow!(reason));
+    }
+
+    let content = definition.map(|(arglist, body)| tcl_list(&[arglist, body]));
+    context
+        .pending_review
+        .get_or_insert_with(HeldChanges::default)
+        .hold(&state_proc_name(name), content);
+    Err(anyhow!("{}; the change is held for an admin to review", reason))
+}
+
+/// A proc named the way the state diff names it: global procs without the
+/// leading ::, namespaced ones fully qualified
+fn state_proc_name(name: &str) -> String {
+    let name = name.trim_start_matches("::");
+    if name.contains("::") {
+        format!("::{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// `review list|diff|approve|reject` - go through the changes to protected
+/// procs held for review (admin only). Approving commits the change as its
+/// submitter, with the caller as committer; the procs are reloaded after the eval
+fn review(tcl_config: &TclConfig, context: &SharedNativeContext, sub: &str, id: &str) -> Result<String> {
+    let mut context = context
+        .lock()
+        .map_err(|e| anyhow!("failed to read eval context: {}", e))?;
+    if !context.is_admin {
+        return Err(anyhow!("review requires admin privileges (use tclAdmin)"));
+    }
+
+    let persistence = state_persistence(tcl_config);
+    if sub == "list" {
+        let entries: Vec<String> = persistence
+            .pending_reviews()?
+            .into_iter()
+            .map(|change| {
+                let date = chrono::DateTime::from_timestamp(change.submitted, 0)
+                    .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_else(|| change.submitted.to_string());
+                tcl_list(&[change.id.to_string(), date, change.nick.clone(), change.changes.summary()])
+            })
+            .collect();
+        return Ok(tcl_list(&entries));
+    }
+
+    let id: u64 = id
+        .trim()
+        .trim_start_matches('#')
+        .parse()
+        .map_err(|_| anyhow!("invalid review id: {}", id))?;
+    match sub {
+        "diff" => {
+            let report = persistence.review_diff(id)?;
+            let mut text = format!(
+                "#{} would commit: {} ({} files, +{} -{})",
+                id, report.changes_summary, report.files_changed, report.insertions, report.deletions
+            );
+            for diff in &report.diffs {
+                text.push('\n');
+                text.push_str(diff.trim_end_matches('\n'));
+            }
+            Ok(text)
+        }
+        "approve" => {
+            if context.dry_run {
+                return Err(anyhow!("review approve isn't available in a dry run"));
+            }
+            let approver = UserInfo::new(context.nick.clone(), context.host.clone());
+            let (commit_info, changes) = persistence.approve_review(id, &approver)?;
+            info!("{} approved change #{} as {}", context.nick, id, commit_info.commit_id);
+
+            let message = format!("Approved #{} as {}: {}", id, short_hash(&commit_info.commit_id), changes.summary());
+            context
+                .pending_reload
+                .get_or_insert_with(StateChanges::default)
+                .merge(changes);
+            Ok(message)
+        }
+        "reject" => {
+            if context.dry_run {
+                return Err(anyhow!("review reject isn't available in a dry run"));
+            }
+            let change = persistence.reject_review(id)?;
+            info!("{} rejected change #{} by {}", context.nick, id, change.nick);
+            Ok(format!("Rejected #{} by {}: {}", id, change.nick, change.changes.summary()))
+        }
+        other => Err(anyhow!("unknown review command: {}", other)),
+    }
+}
+
+/// `chanlist <channel>` - sorted list of nicks in a channel
+fn chanlist(channel_members: &ChannelMembers, channel: &str) -> Result<String> {
+    let members = channel_members
+        .read()
+        .map_err(|e| anyhow!("failed to read channel members: {}", e))?;
+
+    let mut nicks: Vec<&String> = members
+        .get(channel)
+        .map(|nicks| nicks.iter().collect())
+        .unwrap_or_default();
+    nicks.sort();
+
+    Ok(tcl_list(&nicks))
+}
+
+/// `stock::quote|price|detail|history` - Yahoo Finance lookups
+fn stock(sub: &str, symbol: &str, days: &str, interval: &str) -> Result<String> {
+    if symbol.is_empty() || symbol.contains(char::is_whitespace) {
+        return Err(anyhow!("invalid stock symbol: {}", symbol));
+    }
+
+    let mut command = format!("stock::{} {}", sub, symbol);
+    if sub == "history" {
+        let days = days
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| anyhow!("usage: stock::history <symbol> ?days? ?interval?"))?;
+        command.push_str(&format!(" {}", days));
+        if !interval.trim().is_empty() {
+            command.push_str(&format!(" {}", interval.trim()));
+        }
+    }
+
+    crate::stock_commands::handle_stock_command(&command)
+}
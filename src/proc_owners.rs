@@ -0,0 +1,177 @@
+//! Proc ownership and protection
+//!
+//! The author of the commit that first stores a proc becomes its owner.
+//! Owners (and admins) can `protect` a proc, after which anyone else's
+//! `proc` or `rename` on it fails before changing anything (see
+//! proc_guard.tcl). Changes that get past that, like deleting the proc's
+//! namespace, aren't committed: the TCL worker puts the proc back and queues
+//! the change for an admin to review (see review_queue.rs).
+//!
+//! Owners are stored in `procs/_owners`, one `name nick host ?protected?`
+//! line per proc, sorted by name. Procs from before ownership was recorded
+//! have no line until someone protects them.
+
+use crate::state::{StateChanges, UserInfo};
+use std::collections::BTreeMap;
+
+/// The owners file, relative to the state root
+pub const OWNERS_FILE: &str = "procs/_owners";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcOwner {
+    pub nick: String,
+    pub host: String,
+    pub protected: bool,
+}
+
+impl ProcOwner {
+    pub fn new(user_info: &UserInfo) -> Self {
+        Self {
+            nick: user_info.nick.clone(),
+            host: user_info.host.clone(),
+            protected: false,
+        }
+    }
+
+    /// Whether `user_info` is this owner: same nick and host, as for `undo`
+    pub fn is(&self, user_info: &UserInfo) -> bool {
+        self.nick == user_info.nick && self.host == user_info.host
+    }
+}
+
+/// Owners of the procs in a state repository, by proc name (without a
+/// leading ::)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcOwners {
+    owners: BTreeMap<String, ProcOwner>,
+}
+
+impl ProcOwners {
+    pub fn parse(content: &str) -> Self {
+        let mut owners = BTreeMap::new();
+        for line in content.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() < 3 {
+                continue;
+            }
+            owners.insert(
+                parts[0].to_string(),
+                ProcOwner {
+                    nick: parts[1].to_string(),
+                    host: parts[2].to_string(),
+                    protected: parts.get(3) == Some(&"protected"),
+                },
+            );
+        }
+        Self { owners }
+    }
+
+    pub fn render(&self) -> String {
+        let lines: Vec<String> = self
+            .owners
+            .iter()
+            .map(|(name, owner)| {
+                let mut line = format!("{} {} {}", name, owner.nick, owner.host);
+                if owner.protected {
+                    line.push_str(" protected");
+                }
+                line
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ProcOwner> {
+        self.owners.get(key(name))
+    }
+
+    pub fn set(&mut self, name: &str, owner: ProcOwner) {
+        self.owners.insert(key(name).to_string(), owner);
+    }
+
+    /// Make `user_info` the owner of the new procs in `changes` that don't
+    /// have one, and forget deleted procs. Returns whether anything changed
+    pub fn record(&mut self, changes: &StateChanges, user_info: &UserInfo) -> bool {
+        let mut changed = false;
+        for name in &changes.deleted_procs {
+            changed |= self.owners.remove(key(name)).is_some();
+        }
+        for name in &changes.new_procs {
+            // Names the file format can't hold stay unowned
+            if name.is_empty() || name.contains(char::is_whitespace) || self.get(name).is_some() {
+                continue;
+            }
+            self.set(name, ProcOwner::new(user_info));
+            changed = true;
+        }
+        changed
+    }
+
+    /// Why `user_info` may not redefine or delete `name`, if they may not
+    pub fn denied(&self, name: &str, user_info: &UserInfo, is_admin: bool) -> Option<String> {
+        match self.get(name) {
+            Some(owner) if owner.protected && !is_admin && !owner.is(user_info) => Some(format!(
+                "{} is protected by {} (only they or an admin can change it)",
+                key(name),
+                owner.nick
+            )),
+            _ => None,
+        }
+    }
+
+    /// The part of `changes` touching protected procs `user_info` may not
+    /// change
+    pub fn denied_changes(&self, changes: &StateChanges, user_info: &UserInfo, is_admin: bool) -> StateChanges {
+        let denied = |names: &[String]| -> Vec<String> {
+            names
+                .iter()
+                .filter(|name| self.denied(name, user_info, is_admin).is_some())
+                .cloned()
+                .collect()
+        };
+        StateChanges {
+            new_procs: denied(&changes.new_procs),
+            deleted_procs: denied(&changes.deleted_procs),
+            ..Default::default()
+        }
+    }
+}
+
+fn key(name: &str) -> &str {
+    name.trim_start_matches("::")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owners_round_trip_and_protection() {
+        let alice = UserInfo::new("alice".to_string(), "~a@host".to_string());
+        let bob = UserInfo::new("bob".to_string(), "~b@host".to_string());
+
+        let mut owners = ProcOwners::default();
+        let changes = StateChanges {
+            new_procs: vec!["greet".to_string(), "::tools::fmt".to_string()],
+            ..Default::default()
+        };
+        assert!(owners.record(&changes, &alice));
+        // Redefining doesn't change the owner
+        assert!(!owners.record(&changes, &bob));
+
+        let mut greet = owners.get("::greet").unwrap().clone();
+        greet.protected = true;
+        owners.set("greet", greet);
+
+        let owners = ProcOwners::parse(&owners.render());
+        assert_eq!(owners.render(), "greet alice ~a@host protected\ntools::fmt alice ~a@host");
+        assert!(owners.denied("greet", &alice, false).is_none());
+        assert!(owners.denied("greet", &bob, true).is_none());
+        assert_eq!(
+            owners.denied("greet", &bob, false).unwrap(),
+            "greet is protected by alice (only they or an admin can change it)"
+        );
+        assert!(owners.denied("tools::fmt", &bob, false).is_none());
+        assert_eq!(owners.denied_changes(&changes, &bob, false).new_procs, vec!["greet"]);
+    }
+}
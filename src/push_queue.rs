@@ -0,0 +1,288 @@
+//! Background pushing of state commits to the remote
+//!
+//! Commits are pushed by one background thread per state repository instead
+//! of inline after every commit, so a slow or unreachable remote doesn't stall
+//! evaluation. Commits made while a push is waiting or running go out together
+//! in the next push, and failed pushes are retried with exponential backoff.
+//! Admins see the queue with `pushstatus`; it is flushed on graceful shutdown.
+//!
+//! Only the TCL worker commits while the bot runs, so its queue sees every
+//! push. A restarted worker resumes the queue with the commits the remote
+//! doesn't have yet, so nothing the old one left behind is forgotten.
+
+use crate::state::StatePersistence;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long a push waits after a commit for more commits to take along
+const BATCH_DELAY: Duration = Duration::from_secs(2);
+
+/// Delay before retrying a failed push, doubled after every further failure
+const INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+/// How long shutdown waits for pending commits to be pushed
+pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static::lazy_static! {
+    /// Push queues by state path
+    static ref QUEUES: Mutex<HashMap<PathBuf, Arc<PushQueue>>> = Mutex::new(HashMap::new());
+}
+
+/// What admins see of a push queue
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PushStatus {
+    /// Commits not pushed yet
+    pub unpushed: usize,
+    /// Pushes that failed in a row
+    pub failures: u32,
+    pub last_error: Option<String>,
+    /// Time until the next attempt, while one is scheduled
+    pub retry_in: Option<Duration>,
+}
+
+impl PushStatus {
+    /// One line, like "3 commits unpushed, last error: ... (retrying in 40s)"
+    pub fn render(&self) -> String {
+        if self.unpushed == 0 {
+            return "All commits pushed".to_string();
+        }
+        let mut line = format!("{} commits unpushed", self.unpushed);
+        if let Some(ref error) = self.last_error {
+            line.push_str(&format!(", last error: {}", error));
+        }
+        if let Some(retry_in) = self.retry_in.filter(|_| self.failures > 0) {
+            line.push_str(&format!(" (retrying in {}s)", retry_in.as_secs()));
+        }
+        line
+    }
+}
+
+struct QueueState {
+    unpushed: usize,
+    failures: u32,
+    last_error: Option<String>,
+    /// When the next push may start
+    next_attempt: Instant,
+    /// A push is running
+    pushing: bool,
+    /// Pushes attempted so far, so `flush` can tell when one finished
+    attempts: u64,
+}
+
+/// Pending pushes of one state repository, worked off by a background thread
+pub struct PushQueue {
+    persistence: StatePersistence,
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+impl PushQueue {
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Record a new commit to push
+    pub fn notify(&self) {
+        let mut state = self.lock();
+        state.unpushed += 1;
+        // Start the batch window with the first commit, unless backing off
+        if state.unpushed == 1 && state.failures == 0 {
+            state.next_attempt = Instant::now() + BATCH_DELAY;
+        }
+        self.changed.notify_all();
+    }
+
+    /// Take on the commits the remote doesn't have yet, such as ones an
+    /// earlier worker committed but didn't get to push
+    pub fn resume(&self) {
+        let unpushed = match self.persistence.unpushed_commits() {
+            Ok(unpushed) => unpushed,
+            Err(e) => {
+                warn!("Failed to count unpushed commits: {}", e);
+                return;
+            }
+        };
+        let mut state = self.lock();
+        if unpushed > state.unpushed {
+            state.unpushed = unpushed;
+            if state.failures == 0 {
+                state.next_attempt = Instant::now() + BATCH_DELAY;
+            }
+            self.changed.notify_all();
+        }
+    }
+
+    /// Push now instead of waiting out the batch window or backoff
+    pub fn retry(&self) {
+        self.lock().next_attempt = Instant::now();
+        self.changed.notify_all();
+    }
+
+    pub fn status(&self) -> PushStatus {
+        let state = self.lock();
+        PushStatus {
+            unpushed: state.unpushed,
+            failures: state.failures,
+            last_error: state.last_error.clone(),
+            retry_in: (state.unpushed > 0 && !state.pushing)
+                .then(|| state.next_attempt.saturating_duration_since(Instant::now())),
+        }
+    }
+
+    /// Push whatever is pending and wait for it. Fails if the push fails or
+    /// doesn't finish within `timeout`
+    pub fn flush(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        let attempts = state.attempts;
+        state.next_attempt = Instant::now();
+        self.changed.notify_all();
+
+        while state.unpushed > 0 || state.pushing {
+            if state.attempts > attempts && !state.pushing {
+                if let Some(ref error) = state.last_error {
+                    return Err(anyhow!("{} commits unpushed: {}", state.unpushed, error));
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(anyhow!("Timed out pushing with {} commits unpushed", state.unpushed));
+            }
+            state = self
+                .changed
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        Ok(())
+    }
+
+    fn run(&self) {
+        let mut state = self.lock();
+        loop {
+            if state.unpushed == 0 {
+                state = self.changed.wait(state).unwrap_or_else(PoisonError::into_inner);
+                continue;
+            }
+            let now = Instant::now();
+            if now < state.next_attempt {
+                let wait = state.next_attempt - now;
+                state = self
+                    .changed
+                    .wait_timeout(state, wait)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+                continue;
+            }
+
+            let batch = state.unpushed;
+            state.pushing = true;
+            drop(state);
+
+            let result = self.persistence.push_to_remote();
+
+            state = self.lock();
+            state.pushing = false;
+            state.attempts += 1;
+            match result {
+                Ok(()) => {
+                    if state.failures > 0 {
+                        info!("Pushed {} commits after {} failed attempts", batch, state.failures);
+                    }
+                    state.unpushed -= batch;
+                    state.failures = 0;
+                    state.last_error = None;
+                    state.next_attempt = Instant::now() + BATCH_DELAY;
+                }
+                Err(e) => {
+                    state.failures += 1;
+                    let backoff = backoff(state.failures);
+                    warn!(
+                        "Failed to push {} commits to remote (retrying in {}s): {}",
+                        state.unpushed,
+                        backoff.as_secs(),
+                        e
+                    );
+                    state.last_error = Some(e.to_string());
+                    state.next_attempt = Instant::now() + backoff;
+                }
+            }
+            self.changed.notify_all();
+        }
+    }
+}
+
+fn backoff(failures: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+/// The push queue of a state repository, started on first use
+pub fn queue(persistence: &StatePersistence) -> Arc<PushQueue> {
+    let state_path = persistence.state_path();
+    let mut queues = QUEUES.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(queue) = queues.get(state_path) {
+        return queue.clone();
+    }
+
+    let queue = Arc::new(PushQueue {
+        persistence: persistence.clone(),
+        state: Mutex::new(QueueState {
+            unpushed: 0,
+            failures: 0,
+            last_error: None,
+            next_attempt: Instant::now(),
+            pushing: false,
+            attempts: 0,
+        }),
+        changed: Condvar::new(),
+    });
+    let worker = queue.clone();
+    thread::Builder::new()
+        .name("state-push".to_string())
+        .spawn(move || worker.run())
+        .expect("failed to spawn state push thread");
+    queues.insert(state_path.to_path_buf(), queue.clone());
+    queue
+}
+
+/// Flush every push queue (on shutdown), failing with the first error
+pub fn flush_all(timeout: Duration) -> Result<()> {
+    let queues: Vec<Arc<PushQueue>> = QUEUES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .values()
+        .cloned()
+        .collect();
+
+    let mut result = Ok(());
+    for queue in queues {
+        if let Err(e) = queue.flush(timeout) {
+            warn!("Failed to push {:?} on shutdown: {}", queue.persistence.state_path(), e);
+            if result.is_ok() {
+                result = Err(e);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        assert_eq!(backoff(1), INITIAL_BACKOFF);
+        assert_eq!(backoff(2), INITIAL_BACKOFF * 2);
+        assert_eq!(backoff(4), INITIAL_BACKOFF * 8);
+        assert_eq!(backoff(40), MAX_BACKOFF);
+    }
+}
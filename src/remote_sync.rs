@@ -0,0 +1,166 @@
+//! Periodic fetching of the state remote
+//!
+//! A background thread per state repository fetches `state_repo` every
+//! `sync_interval_secs`, so a slow remote never holds up evaluation. The TCL
+//! worker picks up what arrived between evals, merges it (see
+//! `StatePersistence::merge_remote`) and reloads the changed procs and vars.
+//! The interval follows config updates, and the thread stops on shutdown.
+
+use crate::state::StatePersistence;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+lazy_static::lazy_static! {
+    /// Fetchers by state path, so a restarted worker reuses the running one
+    static ref FETCHERS: Mutex<HashMap<PathBuf, Arc<RemoteFetcher>>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Default)]
+struct FetchState {
+    /// Remote tip as of the last fetch
+    tip: Option<String>,
+    /// The tip moved since the worker last looked
+    fetched: bool,
+    last_error: Option<String>,
+    interval: Duration,
+    /// Set on shutdown; the thread exits instead of fetching again
+    stopped: bool,
+}
+
+/// Fetches one state repository's remote in the background
+pub struct RemoteFetcher {
+    persistence: StatePersistence,
+    state: Mutex<FetchState>,
+    changed: Condvar,
+}
+
+impl RemoteFetcher {
+    fn lock(&self) -> MutexGuard<'_, FetchState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Whether a fetch brought in new remote commits since the last call
+    pub fn take_fetched(&self) -> bool {
+        std::mem::take(&mut self.lock().fetched)
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.lock().interval
+    }
+
+    /// Fetch every `interval` from now on
+    pub fn set_interval(&self, interval: Duration) {
+        let mut state = self.lock();
+        if state.interval != interval {
+            state.interval = interval;
+            self.changed.notify_all();
+        }
+    }
+
+    /// Stop fetching; the thread exits at its next wakeup
+    pub fn stop(&self) {
+        self.lock().stopped = true;
+        self.changed.notify_all();
+    }
+
+    fn run(&self) {
+        loop {
+            let result = self.persistence.fetch_remote().and_then(|_| self.persistence.remote_tip());
+
+            let mut state = self.lock();
+            if state.stopped {
+                break;
+            }
+            match result {
+                Ok(tip) => {
+                    if tip != state.tip {
+                        debug!("Fetched new remote state: {:?}", tip);
+                        state.fetched = tip.is_some();
+                        state.tip = tip;
+                    }
+                    state.last_error = None;
+                }
+                Err(e) => {
+                    // Only the first of a run of failures is worth a warning
+                    if state.last_error.is_none() {
+                        warn!("Failed to fetch the state remote: {}", e);
+                    }
+                    state.last_error = Some(e.to_string());
+                }
+            }
+
+            // Sleep out the interval, starting over if it changes
+            let mut started = Instant::now();
+            let mut interval = state.interval;
+            loop {
+                if state.stopped {
+                    return;
+                }
+                if state.interval != interval {
+                    started = Instant::now();
+                    interval = state.interval;
+                }
+                let elapsed = started.elapsed();
+                if elapsed >= interval {
+                    break;
+                }
+                state = self
+                    .changed
+                    .wait_timeout(state, interval - elapsed)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+            }
+        }
+    }
+}
+
+/// The fetcher of a state repository, fetching every `interval` from its
+/// first use
+pub fn fetcher(persistence: &StatePersistence, interval: Duration) -> Arc<RemoteFetcher> {
+    let state_path = persistence.state_path();
+    let mut fetchers = FETCHERS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(fetcher) = fetchers.get(state_path) {
+        return fetcher.clone();
+    }
+
+    let fetcher = Arc::new(RemoteFetcher {
+        persistence: persistence.clone(),
+        state: Mutex::new(FetchState {
+            interval,
+            ..FetchState::default()
+        }),
+        changed: Condvar::new(),
+    });
+    let worker = fetcher.clone();
+    thread::Builder::new()
+        .name("state-fetch".to_string())
+        .spawn(move || worker.run())
+        .expect("failed to spawn state fetch thread");
+    fetchers.insert(state_path.to_path_buf(), fetcher.clone());
+    fetcher
+}
+
+/// Stop the fetcher of a state repository, if it has one
+pub fn stop(state_path: &Path) {
+    let fetcher = FETCHERS.lock().unwrap_or_else(PoisonError::into_inner).remove(state_path);
+    if let Some(fetcher) = fetcher {
+        fetcher.stop();
+    }
+}
+
+/// Stop every fetcher (on shutdown)
+pub fn stop_all() {
+    let fetchers: Vec<Arc<RemoteFetcher>> = FETCHERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .drain()
+        .map(|(_, fetcher)| fetcher)
+        .collect();
+    for fetcher in fetchers {
+        fetcher.stop();
+    }
+}
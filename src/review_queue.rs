@@ -0,0 +1,216 @@
+//! Review queue for changes to protected procs
+//!
+//! When an ordinary user redefines or deletes a proc someone else protected
+//! (see proc_owners.rs), the change isn't made: `proc` and `rename` refuse
+//! it, and anything that got past them is put back as stored. The TCL worker
+//! keeps the would-be proc definitions here and notifies the admins, who go
+//! through the queue with `review`. Approving a
+//! change commits it with the submitter as author and the approver as
+//! committer.
+//!
+//! The queue is kept in the state repository's git directory, so it survives
+//! restarts without being committed or pushed.
+
+use crate::state::{StateChanges, UserInfo};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The queue file, relative to the state repository's git directory
+pub const REVIEWS_FILE: &str = "slopdrop-reviews.json";
+
+/// A change held back for review
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PendingChange {
+    pub id: u64,
+    pub nick: String,
+    pub host: String,
+    /// Unix time the change was submitted
+    pub submitted: i64,
+    /// The evaluated code that made the change
+    pub code: String,
+    /// The procs it redefines (`new_procs`) or deletes (`deleted_procs`)
+    pub changes: StateChanges,
+    /// New `{args} {body}` of each redefined proc
+    pub procs: BTreeMap<String, String>,
+    /// Stored content of each proc when the change was submitted (None if it
+    /// wasn't stored), so approval can tell whether it changed since
+    pub base: BTreeMap<String, Option<String>>,
+}
+
+impl PendingChange {
+    pub fn author(&self) -> UserInfo {
+        UserInfo::new(self.nick.clone(), self.host.clone())
+    }
+
+    pub fn info(&self) -> ReviewInfo {
+        ReviewInfo {
+            id: self.id,
+            author: self.nick.clone(),
+            changes_summary: self.changes.summary(),
+        }
+    }
+}
+
+/// Changes to protected procs held back from one eval, with the new
+/// definitions they would have made
+#[derive(Debug, Clone, Default)]
+pub struct HeldChanges {
+    /// The procs redefined (`new_procs`) or deleted (`deleted_procs`)
+    pub changes: StateChanges,
+    /// New `{args} {body}` of each redefined proc
+    pub procs: BTreeMap<String, String>,
+}
+
+impl HeldChanges {
+    pub fn has_changes(&self) -> bool {
+        self.changes.has_changes()
+    }
+
+    /// Hold a new definition of `name`, or its deletion if `content` is
+    /// None, in place of anything held for it before
+    pub fn hold(&mut self, name: &str, content: Option<String>) {
+        self.changes.new_procs.retain(|held| held != name);
+        self.changes.deleted_procs.retain(|held| held != name);
+        self.procs.remove(name);
+        match content {
+            Some(content) => {
+                self.changes.new_procs.push(name.to_string());
+                self.procs.insert(name.to_string(), content);
+            }
+            None => self.changes.deleted_procs.push(name.to_string()),
+        }
+    }
+
+    /// Add what `other` holds, which wins where both hold the same proc
+    pub fn merge(&mut self, other: HeldChanges) {
+        for name in &other.changes.new_procs {
+            self.hold(name, other.procs.get(name).cloned());
+        }
+        for name in &other.changes.deleted_procs {
+            self.hold(name, None);
+        }
+    }
+}
+
+/// What admins are told about a change held for review
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ReviewInfo {
+    pub id: u64,
+    pub author: String,
+    pub changes_summary: String,
+}
+
+/// The pending changes of one state repository
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct ReviewQueue {
+    #[serde(skip)]
+    path: PathBuf,
+    /// Last id handed out; ids aren't reused
+    last_id: u64,
+    pending: Vec<PendingChange>,
+}
+
+impl ReviewQueue {
+    /// Read the queue at `path`; a missing file is an empty queue
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut queue: Self = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| anyhow!("Invalid review queue {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+        queue.path = path.to_path_buf();
+        Ok(queue)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        fs::write(&self.path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Pending changes, oldest first
+    pub fn pending(&self) -> &[PendingChange] {
+        &self.pending
+    }
+
+    pub fn get(&self, id: u64) -> Result<&PendingChange> {
+        self.pending
+            .iter()
+            .find(|change| change.id == id)
+            .ok_or_else(|| anyhow!("no pending change #{}", id))
+    }
+
+    /// Queue `change` under a new id, which is returned
+    pub fn submit(&mut self, mut change: PendingChange) -> u64 {
+        self.last_id += 1;
+        change.id = self.last_id;
+        self.pending.push(change);
+        self.last_id
+    }
+
+    pub fn remove(&mut self, id: u64) -> Result<PendingChange> {
+        let position = self
+            .pending
+            .iter()
+            .position(|change| change.id == id)
+            .ok_or_else(|| anyhow!("no pending change #{}", id))?;
+        Ok(self.pending.remove(position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(nick: &str) -> PendingChange {
+        PendingChange {
+            id: 0,
+            nick: nick.to_string(),
+            host: "host".to_string(),
+            submitted: 0,
+            code: "proc greet {} {}".to_string(),
+            changes: StateChanges {
+                new_procs: vec!["greet".to_string()],
+                ..Default::default()
+            },
+            procs: BTreeMap::from([("greet".to_string(), "{} {}".to_string())]),
+            base: BTreeMap::from([("greet".to_string(), Some("{} {return hi}".to_string()))]),
+        }
+    }
+
+    #[test]
+    fn test_queue_round_trip_keeps_ids_unique() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(REVIEWS_FILE);
+
+        let mut queue = ReviewQueue::open(&path).unwrap();
+        assert_eq!(queue.submit(change("alice")), 1);
+        assert_eq!(queue.submit(change("bob")), 2);
+        assert_eq!(queue.remove(2).unwrap().nick, "bob");
+        queue.save().unwrap();
+
+        let mut queue = ReviewQueue::open(&path).unwrap();
+        assert_eq!(queue.get(1).unwrap().info().changes_summary, change("alice").changes.summary());
+        assert!(queue.get(2).is_err());
+        assert_eq!(queue.submit(change("carol")), 3);
+        assert_eq!(queue.pending().len(), 2);
+    }
+
+    #[test]
+    fn test_held_changes_keep_the_last_change_per_proc() {
+        let mut held = HeldChanges::default();
+        held.hold("greet", Some("{} {return hi}".to_string()));
+        held.hold("greet", None);
+        held.hold("::tools::fmt", None);
+
+        let mut later = HeldChanges::default();
+        later.hold("::tools::fmt", Some("{s} {return $s}".to_string()));
+        held.merge(later);
+
+        assert_eq!(held.changes.new_procs, vec!["::tools::fmt"]);
+        assert_eq!(held.changes.deleted_procs, vec!["greet"]);
+        assert_eq!(held.procs.keys().collect::<Vec<_>>(), vec!["::tools::fmt"]);
+    }
+}
@@ -89,6 +89,16 @@ pub fn proc_tracking() -> String {
     load_tcl_file("proc_tracking.tcl", include_str!("../tcl/proc_tracking.tcl"))
 }
 
+/// Returns the master-side checks every sandbox proc definition goes through
+pub fn proc_guard() -> String {
+    load_tcl_file("proc_guard.tcl", include_str!("../tcl/proc_guard.tcl"))
+}
+
+/// Returns the master-side transaction journal for sandbox evals
+pub fn transaction_commands() -> String {
+    load_tcl_file("transactions.tcl", include_str!("../tcl/transactions.tcl"))
+}
+
 /// Returns link resolver commands with extensible API
 pub fn linkresolver_commands() -> String {
     load_tcl_file("linkresolver.tcl", include_str!("../tcl/linkresolver.tcl"))
@@ -1,10 +1,17 @@
 use anyhow::{anyhow, Result};
-use git2::{Repository, Signature, IndexAddOption, Cred, RemoteCallbacks, PushOptions, FetchOptions, build::RepoBuilder};
+use git2::{Repository, Signature, IndexAddOption, Cred, CredentialType, RemoteCallbacks, PushOptions, FetchOptions, build::RepoBuilder};
 use sha1::{Digest, Sha1};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
-use tcl::Interpreter;
+use std::path::{Path, PathBuf};
+use crate::config::TclConfig;
+use crate::glob_pattern::GlobPattern;
+use crate::proc_owners::{ProcOwner, ProcOwners, OWNERS_FILE};
+use crate::push_queue;
+use crate::review_queue::{HeldChanges, PendingChange, ReviewInfo, ReviewQueue, REVIEWS_FILE};
+use crate::state_bundle::StateBundle;
+use crate::state_layout::{self, StateLayout, VarType, LAYOUT_FILE, PROC_EXTENSION};
+use crate::tcl_wrapper::{tcl_list, tcl_quote, TclEval};
 use tracing::{debug, info, warn};
 
 /// Information about a git commit
@@ -32,37 +39,122 @@ impl UserInfo {
         Self { nick, host }
     }
 
+    /// Author email used in git commits (nick@host)
+    pub fn email(&self) -> String {
+        format!("{}@{}", self.nick, self.host)
+    }
+
     /// Generate a git author signature from IRC user info
     pub fn to_signature(&self) -> Result<Signature<'static>> {
-        let email = format!("{}@{}", self.nick, self.host);
-        Signature::now(&self.nick, &email)
+        Signature::now(&self.nick, &self.email())
             .map_err(|e| anyhow!("Failed to create signature: {}", e))
     }
 }
 
+/// Global list of the rules added with `persist off`, persisted like any
+/// other var (see "Ephemeral State" in proc_tracking.tcl)
+pub const PERSIST_RULES_VAR: &str = "slopdrop_ephemeral";
+
+/// Which vars and namespaces are ephemeral, i.e. never persisted: the bot's
+/// own ones declared in proc_tracking.tcl and whatever users marked with
+/// `persist off`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EphemeralRules {
+    /// Exact var names
+    pub vars: HashSet<String>,
+    /// `string match` patterns over var names, compiled when the rules load
+    pub patterns: Vec<GlobPattern>,
+    /// Namespaces, including everything below them
+    pub namespaces: Vec<String>,
+}
+
+impl EphemeralRules {
+    /// The rules in effect in an interpreter; none if proc_tracking.tcl isn't
+    /// loaded
+    pub fn capture(interp: &impl TclEval) -> Result<Self> {
+        let rules = interp
+            .eval_tcl(r#"
+                if {[llength [info commands ::slopdrop::persist::rules]]} {
+                    ::slopdrop::persist::rules
+                }
+            "#)
+            .map_err(|e| anyhow!("Failed to get persist rules: {:?}", e))?
+            .get_string();
+
+        let mut result = Self::default();
+        for line in rules.lines() {
+            match line.split_once(' ') {
+                Some(("var", name)) => {
+                    result.vars.insert(name.to_string());
+                }
+                Some(("pattern", pattern)) => result.patterns.push(GlobPattern::new(pattern)),
+                Some(("namespace", namespace)) => result.namespaces.push(namespace.to_string()),
+                _ => warn!("Ignoring malformed persist rule {:?}", line),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Whether a var (named as in `InterpreterState`) is ephemeral
+    pub fn is_ephemeral_var(&self, name: &str) -> bool {
+        if name == PERSIST_RULES_VAR {
+            return false;
+        }
+        self.vars.contains(name)
+            || self.patterns.iter().any(|pattern| pattern.matches(name))
+            || self.namespaces.iter().any(|namespace| is_below(name, namespace))
+    }
+
+    /// Whether a namespace is ephemeral
+    pub fn is_ephemeral_namespace(&self, name: &str) -> bool {
+        self.namespaces
+            .iter()
+            .any(|namespace| name == namespace || is_below(name, namespace))
+    }
+}
+
+/// Whether `name` is qualified with `namespace` (or one of its children)
+fn is_below(name: &str, namespace: &str) -> bool {
+    name.strip_prefix(namespace).is_some_and(|rest| rest.starts_with("::"))
+}
+
 /// Represents the state of procs and vars in the interpreter
+/// Namespace vars and namespaces are named without the leading `::`
 #[derive(Debug, Clone)]
 pub struct InterpreterState {
     pub procs: HashSet<String>,
     pub vars: HashSet<String>,
+    pub namespaces: HashSet<String>,
+    /// Which of the vars and namespaces aren't persisted
+    pub ephemeral: EphemeralRules,
 }
 
 impl InterpreterState {
     /// Capture the current state of the interpreter
-    pub fn capture(interp: &Interpreter) -> Result<Self> {
+    pub fn capture(interp: &impl TclEval) -> Result<Self> {
         let procs = Self::get_procs(interp)?;
         let vars = Self::get_vars(interp)?;
+        let namespaces = Self::get_namespaces(interp)?;
+        let ephemeral = EphemeralRules::capture(interp)?;
 
-        Ok(Self { procs, vars })
+        Ok(Self { procs, vars, namespaces, ephemeral })
     }
 
-    fn get_procs(interp: &Interpreter) -> Result<HashSet<String>> {
+    fn get_procs(interp: &impl TclEval) -> Result<HashSet<String>> {
         // Use TCL to convert the list to a format we can parse safely
         // This avoids issues with special characters in proc names
         // Also validate each proc to filter out invalid entries
-        match interp.eval(r#"
+        // Procs in user namespaces (when proc_tracking.tcl is loaded) are
+        // fully qualified, the way the proc wrapper tracks them
+        match interp.eval_tcl(r#"
+            set procs [info procs]
+            if {[llength [info commands ::slopdrop::user_namespaces]]} {
+                foreach ns [::slopdrop::user_namespaces] {
+                    lappend procs {*}[info procs ${ns}::*]
+                }
+            }
             set validated [list]
-            foreach p [info procs] {
+            foreach p $procs {
                 if {![catch {info args $p}]} {
                     lappend validated $p
                 }
@@ -83,8 +175,8 @@ impl InterpreterState {
 
     /// Get list of procedures that were modified since last check
     /// This uses the TCL proc tracking wrapper to detect which procs were touched
-    pub fn get_modified_procs(interp: &Interpreter) -> Result<HashSet<String>> {
-        match interp.eval("join [::slopdrop::get_modified_procs] \\n") {
+    pub fn get_modified_procs(interp: &impl TclEval) -> Result<HashSet<String>> {
+        match interp.eval_tcl("join [::slopdrop::get_modified_procs] \\n") {
             Ok(obj) => {
                 let procs_str = obj.get_string();
                 Ok(procs_str
@@ -100,16 +192,20 @@ impl InterpreterState {
         }
     }
 
-    fn get_vars(interp: &Interpreter) -> Result<HashSet<String>> {
+    fn get_vars(interp: &impl TclEval) -> Result<HashSet<String>> {
         // Validate each var to filter out invalid entries
         // Use 'info exists' instead of 'set' to properly handle both scalars and arrays
-        match interp.eval(r#"
+        // Vars in user namespaces come from proc_tracking.tcl (when loaded), already validated
+        match interp.eval_tcl(r#"
             set validated [list]
             foreach v [info globals] {
                 if {[info exists ::$v]} {
                     lappend validated $v
                 }
             }
+            if {[llength [info commands ::slopdrop::namespace_vars]]} {
+                set validated [concat $validated [::slopdrop::namespace_vars]]
+            }
             join $validated \n
         "#) {
             Ok(obj) => {
@@ -124,10 +220,27 @@ impl InterpreterState {
         }
     }
 
+    /// User namespaces (see `::slopdrop::user_namespaces` in proc_tracking.tcl)
+    fn get_namespaces(interp: &impl TclEval) -> Result<HashSet<String>> {
+        match interp.eval_tcl(r#"
+            if {[llength [info commands ::slopdrop::user_namespaces]]} {
+                join [::slopdrop::user_namespaces] \n
+            }
+        "#) {
+            Ok(obj) => Ok(obj
+                .get_string()
+                .lines()
+                .filter(|s| !s.is_empty())
+                .map(|s| s.trim_start_matches("::").to_string())
+                .collect()),
+            Err(e) => Err(anyhow!("Failed to get namespaces: {:?}", e)),
+        }
+    }
+
     /// Get list of variables that were modified since last check
     /// This uses the TCL trace tracking to detect which vars were touched
-    pub fn get_modified_vars(interp: &Interpreter) -> Result<HashSet<String>> {
-        match interp.eval("join [::slopdrop::get_modified_vars] \\n") {
+    pub fn get_modified_vars(interp: &impl TclEval) -> Result<HashSet<String>> {
+        match interp.eval_tcl("join [::slopdrop::get_modified_vars] \\n") {
             Ok(obj) => {
                 let vars_str = obj.get_string();
                 Ok(vars_str
@@ -146,21 +259,6 @@ impl InterpreterState {
     /// Find what changed between two states
     /// Uses modified_procs and modified_vars sets to efficiently detect changes
     pub fn diff(&self, other: &Self, modified_procs: &HashSet<String>, modified_vars: &HashSet<String>) -> StateChanges {
-        // Internal context variables that should not be tracked as state changes
-        // These are set by eval_with_context for each command, or are system arrays
-        let internal_vars: HashSet<String> = [
-            "nick", "channel", "mask",  // Context variables set per-eval
-            "slopdrop_channel_members", // Channel member lists synced before each eval
-            "slopdrop_log_lines",       // Message log array
-            "nick_channel",             // HTTP rate limiting context
-            "slopdrop_modified_procs",  // Proc tracking list (proc_tracking.tcl)
-            "v", "p", "validated",      // Temp variables created by state capture itself
-            "errorInfo", "errorCode",   // TCL built-in ephemeral error variables
-        ]
-            .iter()
-            .map(|s| s.to_string())
-            .collect();
-
         let mut new_or_modified_procs = Vec::new();
 
         // New procs (in after but not before)
@@ -182,20 +280,24 @@ impl InterpreterState {
             .cloned()
             .collect();
 
+        // Vars are persisted unless the rules of their state make them
+        // ephemeral; a var that just became ephemeral is deleted, one that
+        // just stopped being ephemeral is new
+        let was_persisted = |name: &String| self.vars.contains(name) && !self.ephemeral.is_ephemeral_var(name);
+        let is_persisted = |name: &String| other.vars.contains(name) && !other.ephemeral.is_ephemeral_var(name);
+
         let mut new_or_modified_vars = Vec::new();
 
-        // New vars (in after but not before)
-        for var_name in other.vars.difference(&self.vars) {
-            if !internal_vars.contains(var_name) {
+        // New vars (persisted after but not before)
+        for var_name in &other.vars {
+            if is_persisted(var_name) && !was_persisted(var_name) {
                 new_or_modified_vars.push(var_name.clone());
             }
         }
 
-        // Modified vars (exist in both and were touched)
+        // Modified vars (persisted in both and were touched)
         for var_name in modified_vars {
-            if !internal_vars.contains(var_name)
-                && self.vars.contains(var_name)
-                && other.vars.contains(var_name) {
+            if was_persisted(var_name) && is_persisted(var_name) {
                 // Var existed before and still exists, and was touched
                 new_or_modified_vars.push(var_name.clone());
             }
@@ -203,31 +305,48 @@ impl InterpreterState {
 
         // Detect deleted vars
         let deleted_vars: Vec<String> = self.vars
-            .difference(&other.vars)
-            .filter(|name| !internal_vars.contains(*name))
+            .iter()
+            .filter(|name| was_persisted(name) && !is_persisted(name))
+            .cloned()
+            .collect();
+
+        let was_user_namespace = |name: &String| self.namespaces.contains(name) && !self.ephemeral.is_ephemeral_namespace(name);
+        let is_user_namespace = |name: &String| other.namespaces.contains(name) && !other.ephemeral.is_ephemeral_namespace(name);
+        let mut new_namespaces: Vec<String> = other.namespaces
+            .iter()
+            .filter(|name| is_user_namespace(name) && !was_user_namespace(name))
+            .cloned()
+            .collect();
+        let mut deleted_namespaces: Vec<String> = self.namespaces
+            .iter()
+            .filter(|name| was_user_namespace(name) && !is_user_namespace(name))
             .cloned()
             .collect();
+        new_namespaces.sort();
+        deleted_namespaces.sort();
 
         StateChanges {
             new_procs: new_or_modified_procs,
             deleted_procs,
             new_vars: new_or_modified_vars,
             deleted_vars,
+            new_namespaces,
+            deleted_namespaces,
         }
     }
 
     /// Get the current content (args + body) of a procedure
-    fn get_proc_content(interp: &Interpreter, proc_name: &str) -> Result<String> {
+    fn get_proc_content(interp: &impl TclEval, proc_name: &str) -> Result<String> {
         let args_cmd = format!("info args {{{}}}", proc_name);
         let body_cmd = format!("info body {{{}}}", proc_name);
 
         let args = interp
-            .eval(args_cmd.as_str())
+            .eval_tcl(args_cmd.as_str())
             .map_err(|e| anyhow!("Failed to get args: {:?}", e))?
             .get_string();
 
         let body = interp
-            .eval(body_cmd.as_str())
+            .eval_tcl(body_cmd.as_str())
             .map_err(|e| anyhow!("Failed to get body: {:?}", e))?
             .get_string();
 
@@ -235,12 +354,16 @@ impl InterpreterState {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct StateChanges {
     pub new_procs: Vec<String>,
     pub deleted_procs: Vec<String>,
     pub new_vars: Vec<String>,
     pub deleted_vars: Vec<String>,
+    #[serde(default)]
+    pub new_namespaces: Vec<String>,
+    #[serde(default)]
+    pub deleted_namespaces: Vec<String>,
 }
 
 impl StateChanges {
@@ -249,6 +372,43 @@ impl StateChanges {
             || !self.deleted_procs.is_empty()
             || !self.new_vars.is_empty()
             || !self.deleted_vars.is_empty()
+            || self.has_namespace_changes()
+    }
+
+    pub fn has_namespace_changes(&self) -> bool {
+        !self.new_namespaces.is_empty() || !self.deleted_namespaces.is_empty()
+    }
+
+    /// Drop the procs, vars and namespaces that also appear in `other`
+    pub fn exclude(&mut self, other: &StateChanges) {
+        let procs: HashSet<&String> = other.new_procs.iter().chain(&other.deleted_procs).collect();
+        let vars: HashSet<&String> = other.new_vars.iter().chain(&other.deleted_vars).collect();
+        let namespaces: HashSet<&String> = other.new_namespaces.iter().chain(&other.deleted_namespaces).collect();
+
+        self.new_procs.retain(|name| !procs.contains(name));
+        self.deleted_procs.retain(|name| !procs.contains(name));
+        self.new_vars.retain(|name| !vars.contains(name));
+        self.deleted_vars.retain(|name| !vars.contains(name));
+        self.new_namespaces.retain(|name| !namespaces.contains(name));
+        self.deleted_namespaces.retain(|name| !namespaces.contains(name));
+    }
+
+    /// Add the procs, vars and namespaces from `other` that aren't listed yet
+    pub fn merge(&mut self, other: StateChanges) {
+        for (list, names) in [
+            (&mut self.new_procs, other.new_procs),
+            (&mut self.deleted_procs, other.deleted_procs),
+            (&mut self.new_vars, other.new_vars),
+            (&mut self.deleted_vars, other.deleted_vars),
+            (&mut self.new_namespaces, other.new_namespaces),
+            (&mut self.deleted_namespaces, other.deleted_namespaces),
+        ] {
+            for name in names {
+                if !list.contains(&name) {
+                    list.push(name);
+                }
+            }
+        }
     }
 
     /// Generate a human-readable summary of changes
@@ -267,6 +427,12 @@ impl StateChanges {
         if !self.deleted_vars.is_empty() {
             parts.push(format!("-var: {}", self.deleted_vars.join(", ")));
         }
+        if !self.new_namespaces.is_empty() {
+            parts.push(format!("+ns: {}", self.new_namespaces.join(", ")));
+        }
+        if !self.deleted_namespaces.is_empty() {
+            parts.push(format!("-ns: {}", self.deleted_namespaces.join(", ")));
+        }
 
         if parts.is_empty() {
             "no changes".to_string()
@@ -276,23 +442,291 @@ impl StateChanges {
     }
 }
 
+/// What `save_changes` would commit, worked out without touching the repository
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct DryRunReport {
+    /// Summary of which procs/vars would be created/updated/removed
+    pub changes_summary: String,
+    /// The commit message that would be used
+    pub commit_message: String,
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    /// Unified diff for each proc/var that would change
+    pub diffs: Vec<String>,
+}
+
+impl DryRunReport {
+    /// Summary line followed by the diffs, as shown to users
+    pub fn render(&self) -> String {
+        let mut text = format!(
+            "[dry run] would commit: {} ({} files, +{} -{})",
+            self.changes_summary, self.files_changed, self.insertions, self.deletions
+        );
+        for diff in &self.diffs {
+            text.push('\n');
+            text.push_str(diff.trim_end_matches('\n'));
+        }
+        text
+    }
+}
+
+/// A problem `fsck` found with the stored procs and vars
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsckIssue {
+    /// An index entry whose content file is missing (v1 layout)
+    MissingBlob { kind: String, name: String, hash: String },
+    /// A content file no index entry refers to (v1), or a file that isn't a
+    /// proc or var at all (v2)
+    Orphan { path: String },
+    /// A proc or var stored more than once; only one copy gets loaded
+    Duplicate { kind: String, name: String, paths: Vec<String> },
+    /// A proc or var whose stored content fails to load
+    Broken { kind: String, name: String, error: String },
+}
+
+impl FsckIssue {
+    /// Whether the issue loses a proc or var on load (orphans are harmless)
+    pub fn breaks_loading(&self) -> bool {
+        !matches!(self, FsckIssue::Orphan { .. })
+    }
+}
+
+impl std::fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FsckIssue::MissingBlob { kind, name, hash } => write!(
+                f,
+                "{} {}: index points at missing file {}/{}",
+                kind.trim_end_matches('s'),
+                name,
+                kind,
+                hash
+            ),
+            FsckIssue::Orphan { path } => write!(f, "orphan file {}", path),
+            FsckIssue::Duplicate { kind, name, paths } => {
+                write!(f, "{} {}: stored {} times ({})", kind.trim_end_matches('s'), name, paths.len(), paths.join(", "))
+            }
+            FsckIssue::Broken { error, .. } => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Result of `StatePersistence::fsck`
+#[derive(Debug, Clone, Default)]
+pub struct FsckReport {
+    pub procs: usize,
+    pub vars: usize,
+    pub issues: Vec<FsckIssue>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Summary line followed by one line per issue
+    pub fn render(&self) -> String {
+        let mut text = format!("Checked {} procs and {} vars: ", self.procs, self.vars);
+        if self.is_clean() {
+            text.push_str("no problems");
+        } else {
+            text.push_str(&format!("{} problems", self.issues.len()));
+        }
+        for issue in &self.issues {
+            text.push_str(&format!("\n- {}", issue));
+        }
+        text
+    }
+}
+
+/// A commit that changed a single proc or var
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ItemVersion {
+    pub commit_id: String,
+    pub timestamp: i64,
+    pub author: String,
+    /// Author email, `nick@host` (see `UserInfo::email`); kept out of the
+    /// web API, which only shows nicks
+    #[serde(skip)]
+    pub author_email: String,
+    /// First line of the commit message
+    pub message: String,
+    /// Content file after the commit, or None if the commit removed it
+    pub content_hash: Option<String>,
+}
+
+/// A line of a proc with the commit that last changed it
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BlameLine {
+    pub commit_id: String,
+    pub author: String,
+    pub line: String,
+}
+
+/// Which side wins where local and remote state changed the same proc or var
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ConflictResolution {
+    /// Leave the remote unmerged and report the conflicts
+    Report,
+    Ours,
+    Theirs,
+}
+
+/// What merging the remote state did (see `StatePersistence::merge_remote`)
+#[derive(Debug, Clone, Default)]
+pub struct SyncOutcome {
+    /// Remote commits that weren't in the local history
+    pub incoming: usize,
+    /// Procs, vars and namespaces the merge changed locally
+    pub changes: StateChanges,
+    /// The merge commit, if both sides had new commits. With conflicts it
+    /// only holds the clean part and has ours as its only parent
+    pub merge_commit: Option<String>,
+    /// Procs, vars and namespaces both sides changed; these were left as ours
+    pub conflicts: Vec<String>,
+}
+
+impl SyncOutcome {
+    pub fn render(&self) -> String {
+        if !self.conflicts.is_empty() {
+            let merged = if self.changes.has_changes() {
+                format!("merged the rest ({}); ", self.changes.summary())
+            } else {
+                String::new()
+            };
+            return format!(
+                "Remote state conflicts with ours on {}; {}sync -ours or sync -theirs to merge them",
+                self.conflicts.join(", "),
+                merged
+            );
+        }
+        if self.incoming == 0 {
+            return "Already up to date with the remote".to_string();
+        }
+        format!("Merged {} remote commits: {}", self.incoming, self.changes.summary())
+    }
+}
+
+/// Somewhere state files can be read from, by path relative to the state root
+pub trait StateSource {
+    /// Read a file, or None if it doesn't exist
+    fn read_file(&self, path: &str) -> Result<Option<String>>;
+
+    /// Files below a directory, recursively, as paths relative to it
+    fn list_files(&self, dir: &str) -> Result<Vec<String>>;
+}
+
+/// The working tree at a state path
+impl StateSource for Path {
+    fn read_file(&self, path: &str) -> Result<Option<String>> {
+        let file = self.join(path);
+        if !file.exists() {
+            return Ok(None);
+        }
+        Ok(Some(fs::read_to_string(file)?))
+    }
+
+    fn list_files(&self, dir: &str) -> Result<Vec<String>> {
+        let mut files = Vec::new();
+        let root = self.join(dir);
+        if root.is_dir() {
+            collect_files(&root, "", &mut files)?;
+        }
+        Ok(files)
+    }
+}
+
+/// The state tree as of one commit, read straight from the git object store
+pub struct CommitSnapshot {
+    repo: Repository,
+    tree_id: git2::Oid,
+    pub commit_id: String,
+}
+
+impl StateSource for CommitSnapshot {
+    fn read_file(&self, path: &str) -> Result<Option<String>> {
+        let tree = self.repo.find_tree(self.tree_id)?;
+        tree_file(&self.repo, &tree, path)
+    }
+
+    fn list_files(&self, dir: &str) -> Result<Vec<String>> {
+        let tree = self.repo.find_tree(self.tree_id)?;
+        tree_files(&self.repo, &tree, dir)
+    }
+}
+
+/// A committed tree borrowed from an open repository, for walking history
+struct TreeSource<'a, 'r> {
+    repo: &'a Repository,
+    tree: &'a git2::Tree<'r>,
+}
+
+impl StateSource for TreeSource<'_, '_> {
+    fn read_file(&self, path: &str) -> Result<Option<String>> {
+        tree_file(self.repo, self.tree, path)
+    }
+
+    fn list_files(&self, dir: &str) -> Result<Vec<String>> {
+        tree_files(self.repo, self.tree, dir)
+    }
+}
+
+/// A stored proc or var: its file, relative to the state root, and content
+#[derive(Debug, Clone)]
+pub struct StoredItem {
+    pub path: String,
+    pub content: String,
+}
+
+/// Credentials for an HTTPS state remote
+#[derive(Debug, Clone, Default)]
+pub struct RemoteAuth {
+    pub username: Option<String>,
+    /// File holding the access token
+    pub token_file: Option<PathBuf>,
+    /// Environment variable holding the access token, if there's no file
+    pub token_env: Option<String>,
+    /// Fall back to git's credential helpers
+    pub credential_helper: bool,
+}
+
+impl RemoteAuth {
+    /// The configured access token, if any
+    pub fn token(&self) -> Result<Option<String>> {
+        let token = if let Some(ref path) = self.token_file {
+            fs::read_to_string(path).map_err(|e| anyhow!("Failed to read token file {:?}: {}", path, e))?
+        } else if let Some(ref name) = self.token_env {
+            std::env::var(name).map_err(|_| anyhow!("Token environment variable {} isn't set", name))?
+        } else {
+            return Ok(None);
+        };
+
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(anyhow!("The configured access token is empty"));
+        }
+        Ok(Some(token.to_string()))
+    }
+}
+
 /// Manages state persistence to disk
+#[derive(Clone)]
 pub struct StatePersistence {
     state_path: PathBuf,
     state_repo: Option<String>,
     ssh_key: Option<PathBuf>,
+    auth: RemoteAuth,
+    /// Remote branch to use instead of the one checked out
+    branch: Option<String>,
 }
 
 impl StatePersistence {
     /// Create a new StatePersistence without remote repository support
-    /// NOTE: Prefer using with_repo() to support remote state cloning
+    /// NOTE: Prefer using from_config() to support remote state cloning
     #[allow(dead_code)]
     pub fn new(state_path: PathBuf) -> Self {
-        Self {
-            state_path,
-            state_repo: None,
-            ssh_key: None,
-        }
+        Self::with_repo(state_path, None, None)
     }
 
     pub fn with_repo(state_path: PathBuf, state_repo: Option<String>, ssh_key: Option<PathBuf>) -> Self {
@@ -300,9 +734,43 @@ impl StatePersistence {
             state_path,
             state_repo,
             ssh_key,
+            auth: RemoteAuth::default(),
+            branch: None,
         }
     }
 
+    /// The state repository as configured in `[tcl]`, including HTTPS
+    /// credentials and the branch
+    pub fn from_config(tcl_config: &TclConfig) -> Self {
+        Self::with_repo(
+            tcl_config.state_path.clone(),
+            tcl_config.state_repo.clone(),
+            tcl_config.ssh_key.clone(),
+        )
+        .with_auth(RemoteAuth {
+            username: tcl_config.state_username.clone(),
+            token_file: tcl_config.state_token_file.clone(),
+            token_env: tcl_config.state_token_env.clone(),
+            credential_helper: tcl_config.state_credential_helper,
+        })
+        .with_branch(tcl_config.state_branch.clone())
+    }
+
+    pub fn with_auth(mut self, auth: RemoteAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Use `branch` of the remote instead of the one checked out locally
+    pub fn with_branch(mut self, branch: Option<String>) -> Self {
+        self.branch = branch;
+        self
+    }
+
+    pub fn state_path(&self) -> &Path {
+        &self.state_path
+    }
+
     /// Ensure state directory and git repository are initialized
     /// If state_repo is set and state_path doesn't exist, clones from remote
     /// Otherwise creates directory structure and initializes git repo if needed
@@ -325,29 +793,14 @@ impl StatePersistence {
     fn clone_from_remote(&self, url: &str) -> Result<()> {
         info!("Cloning state repository from: {}", url);
 
-        // Set up credentials callback for SSH
-        let mut callbacks = RemoteCallbacks::new();
-        let ssh_key = self.ssh_key.clone();
-
-        callbacks.credentials(move |_url, username_from_url, _allowed_types| {
-            let username = username_from_url.unwrap_or("git");
-
-            // Try SSH key if configured
-            if let Some(ref key_path) = ssh_key {
-                debug!("Using SSH key for clone: {:?}", key_path);
-                return Cred::ssh_key(username, None, key_path, None);
-            }
-
-            // Fall back to SSH agent
-            debug!("Using SSH agent for clone authentication");
-            Cred::ssh_key_from_agent(username)
-        });
-
         let mut fetch_options = FetchOptions::new();
-        fetch_options.remote_callbacks(callbacks);
+        fetch_options.remote_callbacks(self.remote_callbacks());
 
         let mut builder = RepoBuilder::new();
         builder.fetch_options(fetch_options);
+        if let Some(ref branch) = self.branch {
+            builder.branch(branch);
+        }
 
         builder.clone(url, &self.state_path)
             .map_err(|e| anyhow!("Failed to clone state repository from {}: {}", url, e))?;
@@ -359,11 +812,43 @@ impl StatePersistence {
     /// Save changed procs and vars to disk and commit to git
     pub fn save_changes(
         &self,
-        interp: &Interpreter,
+        interp: &impl TclEval,
         changes: &StateChanges,
         user_info: &UserInfo,
         eval_code: &str,
     ) -> Result<Option<CommitInfo>> {
+        let commit_msg = Self::format_commit_message(changes, eval_code);
+        self.save_with_message(interp, changes, user_info, &commit_msg)
+    }
+
+    /// Save var changes held back by commit coalescing (see
+    /// `commit_coalescer`) as one commit squashing `evals` evals
+    pub fn save_coalesced_changes(
+        &self,
+        interp: &impl TclEval,
+        changes: &StateChanges,
+        user_info: &UserInfo,
+        evals: usize,
+    ) -> Result<Option<CommitInfo>> {
+        let summary = format!("Coalesced changes from {} evals", evals);
+        let commit_msg = Self::commit_message_with_details(summary, changes);
+        self.save_with_message(interp, changes, user_info, &commit_msg)
+    }
+
+    fn save_with_message(
+        &self,
+        interp: &impl TclEval,
+        changes: &StateChanges,
+        user_info: &UserInfo,
+        commit_msg: &str,
+    ) -> Result<Option<CommitInfo>> {
+        // Procs stored before this commit keep their owner (or lack of one)
+        let stored_procs: HashSet<String> = self
+            .read_index("procs")
+            .into_keys()
+            .map(|name| name.trim_start_matches("::").to_string())
+            .collect();
+
         // Save new/modified procs
         for proc_name in &changes.new_procs {
             if let Err(e) = self.save_proc(interp, proc_name) {
@@ -392,13 +877,38 @@ impl StatePersistence {
             }
         }
 
+        // Record created and deleted namespaces
+        if changes.has_namespace_changes() {
+            let mut namespaces = self.read_namespaces();
+            apply_namespace_changes(&mut namespaces, changes);
+            if let Err(e) = self.write_namespaces(&namespaces) {
+                warn!("Failed to update namespace list: {}", e);
+            }
+        }
+
+        // The author of the commit that first stores a proc owns it
+        let ownership = StateChanges {
+            new_procs: changes
+                .new_procs
+                .iter()
+                .filter(|name| !stored_procs.contains(name.trim_start_matches("::")))
+                .cloned()
+                .collect(),
+            deleted_procs: changes.deleted_procs.clone(),
+            ..Default::default()
+        };
+        let mut owners = self.read_owners();
+        if owners.record(&ownership, user_info) {
+            if let Err(e) = self.write_owners(&owners) {
+                warn!("Failed to update proc owners: {}", e);
+            }
+        }
+
         // Commit changes to git and return commit info
-        match self.git_commit(changes, user_info, eval_code) {
+        match self.git_commit(changes, user_info, commit_msg) {
             Ok(commit_info) => {
                 // Auto-push to remote if configured
-                if let Err(e) = self.push_to_remote() {
-                    warn!("Failed to push to remote: {}", e);
-                }
+                self.queue_push();
 
                 // Run git gc periodically (every 100 commits) to prevent repo bloat
                 if let Err(e) = self.maybe_run_git_gc() {
@@ -414,116 +924,676 @@ impl StatePersistence {
         }
     }
 
-    /// Push changes to remote repository if configured
-    /// Supports both HTTPS and SSH (with key or agent)
-    pub fn push_to_remote(&self) -> Result<()> {
-        // Only push if we have a remote URL configured
-        if self.state_repo.is_none() {
-            debug!("No remote repository configured, skipping push");
-            return Ok(());
-        }
-
-        let repo = Repository::open(&self.state_path)
-            .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
+    /// Work out what `save_changes` would commit without writing anything
+    /// Each changed proc/var is diffed against the version in the repository
+    pub fn preview_changes(
+        &self,
+        interp: &impl TclEval,
+        changes: &StateChanges,
+        eval_code: &str,
+    ) -> Result<DryRunReport> {
+        let mut report = DryRunReport {
+            changes_summary: changes.summary(),
+            commit_message: Self::format_commit_message(changes, eval_code),
+            ..Default::default()
+        };
 
-        // Find the origin remote or create it
-        let remote_name = "origin";
-        let remote_url = self.state_repo.as_ref().unwrap();
+        let layout = self.layout()?;
+        let mut files = Vec::new();
+        for proc_name in &changes.new_procs {
+            files.push(("procs", proc_name, Some(Self::render_item(layout, interp, "procs", proc_name)?)));
+        }
+        for proc_name in &changes.deleted_procs {
+            files.push(("procs", proc_name, None));
+        }
+        for var_name in &changes.new_vars {
+            files.push(("vars", var_name, Some(Self::render_item(layout, interp, "vars", var_name)?)));
+        }
+        for var_name in &changes.deleted_vars {
+            files.push(("vars", var_name, None));
+        }
 
-        // Try to find existing remote
-        let mut remote = match repo.find_remote(remote_name) {
-            Ok(remote) => remote,
-            Err(_) => {
-                // Create remote if it doesn't exist
-                debug!("Creating remote 'origin' with URL: {}", remote_url);
-                repo.remote(remote_name, remote_url)?
+        for (kind, name, new_item) in files {
+            let old_item = source_item(self.state_path.as_path(), kind, name)?;
+            let old_content = old_item.as_ref().map(|item| item.content.as_str());
+            let new_content = new_item.as_ref().map(|item| item.content.as_str());
+            if old_content == new_content {
+                continue;
             }
-        };
-
-        // Set up credentials callback for SSH
-        let mut callbacks = RemoteCallbacks::new();
-        let ssh_key = self.ssh_key.clone();
 
-        callbacks.credentials(move |_url, username_from_url, _allowed_types| {
-            let username = username_from_url.unwrap_or("git");
+            // v1 files are named by hash, so label them by proc/var name instead
+            let path = match (layout, new_item.as_ref().or(old_item.as_ref())) {
+                (StateLayout::V2, Some(item)) => item.path.clone(),
+                _ => format!("{}/{}", kind, name),
+            };
+            let (diff, insertions, deletions) = unified_diff(&path, old_content, new_content)?;
+            report.files_changed += 1;
+            report.insertions += insertions;
+            report.deletions += deletions;
+            report.diffs.push(diff);
+        }
 
-            // Try SSH key if configured
-            if let Some(ref key_path) = ssh_key {
-                debug!("Using SSH key: {:?}", key_path);
-                return Cred::ssh_key(username, None, key_path, None);
+        if changes.has_namespace_changes() {
+            let old_namespaces = self.read_namespaces();
+            let mut new_namespaces = old_namespaces.clone();
+            apply_namespace_changes(&mut new_namespaces, changes);
+            if new_namespaces != old_namespaces {
+                let (diff, insertions, deletions) = unified_diff(
+                    "vars/_namespaces",
+                    Some(&namespace_list(&old_namespaces)),
+                    Some(&namespace_list(&new_namespaces)),
+                )?;
+                report.files_changed += 1;
+                report.insertions += insertions;
+                report.deletions += deletions;
+                report.diffs.push(diff);
             }
+        }
 
-            // Fall back to SSH agent
-            debug!("Using SSH agent for authentication");
-            Cred::ssh_key_from_agent(username)
-        });
-
-        let mut push_options = PushOptions::new();
-        push_options.remote_callbacks(callbacks);
+        Ok(report)
+    }
 
-        // Push to remote
-        info!("Pushing to remote repository: {}", remote_url);
-
-        // Try pushing to main first
-        if let Err(e) = remote.push(
-            &["refs/heads/main:refs/heads/main"],
-            Some(&mut push_options)
-        ) {
-            // If main doesn't exist, try master
-            debug!("Push to main failed ({}), trying master", e);
-
-            // Need fresh callbacks for retry
-            let mut callbacks2 = RemoteCallbacks::new();
-            let ssh_key2 = self.ssh_key.clone();
-            callbacks2.credentials(move |_url, username_from_url, _allowed_types| {
-                let username = username_from_url.unwrap_or("git");
-                if let Some(ref key_path) = ssh_key2 {
-                    return Cred::ssh_key(username, None, key_path, None);
-                }
-                Cred::ssh_key_from_agent(username)
-            });
+    /// Layout of the working tree (see state_layout.rs)
+    fn layout(&self) -> Result<StateLayout> {
+        StateLayout::detect(self.state_path.as_path())
+    }
 
-            let mut push_options2 = PushOptions::new();
-            push_options2.remote_callbacks(callbacks2);
+    /// Every proc or var in the working tree as name -> content hash
+    fn read_index(&self, kind: &str) -> HashMap<String, String> {
+        source_index(self.state_path.as_path(), kind).unwrap_or_else(|e| {
+            warn!("Failed to read {} from state: {}", kind, e);
+            HashMap::new()
+        })
+    }
 
-            remote.push(
-                &["refs/heads/master:refs/heads/master"],
-                Some(&mut push_options2)
-            ).map_err(|e2| anyhow!("Failed to push to main: {} and master: {}", e, e2))?;
-            info!("Successfully pushed to master");
-        } else {
-            info!("Successfully pushed to main");
-        }
+    /// Read procs/_owners (see `proc_owners`)
+    fn read_owners(&self) -> ProcOwners {
+        fs::read_to_string(self.state_path.join(OWNERS_FILE))
+            .map(|content| ProcOwners::parse(&content))
+            .unwrap_or_default()
+    }
 
+    fn write_owners(&self, owners: &ProcOwners) -> Result<()> {
+        fs::create_dir_all(self.state_path.join("procs"))?;
+        fs::write(self.state_path.join(OWNERS_FILE), owners.render())?;
         Ok(())
     }
 
-    /// Initialize git repository if it doesn't exist
-    fn init_git_repo_if_needed(&self) -> Result<()> {
-        // Try to open existing repo first
-        if Repository::open(&self.state_path).is_ok() {
-            return Ok(());
-        }
+    /// Owners of the stored procs
+    pub fn proc_owners(&self) -> ProcOwners {
+        self.read_owners()
+    }
 
-        // Repository doesn't exist, create it
-        debug!("Initializing git repository at {:?}", self.state_path);
+    /// Protect a proc, so only its owner and admins may change it, or lift
+    /// the protection, committed as `user_info`. Only the owner or an admin
+    /// may do either; a proc stored before owners were recorded is claimed by
+    /// the author of its first commit (same nick and host), or an admin
+    pub fn set_proc_protected(
+        &self,
+        proc_name: &str,
+        protected: bool,
+        user_info: &UserInfo,
+        is_admin: bool,
+    ) -> Result<CommitInfo> {
+        let name = proc_name.trim_start_matches("::");
+        if !self.read_index("procs").keys().any(|stored| stored.trim_start_matches("::") == name) {
+            return Err(anyhow!("no stored proc named {}", name));
+        }
+        if name.contains(char::is_whitespace) {
+            return Err(anyhow!("procs with spaces in their name can't be protected"));
+        }
+
+        let mut owners = self.read_owners();
+        let mut owner = match owners.get(name) {
+            Some(owner) => owner.clone(),
+            None => {
+                // The first author has to match on nick and host, as owners do
+                let first = self.proc_history(name, usize::MAX)?.pop();
+                let is_first_author = first.as_ref().map_or(false, |version| {
+                    version.author == user_info.nick && version.author_email == user_info.email()
+                });
+                if !is_admin && !is_first_author {
+                    return Err(anyhow!(
+                        "{} was created by {}; only they or an admin can protect it",
+                        name,
+                        first.as_ref().map_or("someone else", |version| version.author.as_str())
+                    ));
+                }
+                ProcOwner::new(user_info)
+            }
+        };
+        if !is_admin && !owner.is(user_info) {
+            return Err(anyhow!("{} belongs to {}; only they or an admin can change its protection", name, owner.nick));
+        }
+        if owner.protected == protected {
+            return Err(anyhow!("{} is already {}", name, if protected { "protected" } else { "unprotected" }));
+        }
+
+        owner.protected = protected;
+        owners.set(name, owner);
+        self.write_owners(&owners)?;
+
+        let verb = if protected { "Protect" } else { "Unprotect" };
+        let commit_info = self.git_commit(&StateChanges::default(), user_info, &format!("{} proc {}", verb, name))?;
+        self.queue_push();
+        Ok(commit_info)
+    }
+
+    /// The queue of changes held for review (see review_queue.rs)
+    fn review_queue(&self) -> Result<ReviewQueue> {
+        let repo = Repository::open(&self.state_path)
+            .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
+        ReviewQueue::open(&repo.path().join(REVIEWS_FILE))
+    }
+
+    /// Changes made in `interp` to hold for review, with their new
+    /// definitions taken from it
+    pub fn held_changes(interp: &impl TclEval, changes: &StateChanges) -> Result<HeldChanges> {
+        let mut held = HeldChanges::default();
+        for name in &changes.new_procs {
+            held.hold(name, Some(Self::proc_content(interp, name)?));
+        }
+        for name in &changes.deleted_procs {
+            held.hold(name, None);
+        }
+        Ok(held)
+    }
+
+    /// Live procs in `procs` that `user_info` may not change and whose
+    /// definition in `interp` no longer matches the stored one, however
+    /// they were changed
+    pub fn changed_protected_procs(
+        &self,
+        interp: &impl TclEval,
+        procs: &HashSet<String>,
+        user_info: &UserInfo,
+        is_admin: bool,
+    ) -> Result<Vec<String>> {
+        let owners = self.read_owners();
+        let root = self.state_path.as_path();
+        let mut changed = Vec::new();
+        for name in procs {
+            if owners.denied(name, user_info, is_admin).is_none() {
+                continue;
+            }
+            if let Some(stored) = source_item(root, "procs", name)? {
+                if Self::proc_content(interp, name)? != stored.content {
+                    changed.push(name.clone());
+                }
+            }
+        }
+        changed.sort();
+        Ok(changed)
+    }
+
+    /// Queue held changes to protected procs for review instead of saving
+    /// them, along with the stored procs they would replace
+    pub fn submit_for_review(&self, held: &HeldChanges, user_info: &UserInfo, eval_code: &str) -> Result<ReviewInfo> {
+        let mut change = PendingChange {
+            id: 0,
+            nick: user_info.nick.clone(),
+            host: user_info.host.clone(),
+            submitted: chrono::Utc::now().timestamp(),
+            code: eval_code.to_string(),
+            changes: StateChanges {
+                new_procs: held.changes.new_procs.clone(),
+                deleted_procs: held.changes.deleted_procs.clone(),
+                ..Default::default()
+            },
+            procs: held.procs.clone(),
+            base: BTreeMap::new(),
+        };
+        for name in held.changes.new_procs.iter().chain(&held.changes.deleted_procs) {
+            let stored = source_item(self.state_path.as_path(), "procs", name)?;
+            change.base.insert(name.clone(), stored.map(|item| item.content));
+        }
+
+        let mut queue = self.review_queue()?;
+        let id = queue.submit(change);
+        queue.save()?;
+        let review = queue.get(id)?.info();
+        info!("Holding change #{} by {} for review ({})", id, user_info.nick, review.changes_summary);
+        Ok(review)
+    }
+
+    /// Changes waiting for review, oldest first
+    pub fn pending_reviews(&self) -> Result<Vec<PendingChange>> {
+        Ok(self.review_queue()?.pending().to_vec())
+    }
+
+    /// What approving a pending change would commit, diffed against the
+    /// current state
+    pub fn review_diff(&self, id: u64) -> Result<DryRunReport> {
+        let queue = self.review_queue()?;
+        let change = queue.get(id)?;
+        self.preview_changes(&review_interp(change)?, &change.changes, &change.code)
+    }
+
+    /// Commit a pending change with its submitter as author and `approver`
+    /// as committer, and take it off the queue. Refused if one of its procs
+    /// changed since it was submitted. Returns the procs to reload
+    pub fn approve_review(&self, id: u64, approver: &UserInfo) -> Result<(CommitInfo, StateChanges)> {
+        let mut queue = self.review_queue()?;
+        let change = queue.get(id)?.clone();
+
+        let root = self.state_path.as_path();
+        for (name, base) in &change.base {
+            let current = source_item(root, "procs", name)?.map(|item| item.content);
+            if current != *base {
+                return Err(anyhow!("{} changed since #{} was submitted; reject it instead", name, id));
+            }
+        }
+
+        let scratch = review_interp(&change)?;
+        for proc_name in &change.changes.new_procs {
+            self.save_proc(&scratch, proc_name)?;
+        }
+        for proc_name in &change.changes.deleted_procs {
+            self.delete_proc(proc_name)?;
+        }
+        let author = change.author();
+        let mut owners = self.read_owners();
+        if owners.record(&change.changes, &author) {
+            self.write_owners(&owners)?;
+        }
+
+        let message = format!(
+            "{}\n\nReviewed #{} and approved by {}",
+            Self::format_commit_message(&change.changes, &change.code),
+            id,
+            approver.nick
+        );
+        let commit_info = self.git_commit_as(&change.changes, &author, approver, &message)?;
+        queue.remove(id)?;
+        queue.save()?;
+        self.queue_push();
+        Ok((commit_info, change.changes))
+    }
+
+    /// Drop a pending change without applying it
+    pub fn reject_review(&self, id: u64) -> Result<PendingChange> {
+        let mut queue = self.review_queue()?;
+        let change = queue.remove(id)?;
+        queue.save()?;
+        Ok(change)
+    }
+
+    /// Read vars/_namespaces, the user namespaces that exist in the state
+    fn read_namespaces(&self) -> BTreeSet<String> {
+        fs::read_to_string(self.state_path.join("vars/_namespaces"))
+            .map(|content| content.lines().filter(|l| !l.is_empty()).map(String::from).collect())
+            .unwrap_or_default()
+    }
+
+    fn write_namespaces(&self, namespaces: &BTreeSet<String>) -> Result<()> {
+        fs::create_dir_all(self.state_path.join("vars"))?;
+        fs::write(self.state_path.join("vars/_namespaces"), namespace_list(namespaces))?;
+        Ok(())
+    }
+
+    /// Bring the given procs, vars and namespaces in the interpreter in line
+    /// with the working tree: entries still listed there are reloaded, the
+    /// rest removed
+    ///
+    /// Loading bypasses proc and var tracking, so nothing is recommitted.
+    pub fn reload_changes(&self, interp: &impl TclEval, changes: &StateChanges) -> Result<()> {
+        // Namespaces first, so procs and vars can be loaded into them
+        let namespaces = self.read_namespaces();
+        let (kept_namespaces, removed_namespaces): (Vec<&String>, Vec<&String>) = changes
+            .new_namespaces
+            .iter()
+            .chain(&changes.deleted_namespaces)
+            .partition(|name| namespaces.contains(*name));
+        for name in kept_namespaces {
+            let create = format!("namespace eval {} {{}}", tcl_quote(&format!("::{}", name)));
+            interp
+                .eval_tcl(create.as_str())
+                .map_err(|e| anyhow!("Failed to create namespace {}: {}", name, e))?;
+        }
+
+        let root = self.state_path.as_path();
+        for name in changes.new_procs.iter().chain(&changes.deleted_procs) {
+            match source_item(root, "procs", name)? {
+                Some(item) => load_item(interp, "procs", name, &item)?,
+                None => {
+                    let delete = format!("catch {{::slopdrop::_original_rename {} {{}}}}", tcl_quote(name));
+                    interp
+                        .eval_tcl(delete.as_str())
+                        .map_err(|e| anyhow!("Failed to remove proc {}: {}", name, e))?;
+                }
+            }
+        }
+
+        let mut var_names = Vec::new();
+        for name in changes.new_vars.iter().chain(&changes.deleted_vars) {
+            let unset = format!("unset -nocomplain {}", tcl_quote(name));
+            interp
+                .eval_tcl(unset.as_str())
+                .map_err(|e| anyhow!("Failed to remove var {}: {}", name, e))?;
+
+            if let Some(item) = source_item(root, "vars", name)? {
+                load_item(interp, "vars", name, &item)?;
+            }
+            var_names.push(name.as_str());
+        }
+
+        // Reloading isn't a change of its own; the vars are re-traced after the next eval
+        let forget = format!("::slopdrop::forget_vars {}", tcl_quote(&tcl_list(&var_names)));
+        interp
+            .eval_tcl(forget.as_str())
+            .map_err(|e| anyhow!("Failed to reset var tracking: {}", e))?;
+
+        for name in removed_namespaces {
+            let delete = format!("catch {{namespace delete {}}}", tcl_quote(&format!("::{}", name)));
+            interp
+                .eval_tcl(delete.as_str())
+                .map_err(|e| anyhow!("Failed to remove namespace {}: {}", name, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Hand new commits to the background push queue, if a remote is
+    /// configured (see `push_queue`)
+    fn queue_push(&self) {
+        if self.state_repo.is_some() {
+            push_queue::queue(self).notify();
+        }
+    }
+
+    /// Credentials for the remote. HTTPS remotes get the configured token,
+    /// then git's credential helpers if enabled; SSH remotes the configured
+    /// key, or the SSH agent. Each is tried once, so a rejected credential
+    /// fails the operation instead of being offered again
+    fn remote_callbacks(&self) -> RemoteCallbacks<'static> {
+        let mut callbacks = RemoteCallbacks::new();
+        let ssh_key = self.ssh_key.clone();
+        let auth = self.auth.clone();
+        let state_path = self.state_path.clone();
+        let (mut tried_token, mut tried_helper, mut tried_ssh) = (false, false, false);
+
+        callbacks.credentials(move |url, username_from_url, allowed_types| {
+            if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                let username = auth.username.as_deref().or(username_from_url).unwrap_or("git");
+                if !tried_token {
+                    tried_token = true;
+                    if let Some(token) = auth.token().map_err(|e| git2::Error::from_str(&e.to_string()))? {
+                        debug!("Using access token for {}", url);
+                        return Cred::userpass_plaintext(username, &token);
+                    }
+                }
+                if auth.credential_helper && !tried_helper {
+                    tried_helper = true;
+                    debug!("Asking git credential helpers for {}", url);
+                    let config = Repository::open(&state_path)
+                        .and_then(|repo| repo.config())
+                        .or_else(|_| git2::Config::open_default())?;
+                    return Cred::credential_helper(&config, url, Some(username));
+                }
+                return Err(git2::Error::from_str(
+                    "no HTTPS credentials left to try (set state_token_file, state_token_env or state_credential_helper)",
+                ));
+            }
+
+            if allowed_types.contains(CredentialType::USERNAME) {
+                return Cred::username(auth.username.as_deref().or(username_from_url).unwrap_or("git"));
+            }
+
+            if tried_ssh {
+                return Err(git2::Error::from_str("SSH authentication failed"));
+            }
+            tried_ssh = true;
+            let username = username_from_url.unwrap_or("git");
+
+            // Try SSH key if configured
+            if let Some(ref key_path) = ssh_key {
+                debug!("Using SSH key: {:?}", key_path);
+                return Cred::ssh_key(username, None, key_path, None);
+            }
+
+            // Fall back to SSH agent
+            debug!("Using SSH agent for authentication");
+            Cred::ssh_key_from_agent(username)
+        });
+        callbacks
+    }
+
+    /// The remote branch to sync with: the configured one, or the one
+    /// checked out
+    fn remote_branch(&self, repo: &Repository) -> Result<String> {
+        match self.branch {
+            Some(ref branch) => Ok(branch.clone()),
+            None => current_branch(repo),
+        }
+    }
+
+    /// The `origin` remote, created from `state_repo` if the clone has none
+    fn origin_remote<'r>(&self, repo: &'r Repository) -> Result<git2::Remote<'r>> {
+        let remote_url = self
+            .state_repo
+            .as_ref()
+            .ok_or_else(|| anyhow!("No state_repo configured"))?;
+        match repo.find_remote("origin") {
+            Ok(remote) => Ok(remote),
+            Err(_) => {
+                debug!("Creating remote 'origin' with URL: {}", remote_url);
+                Ok(repo.remote("origin", remote_url)?)
+            }
+        }
+    }
+
+    /// Push changes to remote repository if configured
+    /// Supports both HTTPS and SSH (with key or agent)
+    /// Commits are normally pushed in the background by `push_queue`
+    pub fn push_to_remote(&self) -> Result<()> {
+        // Only push if we have a remote URL configured
+        if self.state_repo.is_none() {
+            debug!("No remote repository configured, skipping push");
+            return Ok(());
+        }
+
+        let repo = Repository::open(&self.state_path)
+            .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
+        let mut remote = self.origin_remote(&repo)?;
+
+        let local_branch = current_branch(&repo)?;
+        let branch = self.remote_branch(&repo)?;
+
+        // A rejected ref (say, not a fast-forward) isn't an error to push()
+        // itself, only to this callback
+        let mut callbacks = self.remote_callbacks();
+        callbacks.push_update_reference(|refname, status| match status {
+            Some(reason) => Err(git2::Error::from_str(&format!("{} was rejected: {}", refname, reason))),
+            None => Ok(()),
+        });
+        let mut push_options = PushOptions::new();
+        push_options.remote_callbacks(callbacks);
+
+        info!("Pushing to remote repository: {}", self.state_repo.as_deref().unwrap_or_default());
+        let refspec = format!("refs/heads/{}:refs/heads/{}", local_branch, branch);
+        remote
+            .push(&[refspec.as_str()], Some(&mut push_options))
+            .map_err(|e| anyhow!("Failed to push to {}: {}", branch, e))?;
+        info!("Successfully pushed to {}", branch);
+
+        // Remember what the remote has, so `unpushed_commits` holds across restarts
+        let head = repo.head()?.peel_to_commit()?;
+        repo.reference(&format!("refs/remotes/origin/{}", branch), head.id(), true, "push")?;
+
+        Ok(())
+    }
+
+    /// Number of local commits the remote doesn't have yet, as of the last
+    /// fetch or push. 0 without a remote or before the first fetch
+    pub fn unpushed_commits(&self) -> Result<usize> {
+        if self.state_repo.is_none() {
+            return Ok(0);
+        }
+        let Ok(repo) = Repository::open(&self.state_path) else {
+            return Ok(0);
+        };
+        let branch = self.remote_branch(&repo)?;
+        let theirs = match repo.refname_to_id(&format!("refs/remotes/origin/{}", branch)) {
+            Ok(id) => id,
+            Err(_) => return Ok(0),
+        };
+        let ours = match repo.head().and_then(|head| head.peel_to_commit()) {
+            Ok(commit) => commit.id(),
+            Err(_) => return Ok(0),
+        };
+        let (ahead, _) = repo.graph_ahead_behind(ours, theirs)?;
+        Ok(ahead)
+    }
+
+    /// Fetch the remote branch into `refs/remotes/origin/`
+    pub fn fetch_remote(&self) -> Result<()> {
+        let repo = Repository::open(&self.state_path)
+            .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
+        let branch = self.remote_branch(&repo)?;
+        let mut remote = self.origin_remote(&repo)?;
+
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(self.remote_callbacks());
+        let refspec = format!("+refs/heads/{0}:refs/remotes/origin/{0}", branch);
+        remote
+            .fetch(&[refspec.as_str()], Some(&mut fetch_options), None)
+            .map_err(|e| anyhow!("Failed to fetch from remote: {}", e))?;
+        Ok(())
+    }
+
+    /// The last fetched commit of our branch on the remote, if it has one
+    pub fn remote_tip(&self) -> Result<Option<String>> {
+        let repo = Repository::open(&self.state_path)
+            .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
+        let branch = self.remote_branch(&repo)?;
+        Ok(repo
+            .refname_to_id(&format!("refs/remotes/origin/{}", branch))
+            .ok()
+            .map(|id| id.to_string()))
+    }
+
+    /// Fetch and merge the remote, see `merge_remote`
+    pub fn sync_with_remote(&self, resolution: ConflictResolution) -> Result<SyncOutcome> {
+        self.fetch_remote()?;
+        self.merge_remote(resolution)
+    }
+
+    /// Merge the fetched remote branch into ours. Procs and vars changed on
+    /// one side only merge cleanly, including v1 index lines; ones both sides
+    /// changed are settled by `resolution`, or reported and left as ours while
+    /// the rest is merged.
+    /// Returns the procs and vars that changed (use `reload_changes` to apply
+    /// them)
+    pub fn merge_remote(&self, resolution: ConflictResolution) -> Result<SyncOutcome> {
+        self.init_git_repo_if_needed()?;
+        let repo = Repository::open(&self.state_path)
+            .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
+        let branch = self.remote_branch(&repo)?;
+        let theirs = match repo.find_reference(&format!("refs/remotes/origin/{}", branch)) {
+            Ok(reference) => reference.peel_to_commit()?,
+            Err(_) => return Ok(SyncOutcome::default()),
+        };
+        let ours = repo.head()?.peel_to_commit()?;
+        let (_, incoming) = repo.graph_ahead_behind(ours.id(), theirs.id())?;
+        if incoming == 0 {
+            return Ok(SyncOutcome::default());
+        }
+
+        let procs_before = self.read_index("procs");
+        let vars_before = self.read_index("vars");
+        let namespaces_before = self.read_namespaces();
+        let mut merge_commit = None;
+        let mut conflicts = Vec::new();
+
+        if repo.graph_descendant_of(theirs.id(), ours.id())? {
+            repo.reset(theirs.as_object(), git2::ResetType::Hard, None)
+                .map_err(|e| anyhow!("Failed to fast-forward to the remote: {}", e))?;
+        } else {
+            let mut index = repo.merge_commits(&ours, &theirs, None)?;
+            conflicts = resolve_conflicts(&repo, &mut index, resolution)?;
+            let tree = repo.find_tree(index.write_tree_to(&repo)?)?;
+            let signature = Signature::now("slopdrop", "bot@localhost")?;
+
+            // With conflicts only the clean part comes over, in a commit on top
+            // of ours, so the remote stays unmerged until the conflicts are settled
+            let commit_id = if conflicts.is_empty() {
+                let message = format!("Merge remote state ({} commits)", incoming);
+                Some(repo.commit(Some("HEAD"), &signature, &signature, &message, &tree, &[&ours, &theirs])?)
+            } else if tree.id() != ours.tree_id() {
+                let message = format!(
+                    "Merge remote state ({} commits) except {}",
+                    incoming,
+                    conflicts.join(", ")
+                );
+                Some(repo.commit(Some("HEAD"), &signature, &signature, &message, &tree, &[&ours])?)
+            } else {
+                None
+            };
+            if let Some(commit_id) = commit_id {
+                repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+                    .map_err(|e| anyhow!("Failed to check out the merge: {}", e))?;
+                merge_commit = Some(commit_id.to_string());
+            }
+        }
+
+        let (new_procs, deleted_procs) = index_changes(&procs_before, &self.read_index("procs"));
+        let (new_vars, deleted_vars) = index_changes(&vars_before, &self.read_index("vars"));
+        let namespaces_after = self.read_namespaces();
+        let changes = StateChanges {
+            new_procs,
+            deleted_procs,
+            new_vars,
+            deleted_vars,
+            new_namespaces: namespaces_after.difference(&namespaces_before).cloned().collect(),
+            deleted_namespaces: namespaces_before.difference(&namespaces_after).cloned().collect(),
+        };
+
+        // The remote doesn't have the merge commit yet
+        if merge_commit.is_some() {
+            self.queue_push();
+        }
+
+        info!("Merged {} remote commits ({})", incoming, changes.summary());
+        Ok(SyncOutcome {
+            incoming,
+            changes,
+            merge_commit,
+            conflicts,
+        })
+    }
+
+    /// Initialize git repository if it doesn't exist
+    fn init_git_repo_if_needed(&self) -> Result<()> {
+        // Try to open existing repo first
+        if Repository::open(&self.state_path).is_ok() {
+            return Ok(());
+        }
+
+        // Repository doesn't exist, create it
+        debug!("Initializing git repository at {:?}", self.state_path);
 
         // Create state directory if it doesn't exist
         std::fs::create_dir_all(&self.state_path)?;
         std::fs::create_dir_all(self.state_path.join("procs"))?;
         std::fs::create_dir_all(self.state_path.join("vars"))?;
 
-        // Initialize git repo
-        let repo = Repository::init(&self.state_path)?;
+        // Initialize git repo, on the configured branch if there is one
+        let mut init_options = git2::RepositoryInitOptions::new();
+        if let Some(ref branch) = self.branch {
+            init_options.initial_head(branch);
+        }
+        let repo = Repository::init_opts(&self.state_path, &init_options)?;
 
         // Create initial empty index files
         std::fs::write(self.state_path.join("procs/_index"), "")?;
         std::fs::write(self.state_path.join("vars/_index"), "")?;
+        std::fs::write(self.state_path.join("vars/_namespaces"), "")?;
 
         // Create initial commit
         let mut index = repo.index()?;
         index.add_path(std::path::Path::new("procs/_index"))?;
         index.add_path(std::path::Path::new("vars/_index"))?;
+        index.add_path(std::path::Path::new("vars/_namespaces"))?;
         index.write()?;
 
         let tree_id = index.write_tree()?;
@@ -548,7 +1618,18 @@ impl StatePersistence {
         &self,
         changes: &StateChanges,
         user_info: &UserInfo,
-        eval_code: &str,
+        commit_msg: &str,
+    ) -> Result<CommitInfo> {
+        self.git_commit_as(changes, user_info, user_info, commit_msg)
+    }
+
+    /// `git_commit` with a committer other than the author (approved reviews)
+    fn git_commit_as(
+        &self,
+        changes: &StateChanges,
+        author: &UserInfo,
+        committer: &UserInfo,
+        commit_msg: &str,
     ) -> Result<CommitInfo> {
         self.init_git_repo_if_needed()?;
         let repo = Repository::open(&self.state_path)
@@ -562,9 +1643,14 @@ impl StatePersistence {
         let mut index = repo.index()
             .map_err(|e| anyhow!("Failed to get git index: {}", e))?;
 
-        // Add procs/_index and vars/_index
-        index.add_path(std::path::Path::new("procs/_index"))?;
-        index.add_path(std::path::Path::new("vars/_index"))?;
+        // Add the index files (v1 layout), the proc owners, the namespace list
+        // (missing from repos older than migrate_namespace_index) and the
+        // layout marker
+        for path in ["procs/_index", OWNERS_FILE, "vars/_index", "vars/_namespaces", LAYOUT_FILE] {
+            if self.state_path.join(path).exists() {
+                index.add_path(std::path::Path::new(path))?;
+            }
+        }
 
         // Add all new proc files
         if !changes.new_procs.is_empty() {
@@ -576,24 +1662,25 @@ impl StatePersistence {
             index.add_all(["vars/*"].iter(), IndexAddOption::DEFAULT, None)?;
         }
 
-        index.write()?;
+        // Stage removed files (the v2 layout deletes them rather than index lines)
+        index.update_all(["procs", "vars"].iter(), None)?;
 
-        // Create commit message
-        let commit_msg = Self::format_commit_message(changes, eval_code);
+        index.write()?;
 
         // Get the tree
         let tree_id = index.write_tree()?;
         let tree = repo.find_tree(tree_id)?;
 
-        // Create signature from user info
-        let signature = user_info.to_signature()?;
+        // Create signatures from user info
+        let author_signature = author.to_signature()?;
+        let committer_signature = committer.to_signature()?;
 
         // Create the commit
         let commit_id = repo.commit(
             Some("HEAD"),
-            &signature,
-            &signature,
-            &commit_msg,
+            &author_signature,
+            &committer_signature,
+            commit_msg,
             &tree,
             &[&parent_commit],
         )?;
@@ -607,8 +1694,8 @@ impl StatePersistence {
 
         let commit_info = CommitInfo {
             commit_id: commit_id.to_string(),
-            author: user_info.nick.clone(),
-            message: commit_msg,
+            author: author.nick.clone(),
+            message: commit_msg.to_string(),
             files_changed: stats.files_changed(),
             insertions: stats.insertions(),
             deletions: stats.deletions(),
@@ -617,7 +1704,7 @@ impl StatePersistence {
 
         info!(
             "Created git commit {} by {} ({} files, +{} -{} lines)",
-            commit_id, user_info.nick,
+            commit_id, author.nick,
             commit_info.files_changed, commit_info.insertions, commit_info.deletions
         );
 
@@ -633,7 +1720,12 @@ impl StatePersistence {
             eval_code.to_string()
         };
 
-        let mut msg = format!("Evaluated {}", eval_display);
+        Self::commit_message_with_details(format!("Evaluated {}", eval_display), changes)
+    }
+
+    /// `summary` followed by the procs, vars and namespaces that changed
+    fn commit_message_with_details(summary: String, changes: &StateChanges) -> String {
+        let mut msg = summary;
 
         // Add details about what changed
         if !changes.new_procs.is_empty() {
@@ -648,28 +1740,43 @@ impl StatePersistence {
         if !changes.deleted_vars.is_empty() {
             msg.push_str(&format!("\n\nDeleted vars: {}", changes.deleted_vars.join(", ")));
         }
+        if !changes.new_namespaces.is_empty() {
+            msg.push_str(&format!("\n\nNew namespaces: {}", changes.new_namespaces.join(", ")));
+        }
+        if !changes.deleted_namespaces.is_empty() {
+            msg.push_str(&format!("\n\nDeleted namespaces: {}", changes.deleted_namespaces.join(", ")));
+        }
 
         msg
     }
 
-    fn save_proc(&self, interp: &Interpreter, proc_name: &str) -> Result<()> {
+    /// Proc file content, formatted as {args} {body}
+    fn proc_content(interp: &impl TclEval, proc_name: &str) -> Result<String> {
         // Get proc args and body
         // Note: Invalid proc names are filtered out in TCL via mark_all_procs_modified
         let args_cmd = format!("info args {{{}}}", proc_name);
         let body_cmd = format!("info body {{{}}}", proc_name);
 
         let args = interp
-            .eval(args_cmd.as_str())
+            .eval_tcl(args_cmd.as_str())
             .map_err(|e| anyhow!("Failed to get args for {}: {:?}", proc_name, e))?
             .get_string();
 
         let body = interp
-            .eval(body_cmd.as_str())
+            .eval_tcl(body_cmd.as_str())
             .map_err(|e| anyhow!("Failed to get body for {}: {:?}", proc_name, e))?
             .get_string();
 
-        // Format as {args} {body}
-        let content = format!("{{{}}} {{{}}}", args, body);
+        Ok(format!("{{{}}} {{{}}}", args, body))
+    }
+
+    fn save_proc(&self, interp: &impl TclEval, proc_name: &str) -> Result<()> {
+        if self.layout()? == StateLayout::V2 {
+            let item = Self::render_item(StateLayout::V2, interp, "procs", proc_name)?;
+            return self.save_named_item("procs", proc_name, &item);
+        }
+
+        let content = Self::proc_content(interp, proc_name)?;
 
         // Calculate SHA1 hash
         let hash = Self::sha1_hash(&content);
@@ -704,11 +1811,12 @@ impl StatePersistence {
         Ok(())
     }
 
-    fn save_var(&self, interp: &Interpreter, var_name: &str) -> Result<()> {
+    /// Var file content, formatted as `array {...}` or `scalar {...}`
+    fn var_content(interp: &impl TclEval, var_name: &str) -> Result<String> {
         // Check if it's an array or scalar
         let is_array_cmd = format!("array exists {{{}}}", var_name);
         let is_array = interp
-            .eval(is_array_cmd.as_str())
+            .eval_tcl(is_array_cmd.as_str())
             .map(|obj| obj.get_string() == "1")
             .unwrap_or(false);
 
@@ -717,7 +1825,7 @@ impl StatePersistence {
             // TCL will add braces where needed to preserve list structure
             let array_cmd = format!("array get {{{}}}", var_name);
             let array_data = interp
-                .eval(array_cmd.as_str())
+                .eval_tcl(array_cmd.as_str())
                 .map_err(|e| anyhow!("Failed to get array {}: {:?}", var_name, e))?
                 .get_string();
             // Wrap the array data in braces to make it a single TCL value
@@ -727,13 +1835,24 @@ impl StatePersistence {
             // TCL will add braces where needed to preserve the value
             let value_cmd = format!("set {{{}}}", var_name);
             let value = interp
-                .eval(value_cmd.as_str())
+                .eval_tcl(value_cmd.as_str())
                 .map_err(|e| anyhow!("Failed to get var {}: {:?}", var_name, e))?
                 .get_string();
             // Wrap the value in braces to make it a single TCL value
             format!("scalar {{{}}}", value)
         };
 
+        Ok(content)
+    }
+
+    fn save_var(&self, interp: &impl TclEval, var_name: &str) -> Result<()> {
+        if self.layout()? == StateLayout::V2 {
+            let item = Self::render_item(StateLayout::V2, interp, "vars", var_name)?;
+            return self.save_named_item("vars", var_name, &item);
+        }
+
+        let content = Self::var_content(interp, var_name)?;
+
         // Calculate SHA1 hash
         let hash = Self::sha1_hash(&content);
 
@@ -752,7 +1871,78 @@ impl StatePersistence {
         Ok(())
     }
 
+    /// The file a proc or var would be saved as in the given layout
+    fn render_item(layout: StateLayout, interp: &impl TclEval, kind: &str, name: &str) -> Result<StoredItem> {
+        let (path, content) = match (layout, kind) {
+            (StateLayout::V1, "procs") => {
+                let content = Self::proc_content(interp, name)?;
+                (Self::sha1_hash(&content), content)
+            }
+            (StateLayout::V1, _) => {
+                let content = Self::var_content(interp, name)?;
+                (Self::sha1_hash(&content), content)
+            }
+            (StateLayout::V2, "procs") => {
+                (state_layout::item_path(name, PROC_EXTENSION), Self::proc_content(interp, name)?)
+            }
+            (StateLayout::V2, _) => {
+                let (var_type, content) = typed_var_content(interp, name)?;
+                (state_layout::item_path(name, var_type.extension()), content)
+            }
+        };
+        Ok(StoredItem {
+            path: format!("{}/{}", kind, path),
+            content,
+        })
+    }
+
+    /// Save a proc or var in the v2 layout, dropping its file for any other
+    /// type (a var that went from scalar to array, say)
+    fn save_named_item(&self, kind: &str, name: &str, item: &StoredItem) -> Result<()> {
+        self.remove_item_files(kind, name, Some(&item.path))?;
+        self.write_item(item)?;
+        debug!("Saved {} {} to {}", kind.trim_end_matches('s'), name, item.path);
+        Ok(())
+    }
+
+    fn write_item(&self, item: &StoredItem) -> Result<()> {
+        let file = self.state_path.join(&item.path);
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(file, &item.content)?;
+        Ok(())
+    }
+
+    /// Remove a proc or var's v2 files except `keep`, along with namespace
+    /// directories left empty
+    fn remove_item_files(&self, kind: &str, name: &str, keep: Option<&str>) -> Result<()> {
+        let kind_dir = self.state_path.join(kind);
+        for path in state_layout::item_paths(kind, name) {
+            let path = format!("{}/{}", kind, path);
+            let file = self.state_path.join(&path);
+            if Some(path.as_str()) == keep || !file.exists() {
+                continue;
+            }
+            fs::remove_file(&file)?;
+
+            let mut dir = file.parent();
+            while let Some(current) = dir {
+                if current == kind_dir || fs::read_dir(current)?.next().is_some() {
+                    break;
+                }
+                fs::remove_dir(current)?;
+                dir = current.parent();
+            }
+        }
+        Ok(())
+    }
+
     fn delete_proc(&self, proc_name: &str) -> Result<()> {
+        if self.layout()? == StateLayout::V2 {
+            return self.remove_item_files("procs", proc_name, None);
+        }
+
         // Remove from index
         let index_path = self.state_path.join("procs/_index");
         if !index_path.exists() {
@@ -772,6 +1962,10 @@ impl StatePersistence {
     }
 
     fn delete_var(&self, var_name: &str) -> Result<()> {
+        if self.layout()? == StateLayout::V2 {
+            return self.remove_item_files("vars", var_name, None);
+        }
+
         // Remove from index
         let index_path = self.state_path.join("vars/_index");
         if !index_path.exists() {
@@ -791,68 +1985,34 @@ impl StatePersistence {
     }
 
     fn update_proc_index(&self, proc_name: &str, hash: &str) -> Result<()> {
-        let index_path = self.state_path.join("procs/_index");
-
-        // Read existing index
-        let mut entries: HashMap<String, String> = HashMap::new();
-        if index_path.exists() {
-            let content = fs::read_to_string(&index_path)?;
-            for line in content.lines() {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    entries.insert(parts[0].to_string(), parts[1].to_string());
-                }
-            }
-        }
-
-        // Update entry
+        let mut entries = self.read_index("procs");
         entries.insert(proc_name.to_string(), hash.to_string());
+        self.write_index("procs", &entries)
+    }
+
+    fn update_var_index(&self, var_name: &str, hash: &str) -> Result<()> {
+        let mut entries = self.read_index("vars");
+        entries.insert(var_name.to_string(), hash.to_string());
+        self.write_index("vars", &entries)
+    }
 
-        // Write back sorted
+    /// Write procs/_index or vars/_index back, sorted by name
+    fn write_index(&self, kind: &str, entries: &HashMap<String, String>) -> Result<()> {
         let mut lines: Vec<String> = entries
             .iter()
             .map(|(name, hash)| format!("{} {}", name, hash))
             .collect();
         lines.sort();
 
-        fs::write(&index_path, lines.join("\n"))?;
+        fs::write(self.state_path.join(kind).join("_index"), lines.join("\n"))?;
         Ok(())
     }
 
-    fn update_var_index(&self, var_name: &str, hash: &str) -> Result<()> {
-        let index_path = self.state_path.join("vars/_index");
-
-        // Read existing index
-        let mut entries: HashMap<String, String> = HashMap::new();
-        if index_path.exists() {
-            let content = fs::read_to_string(&index_path)?;
-            for line in content.lines() {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    entries.insert(parts[0].to_string(), parts[1].to_string());
-                }
-            }
-        }
-
-        // Update entry
-        entries.insert(var_name.to_string(), hash.to_string());
-
-        // Write back sorted
-        let mut lines: Vec<String> = entries
-            .iter()
-            .map(|(name, hash)| format!("{} {}", name, hash))
-            .collect();
-        lines.sort();
-
-        fs::write(&index_path, lines.join("\n"))?;
-        Ok(())
-    }
-
-    fn sha1_hash(content: &str) -> String {
-        let mut hasher = Sha1::new();
-        hasher.update(content.as_bytes());
-        format!("{:x}", hasher.finalize())
-    }
+    fn sha1_hash(content: &str) -> String {
+        let mut hasher = Sha1::new();
+        hasher.update(content.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
 
     /// Get git commit history
     /// Returns list of (commit_hash, timestamp, author, message) tuples
@@ -885,27 +2045,622 @@ impl StatePersistence {
         Ok(commits)
     }
 
+    /// The state as of a past commit (full or abbreviated hash, or any other
+    /// revision syntax), for loading into a throwaway interpreter
+    pub fn snapshot(&self, rev: &str) -> Result<CommitSnapshot> {
+        self.init_git_repo_if_needed()?;
+        let repo = Repository::open(&self.state_path)
+            .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
+
+        let commit = resolve_commit(&repo, rev)?;
+        let tree_id = commit.tree_id();
+        let commit_id = commit.id().to_string();
+        drop(commit);
+
+        Ok(CommitSnapshot {
+            repo,
+            tree_id,
+            commit_id,
+        })
+    }
+
+    /// Commits on HEAD that changed a proc, newest first
+    pub fn proc_history(&self, proc_name: &str, count: usize) -> Result<Vec<ItemVersion>> {
+        self.item_history("procs", proc_name, count)
+    }
+
+    /// Commits on HEAD that changed a var, newest first
+    pub fn var_history(&self, var_name: &str, count: usize) -> Result<Vec<ItemVersion>> {
+        self.item_history("vars", var_name, count)
+    }
+
+    /// Walk HEAD for commits where the content hash of the name's file
+    /// differs from the parent commit
+    fn item_history(&self, kind: &str, name: &str, count: usize) -> Result<Vec<ItemVersion>> {
+        self.init_git_repo_if_needed()?;
+        let repo = Repository::open(&self.state_path)
+            .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
+
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push_head()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+
+        let mut versions = Vec::new();
+        for oid in revwalk {
+            if versions.len() >= count {
+                break;
+            }
+
+            let commit = repo.find_commit(oid?)?;
+            let after = tree_hash(&repo, &commit.tree()?, kind, name)?;
+            let before = match commit.parent(0) {
+                Ok(parent) => tree_hash(&repo, &parent.tree()?, kind, name)?,
+                Err(_) => None,
+            };
+            if before == after {
+                continue;
+            }
+
+            versions.push(ItemVersion {
+                commit_id: commit.id().to_string(),
+                timestamp: commit.time().seconds(),
+                author: commit.author().name().unwrap_or("unknown").to_string(),
+                author_email: commit.author().email().unwrap_or("").to_string(),
+                message: commit.summary().unwrap_or("").to_string(),
+                content_hash: after,
+            });
+        }
+
+        Ok(versions)
+    }
+
+    /// Unified diff of a proc between two revisions (any git revision syntax)
+    /// `rev2` defaults to HEAD and `rev1` to just before the proc's last change
+    pub fn diff_proc(&self, proc_name: &str, rev1: Option<&str>, rev2: Option<&str>) -> Result<String> {
+        self.init_git_repo_if_needed()?;
+        let repo = Repository::open(&self.state_path)
+            .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
+
+        let old_commit = match rev1 {
+            Some(rev) => Some(resolve_commit(&repo, rev)?),
+            None => {
+                let last_change = self
+                    .proc_history(proc_name, 1)?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| anyhow!("No history for proc {}", proc_name))?;
+                // A root commit that created the proc diffs against nothing
+                resolve_commit(&repo, &last_change.commit_id)?.parent(0).ok()
+            }
+        };
+        let new_commit = resolve_commit(&repo, rev2.unwrap_or("HEAD"))?;
+
+        let old_content = match old_commit {
+            Some(commit) => tree_content(&repo, &commit.tree()?, "procs", proc_name)?,
+            None => None,
+        };
+        let new_content = tree_content(&repo, &new_commit.tree()?, "procs", proc_name)?;
+        if old_content.is_none() && new_content.is_none() {
+            return Err(anyhow!("Proc {} doesn't exist in either revision", proc_name));
+        }
+        if old_content == new_content {
+            return Ok(String::new());
+        }
+
+        let (diff, _, _) = unified_diff(
+            &format!("procs/{}", proc_name),
+            old_content.as_deref(),
+            new_content.as_deref(),
+        )?;
+        Ok(diff)
+    }
+
+    /// Who last changed each line of a proc as it is at HEAD
+    pub fn blame_proc(&self, proc_name: &str) -> Result<Vec<BlameLine>> {
+        let mut versions = self.proc_history(proc_name, usize::MAX)?;
+        if versions.first().map_or(true, |version| version.content_hash.is_none()) {
+            return Err(anyhow!("Proc {} doesn't exist", proc_name));
+        }
+        versions.reverse();
+
+        let repo = Repository::open(&self.state_path)
+            .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
+
+        // Replay the versions oldest first, carrying each line's commit along
+        let mut content = String::new();
+        let mut blame = Vec::new();
+        for version in &versions {
+            let commit = resolve_commit(&repo, &version.commit_id)?;
+            let new_content = tree_content(&repo, &commit.tree()?, "procs", proc_name)?.unwrap_or_default();
+            blame = blame_step(&content, &new_content, &blame, version)?;
+            content = new_content;
+        }
+
+        Ok(blame)
+    }
+
     /// Rollback to a specific commit
     /// This resets HEAD to the specified commit and updates the working directory
-    pub fn rollback_to(&self, commit_hash: &str) -> Result<()> {
+    /// Accepts full or abbreviated hashes; returns the procs and vars whose
+    /// stored content changed (use `reload_changes` to apply them)
+    pub fn rollback_to(&self, commit_hash: &str) -> Result<StateChanges> {
         self.init_git_repo_if_needed()?;
         let repo = Repository::open(&self.state_path)
             .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
 
-        // Parse the commit hash
-        let oid = git2::Oid::from_str(commit_hash)
-            .map_err(|e| anyhow!("Invalid commit hash: {}", e))?;
-
         // Find the commit
-        let commit = repo.find_commit(oid)
+        let commit = repo
+            .revparse_single(commit_hash)
+            .and_then(|object| object.peel_to_commit())
             .map_err(|e| anyhow!("Commit not found: {}", e))?;
 
+        let procs_before = self.read_index("procs");
+        let vars_before = self.read_index("vars");
+        let namespaces_before = self.read_namespaces();
+
         // Reset to this commit (hard reset)
         repo.reset(commit.as_object(), git2::ResetType::Hard, None)
             .map_err(|e| anyhow!("Failed to reset to commit: {}", e))?;
 
-        info!("Rolled back to commit {}", commit_hash);
-        Ok(())
+        let (new_procs, deleted_procs) = index_changes(&procs_before, &self.read_index("procs"));
+        let (new_vars, deleted_vars) = index_changes(&vars_before, &self.read_index("vars"));
+        let namespaces_after = self.read_namespaces();
+        let changes = StateChanges {
+            new_procs,
+            deleted_procs,
+            new_vars,
+            deleted_vars,
+            new_namespaces: namespaces_after.difference(&namespaces_before).cloned().collect(),
+            deleted_namespaces: namespaces_before.difference(&namespaces_after).cloned().collect(),
+        };
+
+        info!("Rolled back to commit {} ({})", commit.id(), changes.summary());
+        Ok(changes)
+    }
+
+    /// Revert a single commit by applying the inverse of its proc/var changes
+    /// on top of HEAD as a new commit attributed to `user_info`
+    ///
+    /// Later history is kept. Fails without touching anything if a later
+    /// commit changed one of the same procs or vars. Returns the commit info
+    /// and the procs and vars that changed (use `reload_changes` to apply them)
+    pub fn revert_commit(&self, commit_hash: &str, user_info: &UserInfo) -> Result<(CommitInfo, StateChanges)> {
+        self.init_git_repo_if_needed()?;
+        let repo = Repository::open(&self.state_path)
+            .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
+
+        let commit = repo
+            .revparse_single(commit_hash)
+            .and_then(|object| object.peel_to_commit())
+            .map_err(|e| anyhow!("Commit not found: {}", e))?;
+        let short_id = commit.id().to_string()[..8].to_string();
+        let parent = commit
+            .parent(0)
+            .map_err(|_| anyhow!("Commit {} has no parent to revert to", short_id))?;
+        let commit_tree = commit.tree()?;
+        let parent_tree = parent.tree()?;
+
+        // Index entries only compare within one layout
+        let layout = self.layout()?;
+        let parent_source = TreeSource { repo: &repo, tree: &parent_tree };
+        let commit_source = TreeSource { repo: &repo, tree: &commit_tree };
+        if StateLayout::detect(&parent_source)? != layout || StateLayout::detect(&commit_source)? != layout {
+            return Err(anyhow!("Can't revert {}: the state layout has changed since", short_id));
+        }
+
+        // Work out the whole revert before writing anything
+        let mut changes = StateChanges::default();
+        let mut conflicts = Vec::new();
+        let mut indexes = Vec::new();
+        let mut restored_files = Vec::new();
+        for kind in ["procs", "vars"] {
+            let before = source_index(&parent_source, kind)?;
+            let after = source_index(&commit_source, kind)?;
+            let mut current = self.read_index(kind);
+
+            let names: BTreeSet<&String> = before
+                .keys()
+                .chain(after.keys())
+                .filter(|name| before.get(*name) != after.get(*name))
+                .collect();
+
+            for name in names {
+                // Anything changed since the commit belongs to someone else now
+                if current.get(name) != after.get(name) {
+                    conflicts.push(format!("{} {}", kind.trim_end_matches('s'), name));
+                    continue;
+                }
+
+                let (restored, removed) = match kind {
+                    "procs" => (&mut changes.new_procs, &mut changes.deleted_procs),
+                    _ => (&mut changes.new_vars, &mut changes.deleted_vars),
+                };
+                match before.get(name) {
+                    Some(hash) => {
+                        current.insert(name.clone(), hash.clone());
+                        restored_files.push((kind, name.clone(), hash.clone()));
+                        restored.push(name.clone());
+                    }
+                    None => {
+                        current.remove(name);
+                        removed.push(name.clone());
+                    }
+                }
+            }
+            indexes.push((kind, current));
+        }
+
+        if !conflicts.is_empty() {
+            return Err(anyhow!(
+                "Can't revert {}: changed by later commits: {}",
+                short_id,
+                conflicts.join(", ")
+            ));
+        }
+        if !changes.has_changes() {
+            return Err(anyhow!("Commit {} has no proc or var changes to revert", short_id));
+        }
+
+        match layout {
+            StateLayout::V1 => {
+                // Bring back the old content files, in case they were cleaned up since
+                for (kind, _, hash) in restored_files {
+                    let entry = parent_tree.get_path(std::path::Path::new(&format!("{}/{}", kind, hash)))?;
+                    let blob = repo.find_blob(entry.id())?;
+                    fs::create_dir_all(self.state_path.join(kind))?;
+                    fs::write(self.state_path.join(kind).join(&hash), blob.content())?;
+                }
+                for (kind, entries) in &indexes {
+                    self.write_index(kind, entries)?;
+                }
+            }
+            StateLayout::V2 => {
+                for (kind, name, _) in restored_files {
+                    let item = source_item(&parent_source, kind, &name)?
+                        .ok_or_else(|| anyhow!("{} {} missing from {}", kind, name, parent.id()))?;
+                    self.save_named_item(kind, &name, &item)?;
+                }
+                for name in &changes.deleted_procs {
+                    self.remove_item_files("procs", name, None)?;
+                }
+                for name in &changes.deleted_vars {
+                    self.remove_item_files("vars", name, None)?;
+                }
+            }
+        }
+
+        let summary = commit.summary().unwrap_or("").to_string();
+        let commit_msg = format!(
+            "Revert \"{}\"\n\nThis reverts commit {}.\n\n{}",
+            summary,
+            commit.id(),
+            changes.summary()
+        );
+        let commit_info = self.git_commit(&changes, user_info, &commit_msg)?;
+
+        self.queue_push();
+        if let Err(e) = self.maybe_run_git_gc() {
+            warn!("Failed to run git gc: {}", e);
+        }
+
+        info!("Reverted commit {} ({})", commit.id(), changes.summary());
+        Ok((commit_info, changes))
+    }
+
+    /// Most recent commit on HEAD authored by `user_info` that `undo` can take
+    /// back: reverts (including earlier undos) and commits already reverted are
+    /// skipped, so repeated undos walk further back instead of toggling
+    /// With `match_host` the author email must match as well as the nick, so a
+    /// nick change on IRC doesn't give access to someone else's commits
+    pub fn last_commit_by(&self, user_info: &UserInfo, match_host: bool) -> Result<Option<String>> {
+        self.init_git_repo_if_needed()?;
+        let repo = Repository::open(&self.state_path)
+            .map_err(|e| anyhow!("Failed to open git repository: {}", e))?;
+
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push_head()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+
+        let email = user_info.email();
+        let mut reverted = HashSet::new();
+        for oid in revwalk {
+            let commit = repo.find_commit(oid?)?;
+            if let Some(hash) = reverted_commit(commit.message().unwrap_or("")) {
+                reverted.insert(hash.to_string());
+                continue;
+            }
+            if reverted.contains(&commit.id().to_string()) {
+                continue;
+            }
+            let author = commit.author();
+            if author.name() != Some(user_info.nick.as_str()) {
+                continue;
+            }
+            if match_host && author.email() != Some(email.as_str()) {
+                continue;
+            }
+            return Ok(Some(commit.id().to_string()));
+        }
+
+        Ok(None)
+    }
+
+    /// Add vars/_namespaces to repos written before namespace variables were
+    /// tracked, listing the namespaces of any qualified vars already stored
+    /// Returns true if a migration commit was made
+    pub fn migrate_namespace_index(&self) -> Result<bool> {
+        if self.state_path.join("vars/_namespaces").exists() || Repository::open(&self.state_path).is_err() {
+            return Ok(false);
+        }
+
+        let mut namespaces = BTreeSet::new();
+        for name in self.read_index("vars").keys() {
+            let parts: Vec<&str> = name.split("::").collect();
+            for depth in 1..parts.len() {
+                namespaces.insert(parts[..depth].join("::"));
+            }
+        }
+        self.write_namespaces(&namespaces)?;
+
+        let changes = StateChanges {
+            new_namespaces: namespaces.into_iter().collect(),
+            ..Default::default()
+        };
+        let user_info = UserInfo::new("slopdrop".to_string(), "localhost".to_string());
+        self.git_commit(&changes, &user_info, "Track namespaces of persisted variables")?;
+
+        info!("Migrated state repository to track namespaces ({})", changes.summary());
+        Ok(true)
+    }
+
+    /// Convert a v1 repository to the v2 layout (see state_layout.rs) in a
+    /// single commit on top of the existing history
+    /// `scratch` is a plain interpreter the stored vars are re-read through
+    pub fn migrate_layout(&self, scratch: &impl TclEval) -> Result<CommitInfo> {
+        self.init_git_repo_if_needed()?;
+        if self.layout()? == StateLayout::V2 {
+            return Err(anyhow!("State repository already uses the v2 layout"));
+        }
+
+        let root = self.state_path.as_path();
+        let procs = source_items(root, "procs")?;
+        let vars = source_items(root, "vars")?;
+
+        // Render every new file before touching the old ones, collecting
+        // every item that can't be converted rather than stopping at the first
+        let mut items = Vec::new();
+        for (name, item) in &procs {
+            items.push(StoredItem {
+                path: format!("procs/{}", state_layout::item_path(name, PROC_EXTENSION)),
+                content: item.content.clone(),
+            });
+        }
+        let mut report = FsckReport {
+            procs: procs.len(),
+            vars: vars.len(),
+            issues: Vec::new(),
+        };
+        // Index entries whose content file is gone would be dropped silently
+        for kind in ["procs", "vars"] {
+            report.issues.extend(
+                check_structure(root, kind)?
+                    .into_iter()
+                    .filter(|issue| matches!(issue, FsckIssue::MissingBlob { .. })),
+            );
+        }
+        for (name, item) in &vars {
+            let rendered = load_item(scratch, "vars", name, item)
+                .and_then(|()| Self::render_item(StateLayout::V2, scratch, "vars", name));
+            match rendered {
+                Ok(rendered) => items.push(rendered),
+                Err(e) => report.issues.push(FsckIssue::Broken {
+                    kind: "vars".to_string(),
+                    name: name.clone(),
+                    error: format!("{} ({})", e, item.path),
+                }),
+            }
+        }
+        if !report.is_clean() {
+            return Err(anyhow!(
+                "Can't migrate to the v2 layout, run fsck -repair first\n{}",
+                report.render()
+            ));
+        }
+
+        // v1 never removes content files, so clear out every hash-named one
+        for kind in ["procs", "vars"] {
+            let dir = self.state_path.join(kind);
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let file_name = entry.file_name().to_string_lossy().into_owned();
+                let is_hash = file_name.len() == 40 && file_name.chars().all(|c| c.is_ascii_hexdigit());
+                if is_hash || file_name == "_index" {
+                    fs::remove_file(entry.path())?;
+                }
+            }
+        }
+        for item in &items {
+            self.write_item(item)?;
+        }
+        fs::write(self.state_path.join(LAYOUT_FILE), "2\n")?;
+
+        let changes = StateChanges {
+            new_procs: procs.keys().cloned().collect(),
+            new_vars: vars.keys().cloned().collect(),
+            ..Default::default()
+        };
+        let commit_msg = format!(
+            "Migrate state repository to the v2 layout\n\n{} procs and {} vars moved to named files",
+            procs.len(),
+            vars.len()
+        );
+        let user_info = UserInfo::new("slopdrop".to_string(), "localhost".to_string());
+        let commit_info = self.git_commit(&changes, &user_info, &commit_msg)?;
+
+        self.queue_push();
+
+        info!("Migrated state repository to the v2 layout ({} procs, {} vars)", procs.len(), vars.len());
+        Ok(commit_info)
+    }
+
+    /// Check the working tree for index entries without files, orphan files,
+    /// duplicate names, and procs/vars that fail to load into `scratch` (see
+    /// `scratch_interp`)
+    pub fn fsck(&self, scratch: &impl TclEval) -> Result<FsckReport> {
+        self.init_git_repo_if_needed()?;
+        let root = self.state_path.as_path();
+        let mut report = FsckReport::default();
+
+        // Namespaced procs need their namespace to exist, as in load_state
+        for namespace in self.read_namespaces() {
+            let create = format!("namespace eval {} {{}}", tcl_quote(&format!("::{}", namespace)));
+            let _ = scratch.eval_tcl(create.as_str());
+        }
+
+        for kind in ["procs", "vars"] {
+            report.issues.extend(check_structure(root, kind)?);
+
+            let items = source_items(root, kind)?;
+            match kind {
+                "procs" => report.procs = items.len(),
+                _ => report.vars = items.len(),
+            }
+            for (name, item) in &items {
+                if let Err(e) = load_item(scratch, kind, name, item) {
+                    report.issues.push(FsckIssue::Broken {
+                        kind: kind.to_string(),
+                        name: name.clone(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Fix what `fsck` found and commit the result as `user_info`
+    ///
+    /// Orphan files are deleted and duplicates cut down to the copy that
+    /// loads. Missing and broken procs/vars are dropped from the state; any
+    /// earlier good version stays in history. Returns None if there was
+    /// nothing to fix.
+    pub fn fsck_repair(&self, report: &FsckReport, user_info: &UserInfo) -> Result<Option<CommitInfo>> {
+        if report.is_clean() {
+            return Ok(None);
+        }
+
+        let layout = self.layout()?;
+        let mut changes = StateChanges::default();
+        for issue in &report.issues {
+            match issue {
+                FsckIssue::Orphan { path } => {
+                    let file = self.state_path.join(path);
+                    if file.exists() {
+                        fs::remove_file(file)?;
+                    }
+                }
+                FsckIssue::Duplicate { kind, name, .. } => match layout {
+                    // read_index keeps the last entry for a name, like loading does
+                    StateLayout::V1 => self.write_index(kind, &self.read_index(kind))?,
+                    StateLayout::V2 => {
+                        if let Some(item) = source_item(self.state_path.as_path(), kind, name)? {
+                            self.remove_item_files(kind, name, Some(&item.path))?;
+                        }
+                    }
+                },
+                FsckIssue::MissingBlob { kind, name, .. } | FsckIssue::Broken { kind, name, .. } => {
+                    if kind == "procs" {
+                        self.delete_proc(name)?;
+                        changes.deleted_procs.push(name.clone());
+                    } else {
+                        self.delete_var(name)?;
+                        changes.deleted_vars.push(name.clone());
+                    }
+                }
+            }
+        }
+
+        let commit_msg = format!("Repair state (fsck)\n\n{}", report.render());
+        let commit_info = self.git_commit(&changes, user_info, &commit_msg)?;
+
+        self.queue_push();
+
+        info!("Repaired state: {} problems fixed in {}", report.issues.len(), commit_info.commit_id);
+        Ok(Some(commit_info))
+    }
+
+    /// Every persisted proc, var and namespace as one bundle (see
+    /// `state_bundle`). Items that fail to load are left out with a warning.
+    pub fn export_bundle(&self) -> Result<StateBundle> {
+        self.init_git_repo_if_needed()?;
+        let scratch = scratch_interp()?;
+        let names = self.load_into(&scratch)?;
+        StateBundle::capture(&scratch, &names)
+    }
+
+    /// Import a bundle as a single commit by `user_info`, through
+    /// `save_changes`. Merging keeps procs/vars the bundle doesn't mention;
+    /// `replace` deletes them. Returns the commit (None if nothing changed)
+    /// and the changes, for reloading a running interpreter.
+    pub fn import_bundle(
+        &self,
+        bundle: &StateBundle,
+        replace: bool,
+        user_info: &UserInfo,
+    ) -> Result<(Option<CommitInfo>, StateChanges)> {
+        self.init_git_repo_if_needed()?;
+        let scratch = scratch_interp()?;
+        let names = self.load_into(&scratch)?;
+        let current = StateBundle::capture(&scratch, &names)?;
+
+        let changes = bundle.changes_from(&current, replace);
+        if !changes.has_changes() {
+            return Ok((None, changes));
+        }
+
+        bundle.apply(&scratch)?;
+        let description = format!(
+            "{} of a state bundle ({} procs, {} vars)",
+            if replace { "replacing import" } else { "import" },
+            bundle.procs.len(),
+            bundle.vars.len()
+        );
+        let commit_info = self.save_changes(&scratch, &changes, user_info, &description)?;
+        info!("Imported state bundle: {}", changes.summary());
+        Ok((commit_info, changes))
+    }
+
+    /// Load the persisted namespaces, procs and vars into `interp`, returning
+    /// the names of what loaded
+    fn load_into(&self, interp: &impl TclEval) -> Result<InterpreterState> {
+        let mut names = InterpreterState {
+            procs: HashSet::new(),
+            vars: HashSet::new(),
+            namespaces: self.read_namespaces().into_iter().collect(),
+            ephemeral: EphemeralRules::default(),
+        };
+        for namespace in &names.namespaces {
+            let create = format!("namespace eval {} {{}}", tcl_quote(&format!("::{}", namespace)));
+            let _ = interp.eval_tcl(create.as_str());
+        }
+
+        for kind in ["procs", "vars"] {
+            for (name, item) in source_items(self.state_path.as_path(), kind)? {
+                if let Err(e) = load_item(interp, kind, &name, &item) {
+                    warn!("Skipping {}", e);
+                    continue;
+                }
+                match kind {
+                    "procs" => names.procs.insert(name),
+                    _ => names.vars.insert(name),
+                };
+            }
+        }
+        Ok(names)
     }
 
     /// Run git gc if the commit count is a multiple of 100
@@ -943,15 +2698,593 @@ impl StatePersistence {
     }
 }
 
+/// The commit a revert commit (see `revert_commit`) took back, from its
+/// "This reverts commit <hash>." line
+fn reverted_commit(message: &str) -> Option<&str> {
+    message
+        .lines()
+        .find_map(|line| line.strip_prefix("This reverts commit "))
+        .map(|rest| rest.trim_end_matches('.'))
+}
+
+/// Entries added or changed, and entries removed, between two indexes (sorted)
+fn index_changes(before: &HashMap<String, String>, after: &HashMap<String, String>) -> (Vec<String>, Vec<String>) {
+    let mut changed: Vec<String> = after
+        .iter()
+        .filter(|(name, hash)| before.get(*name) != Some(*hash))
+        .map(|(name, _)| name.clone())
+        .collect();
+    let mut removed: Vec<String> = before
+        .keys()
+        .filter(|name| !after.contains_key(*name))
+        .cloned()
+        .collect();
+    changed.sort();
+    removed.sort();
+    (changed, removed)
+}
+
+/// The branch HEAD is on
+fn current_branch(repo: &Repository) -> Result<String> {
+    let head = repo.head().map_err(|e| anyhow!("Failed to read HEAD: {}", e))?;
+    head.shorthand()
+        .filter(|_| head.is_branch())
+        .map(String::from)
+        .ok_or_else(|| anyhow!("The state repository isn't on a branch"))
+}
+
+/// Stage bits of an index entry; conflicting entries have a non-zero stage
+const INDEX_STAGE_MASK: u16 = 0x3000;
+
+/// Settle the conflicts of a merge index. v1 index files, the proc owners
+/// and the namespace list merge entry by entry; other files, and entries both sides changed,
+/// go to `resolution`; `Report` keeps ours for them. Returns the ones
+/// `Report` kept, as "proc name", "var name", "namespace name" or a path
+fn resolve_conflicts(repo: &Repository, index: &mut git2::Index, resolution: ConflictResolution) -> Result<Vec<String>> {
+    let conflicts: Vec<git2::IndexConflict> = index.conflicts()?.collect::<std::result::Result<_, _>>()?;
+    let blob = |entry: &git2::IndexEntry| -> Result<Vec<u8>> { Ok(repo.find_blob(entry.id)?.content().to_vec()) };
+    let text = |entry: &Option<git2::IndexEntry>| -> Result<String> {
+        match entry {
+            Some(entry) => Ok(String::from_utf8_lossy(&blob(entry)?).into_owned()),
+            None => Ok(String::new()),
+        }
+    };
+
+    let mut unresolved = Vec::new();
+    for git2::IndexConflict { ancestor, our, their } in conflicts {
+        let Some(path) = our
+            .as_ref()
+            .or(their.as_ref())
+            .or(ancestor.as_ref())
+            .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+        else {
+            continue;
+        };
+
+        let resolved = if path.ends_with("/_index") || path == "vars/_namespaces" || path == OWNERS_FILE {
+            let (merged, clashes) = merge_entries(&text(&ancestor)?, &text(&our)?, &text(&their)?, resolution);
+            let kind = match path.as_str() {
+                "procs/_index" => "proc",
+                OWNERS_FILE => "owner of",
+                "vars/_index" => "var",
+                _ => "namespace",
+            };
+            unresolved.extend(clashes.into_iter().map(|name| format!("{} {}", kind, name)));
+            our.or(their).map(|entry| (entry, merged.into_bytes()))
+        } else {
+            let side = match resolution {
+                ConflictResolution::Report => {
+                    unresolved.push(describe_state_path(&path));
+                    our
+                }
+                ConflictResolution::Ours => our,
+                ConflictResolution::Theirs => their,
+            };
+            match side {
+                Some(entry) => {
+                    let data = blob(&entry)?;
+                    Some((entry, data))
+                }
+                None => None,
+            }
+        };
+
+        index.conflict_remove(Path::new(&path))?;
+        if let Some((mut entry, data)) = resolved {
+            entry.flags &= !INDEX_STAGE_MASK;
+            index.add_frombuffer(&entry, &data)?;
+        }
+    }
+    // An item clashes in both its file and the index
+    unresolved.sort();
+    unresolved.dedup();
+    Ok(unresolved)
+}
+
+/// Three-way merge of a list with one entry per line (a v1 `_index` or the
+/// namespace list), keyed by each line's first word: every entry takes the
+/// side that changed it. Entries both sides changed differently go to
+/// `resolution`; with `Report` ours is kept and their names are returned
+fn merge_entries(base: &str, ours: &str, theirs: &str, resolution: ConflictResolution) -> (String, Vec<String>) {
+    let entries = |text: &str| -> BTreeMap<String, String> {
+        text.lines()
+            .filter_map(|line| line.split_whitespace().next().map(|name| (name.to_string(), line.to_string())))
+            .collect()
+    };
+    let (base, ours, theirs) = (entries(base), entries(ours), entries(theirs));
+    let names: BTreeSet<&String> = base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+
+    let mut merged = Vec::new();
+    let mut clashes = Vec::new();
+    for name in names {
+        let (base_line, our_line, their_line) = (base.get(name), ours.get(name), theirs.get(name));
+        let line = if our_line == their_line || their_line == base_line {
+            our_line
+        } else if our_line == base_line {
+            their_line
+        } else {
+            match resolution {
+                ConflictResolution::Theirs => their_line,
+                ConflictResolution::Ours => our_line,
+                ConflictResolution::Report => {
+                    clashes.push(name.clone());
+                    our_line
+                }
+            }
+        };
+        merged.extend(line.cloned());
+    }
+    (merged.join("\n"), clashes)
+}
+
+/// "proc name" or "var name" for a v2 item file, otherwise the path itself
+fn describe_state_path(path: &str) -> String {
+    for (dir, kind) in [("procs/", "proc"), ("vars/", "var")] {
+        if let Some((name, _)) = path.strip_prefix(dir).and_then(state_layout::item_name) {
+            return format!("{} {}", kind, name);
+        }
+    }
+    path.to_string()
+}
+
+/// Read procs/_index or vars/_index (v1 layout) as name -> content hash
+fn v1_index<S: StateSource + ?Sized>(source: &S, kind: &str) -> Result<HashMap<String, String>> {
+    let mut entries = HashMap::new();
+    if let Some(content) = source.read_file(&format!("{}/_index", kind))? {
+        for line in content.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() >= 2 {
+                entries.insert(parts[0].to_string(), parts[1].to_string());
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Files below `procs/` or `vars/` that describe the procs and vars rather
+/// than hold one
+fn is_metadata_file(file: &str) -> bool {
+    matches!(file, "_index" | "_namespaces" | "_owners")
+}
+
+/// Structural problems with the procs or vars in a state source: index
+/// entries without files, files nothing refers to, and names stored twice
+pub fn check_structure<S: StateSource + ?Sized>(source: &S, kind: &str) -> Result<Vec<FsckIssue>> {
+    let mut issues = Vec::new();
+    let mut stored: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    match StateLayout::detect(source)? {
+        StateLayout::V1 => {
+            let mut referenced = HashSet::new();
+            let index = source.read_file(&format!("{}/_index", kind))?.unwrap_or_default();
+            for line in index.lines() {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+                if parts.len() < 2 {
+                    continue;
+                }
+                let path = format!("{}/{}", kind, parts[1]);
+                if source.read_file(&path)?.is_none() {
+                    issues.push(FsckIssue::MissingBlob {
+                        kind: kind.to_string(),
+                        name: parts[0].to_string(),
+                        hash: parts[1].to_string(),
+                    });
+                }
+                referenced.insert(parts[1].to_string());
+                stored.entry(parts[0].to_string()).or_default().push(path);
+            }
+
+            for file in source.list_files(kind)? {
+                if !is_metadata_file(&file) && !referenced.contains(&file) {
+                    issues.push(FsckIssue::Orphan {
+                        path: format!("{}/{}", kind, file),
+                    });
+                }
+            }
+        }
+        StateLayout::V2 => {
+            for file in source.list_files(kind)? {
+                let path = format!("{}/{}", kind, file);
+                match state_layout::item_name(&file) {
+                    Some((name, extension)) if state_layout::is_item_extension(kind, extension) => {
+                        stored.entry(name).or_default().push(path);
+                    }
+                    _ if is_metadata_file(&file) => {}
+                    _ => issues.push(FsckIssue::Orphan { path }),
+                }
+            }
+        }
+    }
+
+    for (name, paths) in stored {
+        if paths.len() > 1 {
+            issues.push(FsckIssue::Duplicate {
+                kind: kind.to_string(),
+                name,
+                paths,
+            });
+        }
+    }
+    Ok(issues)
+}
+
+/// Every proc or var in a state source by name, whichever layout it uses
+pub fn source_items<S: StateSource + ?Sized>(source: &S, kind: &str) -> Result<BTreeMap<String, StoredItem>> {
+    let mut items = BTreeMap::new();
+    match StateLayout::detect(source)? {
+        StateLayout::V1 => {
+            for (name, hash) in v1_index(source, kind)? {
+                let path = format!("{}/{}", kind, hash);
+                if let Some(content) = source.read_file(&path)? {
+                    items.insert(name, StoredItem { path, content });
+                }
+            }
+        }
+        StateLayout::V2 => {
+            for file in source.list_files(kind)? {
+                let name = match state_layout::item_name(&file) {
+                    Some((name, extension)) if state_layout::is_item_extension(kind, extension) => name,
+                    _ => continue,
+                };
+                let path = format!("{}/{}", kind, file);
+
+                // A var stored as more than one type loads as the first in
+                // VarType::ALL, the same one source_item finds
+                let rank = |path: &str| VarType::from_path(path).map_or(0, |var_type| var_type as usize);
+                if items.get(&name).map_or(false, |item: &StoredItem| rank(&item.path) <= rank(&path)) {
+                    continue;
+                }
+                if let Some(content) = source.read_file(&path)? {
+                    items.insert(name, StoredItem { path, content });
+                }
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// Every proc or var in a state source as name -> content hash
+/// v1 keeps the hashes in its index; v2 files are hashed the same way, so
+/// procs keep their hash across a migration
+pub fn source_index<S: StateSource + ?Sized>(source: &S, kind: &str) -> Result<HashMap<String, String>> {
+    match StateLayout::detect(source)? {
+        StateLayout::V1 => v1_index(source, kind),
+        StateLayout::V2 => Ok(source_items(source, kind)?
+            .into_iter()
+            .map(|(name, item)| (name, StatePersistence::sha1_hash(&item.content)))
+            .collect()),
+    }
+}
+
+/// One proc or var from a state source, or None if it isn't there
+pub fn source_item<S: StateSource + ?Sized>(source: &S, kind: &str, name: &str) -> Result<Option<StoredItem>> {
+    let paths = match StateLayout::detect(source)? {
+        StateLayout::V1 => v1_index(source, kind)?
+            .remove(name)
+            .map(|hash| vec![format!("{}/{}", kind, hash)])
+            .unwrap_or_default(),
+        StateLayout::V2 => state_layout::item_paths(kind, name)
+            .into_iter()
+            .map(|path| format!("{}/{}", kind, path))
+            .collect(),
+    };
+
+    for path in paths {
+        if let Some(content) = source.read_file(&path)? {
+            return Ok(Some(StoredItem { path, content }));
+        }
+    }
+    Ok(None)
+}
+
+/// Read a file from a committed tree, or None if it isn't there
+fn tree_file(repo: &Repository, tree: &git2::Tree, path: &str) -> Result<Option<String>> {
+    let entry = match tree.get_path(Path::new(path)) {
+        Ok(entry) => entry,
+        Err(_) => return Ok(None),
+    };
+    let blob = repo.find_blob(entry.id())?;
+    Ok(Some(String::from_utf8_lossy(blob.content()).into_owned()))
+}
+
+/// Files below a directory of a committed tree, as paths relative to it
+fn tree_files(repo: &Repository, tree: &git2::Tree, dir: &str) -> Result<Vec<String>> {
+    let subtree = match tree.get_path(Path::new(dir)) {
+        Ok(entry) => entry.to_object(repo)?.peel_to_tree()?,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut files = Vec::new();
+    subtree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() == Some(git2::ObjectType::Blob) {
+            files.push(format!("{}{}", root, entry.name().unwrap_or_default()));
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+    Ok(files)
+}
+
+/// Files below a working tree directory, as paths relative to it
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), &format!("{}/", path), files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Look up a commit by full or abbreviated hash, or any other revision syntax
+fn resolve_commit<'r>(repo: &'r Repository, rev: &str) -> Result<git2::Commit<'r>> {
+    repo.revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|e| anyhow!("Unknown revision {}: {}", rev, e))
+}
+
+/// Content of a proc or var in a committed tree, or None if it isn't there
+fn tree_content(repo: &Repository, tree: &git2::Tree, kind: &str, name: &str) -> Result<Option<String>> {
+    Ok(source_item(&TreeSource { repo, tree }, kind, name)?.map(|item| item.content))
+}
+
+/// Content hash of a proc or var in a committed tree, or None if it isn't there
+fn tree_hash(repo: &Repository, tree: &git2::Tree, kind: &str, name: &str) -> Result<Option<String>> {
+    let source = TreeSource { repo, tree };
+    match StateLayout::detect(&source)? {
+        StateLayout::V1 => Ok(v1_index(&source, kind)?.remove(name)),
+        StateLayout::V2 => Ok(source_item(&source, kind, name)?.map(|item| StatePersistence::sha1_hash(&item.content))),
+    }
+}
+
+/// Unified diff between two versions of a state file, with insertion and
+/// deletion counts; a missing side is shown as the file being added/removed
+fn unified_diff(path: &str, old: Option<&str>, new: Option<&str>) -> Result<(String, usize, usize)> {
+    let old_path = old.map(|_| format!("a/{}", path));
+    let new_path = new.map(|_| format!("b/{}", path));
+    let mut patch = git2::Patch::from_buffers(
+        old.unwrap_or_default().as_bytes(),
+        old_path.as_deref().map(std::path::Path::new),
+        new.unwrap_or_default().as_bytes(),
+        new_path.as_deref().map(std::path::Path::new),
+        None,
+    )?;
+
+    let (_, insertions, deletions) = patch.line_stats()?;
+    Ok((String::from_utf8_lossy(&patch.to_buf()?).into_owned(), insertions, deletions))
+}
+
+/// Carry blame from `old` to `new`: unchanged lines keep their commit,
+/// added lines are attributed to `version`
+fn blame_step(old: &str, new: &str, old_blame: &[BlameLine], version: &ItemVersion) -> Result<Vec<BlameLine>> {
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    // Enough context that every line of the file ends up in the patch
+    let mut opts = git2::DiffOptions::new();
+    opts.context_lines(old.lines().count().max(new_lines.len()) as u32);
+    let patch = git2::Patch::from_buffers(old.as_bytes(), None, new.as_bytes(), None, Some(&mut opts))?;
+
+    // No hunks means nothing changed, so line i still comes from line i
+    let mut sources: Vec<Option<usize>> = (0..new_lines.len()).map(Some).collect();
+    for hunk in 0..patch.num_hunks() {
+        for index in 0..patch.num_lines_in_hunk(hunk)? {
+            let line = patch.line_in_hunk(hunk, index)?;
+            let source = match line.origin() {
+                ' ' => line.old_lineno().map(|n| n as usize - 1),
+                '+' => None,
+                _ => continue,
+            };
+            if let Some(slot) = line.new_lineno().and_then(|n| sources.get_mut(n as usize - 1)) {
+                *slot = source;
+            }
+        }
+    }
+
+    Ok(new_lines
+        .iter()
+        .zip(sources)
+        .map(|(text, source)| {
+            let (commit_id, author) = match source.and_then(|i| old_blame.get(i)) {
+                Some(previous) => (previous.commit_id.clone(), previous.author.clone()),
+                None => (version.commit_id.clone(), version.author.clone()),
+            };
+            BlameLine {
+                commit_id,
+                author,
+                line: text.to_string(),
+            }
+        })
+        .collect())
+}
+
+/// Apply created and deleted namespaces to a namespace list
+/// Deleting a namespace also deletes the namespaces inside it
+fn apply_namespace_changes(namespaces: &mut BTreeSet<String>, changes: &StateChanges) {
+    for name in &changes.deleted_namespaces {
+        let prefix = format!("{}::", name);
+        namespaces.retain(|ns| ns != name && !ns.starts_with(&prefix));
+    }
+    namespaces.extend(changes.new_namespaces.iter().cloned());
+}
+
+/// vars/_namespaces content: one namespace per line, sorted
+fn namespace_list(namespaces: &BTreeSet<String>) -> String {
+    namespaces.iter().cloned().collect::<Vec<_>>().join("\n")
+}
+
+/// Define a proc from its state file content ({args} {body})
+/// Uses the original `proc` so loading doesn't count as a modification
+pub fn load_proc(interp: &impl TclEval, proc_name: &str, content: &str) -> Result<()> {
+    let proc_def = format!("::slopdrop::_original_proc {{{}}} {}", proc_name, content);
+    interp
+        .eval_tcl(proc_def.as_str())
+        .map(|_| ())
+        .map_err(|e| anyhow!("Failed to load proc {}: {}", proc_name, e))
+}
+
+/// A scratch interpreter holding the new definitions of a pending change
+fn review_interp(change: &PendingChange) -> Result<tcl::Interpreter> {
+    let interp = scratch_interp()?;
+    for (name, content) in &change.procs {
+        if let Some((namespace, _)) = name.trim_start_matches("::").rsplit_once("::") {
+            let create = format!("namespace eval {} {{}}", tcl_quote(&format!("::{}", namespace)));
+            interp
+                .eval(create.as_str())
+                .map_err(|e| anyhow!("Failed to create namespace {}: {:?}", namespace, e))?;
+        }
+        load_proc(&interp, name, content)?;
+    }
+    Ok(interp)
+}
+
+/// A plain interpreter for loading stored procs and vars outside the
+/// sandbox, to check or convert them
+pub fn scratch_interp() -> Result<tcl::Interpreter> {
+    let interp = tcl::Interpreter::new().map_err(|e| anyhow!("Failed to create TCL interpreter: {:?}", e))?;
+    // load_proc defines procs through the name proc_tracking.tcl saves `proc` under
+    interp
+        .eval("namespace eval ::slopdrop {}; interp alias {} ::slopdrop::_original_proc {} proc")
+        .map_err(|e| anyhow!("Failed to set up TCL interpreter: {:?}", e))?;
+    Ok(interp)
+}
+
+/// Load a stored proc or var, in the format of whichever layout it came from
+pub fn load_item(interp: &impl TclEval, kind: &str, name: &str, item: &StoredItem) -> Result<()> {
+    if kind == "procs" {
+        return load_proc(interp, name, &item.content);
+    }
+    match VarType::from_path(&item.path) {
+        Some(var_type) => load_typed_var(interp, name, var_type, &item.content),
+        None => load_var(interp, name, &item.content),
+    }
+}
+
+/// Set a global or namespace var from its state file content ("scalar {value}"
+/// or "array {key value ...}"), creating its namespace if needed
+pub fn load_var(interp: &impl TclEval, var_name: &str, content: &str) -> Result<()> {
+    create_var_namespace(interp, var_name)?;
+
+    // Values are TCL-quoted (with braces) directly from the historical format
+    let command = if let Some(value) = content.strip_prefix("scalar ") {
+        format!("set {{{}}} {}", var_name, value)
+    } else if let Some(array_data) = content.strip_prefix("array ") {
+        format!("array set {{{}}} {}", var_name, array_data)
+    } else {
+        return Err(anyhow!("Unknown format for var {}", var_name));
+    };
+
+    interp
+        .eval_tcl(command.as_str())
+        .map(|_| ())
+        .map_err(|e| anyhow!("Failed to load var {}: {}", var_name, e))
+}
+
+/// Set a var from its v2 file content (see `typed_var_content`)
+pub fn load_typed_var(interp: &impl TclEval, var_name: &str, var_type: VarType, content: &str) -> Result<()> {
+    create_var_namespace(interp, var_name)?;
+
+    let name = tcl_quote(var_name);
+    let value = tcl_quote(content);
+    let command = match var_type {
+        VarType::Scalar => format!("set {} {}", name, value),
+        VarType::Array => format!("array set {} {}", name, value),
+        VarType::Dict => format!("set {} [dict create {{*}}{}]", name, value),
+    };
+
+    interp
+        .eval_tcl(command.as_str())
+        .map(|_| ())
+        .map_err(|e| anyhow!("Failed to load var {}: {}", var_name, e))
+}
+
+fn create_var_namespace(interp: &impl TclEval, var_name: &str) -> Result<()> {
+    if let Some((namespace, _)) = var_name.rsplit_once("::") {
+        let create = format!("namespace eval {} {{}}", tcl_quote(&format!("::{}", namespace)));
+        interp
+            .eval_tcl(create.as_str())
+            .map_err(|e| anyhow!("Failed to create namespace {}: {}", namespace, e))?;
+    }
+    Ok(())
+}
+
+/// Reports a var's type on the first line and its v2 file content after it
+/// Dicts are told apart by their internal representation, so a string that
+/// only looks like a dict stays a scalar
+const TYPED_VAR_SCRIPT: &str = r#"apply {{name} {
+    upvar #0 $name value
+    if {[array exists value]} {
+        set lines [list]
+        foreach key [lsort [array names value]] {
+            lappend lines [list $key $value($key)]
+        }
+        return "array\n[join $lines \n]"
+    }
+    if {![catch {::tcl::unsupported::representation $value} rep]
+            && [string match "value is a dict *" $rep]} {
+        set lines [list]
+        dict for {key item} $value {
+            lappend lines [list $key $item]
+        }
+        return "dict\n[join $lines \n]"
+    }
+    return "scalar\n$value"
+}}"#;
+
+/// Type and v2 file content of a var: the raw value for scalars, and one
+/// `{key value}` pair per line for arrays (sorted by key) and dicts (in order)
+fn typed_var_content(interp: &impl TclEval, var_name: &str) -> Result<(VarType, String)> {
+    let command = format!("{} {}", TYPED_VAR_SCRIPT, tcl_quote(var_name));
+    let result = interp
+        .eval_tcl(command.as_str())
+        .map_err(|e| anyhow!("Failed to get var {}: {}", var_name, e))?
+        .get_string();
+
+    let (var_type, content) = result.split_once('\n').unwrap_or((result.as_str(), ""));
+    let var_type = VarType::from_extension(var_type)
+        .ok_or_else(|| anyhow!("Unknown type {} for var {}", var_type, var_name))?;
+    Ok((var_type, content.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// The rules proc_tracking.tcl declares for the bot's own vars
+    fn builtin_rules() -> EphemeralRules {
+        let interp = tcl::Interpreter::new().unwrap();
+        interp.eval(crate::smeggdrop_commands::proc_tracking().as_str()).unwrap();
+        EphemeralRules::capture(&interp).unwrap()
+    }
+
     #[test]
     fn test_ephemeral_vars_filtered_in_diff() {
         // Create a "before" state with some regular vars
         let before = InterpreterState {
             procs: HashSet::new(),
+            namespaces: HashSet::new(),
+            ephemeral: builtin_rules(),
             vars: ["myvar", "anothervar"]
                 .iter()
                 .map(|s| s.to_string())
@@ -961,6 +3294,8 @@ mod tests {
         // Create an "after" state that includes ephemeral error variables
         let after = InterpreterState {
             procs: HashSet::new(),
+            namespaces: HashSet::new(),
+            ephemeral: builtin_rules(),
             vars: [
                 "myvar",      // existing var
                 "anothervar", // existing var
@@ -1022,6 +3357,8 @@ mod tests {
         // Create a "before" state with ephemeral vars present
         let before = InterpreterState {
             procs: HashSet::new(),
+            namespaces: HashSet::new(),
+            ephemeral: builtin_rules(),
             vars: [
                 "myvar",
                 "errorInfo", // ephemeral
@@ -1036,6 +3373,8 @@ mod tests {
         // Create an "after" state where they're gone
         let after = InterpreterState {
             procs: HashSet::new(),
+            namespaces: HashSet::new(),
+            ephemeral: builtin_rules(),
             vars: ["myvar"].iter().map(|s| s.to_string()).collect(),
         };
 
@@ -1066,11 +3405,15 @@ mod tests {
         // Test that temporary variables created during state capture are filtered
         let before = InterpreterState {
             procs: HashSet::new(),
+            namespaces: HashSet::new(),
+            ephemeral: builtin_rules(),
             vars: ["myvar"].iter().map(|s| s.to_string()).collect(),
         };
 
         let after = InterpreterState {
             procs: HashSet::new(),
+            namespaces: HashSet::new(),
+            ephemeral: builtin_rules(),
             vars: [
                 "myvar",
                 "v",         // temp var from validation loop
@@ -1097,11 +3440,15 @@ mod tests {
         // Test that system arrays are filtered
         let before = InterpreterState {
             procs: HashSet::new(),
+            namespaces: HashSet::new(),
+            ephemeral: builtin_rules(),
             vars: HashSet::new(),
         };
 
         let after = InterpreterState {
             procs: HashSet::new(),
+            namespaces: HashSet::new(),
+            ephemeral: builtin_rules(),
             vars: [
                 "slopdrop_channel_members",
                 "slopdrop_log_lines",
@@ -1122,4 +3469,64 @@ mod tests {
             changes.new_vars
         );
     }
+
+    #[test]
+    fn test_vars_deleted_when_they_become_ephemeral() {
+        let vars: HashSet<String> = ["scratch", "keep", "tmp_a", "cache::hits"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let before = InterpreterState {
+            procs: HashSet::new(),
+            vars: vars.clone(),
+            namespaces: ["cache".to_string()].into_iter().collect(),
+            ephemeral: builtin_rules(),
+        };
+        let mut after = before.clone();
+        after.ephemeral.vars.insert("scratch".to_string());
+        after.ephemeral.patterns.push(GlobPattern::new("tmp_*"));
+        after.ephemeral.namespaces.push("cache".to_string());
+
+        let changes = before.diff(&after, &HashSet::new(), &vars);
+        let mut deleted = changes.deleted_vars.clone();
+        deleted.sort();
+        assert_eq!(deleted, vec!["cache::hits", "scratch", "tmp_a"]);
+        assert_eq!(changes.new_vars, vec!["keep"]);
+        assert_eq!(changes.deleted_namespaces, vec!["cache"]);
+
+        // And come back once they're persisted again
+        let changes = after.diff(&before, &HashSet::new(), &HashSet::new());
+        assert_eq!(changes.new_vars.len(), 3);
+        assert_eq!(changes.new_namespaces, vec!["cache"]);
+        assert!(changes.deleted_vars.is_empty());
+    }
+
+    #[test]
+    fn test_ephemeral_patterns_are_string_match_globs() {
+        let rules = EphemeralRules {
+            patterns: vec![GlobPattern::new("tmp[0-9]"), GlobPattern::new("cache.*")],
+            ..Default::default()
+        };
+
+        assert!(rules.is_ephemeral_var("tmp1"));
+        assert!(!rules.is_ephemeral_var("tmpx"));
+        assert!(rules.is_ephemeral_var("cache.hits"));
+        // `.` is literal, not a regex wildcard
+        assert!(!rules.is_ephemeral_var("cachexhits"));
+    }
+
+    #[test]
+    fn test_merge_entries_takes_each_changed_side() {
+        let base = "a 1\nb 1\nc 1";
+        let ours = "a 2\nb 1\nc 2";
+        let theirs = "a 1\nb 3\nc 3\nd 1";
+
+        let (merged, clashes) = merge_entries(base, ours, theirs, ConflictResolution::Report);
+        assert_eq!(merged, "a 2\nb 3\nc 2\nd 1");
+        assert_eq!(clashes, vec!["c"]);
+
+        let (merged, clashes) = merge_entries(base, ours, theirs, ConflictResolution::Theirs);
+        assert_eq!(merged, "a 2\nb 3\nc 3\nd 1");
+        assert!(clashes.is_empty());
+    }
 }
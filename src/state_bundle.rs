@@ -0,0 +1,356 @@
+//! Export and import of the whole persisted state as one portable file
+//!
+//! A bundle holds every persisted proc, var and namespace, either as a TCL
+//! script that recreates them:
+//!
+//! ```text
+//! namespace eval ::cache {}
+//! proc greet name {return "Hello, $name!"}
+//! set ::counter 1
+//! array set ::scores {alice 10 bob 20}
+//! set ::config [dict create {*}{b 2 a 1}]
+//! ```
+//!
+//! or as a JSON document with the same content:
+//!
+//! ```text
+//! {"version": 1, "namespaces": ["cache"],
+//!  "procs": {"greet": {"args": "name", "body": "return \"Hello, $name!\""}},
+//!  "vars": {"counter": {"type": "scalar", "value": "1"}, ...}}
+//! ```
+//!
+//! TCL bundles are evaluated in a safe interpreter to read them back, so any
+//! script that only defines procs and sets vars can be imported.
+
+use crate::state::{EphemeralRules, InterpreterState, StateChanges};
+use crate::state_layout::VarType;
+use crate::tcl_wrapper::{tcl_list, tcl_quote, TclEval};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::Path;
+
+/// Version of the bundle format written by `render`
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Name of the safe child interpreter TCL bundles are read in
+const BUNDLE_INTERP: &str = "bundle";
+
+/// File format of a bundle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BundleFormat {
+    Tcl,
+    Json,
+}
+
+impl BundleFormat {
+    /// Format of a bundle file by extension: `.json` is JSON, anything else TCL
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => BundleFormat::Json,
+            _ => BundleFormat::Tcl,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "tcl" => Ok(BundleFormat::Tcl),
+            "json" => Ok(BundleFormat::Json),
+            other => Err(anyhow!("Unknown bundle format {:?} (use tcl or json)", other)),
+        }
+    }
+}
+
+/// A proc as its argument list (with defaults) and body
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundleProc {
+    pub args: String,
+    pub body: String,
+}
+
+/// A var as its type and value: the raw value for scalars and dicts, and
+/// `array get` output sorted by key for arrays
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundleVar {
+    #[serde(rename = "type")]
+    pub var_type: VarType,
+    pub value: String,
+}
+
+/// Every persisted proc, var and namespace
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateBundle {
+    pub version: u32,
+    #[serde(default)]
+    pub namespaces: BTreeSet<String>,
+    #[serde(default)]
+    pub procs: BTreeMap<String, BundleProc>,
+    #[serde(default)]
+    pub vars: BTreeMap<String, BundleVar>,
+}
+
+impl StateBundle {
+    /// Read the named procs, vars and namespaces out of an interpreter
+    pub fn capture(interp: &impl TclEval, names: &InterpreterState) -> Result<Self> {
+        let mut bundle = StateBundle {
+            version: BUNDLE_VERSION,
+            namespaces: names.namespaces.iter().cloned().collect(),
+            ..Default::default()
+        };
+
+        for name in &names.procs {
+            let args = eval(interp, &format!("{} {}", PROC_ARGS_SCRIPT, tcl_quote(name)))?;
+            let body = eval(interp, &format!("info body {}", tcl_quote(name)))?;
+            bundle.procs.insert(name.clone(), BundleProc { args, body });
+        }
+
+        for name in &names.vars {
+            let result = eval(interp, &format!("{} {}", VAR_SCRIPT, tcl_quote(name)))?;
+            let (var_type, value) = result.split_once('\n').unwrap_or((result.as_str(), ""));
+            let var_type = VarType::from_extension(var_type)
+                .ok_or_else(|| anyhow!("Unknown type {} for var {}", var_type, name))?;
+            bundle.vars.insert(name.clone(), BundleVar { var_type, value: value.to_string() });
+        }
+
+        Ok(bundle)
+    }
+
+    /// Read a bundle in either format
+    pub fn parse(text: &str, format: BundleFormat) -> Result<Self> {
+        match format {
+            BundleFormat::Tcl => Self::from_script(text),
+            BundleFormat::Json => {
+                let bundle: StateBundle =
+                    serde_json::from_str(text).map_err(|e| anyhow!("Invalid JSON bundle: {}", e))?;
+                if bundle.version > BUNDLE_VERSION {
+                    return Err(anyhow!(
+                        "Bundle version {} is newer than this slopdrop supports ({})",
+                        bundle.version,
+                        BUNDLE_VERSION
+                    ));
+                }
+                Ok(bundle)
+            }
+        }
+    }
+
+    /// Read a TCL bundle by evaluating it in a fresh safe interpreter and
+    /// capturing whatever it defined
+    pub fn from_script(script: &str) -> Result<Self> {
+        let master = tcl::Interpreter::new().map_err(|e| anyhow!("Failed to create TCL interpreter: {:?}", e))?;
+        master
+            .eval(format!("interp create -safe {}", BUNDLE_INTERP).as_str())
+            .map_err(|e| anyhow!("Failed to create safe interpreter: {:?}", e))?;
+        let interp = BundleInterp { master: &master };
+
+        let before = defined_names(&interp)?;
+        interp
+            .eval_tcl(script)
+            .map_err(|e| anyhow!("Failed to evaluate bundle: {}", e))?;
+        let after = defined_names(&interp)?;
+
+        let names = InterpreterState {
+            procs: after.procs.difference(&before.procs).cloned().collect(),
+            vars: after.vars.difference(&before.vars).cloned().collect(),
+            namespaces: after.namespaces.difference(&before.namespaces).cloned().collect(),
+            ephemeral: EphemeralRules::default(),
+        };
+        Self::capture(&interp, &names)
+    }
+
+    /// Write the bundle in either format
+    pub fn render(&self, format: BundleFormat) -> Result<String> {
+        match format {
+            BundleFormat::Tcl => Ok(self.to_script()),
+            BundleFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+        }
+    }
+
+    /// A TCL script that recreates the state when evaluated
+    pub fn to_script(&self) -> String {
+        let mut script = format!(
+            "# slopdrop state bundle v{}: {} procs, {} vars\n\
+             # Recreates the state when evaluated; import with `slopdrop --import <file>`\n",
+            BUNDLE_VERSION,
+            self.procs.len(),
+            self.vars.len()
+        );
+        for (_, command) in self.commands() {
+            script.push_str(&command);
+            script.push('\n');
+        }
+        script
+    }
+
+    /// Define everything in the bundle in an interpreter
+    pub fn apply(&self, interp: &impl TclEval) -> Result<()> {
+        for (name, command) in self.commands() {
+            interp
+                .eval_tcl(command.as_str())
+                .map_err(|e| anyhow!("Failed to import {}: {}", name, e))?;
+        }
+        Ok(())
+    }
+
+    /// What importing this bundle over `current` changes. Merging keeps
+    /// everything the bundle doesn't mention; replacing deletes it.
+    pub fn changes_from(&self, current: &StateBundle, replace: bool) -> StateChanges {
+        let mut changes = StateChanges::default();
+
+        for (name, proc_def) in &self.procs {
+            if current.procs.get(name) != Some(proc_def) {
+                changes.new_procs.push(name.clone());
+            }
+        }
+        for (name, var) in &self.vars {
+            if current.vars.get(name) != Some(var) {
+                changes.new_vars.push(name.clone());
+            }
+        }
+        changes.new_namespaces = self.namespaces.difference(&current.namespaces).cloned().collect();
+
+        if replace {
+            changes.deleted_procs = current.procs.keys().filter(|name| !self.procs.contains_key(*name)).cloned().collect();
+            changes.deleted_vars = current.vars.keys().filter(|name| !self.vars.contains_key(*name)).cloned().collect();
+            changes.deleted_namespaces = current.namespaces.difference(&self.namespaces).cloned().collect();
+        }
+
+        changes
+    }
+
+    /// One TCL command per namespace, proc and var, in an order that can be
+    /// evaluated (namespaces first), labelled with what each defines
+    fn commands(&self) -> Vec<(String, String)> {
+        let mut commands = Vec::new();
+
+        for namespace in &self.namespaces {
+            let qualified = format!("::{}", namespace);
+            commands.push((format!("namespace {}", namespace), tcl_list(&["namespace", "eval", qualified.as_str(), ""])));
+        }
+
+        for (name, proc_def) in &self.procs {
+            commands.push((
+                format!("proc {}", name),
+                tcl_list(&["proc", name.as_str(), proc_def.args.as_str(), proc_def.body.as_str()]),
+            ));
+        }
+
+        for (name, var) in &self.vars {
+            let qualified = format!("::{}", name.trim_start_matches("::"));
+            let command = match var.var_type {
+                VarType::Scalar => tcl_list(&["set", qualified.as_str(), var.value.as_str()]),
+                VarType::Array => tcl_list(&["array", "set", qualified.as_str(), var.value.as_str()]),
+                VarType::Dict => format!("set {} [dict create {{*}}{}]", tcl_list(&[&qualified]), tcl_list(&[&var.value])),
+            };
+            commands.push((format!("var {}", name), command));
+        }
+
+        commands
+    }
+}
+
+/// The safe child interpreter a TCL bundle is evaluated in
+struct BundleInterp<'a> {
+    master: &'a tcl::Interpreter,
+}
+
+impl TclEval for BundleInterp<'_> {
+    fn eval_tcl(&self, code: &str) -> std::result::Result<tcl::Obj, String> {
+        let script = format!("interp eval {} {}", BUNDLE_INTERP, tcl_quote(code));
+        self.master.eval(script.as_str()).map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// Every proc, var and namespace defined in an interpreter, named the way
+/// the state repository names them
+fn defined_names(interp: &impl TclEval) -> Result<InterpreterState> {
+    let list = |what: &str| -> Result<HashSet<String>> {
+        Ok(eval(interp, &format!("{} {}", DEFINED_SCRIPT, what))?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect())
+    };
+    Ok(InterpreterState {
+        procs: list("procs")?,
+        vars: list("vars")?,
+        namespaces: list("namespaces")?,
+        ephemeral: EphemeralRules::default(),
+    })
+}
+
+fn eval(interp: &impl TclEval, command: &str) -> Result<String> {
+    interp
+        .eval_tcl(command)
+        .map(|obj| obj.get_string())
+        .map_err(|e| anyhow!("{}", e))
+}
+
+/// Argument list of a proc, including default values
+const PROC_ARGS_SCRIPT: &str = r#"apply {{name} {
+    set result [list]
+    foreach arg [info args $name] {
+        if {[info default $name $arg default]} {
+            lappend result [list $arg $default]
+        } else {
+            lappend result $arg
+        }
+    }
+    return $result
+}}"#;
+
+/// "type\nvalue" of a var (see `BundleVar`)
+const VAR_SCRIPT: &str = r#"apply {{name} {
+    upvar #0 $name value
+    if {[array exists value]} {
+        return "array\n[lsort -stride 2 -index 0 [array get value]]"
+    }
+    if {![catch {::tcl::unsupported::representation $value} rep]
+            && [string match "value is a dict *" $rep]} {
+        return "dict\n$value"
+    }
+    return "scalar\n$value"
+}}"#;
+
+/// Newline-separated procs, vars or namespaces: global procs and vars
+/// unqualified, namespaced procs as `::ns::name`, namespaced vars and
+/// namespaces as `ns::name`
+const DEFINED_SCRIPT: &str = r#"apply {{what} {
+    set namespaces [list]
+    set queue [namespace children ::]
+    while {[llength $queue]} {
+        set queue [lassign $queue ns]
+        lappend namespaces $ns
+        lappend queue {*}[namespace children $ns]
+    }
+
+    set result [list]
+    switch -- $what {
+        procs {
+            set result [info procs]
+            foreach ns $namespaces {
+                lappend result {*}[info procs ${ns}::*]
+            }
+        }
+        vars {
+            foreach name [info globals] {
+                if {[info exists ::$name]} {
+                    lappend result $name
+                }
+            }
+            foreach ns $namespaces {
+                foreach name [info vars ${ns}::*] {
+                    if {[info exists $name]} {
+                        lappend result [string range $name 2 end]
+                    }
+                }
+            }
+        }
+        namespaces {
+            foreach ns $namespaces {
+                lappend result [string range $ns 2 end]
+            }
+        }
+    }
+    join $result \n
+}}"#;
@@ -0,0 +1,204 @@
+//! On-disk layouts of the state repository
+//!
+//! v1 stores every proc and var as a SHA1-named file, listed in
+//! `procs/_index` and `vars/_index`. v2 gives each one a readable file whose
+//! directory mirrors its namespace:
+//!
+//! ```text
+//! LAYOUT                       "2"
+//! procs/greet.tcl              {args} {body}
+//! procs/::foo/bar.tcl          proc ::foo::bar
+//! vars/counter.scalar          the raw value
+//! vars/::cache/::buckets/default.array   one {key value} pair per line
+//! vars/config.dict             one {key value} pair per line
+//! vars/_namespaces             as in v1
+//! ```
+//!
+//! Repos without a `LAYOUT` file use v1. `slopdrop --migrate-state` converts
+//! a v1 repo to v2 in a single commit.
+
+use crate::state::StateSource;
+use anyhow::{anyhow, Result};
+
+/// Marks the layout of a state repository, at its root
+pub const LAYOUT_FILE: &str = "LAYOUT";
+
+/// How procs and vars are stored in a state repository
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateLayout {
+    /// SHA1-named content files listed in `_index` files
+    V1,
+    /// One named file per proc/var, with namespaces as directories
+    V2,
+}
+
+impl StateLayout {
+    /// The layout a state source uses, from its `LAYOUT` file
+    pub fn detect<S: StateSource + ?Sized>(source: &S) -> Result<Self> {
+        match source.read_file(LAYOUT_FILE)? {
+            None => Ok(StateLayout::V1),
+            Some(content) => match content.trim() {
+                "1" => Ok(StateLayout::V1),
+                "2" => Ok(StateLayout::V2),
+                other => Err(anyhow!("Unknown state layout {:?}", other)),
+            },
+        }
+    }
+}
+
+/// Kind of var file in the v2 layout, by extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VarType {
+    Scalar,
+    Array,
+    /// A scalar holding a dict, stored like an array but in insertion order
+    Dict,
+}
+
+impl VarType {
+    pub const ALL: [VarType; 3] = [VarType::Scalar, VarType::Array, VarType::Dict];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            VarType::Scalar => "scalar",
+            VarType::Array => "array",
+            VarType::Dict => "dict",
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|var_type| var_type.extension() == extension)
+    }
+
+    /// Type of a var file from its path; None for v1 (hash-named) files
+    pub fn from_path(path: &str) -> Option<Self> {
+        let file = path.rsplit('/').next().unwrap_or(path);
+        file.rsplit_once('.').and_then(|(_, extension)| Self::from_extension(extension))
+    }
+}
+
+/// Extension of proc files in the v2 layout
+pub const PROC_EXTENSION: &str = "tcl";
+
+/// Path of a proc or var file below `procs/` or `vars/` in the v2 layout
+/// `foo::bar` with extension `tcl` becomes `::foo/bar.tcl`
+pub fn item_path(name: &str, extension: &str) -> String {
+    let mut parts: Vec<&str> = name.trim_start_matches("::").split("::").collect();
+    let leaf = parts.pop().unwrap_or_default();
+
+    let mut path = String::new();
+    for namespace in parts {
+        path.push_str("::");
+        path.push_str(&escape_component(namespace));
+        path.push('/');
+    }
+    path.push_str(&escape_component(leaf));
+    path.push('.');
+    path.push_str(extension);
+    path
+}
+
+/// Name and extension of the proc or var stored at a path below `procs/` or
+/// `vars/`, or None if the path isn't a v2 item file
+pub fn item_name(path: &str) -> Option<(String, &str)> {
+    let (dirs, file) = match path.rsplit_once('/') {
+        Some((dirs, file)) => (Some(dirs), file),
+        None => (None, path),
+    };
+    let (leaf, extension) = file.rsplit_once('.')?;
+
+    let mut parts = Vec::new();
+    for dir in dirs.into_iter().flat_map(|dirs| dirs.split('/')) {
+        parts.push(unescape_component(dir.strip_prefix("::")?)?);
+    }
+    parts.push(unescape_component(leaf)?);
+    Some((parts.join("::"), extension))
+}
+
+/// Paths below `procs/` or `vars/` a proc or var may be stored at in the v2
+/// layout (one per var type)
+pub fn item_paths(kind: &str, name: &str) -> Vec<String> {
+    match kind {
+        "procs" => vec![item_path(name, PROC_EXTENSION)],
+        _ => VarType::ALL.iter().map(|var_type| item_path(name, var_type.extension())).collect(),
+    }
+}
+
+/// Whether files with this extension hold procs or vars of the given kind
+pub fn is_item_extension(kind: &str, extension: &str) -> bool {
+    match kind {
+        "procs" => extension == PROC_EXTENSION,
+        _ => VarType::from_extension(extension).is_some(),
+    }
+}
+
+/// Make one name component safe as a file name: path separators, `%`, `:`,
+/// control characters, characters Windows rejects and a leading `.` become
+/// `%XX` (UTF-8 bytes)
+fn escape_component(component: &str) -> String {
+    let mut escaped = String::with_capacity(component.len());
+    for (i, c) in component.chars().enumerate() {
+        let unsafe_char = matches!(c, '/' | '\\' | '%' | ':' | '<' | '>' | '"' | '|' | '?' | '*')
+            || c.is_control()
+            || (i == 0 && c == '.');
+        if unsafe_char {
+            let mut buf = [0u8; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                escaped.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+fn unescape_component(component: &str) -> Option<String> {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = component.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_item_paths_round_trip() {
+        for (name, path) in [
+            ("greet", "greet.tcl"),
+            ("foo::bar", "::foo/bar.tcl"),
+            ("::foo::bar", "::foo/bar.tcl"),
+            ("a::b::c", "::a/::b/c.tcl"),
+            ("weird/name:x", "weird%2Fname%3Ax.tcl"),
+            (".hidden", "%2Ehidden.tcl"),
+            ("a.b", "a.b.tcl"),
+        ] {
+            assert_eq!(item_path(name, PROC_EXTENSION), path);
+            let (decoded, extension) = item_name(path).unwrap();
+            assert_eq!(decoded, name.trim_start_matches("::"));
+            assert_eq!(extension, PROC_EXTENSION);
+        }
+    }
+
+    #[test]
+    fn test_non_item_paths_rejected() {
+        assert!(item_name("_index").is_none());
+        assert!(item_name("foo/bar.tcl").is_none());
+        assert!(item_name("bad%zz.tcl").is_none());
+        assert_eq!(VarType::from_path("::cache/x.array"), Some(VarType::Array));
+        assert_eq!(VarType::from_path("da39a3ee5e6b4b0d3255bfef95601890afd80709"), None);
+    }
+}
@@ -321,8 +321,8 @@ lazy_static::lazy_static! {
 }
 
 /// Generate TCL wrapper commands that call back into Rust
-/// NOTE: Currently unused - stock commands are intercepted in Rust before TCL evaluation.
-/// Kept for reference in case we want to switch to TCL-based dispatch.
+/// NOTE: Currently unused - stock commands are registered natively (see native_commands.rs)
+/// and the charting procs live in tcl/stocks.tcl.
 #[allow(dead_code)]
 pub fn stock_commands() -> &'static str {
     r#"
@@ -351,7 +351,7 @@ pub fn set_stock_context(user: String, eval_count: u64) {
     STOCK_CLIENT.set_context(user, eval_count);
 }
 
-/// Handle stock command (called from tcl_thread.rs)
+/// Handle stock command (called from native_commands.rs)
 pub fn handle_stock_command(command: &str) -> Result<String> {
     let parts: Vec<&str> = command.trim().split_whitespace().collect();
 
@@ -1,7 +1,8 @@
 use crate::config::{Config, SecurityConfig, TclConfig};
 use crate::file_watcher::{ChangeType, FileChangeEvent};
 use crate::hostmask;
-use crate::tcl_thread::TclThreadHandle;
+use crate::tcl_service::{AdminNotice, EvalContext, SharedTclService, TclService};
+use crate::tcl_thread::EvalMode;
 use crate::types::{ChannelMembers, Message, PluginCommand};
 use crate::validator;
 use anyhow::Result;
@@ -19,7 +20,8 @@ struct OutputCache {
 }
 
 pub struct TclPlugin {
-    tcl_thread: TclThreadHandle,
+    /// TCL service shared with the other frontends
+    tcl_service: SharedTclService,
     tcl_config: TclConfig,
     security_config: SecurityConfig,
     server_config: crate::config::ServerConfig,
@@ -39,18 +41,36 @@ impl TclPlugin {
         config_path: std::path::PathBuf,
         channel_members: ChannelMembers,
     ) -> Result<Self> {
-        let tcl_thread =
-            TclThreadHandle::spawn(tcl_config.clone(), security_config.clone(), channel_members)?;
+        let tcl_service =
+            TclService::new_shared(security_config.clone(), tcl_config.clone(), channel_members)?;
 
-        Ok(Self {
-            tcl_thread,
+        Ok(Self::with_service(
+            security_config,
+            tcl_config,
+            server_config,
+            config_path,
+            tcl_service,
+        ))
+    }
+
+    /// Create a plugin that evaluates through an existing shared TCL service
+    /// The service must have been created with the IRC client's channel members
+    pub fn with_service(
+        security_config: SecurityConfig,
+        tcl_config: TclConfig,
+        server_config: crate::config::ServerConfig,
+        config_path: std::path::PathBuf,
+        tcl_service: SharedTclService,
+    ) -> Self {
+        Self {
+            tcl_service,
             tcl_config,
             security_config,
             server_config,
             config_path,
             output_cache: HashMap::new(),
             admin_nicks: HashSet::new(),
-        })
+        }
     }
 
     /// Main event loop for the TCL plugin
@@ -83,11 +103,11 @@ impl TclPlugin {
                 // Process batched changes - only reload once even if multiple files changed
                 if has_tcl_changes {
                     info!("Reloading TCL modules due to file changes");
-                    self.tcl_thread.reload();
+                    self.tcl_service.lock().await.reload();
                 }
                 if has_config_changes {
                     info!("Reloading configuration from disk");
-                    if let Err(e) = self.reload_config() {
+                    if let Err(e) = self.reload_config().await {
                         error!("Failed to reload configuration: {}", e);
                     }
                 }
@@ -103,7 +123,7 @@ impl TclPlugin {
                             }
                         }
                         Some(PluginCommand::LogMessage { channel, nick, mask, text }) => {
-                            self.tcl_thread.log_message(channel, nick, mask, text);
+                            self.tcl_service.lock().await.log_message(channel, nick, mask, text);
                         }
                         Some(PluginCommand::UserJoin { channel, nick, mask }) => {
                             // Track admin status on join
@@ -184,7 +204,7 @@ impl TclPlugin {
     }
 
     /// Reload configuration from disk
-    fn reload_config(&mut self) -> Result<()> {
+    async fn reload_config(&mut self) -> Result<()> {
         // Load new config from file
         let new_config = Config::from_file(
             self.config_path.to_str().ok_or_else(|| anyhow::anyhow!("Invalid config path"))?
@@ -254,6 +274,12 @@ impl TclPlugin {
                 new_config.security.max_recursion_depth);
         }
 
+        if self.security_config.worker_process != new_config.security.worker_process {
+            warn!("  ⚠ Worker process: {} -> {} (requires restart)",
+                self.security_config.worker_process,
+                new_config.security.worker_process);
+        }
+
         if self.security_config.memory_limit_mb != new_config.security.memory_limit_mb {
             warn!("  ⚠ Memory limit: {}MB -> {}MB (requires restart)",
                 self.security_config.memory_limit_mb,
@@ -290,8 +316,8 @@ impl TclPlugin {
         self.security_config = new_config.security.clone();
         self.tcl_config = new_config.tcl.clone();
 
-        // Update the TCL thread's configuration
-        self.tcl_thread.update_config(new_config.tcl, new_config.security)?;
+        // Update the shared TCL service's configuration
+        self.tcl_service.lock().await.update_config(new_config.tcl, new_config.security)?;
 
         Ok(())
     }
@@ -310,7 +336,7 @@ impl TclPlugin {
         debug!("Dispatching event: {}", dispatch_cmd);
 
         // Evaluate the dispatch command
-        let result = self.tcl_thread.eval_simple(dispatch_cmd).await?;
+        let result = self.tcl_service.lock().await.eval_simple(dispatch_cmd).await?;
 
         if result.trim().is_empty() || result.trim() == "{}" {
             return Ok(());
@@ -335,7 +361,24 @@ impl TclPlugin {
     /// Check for ready timers and send their messages
     async fn check_timers(&mut self, response_tx: &mpsc::Sender<PluginCommand>) -> Result<()> {
         // Evaluate TCL to check timers (using general timer framework)
-        let result = self.tcl_thread.eval_simple("timers check".to_string()).await?;
+        let (result, notices) = {
+            let mut service = self.tcl_service.lock().await;
+            let result = service.eval_simple("timers check".to_string()).await?;
+            (result, service.take_admin_notices())
+        };
+
+        // Timers run every second, so notices the background merge and the
+        // other frontends left for the admins go out from here
+        for notice in notices {
+            match notice {
+                AdminNotice::SyncConflicts(conflicts) => {
+                    self.send_sync_notifications(&conflicts, response_tx).await?;
+                }
+                AdminNotice::Review(review) => {
+                    self.send_review_notifications(&review, "", response_tx).await?;
+                }
+            }
+        }
 
         if result.trim().is_empty() || result.trim() == "{}" {
             return Ok(());
@@ -464,6 +507,31 @@ impl TclPlugin {
             return self.handle_blacklist_command(&message, is_admin, code.trim(), response_tx).await;
         }
 
+        // Handle admin dry runs: evaluate, report the would-be commit, keep nothing
+        let (code, mode) = match code.trim_start().strip_prefix("dryrun ") {
+            Some(_) if !is_admin => {
+                self.send_response(&message, "error: dryrun requires admin privileges (use tclAdmin)".to_string(), response_tx).await?;
+                return Ok(());
+            }
+            Some(rest) => (rest, EvalMode::DryRun),
+            None => (code, EvalMode::Normal),
+        };
+
+        // Handle admin time travel: evaluate against the state as of a past commit
+        // Only under tclAdmin, so a user proc called `at` keeps working with `tcl`
+        let (code, mode) = match code.trim_start().strip_prefix("at ") {
+            Some(rest) if is_admin && mode == EvalMode::Normal => {
+                match rest.trim_start().split_once(char::is_whitespace) {
+                    Some((commit, rest)) => (rest, EvalMode::At(commit.to_string())),
+                    None => {
+                        self.send_response(&message, "usage: tclAdmin at <commit> <code>".to_string(), response_tx).await?;
+                        return Ok(());
+                    }
+                }
+            }
+            _ => (code, mode),
+        };
+
         // Validate bracket balancing
         if let Err(e) = validator::validate_brackets(code) {
             self.send_response(&message, format!("error: {}", e), response_tx)
@@ -491,16 +559,13 @@ impl TclPlugin {
             return Ok(());
         }
 
-        // Send to TCL thread with timeout
-        let result = self.tcl_thread.eval(
-            code.to_string(),
-            is_admin,
-            message.author.nick.clone(),
-            full_host,
-            message.author.channel.clone(),
-        ).await?;
+        // Send to the shared TCL service (the thread applies the eval timeout)
+        let ctx = EvalContext::new(message.author.nick.clone(), full_host)
+            .with_channel(message.author.channel.clone())
+            .with_admin(is_admin);
+        let result = self.tcl_service.lock().await.eval_raw_with_mode(code, &ctx, mode).await?;
 
-        debug!("TCL eval completed, output length: {} bytes", result.output.len());
+        debug!("TCL eval completed ({}), output length: {} bytes", result.stats.summary(), result.output.len() + result.captured_output.len());
 
         // Send PM notifications to admins if state was committed
         if let Some(ref commit_info) = result.commit_info {
@@ -508,12 +573,18 @@ impl TclPlugin {
             self.send_commit_notifications(commit_info, &message, response_tx).await?;
         }
 
+        // Changes to protected procs wait for an admin to review them
+        if let Some(ref review) = result.review {
+            debug!("Sending review notifications");
+            self.send_review_notifications(review, &message.author.nick, response_tx).await?;
+        }
+
         debug!("Starting response send with timeout");
         // Send response with same timeout as TCL evaluation to prevent hanging on huge output
         let timeout = Duration::from_millis(self.security_config.eval_timeout_ms);
         match tokio::time::timeout(
             timeout,
-            self.send_response(&message, result.output, response_tx)
+            self.send_response(&message, result.display_text(), response_tx)
         ).await {
             Ok(Ok(_)) => Ok(()),
             Ok(Err(e)) => Err(e),
@@ -540,7 +611,7 @@ impl TclPlugin {
         // Reject output that's too large - don't even try to send it
         // Commands like 'crash' generate 2GB of output which would create thousands
         // of IRC messages and fill the channel
-        const MAX_OUTPUT_BYTES: usize = 100_000; // 100KB max
+        use crate::tcl_wrapper::MAX_OUTPUT_BYTES;
         if output.len() > MAX_OUTPUT_BYTES {
             warn!("Output too large ({} bytes), sending error instead", output.len());
             response_tx
@@ -626,11 +697,49 @@ impl TclPlugin {
             commit_info.changes_summary
         );
 
+        self.notify_admins(notification, &original_message.author.nick, response_tx).await
+    }
+
+    /// Tell admins about a change held for review, and how to act on it
+    async fn send_review_notifications(
+        &self,
+        review: &crate::review_queue::ReviewInfo,
+        sender: &str,
+        response_tx: &mpsc::Sender<PluginCommand>,
+    ) -> Result<()> {
+        let notification = format!(
+            "[Review] #{} by {} | {} | tclAdmin review diff|approve|reject {}",
+            review.id, review.author, review.changes_summary, review.id
+        );
+
+        self.notify_admins(notification, sender, response_tx).await
+    }
+
+    /// Tell admins the background merge of the state remote ran into conflicts
+    async fn send_sync_notifications(
+        &self,
+        conflicts: &str,
+        response_tx: &mpsc::Sender<PluginCommand>,
+    ) -> Result<()> {
+        let notification = format!("[Sync] {}", conflicts);
+
+        // No one sent this, so every online admin hears about it
+        self.notify_admins(notification, "", response_tx).await
+    }
+
+    /// PM `notification` to each online admin, leaving out the sender unless
+    /// `notify_self` is set
+    async fn notify_admins(
+        &self,
+        notification: String,
+        sender: &str,
+        response_tx: &mpsc::Sender<PluginCommand>,
+    ) -> Result<()> {
         // Send PM to each online admin (tracked via join/part/quit events)
         for admin_nick in &self.admin_nicks {
-            let is_sender = admin_nick == &original_message.author.nick;
+            let is_sender = admin_nick == sender;
             if !is_sender || self.security_config.notify_self {
-                debug!("Sending commit notification to {}", admin_nick);
+                debug!("Sending notification to {}", admin_nick);
                 response_tx
                     .send(PluginCommand::SendToIrc {
                         channel: admin_nick.clone(), // In IRC, nick as channel = PM
@@ -842,6 +951,10 @@ mod tests {
             eval_timeout_ms: 5000,
             memory_limit_mb: 0,
             max_recursion_depth: 1000,
+            max_eval_commands: 0,
+            worker_process: false,
+            worker_cpu_limit_secs: 0,
+            worker_max_open_files: 0,
             privileged_users: vec![],
             blacklisted_users: vec![],
             notify_self: false,
@@ -851,6 +964,14 @@ mod tests {
             state_path,
             state_repo: None,
             ssh_key: None,
+            state_branch: None,
+            state_username: None,
+            state_token_file: None,
+            state_token_env: None,
+            state_credential_helper: false,
+            transactional_evals: false,
+            commit_coalescing: Default::default(),
+            sync_interval_secs: 0,
             max_output_lines: 10,
         };
 
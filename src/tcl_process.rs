@@ -0,0 +1,611 @@
+//! Supervised child-process TCL worker (Unix only)
+//!
+//! Optional alternative to the in-process TCL thread, enabled with
+//! `security.worker_process = true`. The bot re-executes itself with
+//! `--tcl-worker <socket>`; the child runs the usual TclThreadWorker and talks
+//! to the parent over a Unix socket using newline-delimited JSON.
+//!
+//! Because the interpreter lives in its own process it can get its own rlimits
+//! (address space, CPU time, open files) and can be SIGKILLed when it hangs.
+//! An OOM or runaway eval then only kills the worker, which is respawned with
+//! state reloaded from git, while IRC and the web server keep running.
+
+use crate::config::{CoalesceMode, SecurityConfig, TclConfig};
+use crate::tcl_thread::{self, EvalMode, EvalRequest, EvalResult, StateOperation, TclThreadCommand};
+use crate::types::ChannelMembers;
+use anyhow::{anyhow, Context, Result};
+use nix::sys::resource::{setrlimit, Resource};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc as tokio_mpsc, oneshot};
+use tracing::{debug, error, info, warn};
+
+/// Command-line flag that makes the binary run as a worker process
+pub const WORKER_FLAG: &str = "--tcl-worker";
+
+/// Extra time given to the worker after the eval timeout before it is killed
+/// (interpreter limits normally cancel the script first)
+const HUNG_WORKER_GRACE: Duration = Duration::from_secs(5);
+
+/// How long to wait for a freshly spawned worker to connect back
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+static SOCKET_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Messages sent from the parent to the worker process
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WorkerRequest {
+    Init {
+        tcl_config: TclConfig,
+        security_config: SecurityConfig,
+    },
+    Eval {
+        code: String,
+        is_admin: bool,
+        nick: String,
+        host: String,
+        channel: String,
+        mode: EvalMode,
+        /// Snapshot of the parent's channel members (the IRC client lives there)
+        channel_members: HashMap<String, HashSet<String>>,
+    },
+    LogMessage {
+        channel: String,
+        nick: String,
+        mask: String,
+        text: String,
+    },
+    Reload,
+    /// Answered like an eval, with the message or the error as the result
+    State {
+        operation: StateOperation,
+    },
+    UpdateConfig {
+        tcl_config: TclConfig,
+        security_config: SecurityConfig,
+    },
+    Shutdown,
+}
+
+fn write_message<T: Serialize>(stream: &mut UnixStream, message: &T) -> Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Describe how a worker process exited, for logs and user-facing errors
+fn describe_exit(status: Option<ExitStatus>) -> String {
+    match status {
+        Some(status) => match status.signal() {
+            Some(9) => "killed by SIGKILL, likely out of memory".to_string(),
+            Some(24) => "CPU time limit exceeded".to_string(),
+            Some(signal) => format!("killed by signal {}", signal),
+            None => format!("exited with {}", status),
+        },
+        None => "exit status unknown".to_string(),
+    }
+}
+
+/// A running worker process and its connection
+struct WorkerConnection {
+    child: Child,
+    stream: UnixStream,
+    responses: tokio_mpsc::UnboundedReceiver<EvalResult>,
+}
+
+impl WorkerConnection {
+    fn spawn(
+        program: &Path,
+        tcl_config: &TclConfig,
+        security_config: &SecurityConfig,
+    ) -> Result<Self> {
+        let socket_path = std::env::temp_dir().join(format!(
+            "slopdrop-worker-{}-{}.sock",
+            std::process::id(),
+            SOCKET_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_file(&socket_path);
+
+        let listener = UnixListener::bind(&socket_path)
+            .with_context(|| format!("Failed to bind worker socket {}", socket_path.display()))?;
+        listener.set_nonblocking(true)?;
+
+        let mut child = Command::new(program)
+            .arg(WORKER_FLAG)
+            .arg(&socket_path)
+            .spawn()
+            .with_context(|| format!("Failed to spawn worker process {}", program.display()))?;
+
+        let stream = Self::accept(&listener, &mut child);
+        let _ = std::fs::remove_file(&socket_path);
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        };
+
+        write_message(
+            &mut stream,
+            &WorkerRequest::Init {
+                tcl_config: tcl_config.clone(),
+                security_config: security_config.clone(),
+            },
+        )?;
+
+        // Reader thread forwards responses so eval() can await them with a timeout
+        let (response_tx, responses) = tokio_mpsc::unbounded_channel();
+        let reader = BufReader::new(stream.try_clone()?);
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let Ok(line) = line else { break };
+                match serde_json::from_str::<EvalResult>(&line) {
+                    Ok(response) => {
+                        if response_tx.send(response).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!("Invalid message from TCL worker process: {}", e),
+                }
+            }
+        });
+
+        info!("TCL worker process started (pid {})", child.id());
+
+        Ok(Self {
+            child,
+            stream,
+            responses,
+        })
+    }
+
+    /// Wait for the child to connect, bailing out if it exits first
+    fn accept(listener: &UnixListener, child: &mut Child) -> Result<UnixStream> {
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false)?;
+                    return Ok(stream);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            if let Some(status) = child.try_wait()? {
+                return Err(anyhow!(
+                    "TCL worker process exited before connecting ({})",
+                    describe_exit(Some(status))
+                ));
+            }
+            if Instant::now() > deadline {
+                return Err(anyhow!("TCL worker process did not connect within {}s", CONNECT_TIMEOUT.as_secs()));
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// SIGKILL the child and reap it
+    fn kill(&mut self) -> Option<ExitStatus> {
+        let _ = self.child.kill();
+        self.child.wait().ok()
+    }
+}
+
+/// Handle to a supervised TCL worker process
+///
+/// Mirrors the TclThreadHandle API so TclService can use either.
+pub struct TclProcessHandle {
+    program: PathBuf,
+    connection: Option<WorkerConnection>,
+    timeout: Duration,
+    tcl_config: TclConfig,
+    security_config: SecurityConfig,
+    channel_members: ChannelMembers,
+}
+
+impl TclProcessHandle {
+    /// Spawn a worker process by re-executing the current binary
+    pub fn spawn(
+        tcl_config: TclConfig,
+        security_config: SecurityConfig,
+        channel_members: ChannelMembers,
+    ) -> Result<Self> {
+        let program = std::env::current_exe().context("Failed to locate current executable")?;
+        Self::spawn_with_program(program, tcl_config, security_config, channel_members)
+    }
+
+    /// Spawn a worker process using a specific slopdrop binary
+    /// (integration tests use this since they aren't the slopdrop binary themselves)
+    pub fn spawn_with_program(
+        program: PathBuf,
+        tcl_config: TclConfig,
+        security_config: SecurityConfig,
+        channel_members: ChannelMembers,
+    ) -> Result<Self> {
+        let timeout = Duration::from_millis(security_config.eval_timeout_ms);
+        let connection = WorkerConnection::spawn(&program, &tcl_config, &security_config)?;
+
+        Ok(Self {
+            program,
+            connection: Some(connection),
+            timeout,
+            tcl_config,
+            security_config,
+            channel_members,
+        })
+    }
+
+    /// Kill the current worker (if any) and start a fresh one
+    fn restart(&mut self) -> Result<()> {
+        warn!("Restarting TCL worker process");
+        if self.tcl_config.commit_coalescing.mode != CoalesceMode::Off {
+            warn!("Var changes the old worker held back for coalesced commits are lost");
+        }
+
+        if let Some(mut connection) = self.connection.take() {
+            connection.kill();
+        }
+
+        self.connection = Some(WorkerConnection::spawn(
+            &self.program,
+            &self.tcl_config,
+            &self.security_config,
+        )?);
+
+        info!("TCL worker process restarted successfully");
+        Ok(())
+    }
+
+    /// Restart after a failure and build the matching error result
+    fn restart_with_error(&mut self, reason: &str) -> EvalResult {
+        match self.restart() {
+            Ok(()) => EvalResult::restarted(format!("error: {}, restarted", reason)),
+            Err(e) => {
+                error!("Failed to restart TCL worker process: {}", e);
+                EvalResult::error(format!("error: {} and failed to restart: {}", reason, e))
+            }
+        }
+    }
+
+    fn send(&mut self, request: &WorkerRequest) -> Result<()> {
+        let connection = self
+            .connection
+            .as_mut()
+            .ok_or_else(|| anyhow!("TCL worker process is not running"))?;
+        write_message(&mut connection.stream, request)
+    }
+
+    /// Evaluate TCL code in the worker process with timeout
+    pub async fn eval(
+        &mut self,
+        code: String,
+        is_admin: bool,
+        nick: String,
+        host: String,
+        channel: String,
+    ) -> Result<EvalResult> {
+        self.eval_with_mode(code, is_admin, nick, host, channel, EvalMode::Normal).await
+    }
+
+    /// Evaluate TCL code in the worker process, handling state changes as `mode` says
+    pub async fn eval_with_mode(
+        &mut self,
+        code: String,
+        is_admin: bool,
+        nick: String,
+        host: String,
+        channel: String,
+        mode: EvalMode,
+    ) -> Result<EvalResult> {
+        let channel_members = self
+            .channel_members
+            .read()
+            .map(|members| members.clone())
+            .unwrap_or_default();
+
+        let request = WorkerRequest::Eval {
+            code,
+            is_admin,
+            nick,
+            host,
+            channel,
+            mode,
+            channel_members,
+        };
+
+        if let Err(e) = self.send(&request) {
+            error!("Failed to send eval to TCL worker process: {}", e);
+            let status = self.connection.as_mut().and_then(|c| c.kill());
+            let reason = format!("worker process crashed ({})", describe_exit(status));
+            return Ok(self.restart_with_error(&reason));
+        }
+
+        let hard_timeout = self.timeout + HUNG_WORKER_GRACE;
+        debug!("Waiting for TCL worker response with hard timeout of {}ms", hard_timeout.as_millis());
+        let start = Instant::now();
+
+        let connection = self.connection.as_mut().expect("connection checked by send");
+        let response = tokio::time::timeout(hard_timeout, connection.responses.recv()).await;
+        match response {
+            Ok(Some(response)) => {
+                debug!("TCL worker response received after {}ms", start.elapsed().as_millis());
+                Ok(response)
+            }
+            Ok(None) => {
+                // Socket closed - the worker died mid-eval (OOM kill, CPU limit, crash)
+                let status = connection.kill();
+                let reason = describe_exit(status);
+                error!("TCL worker process died during eval: {}", reason);
+                Ok(self.restart_with_error(&format!("worker process died ({})", reason)))
+            }
+            Err(_) => {
+                warn!("TCL worker did not respond within {}ms - killing it", hard_timeout.as_millis());
+                connection.kill();
+                Ok(self.restart_with_error(&format!(
+                    "evaluation timed out after {}s, worker process killed",
+                    self.timeout.as_secs()
+                )))
+            }
+        }
+    }
+
+    /// Simple eval for system-level operations (like timer checking)
+    /// Uses a "system" context without user tracking
+    pub async fn eval_simple(&mut self, code: String) -> Result<String> {
+        let result = self.eval(
+            code,
+            false,
+            "system".to_string(),
+            "system@bot".to_string(),
+            "system".to_string(),
+        ).await?;
+
+        Ok(result.display_text())
+    }
+
+    /// Log a message to the channel history
+    pub fn log_message(&mut self, channel: String, nick: String, mask: String, text: String) {
+        let _ = self.send(&WorkerRequest::LogMessage {
+            channel,
+            nick,
+            mask,
+            text,
+        });
+    }
+
+    /// Reload TCL modules from disk
+    pub fn reload(&mut self) {
+        info!("Sending reload command to TCL worker process");
+        let _ = self.send(&WorkerRequest::Reload);
+    }
+
+    /// Run a state repository operation in the worker process
+    pub async fn state_operation(&mut self, operation: StateOperation) -> Result<String> {
+        if let Err(e) = self.send(&WorkerRequest::State { operation }) {
+            error!("Failed to send state operation to TCL worker process: {}", e);
+            let status = self.connection.as_mut().and_then(|c| c.kill());
+            let reason = format!("worker process crashed ({})", describe_exit(status));
+            let _ = self.restart_with_error(&reason);
+            return Err(anyhow!(reason));
+        }
+
+        // Same deadline as evals: the service mutex is held while we wait
+        let hard_timeout = self.timeout + HUNG_WORKER_GRACE;
+        let connection = self.connection.as_mut().expect("connection checked by send");
+        match tokio::time::timeout(hard_timeout, connection.responses.recv()).await {
+            Ok(Some(result)) if result.is_error => Err(anyhow!(result.output)),
+            Ok(Some(result)) => Ok(result.output),
+            Ok(None) => {
+                let status = connection.kill();
+                let reason = describe_exit(status);
+                error!("TCL worker process died during a state operation: {}", reason);
+                let _ = self.restart_with_error(&format!("worker process died ({})", reason));
+                Err(anyhow!("worker process died ({})", reason))
+            }
+            Err(_) => {
+                warn!("State operation did not return within {}ms - killing the worker", hard_timeout.as_millis());
+                connection.kill();
+                let reason = format!(
+                    "state operation timed out after {}s, worker process killed",
+                    hard_timeout.as_secs()
+                );
+                let _ = self.restart_with_error(&reason);
+                Err(anyhow!(reason))
+            }
+        }
+    }
+
+    /// Update runtime configuration
+    pub fn update_config(
+        &mut self,
+        tcl_config: TclConfig,
+        security_config: SecurityConfig,
+    ) -> Result<()> {
+        info!("Sending config update command to TCL worker process");
+
+        self.timeout = Duration::from_millis(security_config.eval_timeout_ms);
+        self.tcl_config = tcl_config.clone();
+        self.security_config = security_config.clone();
+
+        self.send(&WorkerRequest::UpdateConfig {
+            tcl_config,
+            security_config,
+        })
+    }
+
+    /// Shutdown the worker process
+    pub fn shutdown(&mut self) {
+        let Some(mut connection) = self.connection.take() else {
+            return;
+        };
+
+        info!("Shutting down TCL worker process");
+        let _ = write_message(&mut connection.stream, &WorkerRequest::Shutdown);
+
+        // Give it a moment to exit cleanly, then make sure it's gone
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = connection.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        connection.kill();
+    }
+}
+
+impl Drop for TclProcessHandle {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Apply the worker's own resource limits (the parent process is unaffected)
+fn apply_worker_limits(security_config: &SecurityConfig) -> Result<()> {
+    if security_config.memory_limit_mb > 0 {
+        let limit_bytes = security_config.memory_limit_mb * 1024 * 1024;
+        setrlimit(Resource::RLIMIT_AS, limit_bytes, limit_bytes)
+            .map_err(|e| anyhow!("Failed to set memory limit: {}", e))?;
+        info!("Worker memory limit set to {} MB", security_config.memory_limit_mb);
+    }
+
+    if security_config.worker_cpu_limit_secs > 0 {
+        // Soft limit sends SIGXCPU (fatal), hard limit is a SIGKILL backstop
+        let soft = security_config.worker_cpu_limit_secs;
+        setrlimit(Resource::RLIMIT_CPU, soft, soft + 5)
+            .map_err(|e| anyhow!("Failed to set CPU limit: {}", e))?;
+        info!("Worker CPU time limit set to {}s", soft);
+    }
+
+    if security_config.worker_max_open_files > 0 {
+        let limit = security_config.worker_max_open_files;
+        setrlimit(Resource::RLIMIT_NOFILE, limit, limit)
+            .map_err(|e| anyhow!("Failed to set open file limit: {}", e))?;
+        info!("Worker open file limit set to {}", limit);
+    }
+
+    Ok(())
+}
+
+/// Entry point for `slopdrop --tcl-worker <socket>`
+///
+/// Connects back to the parent, applies rlimits and runs a TclThreadWorker,
+/// forwarding requests from the socket until told to shut down.
+pub fn run_worker_process(socket_path: &Path) -> Result<()> {
+    let mut stream = UnixStream::connect(socket_path)
+        .with_context(|| format!("Failed to connect to parent at {}", socket_path.display()))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let (tcl_config, security_config) = match serde_json::from_str(&line)? {
+        WorkerRequest::Init { tcl_config, security_config } => (tcl_config, security_config),
+        other => return Err(anyhow!("Expected init message from parent, got {:?}", other)),
+    };
+
+    apply_worker_limits(&security_config)?;
+
+    // Channel members are owned by the parent and sent along with each eval
+    let channel_members: ChannelMembers = Arc::new(RwLock::new(HashMap::new()));
+
+    // Run the worker on its own thread, as in thread mode (stock commands
+    // start their own tokio runtime, which can't nest inside ours)
+    let (command_tx, command_rx) = mpsc::channel();
+    let worker_members = channel_members.clone();
+    let worker = thread::spawn(move || {
+        if let Err(e) = tcl_thread::run_worker(tcl_config, security_config, worker_members, command_rx) {
+            error!("Failed to create TCL worker: {}", e);
+        }
+    });
+
+    for line in reader.lines() {
+        let line = line?;
+        let request: WorkerRequest = match serde_json::from_str(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("Invalid message from parent: {}", e);
+                continue;
+            }
+        };
+
+        let command = match request {
+            WorkerRequest::Eval { code, is_admin, nick, host, channel, mode, channel_members: members } => {
+                if let Ok(mut current) = channel_members.write() {
+                    *current = members;
+                }
+
+                let (response_tx, response_rx) = oneshot::channel();
+                let request = EvalRequest {
+                    code,
+                    is_admin,
+                    nick,
+                    host,
+                    channel,
+                    mode,
+                    response_tx,
+                };
+                if command_tx.send(TclThreadCommand::Eval(request)).is_err() {
+                    break;
+                }
+
+                // Worker handles one eval at a time, so just block for the answer
+                let Ok(result) = response_rx.blocking_recv() else { break };
+                write_message(&mut stream, &result)?;
+                continue;
+            }
+            WorkerRequest::LogMessage { channel, nick, mask, text } => {
+                TclThreadCommand::LogMessage { channel, nick, mask, text }
+            }
+            WorkerRequest::Reload => TclThreadCommand::Reload,
+            WorkerRequest::State { operation } => {
+                let (response_tx, response_rx) = oneshot::channel();
+                if command_tx.send(TclThreadCommand::State { operation, response_tx }).is_err() {
+                    break;
+                }
+
+                let Ok(result) = response_rx.blocking_recv() else { break };
+                let result = match result {
+                    Ok(message) => EvalResult::ok(message),
+                    Err(e) => EvalResult::error(e),
+                };
+                write_message(&mut stream, &result)?;
+                continue;
+            }
+            WorkerRequest::UpdateConfig { tcl_config, security_config } => {
+                TclThreadCommand::UpdateConfig { tcl_config, security_config }
+            }
+            WorkerRequest::Shutdown => break,
+            WorkerRequest::Init { .. } => {
+                warn!("Ignoring duplicate init message");
+                continue;
+            }
+        };
+
+        if command_tx.send(command).is_err() {
+            break;
+        }
+    }
+
+    // Parent asked us to stop or went away
+    let _ = command_tx.send(TclThreadCommand::Shutdown);
+    let _ = worker.join();
+    info!("TCL worker process exiting");
+    Ok(())
+}
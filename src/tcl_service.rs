@@ -3,20 +3,29 @@
 //! This module provides a frontend-agnostic TCL evaluation service
 //! that can be used by multiple frontends (IRC, CLI, TUI, Web, etc.)
 //!
-//! NOTE: Currently unused - frontends use TclThreadHandle directly.
-//! This abstraction is kept for future unified frontend management
-//! where multiple frontends share a single TCL service instance.
+//! A single process-wide instance is created in main.rs and shared between
+//! all enabled frontends via `SharedTclService`, so every frontend talks to
+//! the same interpreter and the same stream of git commits.
 
 #![allow(dead_code)]
 
 use crate::config::{SecurityConfig, TclConfig};
-use crate::state::{CommitInfo, StatePersistence};
-use crate::tcl_thread::TclThreadHandle;
+use crate::review_queue::ReviewInfo;
+use crate::state::{CommitInfo, DryRunReport, ItemVersion, StatePersistence};
+use crate::state_bundle::StateBundle;
+#[cfg(unix)]
+use crate::tcl_process::TclProcessHandle;
+use crate::tcl_thread::{EvalMode, EvalResult, EvalStats, StateOperation, TclThreadHandle};
 use crate::types::ChannelMembers;
 use anyhow::Result;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 
+/// TCL service shared between frontends
+/// All frontends lock the same instance, which serializes evaluation
+/// against the single interpreter thread
+pub type SharedTclService = Arc<tokio::sync::Mutex<TclService>>;
+
 /// Context for a TCL evaluation request
 #[derive(Debug, Clone)]
 pub struct EvalContext {
@@ -41,8 +50,6 @@ impl EvalContext {
     }
 
     /// Builder pattern to set channel
-    /// NOTE: Currently unused but part of fluent builder API
-    #[allow(dead_code)]
     pub fn with_channel(mut self, channel: String) -> Self {
         self.channel = Some(channel);
         self
@@ -55,7 +62,7 @@ impl EvalContext {
 }
 
 /// Response from a TCL evaluation
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct EvalResponse {
     /// Lines of output
     pub output: Vec<String>,
@@ -65,18 +72,157 @@ pub struct EvalResponse {
     pub commit_info: Option<CommitInfo>,
     /// Whether more output is available via pagination
     pub more_available: bool,
+    /// The script's return value (or error message), before pagination
+    pub return_value: String,
+    /// Text written with `puts` during the evaluation
+    pub captured_output: String,
+    /// TCL errorCode of a failed evaluation
+    pub error_code: Option<String>,
+    /// Full TCL errorInfo of a failed evaluation
+    pub error_info: Option<String>,
+    /// Timing and command count (None for paginated `more` output)
+    pub stats: Option<EvalStats>,
+    /// Whether the interpreter was restarted while handling the evaluation
+    pub restarted: bool,
+    /// Whether a failed transactional evaluation had its changes undone
+    pub rolled_back: bool,
+    /// What would have been committed (dry runs only)
+    pub dry_run: Option<DryRunReport>,
+    /// Changes to protected procs held back for admin review
+    pub review: Option<ReviewInfo>,
+    /// Conflicts the background merge of the state remote ran into (admin
+    /// evals only, which take them off the admin notices)
+    pub sync_conflicts: Option<String>,
+}
+
+/// Something the admins should hear about, kept until a frontend that can
+/// tell them takes it (the IRC plugin PMs them)
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminNotice {
+    /// The background merge of the state remote ran into conflicts
+    SyncConflicts(String),
+    /// A change to protected procs was held for review
+    Review(ReviewInfo),
+}
+
+/// Admin notices kept at most while no frontend takes them; the oldest go first
+const MAX_ADMIN_NOTICES: usize = 50;
+
+/// The interpreter backend: an in-process thread, or a supervised child process
+/// when `security.worker_process` is enabled
+pub enum TclWorker {
+    Thread(TclThreadHandle),
+    #[cfg(unix)]
+    Process(TclProcessHandle),
+}
+
+impl TclWorker {
+    /// Spawn the worker type selected in the security config
+    pub fn spawn(
+        tcl_config: TclConfig,
+        security_config: SecurityConfig,
+        channel_members: ChannelMembers,
+    ) -> Result<Self> {
+        #[cfg(unix)]
+        if security_config.worker_process {
+            return Ok(Self::Process(TclProcessHandle::spawn(
+                tcl_config,
+                security_config,
+                channel_members,
+            )?));
+        }
+
+        #[cfg(not(unix))]
+        if security_config.worker_process {
+            tracing::warn!("worker_process is only supported on Unix, using a TCL thread");
+        }
+
+        Ok(Self::Thread(TclThreadHandle::spawn(
+            tcl_config,
+            security_config,
+            channel_members,
+        )?))
+    }
+
+    pub async fn eval(
+        &mut self,
+        code: String,
+        is_admin: bool,
+        nick: String,
+        host: String,
+        channel: String,
+        mode: EvalMode,
+    ) -> Result<EvalResult> {
+        match self {
+            Self::Thread(handle) => handle.eval_with_mode(code, is_admin, nick, host, channel, mode).await,
+            #[cfg(unix)]
+            Self::Process(handle) => handle.eval_with_mode(code, is_admin, nick, host, channel, mode).await,
+        }
+    }
+
+    pub async fn eval_simple(&mut self, code: String) -> Result<String> {
+        match self {
+            Self::Thread(handle) => handle.eval_simple(code).await,
+            #[cfg(unix)]
+            Self::Process(handle) => handle.eval_simple(code).await,
+        }
+    }
+
+    pub fn log_message(&mut self, channel: String, nick: String, mask: String, text: String) {
+        match self {
+            Self::Thread(handle) => handle.log_message(channel, nick, mask, text),
+            #[cfg(unix)]
+            Self::Process(handle) => handle.log_message(channel, nick, mask, text),
+        }
+    }
+
+    pub fn reload(&mut self) {
+        match self {
+            Self::Thread(handle) => handle.reload(),
+            #[cfg(unix)]
+            Self::Process(handle) => handle.reload(),
+        }
+    }
+
+    pub async fn state_operation(&mut self, operation: StateOperation) -> Result<String> {
+        match self {
+            Self::Thread(handle) => handle.state_operation(operation).await,
+            #[cfg(unix)]
+            Self::Process(handle) => handle.state_operation(operation).await,
+        }
+    }
+
+    pub fn update_config(&mut self, tcl_config: TclConfig, security_config: SecurityConfig) -> Result<()> {
+        match self {
+            Self::Thread(handle) => handle.update_config(tcl_config, security_config),
+            #[cfg(unix)]
+            Self::Process(handle) => handle.update_config(tcl_config, security_config),
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        match self {
+            Self::Thread(handle) => handle.shutdown(),
+            #[cfg(unix)]
+            Self::Process(handle) => handle.shutdown(),
+        }
+    }
 }
 
 /// Core TCL evaluation service
 ///
-/// This service manages the TCL interpreter thread and provides
+/// This service manages the TCL interpreter worker and provides
 /// a clean API for frontends to evaluate TCL code.
 pub struct TclService {
-    tcl_thread: TclThreadHandle,
+    worker: TclWorker,
     security_config: SecurityConfig,
     tcl_config: TclConfig,
+    /// Channel members shared with the IRC client (empty for non-IRC frontends)
+    channel_members: ChannelMembers,
     /// Cache for paginated output per user/channel
     output_cache: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    /// Notices for the admins no frontend has delivered yet
+    admin_notices: Vec<AdminNotice>,
 }
 
 impl TclService {
@@ -86,38 +232,115 @@ impl TclService {
         tcl_config: TclConfig,
         channel_members: ChannelMembers,
     ) -> Result<Self> {
-        let tcl_thread = TclThreadHandle::spawn(
+        let worker = TclWorker::spawn(
             tcl_config.clone(),
             security_config.clone(),
-            channel_members,
+            channel_members.clone(),
         )?;
 
         Ok(Self {
-            tcl_thread,
+            worker,
             security_config,
             tcl_config,
+            channel_members,
             output_cache: Arc::new(RwLock::new(HashMap::new())),
+            admin_notices: Vec::new(),
         })
     }
 
-    /// Evaluate TCL code
-    pub async fn eval(&mut self, code: &str, ctx: EvalContext) -> Result<EvalResponse> {
+    /// Create a new TCL service wrapped for sharing between frontends
+    pub fn new_shared(
+        security_config: SecurityConfig,
+        tcl_config: TclConfig,
+        channel_members: ChannelMembers,
+    ) -> Result<SharedTclService> {
+        let service = Self::new(security_config, tcl_config, channel_members)?;
+        Ok(Arc::new(tokio::sync::Mutex::new(service)))
+    }
+
+    /// Evaluate TCL code and return the raw thread result without pagination
+    /// Used by frontends that do their own output handling (IRC)
+    pub async fn eval_raw(&mut self, code: &str, ctx: &EvalContext) -> Result<EvalResult> {
+        self.eval_raw_with_mode(code, ctx, EvalMode::Normal).await
+    }
+
+    /// Like `eval_raw`, handling state changes as `mode` says
+    pub async fn eval_raw_with_mode(&mut self, code: &str, ctx: &EvalContext, mode: EvalMode) -> Result<EvalResult> {
         let channel = ctx.channel.clone().unwrap_or_else(|| "default".to_string());
 
-        // Evaluate the code
-        let result = self.tcl_thread.eval(
+        let mut result = self.worker.eval(
             code.to_string(),
             ctx.is_admin,
             ctx.user.clone(),
             ctx.host.clone(),
-            channel.clone(),
+            channel,
+            mode,
         ).await?;
 
-        // Split output into lines
-        let all_lines: Vec<String> = if result.output.is_empty() {
+        // Background merge conflicts ride along with whatever eval comes
+        // next; they're kept here until someone tells the admins
+        if let Some(report) = result.sync_conflicts.take() {
+            self.push_admin_notice(AdminNotice::SyncConflicts(report));
+        }
+        Ok(result)
+    }
+
+    /// Take the notices waiting for the admins
+    pub fn take_admin_notices(&mut self) -> Vec<AdminNotice> {
+        std::mem::take(&mut self.admin_notices)
+    }
+
+    fn push_admin_notice(&mut self, notice: AdminNotice) {
+        if self.admin_notices.len() >= MAX_ADMIN_NOTICES {
+            self.admin_notices.remove(0);
+        }
+        self.admin_notices.push(notice);
+    }
+
+    /// Take the waiting sync conflict reports, joined into one
+    fn take_sync_conflicts(&mut self) -> Option<String> {
+        let mut reports = Vec::new();
+        self.admin_notices.retain(|notice| match notice {
+            AdminNotice::SyncConflicts(report) => {
+                reports.push(report.clone());
+                false
+            }
+            AdminNotice::Review(_) => true,
+        });
+        (!reports.is_empty()).then(|| reports.join("\n"))
+    }
+
+    /// Evaluate TCL code
+    pub async fn eval(&mut self, code: &str, ctx: EvalContext) -> Result<EvalResponse> {
+        self.eval_with_mode(code, ctx, EvalMode::Normal).await
+    }
+
+    /// Evaluate TCL code without keeping any state changes
+    /// The response carries the would-be commit summary and per-file diffs
+    pub async fn dry_run(&mut self, code: &str, ctx: EvalContext) -> Result<EvalResponse> {
+        self.eval_with_mode(code, ctx, EvalMode::DryRun).await
+    }
+
+    /// Evaluate TCL code, handling state changes as `mode` says
+    pub async fn eval_with_mode(&mut self, code: &str, ctx: EvalContext, mode: EvalMode) -> Result<EvalResponse> {
+        let channel = ctx.channel.clone().unwrap_or_else(|| "default".to_string());
+
+        // Evaluate the code
+        let result = self.eval_raw_with_mode(code, &ctx, mode).await?;
+
+        // The IRC plugin tells admins about reviews itself (it uses eval_raw);
+        // for the other frontends they wait with the admin notices
+        if let Some(ref review) = result.review {
+            self.push_admin_notice(AdminNotice::Review(review.clone()));
+        }
+        let sync_conflicts = if ctx.is_admin { self.take_sync_conflicts() } else { None };
+
+        // Split output (captured puts output first) into lines
+        let text = result.display_text();
+        let all_lines: Vec<String> = if text.is_empty() {
             vec![]
         } else {
-            result.output.lines().map(|s| s.to_string()).collect()
+            text.lines().map(|s| s.to_string()).collect()
         };
 
         // Apply pagination
@@ -142,6 +365,16 @@ impl TclService {
             is_error: result.is_error,
             commit_info: result.commit_info,
             more_available,
+            return_value: result.output,
+            captured_output: result.captured_output,
+            error_code: result.error_code,
+            error_info: result.error_info,
+            stats: Some(result.stats),
+            restarted: result.restarted,
+            rolled_back: result.rolled_back,
+            dry_run: result.dry_run,
+            review: result.review,
+            sync_conflicts,
         })
     }
 
@@ -160,6 +393,7 @@ impl TclService {
                     is_error: false,
                     commit_info: None,
                     more_available: false,
+                    ..Default::default()
                 });
             }
 
@@ -178,6 +412,7 @@ impl TclService {
                 is_error: false,
                 commit_info: None,
                 more_available,
+                ..Default::default()
             })
         } else {
             Ok(EvalResponse {
@@ -185,17 +420,14 @@ impl TclService {
                 is_error: false,
                 commit_info: None,
                 more_available: false,
+                ..Default::default()
             })
         }
     }
 
     /// Get git history
     pub async fn history(&self, limit: usize) -> Result<Vec<CommitInfo>> {
-        let persistence = StatePersistence::with_repo(
-            self.tcl_config.state_path.clone(),
-            self.tcl_config.state_repo.clone(),
-            self.tcl_config.ssh_key.clone(),
-        );
+        let persistence = StatePersistence::from_config(&self.tcl_config);
 
         let history = persistence.get_history(limit)?;
 
@@ -214,37 +446,79 @@ impl TclService {
             .collect())
     }
 
+    /// Commits that changed a single proc, newest first
+    pub async fn proc_history(&self, proc_name: &str, limit: usize) -> Result<Vec<ItemVersion>> {
+        let persistence = StatePersistence::from_config(&self.tcl_config);
+
+        persistence.proc_history(proc_name, limit)
+    }
+
     /// Rollback to a specific commit
+    /// The worker resets the repository and reloads what changed
     pub async fn rollback(&mut self, commit_hash: &str) -> Result<String> {
-        let persistence = StatePersistence::with_repo(
-            self.tcl_config.state_path.clone(),
-            self.tcl_config.state_repo.clone(),
-            self.tcl_config.ssh_key.clone(),
-        );
+        self.worker
+            .state_operation(StateOperation::Rollback {
+                commit_hash: commit_hash.to_string(),
+            })
+            .await
+    }
 
-        persistence.rollback_to(commit_hash)?;
+    /// Revert a single commit, keeping the history after it
+    /// The revert is committed as the requesting user
+    pub async fn revert(&mut self, commit_hash: &str, ctx: &EvalContext) -> Result<String> {
+        self.worker
+            .state_operation(StateOperation::Revert {
+                commit_hash: commit_hash.to_string(),
+                nick: ctx.user.clone(),
+                host: ctx.host.clone(),
+            })
+            .await
+    }
 
-        // Need to restart the TCL thread to reload state
-        self.restart_tcl_thread().await?;
+    /// Every persisted proc, var and namespace as one portable bundle
+    pub async fn export_state(&self) -> Result<StateBundle> {
+        let persistence = StatePersistence::from_config(&self.tcl_config);
 
-        Ok(format!("Rolled back to commit {}. TCL thread restarted with new state.", &commit_hash[..8]))
+        persistence.export_bundle()
     }
 
-    /// Restart the TCL thread
-    async fn restart_tcl_thread(&mut self) -> Result<()> {
-        self.tcl_thread.shutdown();
+    /// Import a bundle as one commit by the requesting user, merging into or
+    /// replacing the current state
+    pub async fn import_state(&mut self, bundle: &StateBundle, replace: bool, ctx: &EvalContext) -> Result<String> {
+        self.worker
+            .state_operation(StateOperation::Import {
+                bundle: bundle.clone(),
+                replace,
+                nick: ctx.user.clone(),
+                host: ctx.host.clone(),
+            })
+            .await
+    }
 
-        // Create empty channel members for now
-        // In the future, frontends can provide their own channel members
-        let channel_members = Arc::new(RwLock::new(HashMap::new()));
+    /// Simple eval for system-level operations (timers, trigger dispatch)
+    /// Uses a "system" context without user tracking, as the worker's eval_simple
+    pub async fn eval_simple(&mut self, code: String) -> Result<String> {
+        let ctx = EvalContext::new("system".to_string(), "system@bot".to_string())
+            .with_channel("system".to_string());
+        let result = self.eval_raw_with_mode(&code, &ctx, EvalMode::Normal).await?;
+        Ok(result.display_text())
+    }
 
-        self.tcl_thread = TclThreadHandle::spawn(
-            self.tcl_config.clone(),
-            self.security_config.clone(),
-            channel_members,
-        )?;
+    /// Log a message to the channel history
+    pub fn log_message(&mut self, channel: String, nick: String, mask: String, text: String) {
+        self.worker.log_message(channel, nick, mask, text);
+    }
+
+    /// Reload TCL modules from disk
+    pub fn reload(&mut self) {
+        self.worker.reload();
+    }
 
-        Ok(())
+    /// Update runtime configuration
+    pub fn update_config(&mut self, tcl_config: TclConfig, security_config: SecurityConfig) -> Result<()> {
+        self.tcl_config = tcl_config.clone();
+        self.security_config = security_config.clone();
+        self.worker.update_config(tcl_config, security_config)
     }
 
     /// Check if a user is admin based on hostmask pattern matching
@@ -259,6 +533,6 @@ impl TclService {
     /// NOTE: Used by frontends in their stop() methods during graceful shutdown
     #[allow(dead_code)]
     pub fn shutdown(&mut self) {
-        self.tcl_thread.shutdown();
+        self.worker.shutdown();
     }
 }
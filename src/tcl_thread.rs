@@ -1,41 +1,32 @@
-use crate::config::TclConfig;
-use crate::state::{InterpreterState, StatePersistence, UserInfo};
-use crate::tcl_wrapper::SafeTclInterp;
+use crate::commit_coalescer::CommitCoalescer;
+use crate::config::{CoalesceMode, TclConfig};
+use crate::eval_limits::{self, EvalLimits, LimitExceeded};
+use crate::native_commands::{self, NativeContext, SharedNativeContext, SharedTclConfig};
+use crate::remote_sync::{self, RemoteFetcher};
+use crate::review_queue::ReviewInfo;
+use crate::state::{ConflictResolution, DryRunReport, InterpreterState, StateChanges, StatePersistence, UserInfo};
+use crate::tcl_wrapper::{SafeTclInterp, TclError};
 use crate::types::ChannelMembers;
 use anyhow::Result;
 use std::collections::HashSet;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
 use tracing::{debug, error, info, warn};
 
-#[cfg(unix)]
-use nix::sys::resource::{setrlimit, Resource};
-
-/// Set memory limit for current process (Unix only)
-#[cfg(unix)]
-fn set_memory_limit(limit_mb: u64) -> Result<()> {
-    if limit_mb == 0 {
-        // 0 means no limit
-        return Ok(());
-    }
-
-    let limit_bytes = limit_mb * 1024 * 1024;
-
-    // Set virtual memory limit (RLIMIT_AS)
-    setrlimit(Resource::RLIMIT_AS, limit_bytes, limit_bytes)
-        .map_err(|e| anyhow::anyhow!("Failed to set memory limit: {}", e))?;
-
-    info!("Memory limit set to {} MB", limit_mb);
-    Ok(())
+/// CPU time used by the current thread so far
+#[cfg(target_os = "linux")]
+fn thread_cpu_time() -> Option<Duration> {
+    use nix::time::{clock_gettime, ClockId};
+    clock_gettime(ClockId::CLOCK_THREAD_CPUTIME_ID)
+        .ok()
+        .map(Duration::from)
 }
 
-#[cfg(not(unix))]
-fn set_memory_limit(_limit_mb: u64) -> Result<()> {
-    // Memory limits not supported on non-Unix platforms
-    warn!("Memory limits not supported on this platform");
-    Ok(())
+#[cfg(not(target_os = "linux"))]
+fn thread_cpu_time() -> Option<Duration> {
+    None
 }
 
 /// Request to evaluate TCL code
@@ -46,19 +37,153 @@ pub struct EvalRequest {
     pub nick: String,
     pub host: String,
     pub channel: String,
+    pub mode: EvalMode,
     pub response_tx: oneshot::Sender<EvalResult>,
 }
 
+/// How an evaluation's state changes are handled
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum EvalMode {
+    /// Keep the changes and commit them to the state repository
+    #[default]
+    Normal,
+    /// Report what would be committed, then undo the changes
+    DryRun,
+    /// Evaluate in a throwaway interpreter loaded with the state as of the
+    /// given commit; nothing is kept
+    At(String),
+}
+
+/// A change to the state repository made on the worker, so the worker is the
+/// only one writing to the repository
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum StateOperation {
+    /// Reset the state to a commit
+    Rollback { commit_hash: String },
+    /// Revert a single commit, committed as `nick`
+    Revert { commit_hash: String, nick: String, host: String },
+    /// Revert the newest commit by `author` (the caller `nick` if unset),
+    /// committed as `nick`
+    Undo { nick: String, host: String, author: Option<String> },
+    /// Fetch `state_repo` and merge it, settling conflicts as `resolution` says
+    Sync { resolution: ConflictResolution },
+    /// Import a bundle as one commit by `nick`, merging into or replacing the state
+    Import {
+        bundle: crate::state_bundle::StateBundle,
+        replace: bool,
+        nick: String,
+        host: String,
+    },
+}
+
+/// Timing and work counters for one evaluation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EvalStats {
+    /// Wall-clock time spent evaluating, in microseconds
+    pub wall_time_us: u64,
+    /// CPU time used by the interpreter thread, in microseconds (Linux only)
+    pub cpu_time_us: Option<u64>,
+    /// Number of TCL commands executed
+    pub commands: u64,
+}
+
+impl EvalStats {
+    /// Short human-readable summary, e.g. "took 12ms, 3400 cmds"
+    pub fn summary(&self) -> String {
+        let ms = self.wall_time_us as f64 / 1000.0;
+        let took = if ms < 10.0 {
+            format!("{:.1}ms", ms)
+        } else {
+            format!("{:.0}ms", ms)
+        };
+        format!("took {}, {} cmds", took, self.commands)
+    }
+}
+
 /// Result of TCL evaluation
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct EvalResult {
+    /// The script's return value, or the error message if it failed
     pub output: String,
     /// Indicates whether the output is an error message
-    /// Currently not used but kept for future error handling improvements
-    #[allow(dead_code)]
     pub is_error: bool,
     /// Git commit information (if state changed and was committed)
     pub commit_info: Option<crate::state::CommitInfo>,
+    /// Text written to stdout/stderr with `puts` during the evaluation
+    pub captured_output: String,
+    /// TCL `errorCode` of a failed script (e.g. "TCL LOOKUP COMMAND foo")
+    pub error_code: Option<String>,
+    /// Full TCL `errorInfo` stack trace of a failed script
+    pub error_info: Option<String>,
+    /// Timing and command count
+    pub stats: EvalStats,
+    /// Whether the worker was restarted while handling this eval
+    pub restarted: bool,
+    /// Whether the eval failed inside a transaction and its changes were undone
+    pub rolled_back: bool,
+    /// What would have been committed (dry runs only)
+    pub dry_run: Option<DryRunReport>,
+    /// Changes to protected procs held back for admin review
+    #[serde(default)]
+    pub review: Option<ReviewInfo>,
+    /// Conflicts the background merge of the state remote ran into since
+    /// the last eval, for the admins
+    #[serde(default)]
+    pub sync_conflicts: Option<String>,
+}
+
+impl EvalResult {
+    /// Successful result with the given return value
+    pub fn ok(output: String) -> Self {
+        Self {
+            output,
+            ..Self::default()
+        }
+    }
+
+    /// Failed result with the given error message
+    pub fn error(output: String) -> Self {
+        Self {
+            output,
+            is_error: true,
+            ..Self::default()
+        }
+    }
+
+    /// Failed result for an eval that forced a worker restart
+    pub fn restarted(output: String) -> Self {
+        Self {
+            restarted: true,
+            ..Self::error(output)
+        }
+    }
+
+    /// Captured `puts` output followed by the result, as shown to users
+    pub fn display_text(&self) -> String {
+        let text = if self.captured_output.is_empty() {
+            self.output.clone()
+        } else if self.output.is_empty() {
+            self.captured_output.trim_end_matches('\n').to_string()
+        } else {
+            format!("{}\n{}", self.captured_output.trim_end_matches('\n'), self.output)
+        };
+
+        let note = match (&self.dry_run, &self.review) {
+            (Some(report), _) => report.render(),
+            (None, _) if self.rolled_back => "(state changes rolled back)".to_string(),
+            (None, Some(review)) => format!(
+                "(changes to protected procs held for review as #{}: {})",
+                review.id, review.changes_summary
+            ),
+            (None, None) => return text,
+        };
+
+        if text.is_empty() {
+            note
+        } else {
+            format!("{}\n{}", text, note)
+        }
+    }
 }
 
 /// Commands that can be sent to the TCL thread
@@ -71,6 +196,12 @@ pub enum TclThreadCommand {
         text: String,
     },
     Reload,
+    /// Change the state repository and reload what changed; replies with a
+    /// message for the user or the error
+    State {
+        operation: StateOperation,
+        response_tx: oneshot::Sender<std::result::Result<String, String>>,
+    },
     UpdateConfig {
         tcl_config: TclConfig,
         security_config: crate::config::SecurityConfig,
@@ -78,6 +209,10 @@ pub enum TclThreadCommand {
     Shutdown,
 }
 
+/// Extra time given to the worker after the eval timeout before the thread is
+/// considered hung (interpreter limits normally cancel the script first)
+const HUNG_THREAD_GRACE: Duration = Duration::from_secs(5);
+
 /// Handle to communicate with the TCL thread
 pub struct TclThreadHandle {
     command_tx: mpsc::Sender<TclThreadCommand>,
@@ -102,12 +237,15 @@ impl TclThreadHandle {
         let security_config_clone = security_config.clone();
         let channel_members_clone = channel_members.clone();
 
-        let thread_handle = thread::spawn(move || {
-            // Set memory limit for this thread
-            if let Err(e) = set_memory_limit(security_config_clone.memory_limit_mb) {
-                error!("Failed to set memory limit: {}", e);
-            }
+        // RLIMIT_AS can't be scoped to a thread; it would cap the whole bot
+        if security_config.memory_limit_mb > 0 {
+            warn!(
+                "memory_limit_mb ({} MB) is ignored in thread mode; set worker_process = true to limit the TCL worker",
+                security_config.memory_limit_mb
+            );
+        }
 
+        let thread_handle = thread::spawn(move || {
             let worker = TclThreadWorker::new(
                 tcl_config_clone,
                 security_config_clone,
@@ -133,9 +271,12 @@ impl TclThreadHandle {
         })
     }
 
-    /// Restart the TCL thread (called after timeout/hang)
+    /// Restart the TCL thread (called after a crash or a hang the limits couldn't cancel)
     fn restart(&mut self) -> Result<()> {
         warn!("Restarting hung TCL thread");
+        if self.tcl_config.commit_coalescing.mode != CoalesceMode::Off {
+            warn!("Var changes the old thread held back for coalesced commits are lost");
+        }
 
         // Drop old thread handle (abandon hung thread)
         if let Some(handle) = self.thread_handle.take() {
@@ -152,11 +293,6 @@ impl TclThreadHandle {
         let channel_members = self.channel_members.clone();
 
         let thread_handle = thread::spawn(move || {
-            // Set memory limit for this thread
-            if let Err(e) = set_memory_limit(security_config.memory_limit_mb) {
-                error!("Failed to set memory limit after restart: {}", e);
-            }
-
             let worker = TclThreadWorker::new(tcl_config, security_config, channel_members);
             if let Err(e) = worker {
                 error!("Failed to create TCL worker after restart: {}", e);
@@ -182,6 +318,19 @@ impl TclThreadHandle {
         nick: String,
         host: String,
         channel: String,
+    ) -> Result<EvalResult> {
+        self.eval_with_mode(code, is_admin, nick, host, channel, EvalMode::Normal).await
+    }
+
+    /// Evaluate TCL code with timeout, handling state changes as `mode` says
+    pub async fn eval_with_mode(
+        &mut self,
+        code: String,
+        is_admin: bool,
+        nick: String,
+        host: String,
+        channel: String,
+        mode: EvalMode,
     ) -> Result<EvalResult> {
         let (response_tx, response_rx) = oneshot::channel();
 
@@ -191,6 +340,7 @@ impl TclThreadHandle {
             nick,
             host,
             channel,
+            mode,
             response_tx,
         };
 
@@ -202,24 +352,19 @@ impl TclThreadHandle {
             // Restart the thread
             if let Err(restart_err) = self.restart() {
                 error!("Failed to restart TCL thread after crash: {}", restart_err);
-                return Ok(EvalResult {
-                    output: format!("error: thread crashed and failed to restart: {}", restart_err),
-                    is_error: true,
-                    commit_info: None,
-                });
+                return Ok(EvalResult::error(format!("error: thread crashed and failed to restart: {}", restart_err)));
             }
 
-            return Ok(EvalResult {
-                output: "error: thread crashed (likely out of memory), restarted".to_string(),
-                is_error: true,
-                commit_info: None,
-            });
+            return Ok(EvalResult::restarted("error: thread crashed (likely out of memory), restarted".to_string()));
         }
 
-        // Wait for response with timeout
-        debug!("Waiting for TCL response with timeout of {}ms", self.timeout.as_millis());
+        // Wait for response. The worker cancels runaway scripts itself via
+        // interpreter limits, so only wait longer than the eval timeout to
+        // catch scripts stuck inside a blocking C call
+        let hard_timeout = self.timeout + HUNG_THREAD_GRACE;
+        debug!("Waiting for TCL response with hard timeout of {}ms", hard_timeout.as_millis());
         let start = Instant::now();
-        match tokio::time::timeout(self.timeout, response_rx).await {
+        match tokio::time::timeout(hard_timeout, response_rx).await {
             Ok(Ok(result)) => {
                 debug!("TCL response received after {}ms", start.elapsed().as_millis());
                 Ok(result)
@@ -231,38 +376,23 @@ impl TclThreadHandle {
                 // Restart the thread
                 if let Err(restart_err) = self.restart() {
                     error!("Failed to restart TCL thread after crash: {}", restart_err);
-                    return Ok(EvalResult {
-                        output: format!("error: thread died and failed to restart: {}", restart_err),
-                        is_error: true,
-                        commit_info: None,
-                    });
+                    return Ok(EvalResult::error(format!("error: thread died and failed to restart: {}", restart_err)));
                 }
 
-                Ok(EvalResult {
-                    output: "error: thread died unexpectedly (likely out of memory), restarted".to_string(),
-                    is_error: true,
-                    commit_info: None,
-                })
+                Ok(EvalResult::restarted("error: thread died unexpectedly (likely out of memory), restarted".to_string()))
             }
             Err(_) => {
-                // Timeout! The TCL thread is hung
-                warn!("TCL evaluation timed out after {}ms - thread is hung, restarting", self.timeout.as_millis());
+                // Interpreter limits didn't fire - the thread is stuck outside
+                // the TCL bytecode loop, so fall back to abandoning it
+                warn!("TCL evaluation did not return within {}ms - thread is hung, restarting", hard_timeout.as_millis());
 
                 // Restart the thread
                 if let Err(e) = self.restart() {
                     error!("Failed to restart TCL thread: {}", e);
-                    return Ok(EvalResult {
-                        output: format!("error: timeout and failed to restart: {}", e),
-                        is_error: true,
-                        commit_info: None,
-                    });
+                    return Ok(EvalResult::error(format!("error: timeout and failed to restart: {}", e)));
                 }
 
-                Ok(EvalResult {
-                    output: format!("error: evaluation timed out after {}s (thread restarted)", self.timeout.as_secs()),
-                    is_error: true,
-                    commit_info: None,
-                })
+                Ok(EvalResult::restarted(format!("error: evaluation timed out after {}s (thread restarted)", self.timeout.as_secs())))
             }
         }
     }
@@ -278,7 +408,7 @@ impl TclThreadHandle {
             "system".to_string(),
         ).await?;
 
-        Ok(result.output)
+        Ok(result.display_text())
     }
 
     /// Log a message to the channel history
@@ -297,6 +427,41 @@ impl TclThreadHandle {
         let _ = self.command_tx.send(TclThreadCommand::Reload);
     }
 
+    /// Run a state repository operation on the worker
+    pub async fn state_operation(&mut self, operation: StateOperation) -> Result<String> {
+        let (response_tx, response_rx) = oneshot::channel();
+        if self.command_tx.send(TclThreadCommand::State { operation, response_tx }).is_err() {
+            error!("TCL thread channel closed (thread crashed)");
+            if let Err(e) = self.restart() {
+                error!("Failed to restart TCL thread after crash: {}", e);
+            }
+            return Err(anyhow::anyhow!("TCL thread crashed, restarted"));
+        }
+
+        // Same deadline as evals: the service mutex is held while we wait
+        let hard_timeout = self.timeout + HUNG_THREAD_GRACE;
+        match tokio::time::timeout(hard_timeout, response_rx).await {
+            Ok(Ok(result)) => result.map_err(|e| anyhow::anyhow!(e)),
+            Ok(Err(_)) => {
+                error!("TCL thread died during a state operation");
+                if let Err(e) = self.restart() {
+                    error!("Failed to restart TCL thread after crash: {}", e);
+                }
+                Err(anyhow::anyhow!("TCL thread died during the state operation, restarted"))
+            }
+            Err(_) => {
+                warn!("State operation did not return within {}ms - thread is hung, restarting", hard_timeout.as_millis());
+                if let Err(e) = self.restart() {
+                    error!("Failed to restart TCL thread: {}", e);
+                }
+                Err(anyhow::anyhow!(
+                    "state operation timed out after {}s (thread restarted)",
+                    hard_timeout.as_secs()
+                ))
+            }
+        }
+    }
+
     /// Update runtime configuration
     pub fn update_config(
         &mut self,
@@ -338,13 +503,67 @@ impl Drop for TclThreadHandle {
     }
 }
 
+/// Create a TCL worker and run its command loop on the current thread
+/// Used by the child-process worker (see tcl_process.rs)
+#[cfg(unix)]
+pub(crate) fn run_worker(
+    tcl_config: TclConfig,
+    security_config: crate::config::SecurityConfig,
+    channel_members: ChannelMembers,
+    command_rx: mpsc::Receiver<TclThreadCommand>,
+) -> Result<()> {
+    let worker = TclThreadWorker::new(tcl_config, security_config, channel_members)?;
+    worker.run(command_rx);
+    Ok(())
+}
+
+/// Start the background fetcher of `tcl_config`'s remote, or reuse the
+/// running one, if periodic sync is on; either way it fetches at the
+/// configured interval
+fn start_remote_fetcher(tcl_config: &TclConfig) -> Option<Arc<RemoteFetcher>> {
+    if tcl_config.state_repo.is_none() || tcl_config.sync_interval_secs == 0 {
+        return None;
+    }
+    let interval = Duration::from_secs(tcl_config.sync_interval_secs);
+    let fetcher = remote_sync::fetcher(&StatePersistence::from_config(tcl_config), interval);
+    fetcher.set_interval(interval);
+    Some(fetcher)
+}
+
+/// Whether both configs fetch the same remote into the same repository
+fn same_remote(a: &TclConfig, b: &TclConfig) -> bool {
+    a.state_path == b.state_path
+        && a.state_repo == b.state_repo
+        && a.ssh_key == b.ssh_key
+        && a.state_branch == b.state_branch
+        && a.state_username == b.state_username
+        && a.state_token_file == b.state_token_file
+        && a.state_token_env == b.state_token_env
+        && a.state_credential_helper == b.state_credential_helper
+}
+
 /// Worker that runs in the TCL thread
 struct TclThreadWorker {
     interp: SafeTclInterp,
     tcl_config: TclConfig,
     security_config: crate::config::SecurityConfig,
     timeout: Duration,
+    native_context: SharedNativeContext,
+    /// `tcl_config` as the native commands see it, kept in step on reloads
+    native_config: SharedTclConfig,
     channel_members: ChannelMembers,
+    /// Var changes held back to be committed together
+    coalescer: CommitCoalescer,
+    /// Background fetcher of `state_repo`, if periodic sync is on
+    remote_fetcher: Option<Arc<RemoteFetcher>>,
+    /// When to next look for fetched remote commits
+    next_sync_check: Option<Instant>,
+    /// Merging fetched remote commits failed and is retried at the next check
+    merge_pending: bool,
+    /// Conflicts of the last background merge, so each set is reported once
+    sync_conflicts: Vec<String>,
+    /// Conflict report for the admins, handed out with the next eval result
+    sync_report: Option<String>,
 }
 
 impl TclThreadWorker {
@@ -353,105 +572,74 @@ impl TclThreadWorker {
         security_config: crate::config::SecurityConfig,
         channel_members: ChannelMembers,
     ) -> Result<Self> {
-        let interp = SafeTclInterp::new(
+        let interp = SafeTclInterp::with_persistence(
             security_config.eval_timeout_ms,
-            &tcl_config.state_path,
-            tcl_config.state_repo.clone(),
-            tcl_config.ssh_key.clone(),
+            &StatePersistence::from_config(&tcl_config),
             security_config.max_recursion_depth,
         )?;
 
-        // Register chanlist command
-        Self::register_chanlist_command(interp.interpreter(), channel_members.clone())?;
+        // Register Rust-backed commands (history, rollback, chanlist, stock::*)
+        let native_context = Arc::new(Mutex::new(NativeContext::default()));
+        let native_config = Arc::new(RwLock::new(tcl_config.clone()));
+        native_commands::register(
+            interp.interpreter(),
+            native_config.clone(),
+            channel_members.clone(),
+            native_context.clone(),
+        )?;
 
         let timeout = Duration::from_millis(security_config.eval_timeout_ms);
+        let coalescer = CommitCoalescer::new(tcl_config.commit_coalescing.clone());
+        let remote_fetcher = start_remote_fetcher(&tcl_config);
+        let next_sync_check = remote_fetcher.as_ref().map(|_| Instant::now());
+        if tcl_config.state_repo.is_some() {
+            // Push what an earlier worker left unpushed without waiting for a commit
+            crate::push_queue::queue(&StatePersistence::from_config(&tcl_config)).resume();
+        }
 
         Ok(Self {
             interp,
             tcl_config,
             security_config,
             timeout,
+            native_context,
+            native_config,
             channel_members,
+            coalescer,
+            remote_fetcher,
+            next_sync_check,
+            merge_pending: false,
+            sync_conflicts: Vec::new(),
+            sync_report: None,
         })
     }
 
-    /// Register the chanlist command that reads from the synced channel members array
-    fn register_chanlist_command(
-        interp: &tcl::Interpreter,
-        _channel_members: ChannelMembers,
-    ) -> Result<()> {
-        // Create a proc that reads from the ::slopdrop_channel_members array
-        // This array is synced before each eval by sync_channel_members()
-        interp.eval(r#"
-            # chanlist command - returns list of nicks in a channel
-            # Reads from ::slopdrop_channel_members which is synced before each eval
-            proc chanlist {channel} {
-                if {[info exists ::slopdrop_channel_members($channel)]} {
-                    return $::slopdrop_channel_members($channel)
-                } else {
-                    return ""
-                }
-            }
-        "#).map_err(|e| anyhow::anyhow!("Failed to register chanlist command: {:?}", e))?;
-
-        Ok(())
-    }
-
-    /// Sync channel members from Rust to TCL global array
-    fn sync_channel_members(&self) {
-        match self.channel_members.read() {
-            Ok(members) => {
-                for (channel, nicks) in members.iter() {
-                    if !nicks.is_empty() {
-                        let mut sorted: Vec<_> = nicks.iter().cloned().collect();
-                        sorted.sort();
-
-                        // Escape channel name and nicks for TCL
-                        let escaped_channel = channel
-                            .replace('\\', "\\\\")
-                            .replace('{', "\\{")
-                            .replace('}', "\\}");
-
-                        let escaped_nicks: Vec<String> = sorted.iter()
-                            .map(|n| n
-                                .replace('\\', "\\\\")
-                                .replace('{', "\\{")
-                                .replace('}', "\\}"))
-                            .collect();
-
-                        let tcl_code = format!(
-                            "set ::slopdrop_channel_members({}) {{{}}}",
-                            escaped_channel,
-                            escaped_nicks.join(" ")
-                        );
-
-                        if let Err(e) = self.interp.interpreter().eval(tcl_code.as_str()) {
-                            warn!("Failed to sync channel members for {}: {:?}", channel, e);
-                        }
-                    } else {
-                        // Empty channel - unset if exists
-                        let escaped_channel = channel
-                            .replace('\\', "\\\\")
-                            .replace('{', "\\{")
-                            .replace('}', "\\}");
-                        let unset_cmd = format!(
-                            "catch {{unset ::slopdrop_channel_members({})}}",
-                            escaped_channel
-                        );
-                        let _ = self.interp.interpreter().eval(unset_cmd.as_str());
-                    }
-                }
-            }
-            Err(e) => {
-                warn!("Failed to read channel members: {:?}", e);
-            }
-        }
-    }
-
     fn run(mut self, command_rx: mpsc::Receiver<TclThreadCommand>) {
         info!("TCL thread worker started");
 
-        for command in command_rx {
+        loop {
+            // Wake up for coalesced commits that fall due while idle, and for
+            // remote commits fetched in the background
+            let wake_at = match (self.coalescer.next_due(), self.next_sync_check) {
+                (Some(due), Some(check)) => Some(due.min(check)),
+                (due, check) => due.or(check),
+            };
+            let command = match wake_at {
+                Some(wake_at) => match command_rx.recv_timeout(wake_at.saturating_duration_since(Instant::now())) {
+                    Ok(command) => command,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        self.commit_coalesced(false);
+                        self.merge_fetched();
+                        continue;
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                },
+                None => match command_rx.recv() {
+                    Ok(command) => command,
+                    Err(_) => break,
+                },
+            };
+
             match command {
                 TclThreadCommand::Eval(request) => {
                     self.handle_eval(request);
@@ -462,6 +650,10 @@ impl TclThreadWorker {
                 TclThreadCommand::Reload => {
                     self.handle_reload();
                 }
+                TclThreadCommand::State { operation, response_tx } => {
+                    let result = self.handle_state_operation(operation).map_err(|e| e.to_string());
+                    let _ = response_tx.send(result);
+                }
                 TclThreadCommand::UpdateConfig { tcl_config, security_config } => {
                     self.handle_config_update(tcl_config, security_config);
                 }
@@ -470,6 +662,186 @@ impl TclThreadWorker {
                     break;
                 }
             }
+
+            // A busy bot may never sit idle long enough for the timeout above
+            self.commit_coalesced(false);
+            self.merge_fetched();
+        }
+
+        self.commit_coalesced(true);
+        remote_sync::stop_all();
+
+        // Get the last commits to the remote before the worker goes away
+        if let Err(e) = crate::push_queue::flush_all(crate::push_queue::FLUSH_TIMEOUT) {
+            warn!("Shutting down with unpushed state: {}", e);
+        }
+    }
+
+    /// Commit the coalesced var changes that are due, or all of them with `force`
+    fn commit_coalesced(&mut self, force: bool) {
+        let commits = self.coalescer.due(Instant::now(), force);
+        if commits.is_empty() {
+            return;
+        }
+
+        let persistence = StatePersistence::from_config(&self.tcl_config);
+
+        for commit in commits {
+            match persistence.save_coalesced_changes(
+                self.interp.interpreter(),
+                &commit.changes,
+                &commit.user_info,
+                commit.evals,
+            ) {
+                Ok(Some(commit_info)) => info!(
+                    "Committed coalesced changes of {} from {} evals in {}: {}",
+                    commit.user_info.nick,
+                    commit.evals,
+                    &commit_info.commit_id[..8],
+                    commit.changes.summary()
+                ),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to save coalesced changes of {}: {}", commit.user_info.nick, e),
+            }
+        }
+    }
+
+    /// Merge remote commits the background fetcher brought in, if it's time
+    /// to look, and reload what they changed
+    fn merge_fetched(&mut self) {
+        let Some(fetcher) = self.remote_fetcher.clone() else {
+            return;
+        };
+        let now = Instant::now();
+        if self.next_sync_check.is_some_and(|check| now < check) {
+            return;
+        }
+        self.next_sync_check = Some(now + fetcher.interval());
+
+        // Each new remote tip is merged once; conflicts it leaves wait for
+        // the next tip or a `sync`. Only a merge that failed is retried
+        let fetched = fetcher.take_fetched();
+        if !fetched && !self.merge_pending {
+            return;
+        }
+
+        // Held back var changes go in before a new tip is merged so they take part in it
+        if fetched {
+            self.commit_coalesced(true);
+        }
+
+        let persistence = StatePersistence::from_config(&self.tcl_config);
+        let outcome = match persistence.merge_remote(ConflictResolution::Report) {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                warn!("Failed to merge the state remote: {}", e);
+                self.merge_pending = true;
+                return;
+            }
+        };
+        self.merge_pending = false;
+
+        if outcome.changes.has_changes() {
+            self.reload_state(&outcome.changes);
+            let _ = self.interp.interpreter().eval("::slopdrop::update_var_traces");
+        }
+
+        // Each new set of conflicts goes to the admins
+        if outcome.conflicts.is_empty() {
+            self.sync_conflicts.clear();
+        } else if outcome.conflicts != self.sync_conflicts {
+            warn!("Conflicts merging the state remote: {}", outcome.render());
+            self.sync_report = Some(outcome.render());
+            self.sync_conflicts = outcome.conflicts;
+        }
+    }
+
+    /// Roll back, revert or import, then bring the interpreter in line with
+    /// the new working tree
+    fn handle_state_operation(&mut self, operation: StateOperation) -> Result<String> {
+        let (message, changes) = self.run_state_operation(operation)?;
+        self.reload_state(&changes);
+        let _ = self.interp.interpreter().eval("::slopdrop::update_var_traces");
+        Ok(message)
+    }
+
+    /// Run a state operation on the repository, returning its reply and the
+    /// procs/vars it changed on disk
+    fn run_state_operation(&mut self, operation: StateOperation) -> Result<(String, StateChanges)> {
+        // Held back var changes go in first, so a revert or import builds on them
+        self.commit_coalesced(true);
+
+        let persistence = StatePersistence::from_config(&self.tcl_config);
+        let (message, changes) = match operation {
+            StateOperation::Rollback { commit_hash } => {
+                let changes = persistence.rollback_to(&commit_hash)?;
+                let message = format!(
+                    "Rolled back to commit {}: {}",
+                    commit_hash.get(..8).unwrap_or(commit_hash.as_str()),
+                    changes.summary()
+                );
+                (message, changes)
+            }
+            StateOperation::Revert { commit_hash, nick, host } => {
+                let user_info = UserInfo::new(nick, host);
+                let (commit_info, changes) = persistence.revert_commit(&commit_hash, &user_info)?;
+                let message = format!(
+                    "Reverted {} as {}: {}",
+                    commit_hash.get(..8).unwrap_or(commit_hash.as_str()),
+                    &commit_info.commit_id[..8],
+                    changes.summary()
+                );
+                (message, changes)
+            }
+            StateOperation::Undo { nick, host, author } => {
+                let caller = UserInfo::new(nick, host);
+                let (author, match_host) = match author {
+                    Some(author) => (UserInfo::new(author, String::new()), false),
+                    None => (caller.clone(), true),
+                };
+                let hash = persistence
+                    .last_commit_by(&author, match_host)?
+                    .ok_or_else(|| anyhow::anyhow!("no changes by {} to undo", author.nick))?;
+                let (_, changes) = persistence.revert_commit(&hash, &caller)?;
+                info!("{} undid commit {} by {}", caller.nick, hash, author.nick);
+                (format!("Undid {}: {}", &hash[..8], changes.summary()), changes)
+            }
+            StateOperation::Sync { resolution } => {
+                let outcome = persistence.sync_with_remote(resolution)?;
+                if outcome.incoming > 0 && outcome.conflicts.is_empty() {
+                    info!("Merged {} remote commits", outcome.incoming);
+                }
+                // Whatever is still in conflict waits for the next remote tip
+                // or sync, like after a background merge
+                self.merge_pending = false;
+                self.sync_conflicts = outcome.conflicts.clone();
+                (outcome.render(), outcome.changes)
+            }
+            StateOperation::Import { bundle, replace, nick, host } => {
+                let user_info = UserInfo::new(nick, host);
+                match persistence.import_bundle(&bundle, replace, &user_info)? {
+                    (Some(commit_info), changes) => {
+                        let message = format!("Imported as {}: {}", &commit_info.commit_id[..8], changes.summary());
+                        (message, changes)
+                    }
+                    (None, _) => ("Nothing to import: the state already matches".to_string(), StateChanges::default()),
+                }
+            }
+        };
+
+        Ok((message, changes))
+    }
+
+    /// Reload procs/vars from the state directory after it was rolled back
+    fn reload_state(&mut self, changes: &StateChanges) {
+        // Whatever was held back for these is superseded by the reload
+        self.coalescer.forget(changes);
+
+        let persistence = StatePersistence::from_config(&self.tcl_config);
+
+        match persistence.reload_changes(self.interp.interpreter(), changes) {
+            Ok(()) => info!("Reloaded state after rollback ({})", changes.summary()),
+            Err(e) => error!("Failed to reload state after rollback: {}", e),
         }
     }
 
@@ -483,11 +855,35 @@ impl TclThreadWorker {
 
     fn handle_config_update(
         &mut self,
-        _tcl_config: TclConfig,
+        tcl_config: TclConfig,
         security_config: crate::config::SecurityConfig,
     ) {
         info!("Updating runtime configuration");
 
+        // Held back var changes belong to the repository they were made against
+        self.commit_coalesced(true);
+
+        // A fetcher keeps the remote it was started for; a new interval
+        // applies to the running one straight away
+        let syncing = tcl_config.state_repo.is_some() && tcl_config.sync_interval_secs > 0;
+        if !syncing || !same_remote(&self.tcl_config, &tcl_config) {
+            if self.remote_fetcher.take().is_some() {
+                remote_sync::stop(&self.tcl_config.state_path);
+            }
+            self.merge_pending = false;
+            self.sync_conflicts.clear();
+        }
+        self.remote_fetcher = start_remote_fetcher(&tcl_config);
+        self.next_sync_check = self.remote_fetcher.as_ref().map(|_| Instant::now());
+
+        // State settings (path, remote, branch, ...) apply from the next eval,
+        // including to the native commands
+        match self.native_config.write() {
+            Ok(mut config) => *config = tcl_config.clone(),
+            Err(poisoned) => *poisoned.into_inner() = tcl_config.clone(),
+        }
+        self.tcl_config = tcl_config;
+
         // Update timeout for evaluations
         self.timeout = Duration::from_millis(security_config.eval_timeout_ms);
         info!("  Eval timeout updated to {}ms", security_config.eval_timeout_ms);
@@ -564,7 +960,7 @@ impl TclThreadWorker {
         }
     }
 
-    fn handle_eval(&self, request: EvalRequest) {
+    fn handle_eval(&mut self, request: EvalRequest) {
         debug!("TCL thread evaluating: {}", request.code);
 
         // Check privilege level using hostmask matching
@@ -579,17 +975,19 @@ impl TclThreadWorker {
             });
 
             if !is_privileged {
-                let _ = request.response_tx.send(EvalResult {
-                    output: format!("error: tclAdmin requires privileges (your hostmask: {})", hostmask),
-                    is_error: true,
-                    commit_info: None,
-                });
+                let _ = request.response_tx.send(EvalResult::error(format!("error: tclAdmin requires privileges (your hostmask: {})", hostmask)));
                 return;
             }
         }
 
+        if let EvalMode::At(rev) = &request.mode {
+            let rev = rev.clone();
+            self.handle_eval_at(request, &rev);
+            return;
+        }
+
         // Get eval count for rate limiting (needed for all commands)
-        let eval_count_result = self.interp.interpreter().eval("::httpx::increment_eval");
+        let eval_count_result = self.interp.interpreter().master().eval("::httpx::increment_eval");
         let eval_count = eval_count_result
             .ok()
             .and_then(|obj| obj.get_string().parse::<u64>().ok())
@@ -602,61 +1000,78 @@ impl TclThreadWorker {
         // Set stock context for rate limiting
         crate::stock_commands::set_stock_context(request.nick.clone(), eval_count);
 
-        // Sync channel members to TCL array before evaluation
-        self.sync_channel_members();
-
-        // Check for special commands
-        let code_trimmed = request.code.trim();
-        if code_trimmed == "history" || code_trimmed.starts_with("history ") {
-            self.handle_history_command(request);
-            return;
-        }
-        if code_trimmed.starts_with("rollback ") {
-            self.handle_rollback_command(request);
-            return;
-        }
-        if code_trimmed.starts_with("chanlist ") {
-            self.handle_chanlist_command(request);
-            return;
-        }
-        // Intercept stock commands that need Rust backend
-        if code_trimmed.starts_with("stock::quote ")
-            || code_trimmed.starts_with("stock::price ")
-            || code_trimmed.starts_with("stock::detail ")
-            || code_trimmed.starts_with("stock::history ")
-            || code_trimmed.starts_with("stock::chart ")
-        {
-            self.handle_stock_command(request);
-            return;
+        // Native commands read who is calling from here
+        if let Ok(mut context) = self.native_context.lock() {
+            *context = NativeContext {
+                nick: request.nick.clone(),
+                host: request.host.clone(),
+                is_admin: request.is_admin,
+                dry_run: request.mode == EvalMode::DryRun,
+                pending_reload: None,
+                pending_review: None,
+                pending_operation: None,
+            };
         }
 
         // Capture state before evaluation
         let state_before = InterpreterState::capture(self.interp.interpreter());
 
-        // Evaluate the code
-        let result = if request.is_admin {
-            self.interp.eval(&request.code)
-        } else {
-            self.interp.eval_with_context(
-                &request.code,
-                &request.nick,
-                &request.host,
-                &request.channel,
-            )
-        };
+        // Transactional evals journal changes so a failure can be undone;
+        // scripts can also opt in themselves with `transaction on`.
+        // A dry run is a transaction that is always rolled back
+        let dry_run = request.mode == EvalMode::DryRun;
+        if dry_run || self.tcl_config.transactional_evals {
+            if let Err(e) = self.interp.begin_transaction(dry_run) {
+                warn!("{}", e);
+            }
+        }
 
-        let output = match result {
-            Ok(output) => EvalResult {
-                output,
-                is_error: false,
-                commit_info: None,
-            },
-            Err(e) => EvalResult {
-                output: format!("error: {}", e),
-                is_error: true,
-                commit_info: None,
-            },
-        };
+        let mut output = self.eval_limited(&self.interp, &request);
+
+        // Undo a failed transaction before the state diff sees its changes.
+        // Runs after disarm so a cancelled script's limits don't block it
+        // Dry runs are rolled back further down, once the changes have been read
+        if !dry_run {
+            if output.is_error {
+                match self.interp.rollback_transaction() {
+                    Ok(rolled_back) => output.rolled_back = rolled_back,
+                    Err(e) => warn!("{}", e),
+                }
+            } else {
+                self.interp.finish_transaction();
+            }
+        }
+
+        // A `rollback` during the eval reset the repository; bring the
+        // interpreter in line with it (its changes aren't recommitted below).
+        // The eval is over, so the context is cleared: procs reloaded from
+        // here on are loaded by the bot, not the caller
+        let context = self
+            .native_context
+            .lock()
+            .map(|mut context| std::mem::take(&mut *context))
+            .unwrap_or_default();
+        let mut reload = context.pending_reload;
+        let mut refused = context.pending_review;
+
+        // A `rollback`, `revert` or `undo` runs now, once held back var
+        // changes are committed; a failed eval doesn't run it
+        if let Some(operation) = context.pending_operation.filter(|_| !output.is_error) {
+            match self.run_state_operation(operation) {
+                Ok((message, changes)) => {
+                    output.output = message;
+                    reload.get_or_insert_with(StateChanges::default).merge(changes);
+                }
+                Err(e) => {
+                    output.output = e.to_string();
+                    output.is_error = true;
+                }
+            }
+        }
+
+        if let Some(reload) = &reload {
+            self.reload_state(reload);
+        }
 
         // Update var traces AFTER eval to catch any new variables that were created
         // This is more efficient than checking all 1000+ vars before every eval
@@ -664,7 +1079,6 @@ impl TclThreadWorker {
         let _ = self.interp.interpreter().eval("::slopdrop::update_var_traces");
 
         // Capture state after and save if changed
-        let mut output = output;
         if let Ok(state_after) = InterpreterState::capture(self.interp.interpreter()) {
             if let Ok(state_before) = state_before {
                 // Get list of procs and vars that were modified during this eval
@@ -678,7 +1092,10 @@ impl TclThreadWorker {
                 debug!("State before vars: {} procs: {}", state_before.vars.len(), state_before.procs.len());
                 debug!("State after vars: {} procs: {}", state_after.vars.len(), state_after.procs.len());
 
-                let changes = state_before.diff(&state_after, &modified_procs, &modified_vars);
+                let mut changes = state_before.diff(&state_after, &modified_procs, &modified_vars);
+                if let Some(reload) = &reload {
+                    changes.exclude(reload);
+                }
 
                 debug!("Changes detected: new_procs={}, new_vars={}, deleted_procs={}, deleted_vars={}",
                     changes.new_procs.len(), changes.new_vars.len(),
@@ -688,29 +1105,74 @@ impl TclThreadWorker {
                 // Only persist state changes from actual user interactions
                 let is_system_eval = request.nick == "system";
 
-                if changes.has_changes() && !is_system_eval {
-                    debug!("State changed: {:?}", changes);
-
-                    let user_info = UserInfo::new(request.nick.clone(), request.host.clone());
-                    let persistence = StatePersistence::with_repo(
-                        self.tcl_config.state_path.clone(),
-                        self.tcl_config.state_repo.clone(),
-                        self.tcl_config.ssh_key.clone(),
-                    );
-
-                    match persistence.save_changes(
-                        self.interp.interpreter(),
-                        &changes,
-                        &user_info,
-                        &request.code,
-                    ) {
-                        Ok(commit_info) => {
-                            debug!("State saved successfully");
-                            output.commit_info = commit_info;
+                let persistence = StatePersistence::from_config(&self.tcl_config);
+                let user_info = UserInfo::new(request.nick.clone(), request.host.clone());
+
+                // Changes to other users' protected procs aren't committed:
+                // they are queued for admin review. `proc` and `rename` refused
+                // them already; anything else that changed such a proc, as
+                // told by its stored definition, is put back
+                if !dry_run {
+                    let interp = self.interp.interpreter();
+                    let mut held = persistence.proc_owners().denied_changes(&changes, &user_info, request.is_admin);
+                    match persistence.changed_protected_procs(interp, &state_after.procs, &user_info, request.is_admin) {
+                        Ok(names) => {
+                            for name in names {
+                                if !held.new_procs.contains(&name) {
+                                    held.new_procs.push(name);
+                                }
+                            }
                         }
-                        Err(e) => {
-                            warn!("Failed to save state: {}", e);
+                        Err(e) => warn!("Failed to check protected procs: {}", e),
+                    }
+
+                    if held.has_changes() || refused.is_some() {
+                        if is_system_eval {
+                            warn!("Undoing system changes to protected procs: {}", held.summary());
+                        } else {
+                            let submission = StatePersistence::held_changes(interp, &held).map(|mut submission| {
+                                if let Some(refused) = refused.take() {
+                                    submission.merge(refused);
+                                }
+                                submission
+                            });
+                            match submission.and_then(|submission| {
+                                persistence.submit_for_review(&submission, &user_info, &request.code)
+                            }) {
+                                Ok(review) => output.review = Some(review),
+                                Err(e) => warn!("Failed to queue change by {} for review: {}", request.nick, e),
+                            }
                         }
+                        changes.exclude(&held);
+                        self.reload_state(&held);
+                    }
+                }
+
+                if dry_run {
+                    match persistence.preview_changes(self.interp.interpreter(), &changes, &request.code) {
+                        Ok(report) => output.dry_run = Some(report),
+                        Err(e) => warn!("Failed to preview state changes: {}", e),
+                    }
+                } else if changes.has_changes() && !is_system_eval {
+                    debug!("State changed: {:?}", changes);
+
+                    // Changes to hot vars may be held back and committed later
+                    match self.coalescer.submit(changes, &user_info, Instant::now()) {
+                        None => debug!("Holding back var changes of {} for a coalesced commit", request.nick),
+                        Some(changes) => match persistence.save_changes(
+                            self.interp.interpreter(),
+                            &changes,
+                            &user_info,
+                            &request.code,
+                        ) {
+                            Ok(commit_info) => {
+                                debug!("State saved successfully");
+                                output.commit_info = commit_info;
+                            }
+                            Err(e) => {
+                                warn!("Failed to save state: {}", e);
+                            }
+                        },
                     }
                 } else if changes.has_changes() && is_system_eval {
                     debug!("Skipping state persistence for system eval (nick={})", request.nick);
@@ -721,269 +1183,123 @@ impl TclThreadWorker {
             }
         }
 
+        if dry_run {
+            if let Err(e) = self.interp.rollback_transaction() {
+                warn!("{}", e);
+            }
+        }
+
+        output.sync_conflicts = self.sync_report.take();
+
         // Send response back
         let _ = request.response_tx.send(output);
     }
 
-    fn handle_history_command(&self, request: EvalRequest) {
-        let code = request.code.trim();
-
-        // Parse count from "history" or "history <count>"
-        let count = if code == "history" {
-            10 // default
-        } else if let Some(count_str) = code.strip_prefix("history ") {
-            count_str.trim().parse::<usize>().unwrap_or(10)
-        } else {
-            10
+    /// Evaluate in a throwaway interpreter holding the state as of `rev`
+    /// Nothing is tracked, committed or carried over to the live interpreter
+    fn handle_eval_at(&self, request: EvalRequest, rev: &str) {
+        let output = match self.historical_interp(&request, rev) {
+            Ok(interp) => self.eval_limited(&interp, &request),
+            Err(e) => EvalResult::error(format!("error: {}", e)),
         };
 
-        let persistence = StatePersistence::with_repo(
-            self.tcl_config.state_path.clone(),
-            self.tcl_config.state_repo.clone(),
-            self.tcl_config.ssh_key.clone(),
-        );
-
-        match persistence.get_history(count) {
-            Ok(commits) => {
-                if commits.is_empty() {
-                    let _ = request.response_tx.send(EvalResult {
-                        output: "No commits found".to_string(),
-                        is_error: false,
-                        commit_info: None,
-                    });
-                    return;
-                }
+        let _ = request.response_tx.send(output);
+    }
 
-                // Format commits as TCL list
-                let mut output = String::new();
-                for (hash, timestamp, author, message) in commits {
-                    // Format: {hash timestamp author message}
-                    let date = chrono::DateTime::from_timestamp(timestamp, 0)
-                        .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
-                        .unwrap_or_else(|| timestamp.to_string());
+    /// Fresh interpreter loaded from `rev`, with the native commands registered
+    fn historical_interp(&self, request: &EvalRequest, rev: &str) -> Result<SafeTclInterp> {
+        let persistence = StatePersistence::from_config(&self.tcl_config);
+        let snapshot = persistence.snapshot(rev)?;
+        let interp = SafeTclInterp::from_snapshot(
+            self.security_config.eval_timeout_ms,
+            &snapshot,
+            self.security_config.max_recursion_depth,
+        )?;
 
-                    output.push_str(&format!("{} {} {} {}\n",
-                        &hash[..8], date, author, message));
-                }
+        // Native commands work, but anything that writes to the repository is
+        // refused the same way it is in a dry run
+        let context = Arc::new(Mutex::new(NativeContext {
+            nick: request.nick.clone(),
+            host: request.host.clone(),
+            is_admin: request.is_admin,
+            dry_run: true,
+            pending_reload: None,
+            pending_review: None,
+            pending_operation: None,
+        }));
+        native_commands::register(
+            interp.interpreter(),
+            Arc::new(RwLock::new(self.tcl_config.clone())),
+            self.channel_members.clone(),
+            context,
+        )?;
 
-                let _ = request.response_tx.send(EvalResult {
-                    output: output.trim_end().to_string(),
-                    is_error: false,
-                    commit_info: None,
-                });
-            }
-            Err(e) => {
-                let _ = request.response_tx.send(EvalResult {
-                    output: format!("error: {}", e),
-                    is_error: true,
-                    commit_info: None,
-                });
-            }
-        }
+        info!("Evaluating against state as of commit {}", snapshot.commit_id);
+        Ok(interp)
     }
 
-    fn handle_rollback_command(&self, request: EvalRequest) {
-        // Rollback is admin-only
-        if !request.is_admin {
-            let _ = request.response_tx.send(EvalResult {
-                output: "error: rollback requires admin privileges (use tclAdmin)".to_string(),
-                is_error: true,
-                commit_info: None,
-            });
-            return;
-        }
+    /// Evaluate the code under interpreter limits so runaway scripts are
+    /// cancelled by TCL itself instead of hanging the thread
+    fn eval_limited(&self, interp: &SafeTclInterp, request: &EvalRequest) -> EvalResult {
+        interp.set_caller(&request.nick, &request.host, &request.channel);
 
-        let code = request.code.trim();
+        let limits = EvalLimits::new(self.timeout, self.security_config.max_eval_commands);
+        let commands_before = eval_limits::command_count(interp.interpreter());
+        let cpu_before = thread_cpu_time();
+        let started = Instant::now();
+        let armed = eval_limits::arm(interp.interpreter(), &limits);
 
-        // Parse commit hash from "rollback <hash>"
-        let hash = if let Some(h) = code.strip_prefix("rollback ") {
-            h.trim()
+        let result = if request.is_admin {
+            interp.eval(&request.code)
         } else {
-            let _ = request.response_tx.send(EvalResult {
-                output: "error: usage: rollback <commit-hash>".to_string(),
-                is_error: true,
-                commit_info: None,
-            });
-            return;
+            interp.eval_with_context(
+                &request.code,
+                &request.nick,
+                &request.host,
+                &request.channel,
+            )
         };
 
-        if hash.is_empty() {
-            let _ = request.response_tx.send(EvalResult {
-                output: "error: usage: rollback <commit-hash>".to_string(),
-                is_error: true,
-                commit_info: None,
-            });
-            return;
-        }
-
-        let persistence = StatePersistence::with_repo(
-            self.tcl_config.state_path.clone(),
-            self.tcl_config.state_repo.clone(),
-            self.tcl_config.ssh_key.clone(),
-        );
-
-        match persistence.rollback_to(hash) {
-            Ok(()) => {
-                // After rollback, state files have been reset via git
-                // The TCL interpreter still has old state in memory
-                // Restarting the bot (or just the TCL thread) loads fresh state from disk
-                // Since rollback is an admin-only operation rarely used, manual restart is acceptable
-                let _ = request.response_tx.send(EvalResult {
-                    output: format!("Rolled back to commit {}. Note: Restart bot to reload state.", hash),
-                    is_error: false,
-                    commit_info: None,
-                });
+        let wall_time = started.elapsed();
+        let cpu_time = thread_cpu_time()
+            .zip(cpu_before)
+            .map(|(after, before)| after.saturating_sub(before));
+
+        let error = result.as_ref().err().map(|e| e.to_string());
+        let exceeded = eval_limits::disarm(interp.interpreter(), armed, error.as_deref());
+        let commands = eval_limits::command_count(interp.interpreter()).saturating_sub(commands_before);
+        let tcl_error = result
+            .as_ref()
+            .err()
+            .and_then(|e| e.downcast_ref::<TclError>())
+            .cloned();
+
+        let mut output = match (exceeded, result) {
+            (Some(LimitExceeded::Time), _) => {
+                warn!("TCL evaluation cancelled after {}ms (time limit)", self.timeout.as_millis());
+                EvalResult::error(format!("error: evaluation timed out after {}s", self.timeout.as_secs()))
             }
-            Err(e) => {
-                let _ = request.response_tx.send(EvalResult {
-                    output: format!("error: {}", e),
-                    is_error: true,
-                    commit_info: None,
-                });
+            (Some(LimitExceeded::Commands), _) => {
+                warn!("TCL evaluation cancelled (command limit {})", limits.max_commands);
+                EvalResult::error(format!("error: command limit exceeded (max {} commands)", limits.max_commands))
             }
-        }
-    }
-
-    fn handle_chanlist_command(&self, request: EvalRequest) {
-        let code = request.code.trim();
-
-        // Parse channel from "chanlist <channel>"
-        let channel = if let Some(ch) = code.strip_prefix("chanlist ") {
-            ch.trim()
-        } else {
-            let _ = request.response_tx.send(EvalResult {
-                output: "error: usage: chanlist <channel>".to_string(),
-                is_error: true,
-                commit_info: None,
-            });
-            return;
+            (None, Ok(output)) => EvalResult::ok(output),
+            (None, Err(e)) => EvalResult::error(format!("error: {}", e)),
         };
 
-        if channel.is_empty() {
-            let _ = request.response_tx.send(EvalResult {
-                output: "error: usage: chanlist <channel>".to_string(),
-                is_error: true,
-                commit_info: None,
-            });
-            return;
-        }
-
-        // Read from shared channel members
-        match self.channel_members.read() {
-            Ok(members) => {
-                if let Some(nicks) = members.get(channel) {
-                    if nicks.is_empty() {
-                        let _ = request.response_tx.send(EvalResult {
-                            output: String::new(),
-                            is_error: false,
-                            commit_info: None,
-                        });
-                    } else {
-                        let mut sorted: Vec<_> = nicks.iter().cloned().collect();
-                        sorted.sort();
-                        let _ = request.response_tx.send(EvalResult {
-                            output: sorted.join(" "),
-                            is_error: false,
-                            commit_info: None,
-                        });
-                    }
-                } else {
-                    // Channel not found - return empty list
-                    let _ = request.response_tx.send(EvalResult {
-                        output: String::new(),
-                        is_error: false,
-                        commit_info: None,
-                    });
-                }
-            }
-            Err(e) => {
-                let _ = request.response_tx.send(EvalResult {
-                    output: format!("error: failed to read channel members: {}", e),
-                    is_error: true,
-                    commit_info: None,
-                });
-            }
+        if let Some(tcl_error) = tcl_error {
+            output.error_code = Some(tcl_error.error_code);
+            output.error_info = Some(tcl_error.error_info);
         }
-    }
-
-    fn handle_stock_command(&self, request: EvalRequest) {
-        let code = request.code.trim();
-
-        // Special handling for stock::chart - needs to call TCL code with data
-        if code.starts_with("stock::chart ") {
-            let parts: Vec<&str> = code.split_whitespace().collect();
-            if parts.len() < 2 {
-                let _ = request.response_tx.send(EvalResult {
-                    output: "error: Usage: stock::chart <symbol> [days] [interval]".to_string(),
-                    is_error: true,
-                    commit_info: None,
-                });
-                return;
-            }
-
-            let symbol = parts[1];
-            let days = if parts.len() > 2 {
-                parts[2].parse::<usize>().unwrap_or(7)
-            } else {
-                7
-            };
-
-            // Get optional interval (e.g., "1m", "5m", "1h", "1d")
-            let interval_arg = if parts.len() > 3 {
-                format!(" {}", parts[3])
-            } else {
-                String::new()
-            };
+        output.stats = EvalStats {
+            wall_time_us: wall_time.as_micros() as u64,
+            cpu_time_us: cpu_time.map(|t| t.as_micros() as u64),
+            commands,
+        };
 
-            // Get historical data from Rust backend
-            match crate::stock_commands::handle_stock_command(&format!("stock::history {} {}{}", symbol, days, interval_arg)) {
-                Ok(history_data) => {
-                    // Call TCL chart_from_data with the history
-                    let tcl_code = format!("stock::chart_from_data {{{}}} {{{}}}", symbol, history_data);
-                    match self.interp.interpreter().eval(tcl_code.as_str()) {
-                        Ok(result) => {
-                            let _ = request.response_tx.send(EvalResult {
-                                output: result.to_string(),
-                                is_error: false,
-                                commit_info: None,
-                            });
-                        }
-                        Err(e) => {
-                            let _ = request.response_tx.send(EvalResult {
-                                output: format!("error: Failed to generate chart: {:?}", e),
-                                is_error: true,
-                                commit_info: None,
-                            });
-                        }
-                    }
-                }
-                Err(e) => {
-                    let _ = request.response_tx.send(EvalResult {
-                        output: format!("error: {}", e),
-                        is_error: true,
-                        commit_info: None,
-                    });
-                }
-            }
-            return;
-        }
+        // Attach whatever the script printed, even if it was cancelled
+        output.captured_output = interp.take_output();
 
-        // Call the stock command handler from stock_commands module
-        match crate::stock_commands::handle_stock_command(code) {
-            Ok(output) => {
-                let _ = request.response_tx.send(EvalResult {
-                    output,
-                    is_error: false,
-                    commit_info: None,
-                });
-            }
-            Err(e) => {
-                let _ = request.response_tx.send(EvalResult {
-                    output: format!("error: {}", e),
-                    is_error: true,
-                    commit_info: None,
-                });
-            }
-        }
+        output
     }
 }
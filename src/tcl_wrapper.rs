@@ -1,8 +1,251 @@
+use crate::state::{CommitSnapshot, StatePersistence, StateSource};
 use anyhow::{anyhow, Result};
 use regex::Regex;
 use std::path::{Path, PathBuf};
 use tcl::Interpreter;
-use tracing::debug;
+use tracing::{debug, warn};
+
+/// Name of the safe child interpreter that user code runs in
+pub const SANDBOX_NAME: &str = "sandbox";
+
+/// Master-side helpers for exposing vetted commands to the sandbox
+///
+/// Exposed commands run in the master, where the caller's context variables
+/// (nick, channel, ...) are set by `SafeTclInterp::set_caller` before each
+/// eval, so e.g. the HTTP rate limiter knows who is calling. They are never
+/// read back from the sandbox, where scripts can overwrite them.
+const SANDBOX_BRIDGE: &str = r#"
+namespace eval ::slopdrop {}
+
+proc ::slopdrop::sandbox_call {cmd args} {
+    uplevel #0 [list $cmd {*}$args]
+}
+
+# {arglist body} of the sandbox proc `name`, with defaults, or {} if it isn't one
+proc ::slopdrop::sandbox_proc {name} {
+    interp eval sandbox [list apply {{name} {
+        if {[catch {info args $name} arglist]} {
+            return {}
+        }
+        set params [list]
+        foreach arg $arglist {
+            if {[info default $name $arg default]} {
+                lappend params [list $arg $default]
+            } else {
+                lappend params $arg
+            }
+        }
+        return [list $params [info body $name]]
+    }} $name]
+}
+
+# Alias $cmd in the sandbox to a master command prefix (defaults to $cmd)
+proc ::slopdrop::expose {cmd args} {
+    if {![llength $args]} {
+        set args [list $cmd]
+    }
+    set ns [namespace qualifiers $cmd]
+    if {$ns ne ""} {
+        interp eval sandbox [list namespace eval $ns {}]
+    }
+    interp alias sandbox $cmd {} ::slopdrop::sandbox_call {*}$args
+}
+
+proc ::slopdrop::expose_capabilities {} {
+    # HTTP entry points only: rate limiting, SSRF checks and their
+    # bookkeeping stay in the master. The sandbox keeps its own safe clock
+    foreach cmd {
+        ::httpx::http_get ::httpx::http_post ::httpx::http_head
+        ::http ::http::get ::http::post ::http::head ::sha1
+    } {
+        if {[llength [info commands $cmd]]} {
+            ::slopdrop::expose $cmd
+        }
+    }
+
+    # Read-only snapshot of the HTTP limits so scripts can inspect them
+    foreach var [info vars ::httpx::*] {
+        if {[info exists $var] && ![array exists $var]} {
+            interp eval sandbox [list set $var [set $var]]
+        }
+    }
+}
+"#;
+
+/// Maximum size of output shown for one evaluation
+/// Also caps how much `puts` output is buffered per eval
+pub const MAX_OUTPUT_BYTES: usize = 100_000;
+
+/// Master-side buffer for `puts` output from the sandbox
+///
+/// The sandbox's `puts` is hidden and aliased to `capture_puts`, so writes to
+/// stdout/stderr end up in the eval result instead of the bot's terminal.
+/// Other channels (e.g. ones the script created itself) use the real `puts`.
+const OUTPUT_CAPTURE: &str = r#"
+namespace eval ::slopdrop {
+    variable output ""
+    variable output_truncated 0
+}
+
+proc ::slopdrop::capture_puts {args} {
+    set newline 1
+    if {[lindex $args 0] eq "-nonewline"} {
+        set newline 0
+        set args [lrange $args 1 end]
+    }
+    switch [llength $args] {
+        1 {
+            set chan stdout
+            set text [lindex $args 0]
+        }
+        2 {
+            lassign $args chan text
+        }
+        default {
+            return -code error {wrong # args: should be "puts ?-nonewline? ?channelId? string"}
+        }
+    }
+
+    if {$chan ni {stdout stderr}} {
+        set cmd [list puts]
+        if {!$newline} {
+            lappend cmd -nonewline
+        }
+        return [interp invokehidden sandbox {*}$cmd $chan $text]
+    }
+
+    if {$newline} {
+        append text \n
+    }
+    variable output
+    variable output_limit
+    set room [expr {$output_limit - [string length $output]}]
+    if {[string length $text] > $room} {
+        append output [string range $text 0 [expr {$room - 1}]]
+        variable output_truncated 1
+        return
+    }
+    append output $text
+    return
+}
+
+proc ::slopdrop::reset_output {} {
+    variable output ""
+    variable output_truncated 0
+}
+
+proc ::slopdrop::take_output {} {
+    variable output
+    variable output_truncated
+    set result $output
+    if {$output_truncated} {
+        append result "\n... (output truncated)"
+    }
+    reset_output
+    return $result
+}
+"#;
+
+/// Quote a string as a single TCL word using backslash escapes
+/// Safe for arbitrary content, unlike wrapping in braces
+pub fn tcl_quote(s: &str) -> String {
+    if s.is_empty() {
+        return "{}".to_string();
+    }
+
+    let mut quoted = String::with_capacity(s.len() + 8);
+    for c in s.chars() {
+        match c {
+            '\\' | '{' | '}' | '[' | ']' | '$' | '"' | ';' | ' ' | '#' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\x0b' => quoted.push_str("\\v"),
+            '\x0c' => quoted.push_str("\\f"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted
+}
+
+/// Format strings as a TCL list, bracing elements where that's enough
+/// Falls back to backslash quoting for elements with unbalanced braces
+pub fn tcl_list<S: AsRef<str>>(items: &[S]) -> String {
+    items
+        .iter()
+        .map(|item| tcl_list_element(item.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn tcl_list_element(s: &str) -> String {
+    let needs_quoting = s.is_empty()
+        || s.starts_with('#')
+        || s.chars().any(|c| matches!(c, ' ' | '\t' | '\n' | '\r' | '{' | '}' | '[' | ']' | '$' | '"' | ';' | '\\'));
+    if !needs_quoting {
+        return s.to_string();
+    }
+
+    let mut depth = 0i32;
+    let balanced = s.chars().all(|c| {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        depth >= 0
+    }) && depth == 0;
+
+    if balanced && !s.contains('\\') {
+        format!("{{{}}}", s)
+    } else {
+        tcl_quote(s)
+    }
+}
+
+/// Something TCL code can be evaluated in: a plain interpreter or the sandbox
+/// Lets state capture work the same on both
+pub trait TclEval {
+    fn eval_tcl(&self, code: &str) -> std::result::Result<tcl::Obj, String>;
+}
+
+impl TclEval for Interpreter {
+    fn eval_tcl(&self, code: &str) -> std::result::Result<tcl::Obj, String> {
+        self.eval(code).map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// The safe child interpreter (`interp create -safe`) user code runs in
+///
+/// Owns the master interpreter; everything evaluated through `eval` runs
+/// inside the sandbox, while `master()` gives access to the trusted side.
+pub struct TclSandbox {
+    master: Interpreter,
+}
+
+impl TclSandbox {
+    /// Evaluate TCL code inside the sandbox
+    pub fn eval(&self, code: &str) -> std::result::Result<tcl::Obj, String> {
+        let script = format!("interp eval {} {}", SANDBOX_NAME, tcl_quote(code));
+        self.master
+            .eval(script.as_str())
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    /// The trusted master interpreter (never expose this to user code)
+    pub fn master(&self) -> &Interpreter {
+        &self.master
+    }
+}
+
+impl TclEval for TclSandbox {
+    fn eval_tcl(&self, code: &str) -> std::result::Result<tcl::Obj, String> {
+        self.eval(code)
+    }
+}
 
 /// Sanitize error messages to prevent information disclosure
 /// Removes filesystem paths and other sensitive information
@@ -27,35 +270,125 @@ fn sanitize_error_message(error_msg: &str) -> String {
     sanitized
 }
 
+/// A failed evaluation, with TCL's error details
+#[derive(Debug, Clone)]
+pub struct TclError {
+    /// The error message (the script's result)
+    pub message: String,
+    /// TCL `errorCode`, e.g. "TCL LOOKUP COMMAND foo" or "NONE"
+    pub error_code: String,
+    /// Full TCL `errorInfo` stack trace
+    pub error_info: String,
+}
+
+impl std::fmt::Display for TclError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TCL Error: {}", self.error_info)
+    }
+}
+
+impl std::error::Error for TclError {}
+
 /// Wrapper around a TCL interpreter with safety features
 /// Note: This is not Send/Sync due to TCL interpreter limitations
 /// It should be created and used within a single thread
 pub struct SafeTclInterp {
-    interpreter: Interpreter,
+    sandbox: TclSandbox,
     _timeout_ms: u64,
 }
 
 impl SafeTclInterp {
-    /// Get a reference to the underlying interpreter
-    pub fn interpreter(&self) -> &Interpreter {
-        &self.interpreter
+    /// Get a reference to the sandbox holding user procs and vars
+    pub fn interpreter(&self) -> &TclSandbox {
+        &self.sandbox
     }
 }
 
 impl SafeTclInterp {
     /// Create a new safe TCL interpreter
+    #[allow(dead_code)]
     pub fn new(timeout_ms: u64, state_path: &Path, state_repo: Option<String>, ssh_key: Option<PathBuf>, max_recursion_depth: u32) -> Result<Self> {
-        // Create a new TCL interpreter (safe mode will be applied next)
-        let interpreter = Interpreter::new().map_err(|e| anyhow!("Failed to create TCL interpreter: {:?}", e))?;
+        let persistence = StatePersistence::with_repo(state_path.to_path_buf(), state_repo, ssh_key);
+        Self::with_persistence(timeout_ms, &persistence, max_recursion_depth)
+    }
 
-        // Set recursion limit (0 = no limit, use TCL default)
-        if max_recursion_depth > 0 {
-            let recursion_cmd = format!("interp recursionlimit {{}} {}", max_recursion_depth);
-            interpreter.eval(recursion_cmd.as_str())
-                .map_err(|e| anyhow!("Failed to set recursion limit: {:?}", e))?;
-            debug!("Set TCL recursion limit to {}", max_recursion_depth);
+    /// Create a new safe TCL interpreter with the state `persistence`
+    /// manages, cloning it with the persistence's credentials if needed
+    pub fn with_persistence(timeout_ms: u64, persistence: &StatePersistence, max_recursion_depth: u32) -> Result<Self> {
+        let interpreter = Self::create_sandbox(max_recursion_depth)?;
+        let state_path = persistence.state_path();
+
+        // Ensure state directory exists and git repo is initialized
+        // If state_repo is set and state doesn't exist, clone from remote
+        // Otherwise create empty repo if needed
+        if !state_path.exists() {
+            debug!("State path doesn't exist, initializing: {:?}", state_path);
+            // This will clone from remote or create directory structure
+            if let Err(e) = persistence.ensure_initialized() {
+                debug!("Failed to initialize state directory: {}. Will be created on first save.", e);
+            }
         }
 
+        // Load state if it exists
+        if state_path.exists() {
+            // Repos from before namespace tracking get their namespace list first
+            if let Err(e) = persistence.migrate_namespace_index() {
+                debug!("Failed to migrate state repository: {}", e);
+            }
+
+            debug!("Loading TCL state from {:?}", state_path);
+            let problems = Self::load_state(&interpreter, state_path)?;
+            if !problems.is_empty() {
+                warn!(
+                    "{} problems loading TCL state from {:?}; procs/vars are missing. Run `slopdrop --fsck` for a report and `--fsck --repair` to fix:",
+                    problems.len(),
+                    state_path
+                );
+                for problem in &problems {
+                    warn!("  {}", problem);
+                }
+            }
+
+            // Clear the modified procs/vars list after loading state
+            // State loading triggers the proc wrapper, but these are initialization procs
+            // not actual modifications, so we clear the tracking lists
+            debug!("Clearing modified tracking after state load");
+            let _ = interpreter.eval("set ::slopdrop_modified_procs [list]");
+            let _ = interpreter.eval("set ::slopdrop_modified_vars [list]");
+        }
+
+        Ok(Self {
+            sandbox: interpreter,
+            _timeout_ms: timeout_ms,
+        })
+    }
+
+    /// Create a throwaway interpreter holding the state as of a past commit
+    /// It has no state path, so nothing evaluated in it is ever persisted
+    pub fn from_snapshot(timeout_ms: u64, snapshot: &CommitSnapshot, max_recursion_depth: u32) -> Result<Self> {
+        let interpreter = Self::create_sandbox(max_recursion_depth)?;
+
+        debug!("Loading TCL state from commit {}", snapshot.commit_id);
+        for problem in Self::load_state(&interpreter, snapshot)? {
+            debug!("Warning: {}", problem);
+        }
+        let _ = interpreter.eval("set ::slopdrop_modified_procs [list]");
+        let _ = interpreter.eval("set ::slopdrop_modified_vars [list]");
+
+        Ok(Self {
+            sandbox: interpreter,
+            _timeout_ms: timeout_ms,
+        })
+    }
+
+    /// Create the master and sandbox interpreters with all bundled commands,
+    /// before any user state is loaded
+    fn create_sandbox(max_recursion_depth: u32) -> Result<TclSandbox> {
+        // Create the trusted master interpreter
+        // Packages that need filesystem/socket access are loaded here and only
+        // reach user code through aliases into the sandbox
+        let interpreter = Interpreter::new().map_err(|e| anyhow!("Failed to create TCL interpreter: {:?}", e))?;
+
         // Add tcllib path to auto_path for package loading (sha1, etc.)
         let _ = interpreter.eval("lappend auto_path /usr/share/tcltk");
 
@@ -66,12 +399,6 @@ impl SafeTclInterp {
             debug!("Bot will work but HTTP commands will not be available");
         }
 
-        // Inject stocks commands (includes both API wrappers and charting)
-        if let Err(e) = interpreter.eval(crate::http_tcl_commands::stocks_commands().as_str()) {
-            debug!("Stocks commands not available: {:?}", e);
-            debug!("Bot will work but stock functionality will not be available");
-        }
-
         // Inject SHA1 command BEFORE making interpreter safe
         // (package require sha1 needs unrestricted access to TCL package system)
         if let Err(e) = interpreter.eval(crate::smeggdrop_commands::sha1_command().as_str()) {
@@ -111,15 +438,33 @@ impl SafeTclInterp {
         interpreter.eval("encoding system utf-8")
             .map_err(|e| anyhow::anyhow!("Failed to set UTF-8 encoding: {:?}", e))?;
 
-        // Make the interpreter safe (AFTER loading packages)
-        Self::setup_safe_interp(&interpreter)?;
+        // Create the safe child interpreter and expose vetted commands to it
+        Self::setup_safe_interp(&interpreter, max_recursion_depth)?;
+        let interpreter = TclSandbox { master: interpreter };
+
+        // Everything below runs inside the sandbox
+
+        // Define context commands that return cached variables
+        // These are set once and read from global variables updated before each eval
+        let _ = interpreter.eval("proc nick {} {return $::nick}");
+        let _ = interpreter.eval("proc channel {} {return $::channel}");
+        let _ = interpreter.eval("proc mask {} {return $::mask}");
 
-        // Load proc tracking wrapper FIRST to intercept all proc definitions
+        // Inject stocks commands (includes both API wrappers and charting)
+        if let Err(e) = interpreter.eval(crate::http_tcl_commands::stocks_commands().as_str()) {
+            debug!("Stocks commands not available: {:?}", e);
+            debug!("Bot will work but stock functionality will not be available");
+        }
+
+        // Hide the sandbox's proc and rename behind the master's checks, then
+        // load proc tracking FIRST to intercept all proc definitions
+        interpreter.master().eval(crate::smeggdrop_commands::proc_guard().as_str())
+            .map_err(|e| anyhow::anyhow!("Failed to guard proc definitions: {:?}", e))?;
         interpreter.eval(crate::smeggdrop_commands::proc_tracking().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to inject proc tracking: {:?}", e))?;
 
         // Inject other smeggdrop commands (cache, utils, encoding)
-        // These don't require package loading so they can be loaded after making safe
+        // These are pure TCL so they live in the sandbox with the user's state
         interpreter.eval(crate::smeggdrop_commands::cache_commands().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to inject cache commands: {:?}", e))?;
         interpreter.eval(crate::smeggdrop_commands::utility_commands().as_str())
@@ -139,103 +484,72 @@ impl SafeTclInterp {
         interpreter.eval(crate::smeggdrop_commands::linkresolver_examples().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to inject linkresolver examples: {:?}", e))?;
 
-        // Stock commands are handled natively in Rust (see stock_commands.rs and tcl_thread.rs)
-        // No TCL injection needed - commands are intercepted before TCL evaluation
+        // Stock lookups, history, rollback and chanlist are native commands,
+        // registered by the TCL worker (see native_commands.rs)
 
-        // Ensure state directory exists and git repo is initialized
-        // If state_repo is set and state doesn't exist, clone from remote
-        // Otherwise create empty repo if needed
-        if !state_path.exists() {
-            debug!("State path doesn't exist, initializing: {:?}", state_path);
-            let persistence = crate::state::StatePersistence::with_repo(
-                state_path.to_path_buf(),
-                state_repo.clone(),
-                ssh_key.clone(),
-            );
-            // This will clone from remote or create directory structure
-            if let Err(e) = persistence.ensure_initialized() {
-                debug!("Failed to initialize state directory: {}. Will be created on first save.", e);
-            }
-        }
+        Ok(interpreter)
+    }
 
-        // Load state if it exists
-        if state_path.exists() {
-            debug!("Loading TCL state from {:?}", state_path);
-            Self::load_state(&interpreter, state_path)?;
+    /// Create the safe child interpreter and expose vetted commands to it
+    ///
+    /// `interp create -safe` hides exec, open, file, socket, source, load, cd,
+    /// glob, exit etc. at the C level, so renaming or redefining commands from
+    /// inside the sandbox can't bring them back. Commands like `interp`,
+    /// `namespace`, `trace` and `vwait` stay usable for user code.
+    fn setup_safe_interp(master: &Interpreter, max_recursion_depth: u32) -> Result<()> {
+        master.eval(format!("interp create -safe {}", SANDBOX_NAME).as_str())
+            .map_err(|e| anyhow!("Failed to create safe interpreter: {:?}", e))?;
 
-            // Clear the modified procs/vars list after loading state
-            // State loading triggers the proc wrapper, but these are initialization procs
-            // not actual modifications, so we clear the tracking lists
-            debug!("Clearing modified tracking after state load");
-            let _ = interpreter.eval("set ::slopdrop_modified_procs [list]");
-            let _ = interpreter.eval("set ::slopdrop_modified_vars [list]");
+        // Set recursion limit (0 = no limit, use TCL default)
+        if max_recursion_depth > 0 {
+            let recursion_cmd = format!("interp recursionlimit {} {}", SANDBOX_NAME, max_recursion_depth);
+            master.eval(recursion_cmd.as_str())
+                .map_err(|e| anyhow!("Failed to set recursion limit: {:?}", e))?;
+            debug!("Set TCL recursion limit to {}", max_recursion_depth);
         }
 
-        Ok(Self {
-            interpreter,
-            _timeout_ms: timeout_ms,
-        })
-    }
-
-    /// Configure the interpreter to be safe
-    fn setup_safe_interp(interp: &Interpreter) -> Result<()> {
-        // Hide dangerous commands that could break out of sandbox
-        // Note: socket is allowed for http package (protected by timeout and rate limiting)
-        // Note: namespace is allowed for cache commands to work
-        // Note: apply is allowed - needed for functional programming and stolen-treasure.tcl
-        let dangerous_commands = vec![
-            "interp",
-            // "namespace",  // Allowed for cache/utils commands
-            // "trace",  // Allowed - needed for variable modification tracking
-            // "vwait",  // Allowed - needed by http package for event loop
-            // "apply",  // Allowed - needed for lambdas and stolen-treasure.tcl
-            "yield",
-            "exec",
-            "open",
-            "file",
-            // "socket",  // Allowed for http package
-            "source",
-            "load",
-            "cd",
-            "pwd",
-            "glob",
-            "exit",
-        ];
-
-        for cmd in dangerous_commands {
-            // Try to rename the command to make it unavailable
-            let rename_cmd = format!("catch {{rename {} {{}}}}", cmd);
-            let _ = interp.eval(rename_cmd.as_str());
-        }
-
-        // Note: Proc tracking is handled via state diff mechanism in tcl_thread.rs
-        // We capture full state before/after each eval and detect changes
-
-        // Define context commands that return cached variables
-        // These are set once and read from global variables updated before each eval
-        let _ = interp.eval("proc nick {} {return $::nick}");
-        let _ = interp.eval("proc channel {} {return $::channel}");
-        let _ = interp.eval("proc mask {} {return $::mask}");
+        // Safe interpreters don't get the standard channels; share stdout/stderr
+        // so flush/fconfigure on them keep working (puts itself is captured below)
+        let _ = master.eval(format!("interp share {{}} stdout {}", SANDBOX_NAME).as_str());
+        let _ = master.eval(format!("interp share {{}} stderr {}", SANDBOX_NAME).as_str());
+
+        master.eval(SANDBOX_BRIDGE)
+            .map_err(|e| anyhow!("Failed to set up sandbox bridge: {:?}", e))?;
+
+        // Redirect puts into the per-eval output buffer
+        master.eval(OUTPUT_CAPTURE)
+            .map_err(|e| anyhow!("Failed to set up output capture: {:?}", e))?;
+        let capture_cmd = format!(
+            "set ::slopdrop::output_limit {limit}; interp hide {name} puts; interp alias {name} puts {{}} ::slopdrop::capture_puts",
+            limit = MAX_OUTPUT_BYTES,
+            name = SANDBOX_NAME
+        );
+        master.eval(capture_cmd.as_str())
+            .map_err(|e| anyhow!("Failed to redirect puts: {:?}", e))?;
+        master.eval("::slopdrop::expose_capabilities")
+            .map_err(|e| anyhow!("Failed to expose commands to sandbox: {:?}", e))?;
+
+        // Transactions journal sandbox changes from the master, where scripts can't reach them
+        master.eval(crate::smeggdrop_commands::transaction_commands().as_str())
+            .map_err(|e| anyhow!("Failed to set up transactions: {:?}", e))?;
 
         debug!("Safe TCL interpreter configured");
         Ok(())
     }
 
-    /// Load state from the state directory
-    fn load_state(interp: &Interpreter, state_path: &Path) -> Result<()> {
+    /// Load state from the state directory, or from any other source of
+    /// state files such as a past commit
+    /// Returns the problems that lost a proc or var (see `StatePersistence::fsck`)
+    fn load_state<S: StateSource + ?Sized>(interp: &TclSandbox, source: &S) -> Result<Vec<String>> {
         // 1. Load stolen-treasure.tcl (base library)
-        let stolen_treasure = state_path.join("stolen-treasure.tcl");
-        if stolen_treasure.exists() {
+        if let Some(content) = source.read_file("stolen-treasure.tcl")? {
             debug!("Loading stolen-treasure.tcl");
-            let content = std::fs::read_to_string(&stolen_treasure)?;
             interp.eval(content.as_str()).map_err(|e| anyhow!("Failed to load stolen-treasure.tcl: {:?}", e))?;
         }
 
         // 2. Load restore_missing_vars.tcl (variable restoration script)
-        let restore_vars = state_path.join("restore_missing_vars.tcl");
-        if restore_vars.exists() {
+        if let Some(content) = source.read_file("restore_missing_vars.tcl")? {
             debug!("Loading restore_missing_vars.tcl");
-            let content = std::fs::read_to_string(&restore_vars)?;
             interp.eval(content.as_str()).map_err(|e| anyhow!("Failed to load restore_missing_vars.tcl: {:?}", e))?;
         }
 
@@ -246,92 +560,126 @@ impl SafeTclInterp {
         let default_words = "set english_words {the be to of and a in that have I it for not on with he as you do at this but his by from they we say her she or an will my one all would there their what so up out if about who get which go me when make can like time no just him know take people into year your good some could them see other than then now look only come its over think also back after use two how our work first well way even new want because any these give day most us}";
         interp.eval(default_words).map_err(|e| anyhow!("Failed to set up english_words: {:?}", e))?;
 
-        // 4. Load procs from procs/_index
-        let procs_index = state_path.join("procs/_index");
-        if procs_index.exists() {
-            debug!("Loading procs from state");
-            let index_content = std::fs::read_to_string(&procs_index)?;
-            for line in index_content.lines() {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let proc_name = parts[0];
-                    let file_hash = parts[1];
-                    let proc_file = state_path.join("procs").join(file_hash);
-
-                    if proc_file.exists() {
-                        let proc_content = std::fs::read_to_string(&proc_file)?;
-                        // proc_content is: {args} {body}
-                        let proc_def = format!("proc {{{}}} {}", proc_name, proc_content);
-                        if let Err(e) = interp.eval(proc_def.as_str()) {
-                            debug!("Warning: Failed to load proc {}: {:?}", proc_name, e);
-                        }
-                    }
+        // 4. Create namespaces from vars/_namespaces, so procs and vars can go in them
+        if let Some(namespaces) = source.read_file("vars/_namespaces")? {
+            debug!("Creating namespaces from state");
+            for namespace in namespaces.lines().filter(|l| !l.is_empty()) {
+                let create = format!("namespace eval {} {{}}", tcl_quote(&format!("::{}", namespace)));
+                if let Err(e) = interp.eval(create.as_str()) {
+                    debug!("Warning: failed to create namespace {}: {:?}", namespace, e);
                 }
             }
         }
 
-        // 5. Load vars from vars/_index
-        let vars_index = state_path.join("vars/_index");
-        if vars_index.exists() {
-            debug!("Loading vars from state");
-            let index_content = std::fs::read_to_string(&vars_index)?;
-            for line in index_content.lines() {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let var_name = parts[0];
-                    let file_hash = parts[1];
-                    let var_file = state_path.join("vars").join(file_hash);
-
-                    if var_file.exists() {
-                        let var_content = std::fs::read_to_string(&var_file)?;
-                        // var_content is either: "scalar {value}" or "array {key value key value}"
-                        // Values are TCL-quoted (with braces) directly from the historical format
-                        if var_content.starts_with("scalar ") {
-                            let value = var_content.strip_prefix("scalar ").unwrap_or("");
-                            // Value is TCL-quoted, use it directly
-                            let set_cmd = format!("set {{{}}} {}", var_name, value);
-                            if let Err(e) = interp.eval(set_cmd.as_str()) {
-                                debug!("Warning: Failed to load var {}: {:?}", var_name, e);
-                            }
-                        } else if var_content.starts_with("array ") {
-                            let array_data = var_content.strip_prefix("array ").unwrap_or("");
-                            // Array data is TCL-quoted, use it directly
-                            let array_cmd = format!("array set {{{}}} {}", var_name, array_data);
-                            if let Err(e) = interp.eval(array_cmd.as_str()) {
-                                debug!("Warning: Failed to load array {}: {:?}", var_name, e);
-                            }
-                        }
-                    }
+        // 5. Load procs, then vars, from whichever layout the state uses
+        let mut problems = Vec::new();
+        for kind in ["procs", "vars"] {
+            debug!("Loading {} from state", kind);
+            for issue in crate::state::check_structure(source, kind)? {
+                if issue.breaks_loading() {
+                    problems.push(issue.to_string());
+                }
+            }
+            for (name, item) in crate::state::source_items(source, kind)? {
+                if let Err(e) = crate::state::load_item(interp, kind, &name, &item) {
+                    problems.push(e.to_string());
                 }
             }
         }
 
-        Ok(())
+        Ok(problems)
     }
 
     /// Evaluate TCL code
     ///
     /// Note: Timeout is handled at the thread level (see tcl_thread.rs)
     /// This method is called from within the TCL worker thread
+    ///
+    /// Failures are returned as a [`TclError`] carrying errorCode and errorInfo
     pub fn eval(&self, code: &str) -> Result<String> {
-        match self.interpreter.eval(code) {
-            Ok(obj) => {
-                let result = obj.get_string();
-                Ok(result)
-            }
-            Err(e) => {
-                // Get error info
-                let error_info = self.interpreter
-                    .eval("set errorInfo")
-                    .ok()
-                    .map(|obj| obj.get_string())
-                    .unwrap_or_else(|| format!("{:?}", e));
-
-                // Sanitize error message to prevent path disclosure
-                let sanitized = sanitize_error_message(&error_info);
-
-                Err(anyhow!("TCL Error: {}", sanitized))
-            }
+        // Start every eval with an empty puts buffer (see take_output)
+        let master = self.sandbox.master();
+        let _ = master.eval("::slopdrop::reset_output");
+
+        // Catch in the master so the error details survive limit errors too
+        let script = format!(
+            "catch {{interp eval {} {}}} ::slopdrop::eval_result ::slopdrop::eval_options",
+            SANDBOX_NAME,
+            tcl_quote(code)
+        );
+        let status = master
+            .eval(script.as_str())
+            .map_err(|e| anyhow!("TCL Error: {:?}", e))?
+            .get_string();
+        let read = |var: &str| {
+            master
+                .eval(var)
+                .map(|obj| obj.get_string())
+                .unwrap_or_default()
+        };
+        let result = read("set ::slopdrop::eval_result");
+
+        if status != "1" {
+            return Ok(result);
+        }
+
+        // Sanitize error messages to prevent path disclosure
+        Err(TclError {
+            message: sanitize_error_message(&result),
+            error_code: read("dict get $::slopdrop::eval_options -errorcode"),
+            error_info: sanitize_error_message(&read("dict get $::slopdrop::eval_options -errorinfo")),
+        }
+        .into())
+    }
+
+    /// Take the `puts` output written during the last eval
+    pub fn take_output(&self) -> String {
+        self.sandbox
+            .master()
+            .eval("::slopdrop::take_output")
+            .map(|obj| obj.get_string())
+            .unwrap_or_default()
+    }
+
+    /// Open a transaction: procs and globals changed from now on can be
+    /// restored with `rollback_transaction` (see transactions.tcl)
+    /// A pinned transaction can't be ended by the script with `transaction off`
+    pub fn begin_transaction(&self, pinned: bool) -> Result<()> {
+        let script = format!("::slopdrop::txn::begin {}", pinned as u8);
+        self.sandbox
+            .master()
+            .eval(script.as_str())
+            .map(|_| ())
+            .map_err(|e| anyhow!("Failed to begin transaction: {}", e))
+    }
+
+    /// Undo everything changed since the transaction opened
+    /// Returns false if no transaction was open (e.g. the script ran `transaction off`)
+    pub fn rollback_transaction(&self) -> Result<bool> {
+        self.sandbox
+            .master()
+            .eval("::slopdrop::txn::rollback")
+            .map(|obj| obj.get_string() == "1")
+            .map_err(|e| anyhow!("Failed to roll back transaction: {}", e))
+    }
+
+    /// Close the transaction and keep its changes
+    pub fn finish_transaction(&self) {
+        let _ = self.sandbox.master().eval("::slopdrop::txn::finish");
+    }
+
+    /// Tell the master who the next eval is for; exposed commands (HTTP, stock
+    /// quotes) rate limit and bill by these, whatever the script sets
+    pub fn set_caller(&self, nick: &str, mask: &str, channel: &str) {
+        let script = format!(
+            "set ::nick {}; set ::mask {}; set ::channel {}; set ::nick_channel {}",
+            tcl_quote(nick),
+            tcl_quote(mask),
+            tcl_quote(channel),
+            tcl_quote(channel)
+        );
+        if let Err(e) = self.sandbox.master().eval(script.as_str()) {
+            warn!("Failed to set the caller in the master: {:?}", e);
         }
     }
 
@@ -344,10 +692,10 @@ impl SafeTclInterp {
         channel: &str,
     ) -> Result<String> {
         // Set context variables that the nick/channel/mask procs will read
-        // The procs are defined once in setup_safe_interp
-        let _ = self.interpreter.eval(format!("set ::nick {{{}}}", nick).as_str());
-        let _ = self.interpreter.eval(format!("set ::channel {{{}}}", channel).as_str());
-        let _ = self.interpreter.eval(format!("set ::mask {{{}}}", mask).as_str());
+        // The procs are defined once in SafeTclInterp::new
+        let _ = self.sandbox.eval(format!("set ::nick {}", tcl_quote(nick)).as_str());
+        let _ = self.sandbox.eval(format!("set ::channel {}", tcl_quote(channel)).as_str());
+        let _ = self.sandbox.eval(format!("set ::mask {}", tcl_quote(mask)).as_str());
 
         self.eval(code)
     }
@@ -356,7 +704,7 @@ impl SafeTclInterp {
     /// NOTE: Currently unused - state diff in tcl_thread.rs handles proc tracking
     #[allow(dead_code)]
     pub fn get_procs(&self) -> Result<Vec<String>> {
-        match self.interpreter.eval("info procs") {
+        match self.sandbox.eval("info procs") {
             Ok(obj) => {
                 let procs_str = obj.get_string();
                 Ok(procs_str.split_whitespace().map(|s| s.to_string()).collect())
@@ -369,7 +717,7 @@ impl SafeTclInterp {
     /// NOTE: Currently unused - state diff in tcl_thread.rs handles var tracking
     #[allow(dead_code)]
     pub fn get_vars(&self) -> Result<Vec<String>> {
-        match self.interpreter.eval("info globals") {
+        match self.sandbox.eval("info globals") {
             Ok(obj) => {
                 let vars_str = obj.get_string();
                 Ok(vars_str.split_whitespace().map(|s| s.to_string()).collect())
@@ -398,44 +746,51 @@ impl SafeTclInterp {
     pub fn reload_modules(&self) -> Result<()> {
         debug!("Reloading TCL modules from disk");
 
-        // Reload HTTP commands (must be done before safety was applied, but interpreter is already safe)
-        // We can still eval new code in a safe interpreter
-        if let Err(e) = self.interpreter.eval(crate::http_tcl_commands::http_commands().as_str()) {
+        // Reload master-side modules, then re-expose them in case commands were added
+        let master = self.sandbox.master();
+        if let Err(e) = master.eval(crate::http_tcl_commands::http_commands().as_str()) {
             debug!("Failed to reload HTTP commands: {:?}", e);
         }
+        if let Err(e) = master.eval(crate::smeggdrop_commands::sha1_command().as_str()) {
+            debug!("Failed to reload SHA1 command: {:?}", e);
+        }
+        if let Err(e) = master.eval("::slopdrop::expose_capabilities") {
+            debug!("Failed to re-expose commands to sandbox: {:?}", e);
+        }
+        if let Err(e) = master.eval(crate::smeggdrop_commands::transaction_commands().as_str()) {
+            debug!("Failed to reload transactions: {:?}", e);
+        }
 
         // Reload stocks commands (includes both API and charting)
-        if let Err(e) = self.interpreter.eval(crate::http_tcl_commands::stocks_commands().as_str()) {
+        if let Err(e) = self.sandbox.eval(crate::http_tcl_commands::stocks_commands().as_str()) {
             debug!("Failed to reload stocks commands: {:?}", e);
         }
 
-        // Reload SHA1 command
-        if let Err(e) = self.interpreter.eval(crate::smeggdrop_commands::sha1_command().as_str()) {
-            debug!("Failed to reload SHA1 command: {:?}", e);
-        }
-
         // Reload proc tracking wrapper FIRST to intercept all proc definitions
-        self.interpreter.eval(crate::smeggdrop_commands::proc_tracking().as_str())
+        if let Err(e) = master.eval(crate::smeggdrop_commands::proc_guard().as_str()) {
+            debug!("Failed to reload proc guard: {:?}", e);
+        }
+        self.sandbox.eval(crate::smeggdrop_commands::proc_tracking().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to reload proc tracking: {:?}", e))?;
 
         // Reload other smeggdrop commands
-        self.interpreter.eval(crate::smeggdrop_commands::cache_commands().as_str())
+        self.sandbox.eval(crate::smeggdrop_commands::cache_commands().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to reload cache commands: {:?}", e))?;
-        self.interpreter.eval(crate::smeggdrop_commands::utility_commands().as_str())
+        self.sandbox.eval(crate::smeggdrop_commands::utility_commands().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to reload utility commands: {:?}", e))?;
-        self.interpreter.eval(crate::smeggdrop_commands::encoding_commands().as_str())
+        self.sandbox.eval(crate::smeggdrop_commands::encoding_commands().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to reload encoding commands: {:?}", e))?;
-        self.interpreter.eval(crate::smeggdrop_commands::magick_commands().as_str())
+        self.sandbox.eval(crate::smeggdrop_commands::magick_commands().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to reload magick commands: {:?}", e))?;
-        self.interpreter.eval(crate::smeggdrop_commands::timer_commands().as_str())
+        self.sandbox.eval(crate::smeggdrop_commands::timer_commands().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to reload timer commands: {:?}", e))?;
-        self.interpreter.eval(crate::smeggdrop_commands::trigger_commands().as_str())
+        self.sandbox.eval(crate::smeggdrop_commands::trigger_commands().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to reload trigger commands: {:?}", e))?;
-        self.interpreter.eval(crate::smeggdrop_commands::timtom_commands().as_str())
+        self.sandbox.eval(crate::smeggdrop_commands::timtom_commands().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to reload timtom commands: {:?}", e))?;
-        self.interpreter.eval(crate::smeggdrop_commands::linkresolver_commands().as_str())
+        self.sandbox.eval(crate::smeggdrop_commands::linkresolver_commands().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to reload linkresolver commands: {:?}", e))?;
-        self.interpreter.eval(crate::smeggdrop_commands::linkresolver_examples().as_str())
+        self.sandbox.eval(crate::smeggdrop_commands::linkresolver_examples().as_str())
             .map_err(|e| anyhow::anyhow!("Failed to reload linkresolver examples: {:?}", e))?;
 
         debug!("TCL modules reloaded successfully");
@@ -467,6 +822,20 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_only_public_http_commands_exposed() {
+        let state_path = PathBuf::from("/tmp/tcl_test_state");
+        let interp = SafeTclInterp::new(30000, &state_path, None, None, 1000).unwrap();
+
+        // Rate limit bookkeeping is master-only
+        assert!(interp.eval("::httpx::increment_eval").is_err());
+        assert!(interp.eval("::httpx::check_limits 0").is_err());
+
+        // The sandbox's own clock can't read zone files from disk
+        assert!(interp.eval("clock format 0 -gmt 1").is_ok());
+        assert!(interp.eval("clock format 0 -timezone :/etc/passwd").is_err());
+    }
+
     #[test]
     fn test_proc_creation() {
         let state_path = PathBuf::from("/tmp/tcl_test_state");
@@ -476,4 +845,55 @@ mod tests {
         let result = interp.eval("hello").unwrap();
         assert_eq!(result.trim(), "world");
     }
+
+    #[test]
+    fn test_hidden_commands_unreachable() {
+        let state_path = PathBuf::from("/tmp/tcl_test_state");
+        let interp = SafeTclInterp::new(30000, &state_path, None, None, 1000).unwrap();
+
+        // A safe interp can't invoke its own hidden commands
+        assert!(interp.eval("interp invokehidden {} exec ls").is_err());
+        assert!(interp.eval("socket localhost 80").is_err());
+
+        // But it can create its own (safe) child interpreters
+        let result = interp.eval("interp create inner; inner eval {expr {6 * 7}}").unwrap();
+        assert_eq!(result.trim(), "42");
+    }
+
+    #[test]
+    fn test_error_details() {
+        let state_path = PathBuf::from("/tmp/tcl_test_state");
+        let interp = SafeTclInterp::new(30000, &state_path, None, None, 1000).unwrap();
+
+        let err = interp.eval("proc fails {} {error boom {} {MY CODE}}; fails").unwrap_err();
+        let tcl_error = err.downcast_ref::<TclError>().unwrap();
+        assert_eq!(tcl_error.message, "boom");
+        assert_eq!(tcl_error.error_code, "MY CODE");
+        assert!(tcl_error.error_info.contains("fails"));
+    }
+
+    #[test]
+    fn test_puts_is_captured() {
+        let state_path = PathBuf::from("/tmp/tcl_test_state");
+        let interp = SafeTclInterp::new(30000, &state_path, None, None, 1000).unwrap();
+
+        let result = interp.eval("puts hello; puts -nonewline stderr world; chan puts stdout !; return done").unwrap();
+        assert_eq!(result, "done");
+        assert_eq!(interp.take_output(), "hello\nworld!\n");
+
+        // The buffer is per eval
+        interp.eval("expr 1").unwrap();
+        assert_eq!(interp.take_output(), "");
+    }
+
+    #[test]
+    fn test_tcl_quote_round_trip() {
+        let state_path = PathBuf::from("/tmp/tcl_test_state");
+        let interp = SafeTclInterp::new(30000, &state_path, None, None, 1000).unwrap();
+
+        for value in ["", "plain", "a b", "}{ [exec ls] $x \\ \"", "line1\nline2", "# not a comment"] {
+            let result = interp.eval(&format!("set x {}", tcl_quote(value))).unwrap();
+            assert_eq!(result, value);
+        }
+    }
 }
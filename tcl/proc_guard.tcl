@@ -0,0 +1,79 @@
+# Proc definitions for the sandbox
+#
+# Runs in the master interpreter. The sandbox's `proc` and `rename` are
+# hidden, and the names proc_tracking.tcl builds its wrappers on
+# (::slopdrop::_original_proc and ::slopdrop::_original_rename) are aliases
+# to the procs below. Every proc definition, rename and deletion in the
+# sandbox comes through here, whatever the script calls, so changes to
+# protected procs are held for review instead of made, and transactions are
+# journaled before anything changes.
+
+namespace eval ::slopdrop::guard {}
+
+# Fully qualified name of a command named in the sandbox namespace `ns`
+proc ::slopdrop::guard::qualify {ns name} {
+    if {$ns ne "::" && ![string match "::*" $name]} {
+        return "${ns}::${name}"
+    }
+    return ::[string trimleft $name :]
+}
+
+# Ask whether the caller of the current eval may change the proc `name` to
+# `definition` ({arglist body}, empty for a deletion). A refusal is an error,
+# and the change is held for an admin to review (see native_commands.rs).
+# Before the native commands are registered, only the bot defines procs
+proc ::slopdrop::guard::check {name {definition {}}} {
+    if {[llength [info commands ::slopdrop::native::proc_allowed]]} {
+        lassign $definition arglist body
+        set defined [expr {[llength $definition] > 0}]
+        ::slopdrop::native_result [::slopdrop::native::proc_allowed $name $defined $arglist $body]
+    }
+}
+
+# `proc`, in the namespace the sandbox called it from
+proc ::slopdrop::guard::define_proc {name arglist body} {
+    set ns [interp eval sandbox {namespace current}]
+    set qualified [qualify $ns $name]
+    check $qualified [list $arglist $body]
+    ::slopdrop::txn::save_proc $qualified
+    interp invokehidden sandbox -namespace $ns proc $name $arglist $body
+}
+
+# `rename`, which also deletes commands when `newName` is empty. The old
+# name goes away and the new one takes over the old definition; both are
+# checked, so every refusal is held
+proc ::slopdrop::guard::rename_proc {oldName newName} {
+    set ns [interp eval sandbox {namespace current}]
+    set changes [list]
+    set resolved [interp eval sandbox [list namespace which -command $oldName]]
+    if {$resolved ne ""} {
+        lappend changes $resolved {}
+    }
+    if {$newName ne ""} {
+        lappend changes [qualify $ns $newName] [::slopdrop::sandbox_proc $resolved]
+    }
+
+    set refused [list]
+    foreach {name definition} $changes {
+        if {[catch {check $name $definition} error]} {
+            lappend refused $error
+        }
+    }
+    if {[llength $refused]} {
+        return -code error [join $refused \n]
+    }
+
+    foreach {name definition} $changes {
+        ::slopdrop::txn::save_proc $name
+    }
+    interp invokehidden sandbox -namespace $ns rename $oldName $newName
+}
+
+# Only hide once: on a module reload the exposed `proc` is the tracking wrapper
+if {"proc" ni [interp hidden sandbox]} {
+    interp hide sandbox proc
+    interp hide sandbox rename
+}
+interp eval sandbox {namespace eval ::slopdrop {}}
+interp alias sandbox ::slopdrop::_original_proc {} ::slopdrop::guard::define_proc
+interp alias sandbox ::slopdrop::_original_rename {} ::slopdrop::guard::rename_proc
@@ -15,11 +15,16 @@ if {![info exists ::slopdrop_traced_vars]} {
 # Rename built-in commands to protect them from user-defined procs
 # User code might define `proc trace {}` which would override the built-in
 # Only rename if not already renamed (important for hot-reloading)
+# In the sandbox the master has already hidden `proc` and `rename` and put
+# its checked versions under these names (see proc_guard.tcl)
+if {[llength [info commands ::slopdrop::_original_rename]] == 0} {
+    rename rename ::slopdrop::_original_rename
+}
 if {[llength [info commands ::slopdrop::_original_proc]] == 0} {
-    rename proc ::slopdrop::_original_proc
+    ::slopdrop::_original_rename proc ::slopdrop::_original_proc
 }
 if {[llength [info commands ::slopdrop::_original_trace]] == 0} {
-    rename trace ::slopdrop::_original_trace
+    ::slopdrop::_original_rename trace ::slopdrop::_original_trace
 }
 
 # Create wrapper that tracks proc definitions
@@ -49,6 +54,12 @@ if {[llength [info commands ::slopdrop::_original_trace]] == 0} {
     }
 }
 
+# Wrapper for rename, so the built-in stays out of reach of user procs
+# Renamed procs are picked up by the state diff
+::slopdrop::_original_proc rename {oldName newName} {
+    uplevel 1 [list ::slopdrop::_original_rename $oldName $newName]
+}
+
 # Helper proc to get and clear the modified procs list
 ::slopdrop::_original_proc ::slopdrop::get_modified_procs {} {
     global slopdrop_modified_procs
@@ -90,8 +101,53 @@ if {[llength [info commands ::slopdrop::_original_trace]] == 0} {
 # ====================
 # Variable Tracking
 # ====================
+# Globals and variables in user namespaces are tracked. Namespace variables
+# are named like `cache::buckets::foo` (qualified, without the leading ::),
+# globals by their bare name, as `info globals` returns them.
+
+# Namespaces that hold bookkeeping rather than user state
+set ::slopdrop::untracked_namespaces {::slopdrop ::tcl ::oo ::msgcat ::httpx}
+
+# User namespaces below `parent`, recursively, fully qualified
+::slopdrop::_original_proc ::slopdrop::user_namespaces {{parent ::}} {
+    set result [list]
+    foreach ns [namespace children $parent] {
+        if {$ns in $::slopdrop::untracked_namespaces} {
+            continue
+        }
+        lappend result $ns {*}[::slopdrop::user_namespaces $ns]
+    }
+    return $result
+}
+
+# Variables that exist in user namespaces
+::slopdrop::_original_proc ::slopdrop::namespace_vars {} {
+    set result [list]
+    foreach ns [::slopdrop::user_namespaces] {
+        foreach varname [info vars ${ns}::*] {
+            if {[info exists $varname]} {
+                lappend result [string range $varname 2 end]
+            }
+        }
+    }
+    return $result
+}
+
+# Every tracked variable: globals (minus the tracking lists) and namespace vars
+# The persist rules are user state despite their prefix
+::slopdrop::_original_proc ::slopdrop::tracked_vars {} {
+    set result [list]
+    foreach varname [info globals] {
+        if {![string match "slopdrop_*" $varname] || $varname eq "slopdrop_ephemeral"} {
+            lappend result $varname
+        }
+    }
+    return [concat $result [::slopdrop::namespace_vars]]
+}
 
 # Trace callback for variable writes
+# Traces added before namespace tracking still call this one; the name it
+# gets is whatever the writer used, which may be a local alias
 ::slopdrop::_original_proc ::slopdrop::var_write_trace {varname index op} {
     global slopdrop_modified_vars
 
@@ -107,48 +163,39 @@ if {[llength [info commands ::slopdrop::_original_trace]] == 0} {
     }
 }
 
+# Trace callback that records the variable under its tracked name, so writes
+# through `variable`, `global` or `upvar` aliases are attributed correctly
+::slopdrop::_original_proc ::slopdrop::var_write_trace_named {name varname index op} {
+    global slopdrop_modified_vars
+
+    if {[lsearch -exact $slopdrop_modified_vars $name] == -1} {
+        lappend slopdrop_modified_vars $name
+    }
+}
+
 # Helper to add trace to a variable
 ::slopdrop::_original_proc ::slopdrop::add_var_trace {varname} {
     global slopdrop_traced_vars
     if {[lsearch -exact $slopdrop_traced_vars $varname] == -1} {
         # Add write trace (silently ignore errors)
-        if {![catch {::slopdrop::_original_trace add variable ::$varname write ::slopdrop::var_write_trace}]} {
+        if {![catch {::slopdrop::_original_trace add variable ::$varname write [list ::slopdrop::var_write_trace_named $varname]}]} {
             lappend slopdrop_traced_vars $varname
         }
     }
 }
 
-# Initialize traces for all existing global vars
+# Initialize traces for all existing tracked vars
 ::slopdrop::_original_proc ::slopdrop::init_var_traces {} {
-    foreach varname [info globals] {
-        # Skip internal tracking vars
-        if {[string match "slopdrop_*" $varname]} {
-            continue
-        }
+    foreach varname [::slopdrop::tracked_vars] {
         ::slopdrop::add_var_trace $varname
     }
 }
 
 # Periodically update traces for new vars (called after each eval)
+# add_var_trace skips vars that are already traced (the common case)
 ::slopdrop::_original_proc ::slopdrop::update_var_traces {} {
-    global slopdrop_traced_vars
-
-    # Only process vars that aren't already traced
-    foreach varname [info globals] {
-        # Skip internal tracking vars
-        if {[string match "slopdrop_*" $varname]} {
-            continue
-        }
-
-        # Skip if already traced (most common case)
-        if {[lsearch -exact $slopdrop_traced_vars $varname] != -1} {
-            continue
-        }
-
-        # New variable - add trace
-        if {![catch {::slopdrop::_original_trace add variable ::$varname write ::slopdrop::var_write_trace}]} {
-            lappend slopdrop_traced_vars $varname
-        }
+    foreach varname [::slopdrop::tracked_vars] {
+        ::slopdrop::add_var_trace $varname
     }
 }
 
@@ -162,12 +209,141 @@ if {[llength [info commands ::slopdrop::_original_trace]] == 0} {
     return $result
 }
 
+# Drop vars from the modified and traced lists after they were reloaded from
+# disk, so the reload isn't committed and the vars get fresh traces
+::slopdrop::_original_proc ::slopdrop::forget_vars {names} {
+    global slopdrop_modified_vars slopdrop_traced_vars
+    set slopdrop_modified_vars [lmap v $slopdrop_modified_vars {
+        if {$v in $names} continue
+        set v
+    }]
+    set slopdrop_traced_vars [lmap v $slopdrop_traced_vars {
+        if {$v in $names} continue
+        set v
+    }]
+}
+
 # Mark all existing vars as modified (for migration)
 ::slopdrop::_original_proc ::slopdrop::mark_all_vars_modified {} {
     global slopdrop_modified_vars
-    set slopdrop_modified_vars [info globals]
+    set slopdrop_modified_vars [::slopdrop::tracked_vars]
     return [llength $slopdrop_modified_vars]
 }
 
+# ====================
+# Ephemeral State
+# ====================
+# Vars and namespaces marked ephemeral are never persisted: the state diff
+# leaves them out, and persisted ones are deleted once they become ephemeral.
+# A rule is a {kind name} pair, where kind is `var` (one var), `pattern` (a
+# glob over var names) or `namespace` (a namespace, everything below it and
+# its vars). Names are qualified like tracked vars, without the leading ::.
+# The bot's own vars are declared here; rules added with `persist off` live in
+# ::slopdrop_ephemeral, which is persisted like any other var.
+
+namespace eval ::slopdrop::persist {
+    variable builtin [list]
+    # Context variables set per-eval
+    lappend builtin {var nick} {var channel} {var mask}
+    # Channel member lists synced before each eval
+    lappend builtin {var slopdrop_channel_members}
+    # Message log array
+    lappend builtin {var slopdrop_log_lines}
+    # HTTP rate limiting context
+    lappend builtin {var nick_channel}
+    # Proc tracking list (see above)
+    lappend builtin {var slopdrop_modified_procs}
+    # Temp variables created by state capture itself
+    lappend builtin {var v} {var p} {var validated}
+    # TCL built-in ephemeral error variables
+    lappend builtin {var errorInfo} {var errorCode}
+}
+
+# Every rule in effect, one "kind name" per line (read by the state diff)
+::slopdrop::_original_proc ::slopdrop::persist::rules {} {
+    variable builtin
+    set result [list]
+    foreach rule [concat $builtin [::slopdrop::persist::user_rules]] {
+        lappend result [join $rule " "]
+    }
+    return [join $result \n]
+}
+
+::slopdrop::_original_proc ::slopdrop::persist::user_rules {} {
+    if {[info exists ::slopdrop_ephemeral]} {
+        return $::slopdrop_ephemeral
+    }
+    return [list]
+}
+
+# The rule `persist` args describe: -pattern and -namespace pick the kind,
+# otherwise a name is a namespace if one by that name exists, else a var
+::slopdrop::_original_proc ::slopdrop::persist::rule {args} {
+    switch -- [llength $args] {
+        1 {
+            set name [string trimleft [lindex $args 0] :]
+            if {[namespace exists ::$name] && ![info exists ::$name]} {
+                return [list namespace $name]
+            }
+            return [list var $name]
+        }
+        2 {
+            lassign $args flag name
+            set name [string trimleft $name :]
+            switch -- $flag {
+                -pattern { return [list pattern $name] }
+                -namespace { return [list namespace $name] }
+            }
+        }
+    }
+    return -code error "usage: persist on|off ?-pattern|-namespace? name"
+}
+
+# User-facing command: `persist off name` (or `persist ephemeral name`) stops
+# persisting a var or namespace, `persist on name` undoes that and
+# `persist list` shows every rule
+::slopdrop::_original_proc persist {{mode ""} args} {
+    switch -- $mode {
+        off - ephemeral {
+            set rule [::slopdrop::persist::rule {*}$args]
+            if {$rule eq [list var slopdrop_ephemeral]} {
+                return -code error "the persist rules are always persisted"
+            }
+            set rules [::slopdrop::persist::user_rules]
+            if {$rule ni $rules} {
+                lappend rules $rule
+                set ::slopdrop_ephemeral $rules
+            }
+            return "[lindex $rule 1] is ephemeral"
+        }
+        on {
+            set rule [::slopdrop::persist::rule {*}$args]
+            if {$rule in $::slopdrop::persist::builtin} {
+                return -code error "[lindex $rule 1] is always ephemeral"
+            }
+            set rules [::slopdrop::persist::user_rules]
+            set index [lsearch -exact $rules $rule]
+            if {$index == -1} {
+                return -code error "no persist rule for [lindex $rule 1]"
+            }
+            set ::slopdrop_ephemeral [lreplace $rules $index $index]
+            return "[lindex $rule 1] is persisted"
+        }
+        list {
+            set result [list]
+            foreach rule [::slopdrop::persist::user_rules] {
+                lappend result [join $rule " "]
+            }
+            foreach rule $::slopdrop::persist::builtin {
+                lappend result "[join $rule " "] (built in)"
+            }
+            return [join $result \n]
+        }
+        default {
+            return -code error "usage: persist on|off|list ?-pattern|-namespace? ?name?"
+        }
+    }
+}
+
 # Initialize traces for existing variables
 ::slopdrop::init_var_traces
@@ -5,45 +5,16 @@ namespace eval stock {
 
     #=========================================================================
     # PUBLIC API - Rust-backed stock commands
-    # These wrappers allow TCL code to call the Rust stock backend
-    #=========================================================================
-
-    # Get current quote for a symbol
-    # Args: symbol - stock symbol (e.g., "AAPL")
-    # Returns: Formatted quote string
-    proc quote {symbol} {
-        # Use uplevel to eval at top level where Rust interception happens
-        return [uplevel #0 [list eval "stock::quote $symbol"]]
-    }
-
-    # Get current price for a symbol
-    # Args: symbol - stock symbol (e.g., "AAPL")
-    # Returns: Price as string
-    proc price {symbol} {
-        return [uplevel #0 [list eval "stock::price $symbol"]]
-    }
-
-    # Get detailed quote information
-    # Args: symbol - stock symbol (e.g., "AAPL")
-    # Returns: Detailed quote as dict-formatted string
-    proc detail {symbol} {
-        return [uplevel #0 [list eval "stock::detail $symbol"]]
-    }
-
-    # Get historical quotes
-    # Args: symbol - stock symbol (e.g., "AAPL")
-    #       days - number of days (default: 7)
-    #       interval - optional interval ("1m", "5m", "15m", "30m", "1h", "1d", "1wk", "1mo")
+    # stock::quote, stock::price, stock::detail and stock::history are native
+    # commands registered by the bot (see src/native_commands.rs):
+    #   stock::quote <symbol>                    - Formatted quote string
+    #   stock::price <symbol>                    - Price as string
+    #   stock::detail <symbol>                   - Detailed quote as dict-formatted string
+    #   stock::history <symbol> ?days? ?interval? - List of {timestamp price} pairs
+    #                  interval: "1m", "5m", "15m", "30m", "1h", "1d", "1wk", "1mo"
     #                  If not specified, uses smart defaults based on time range:
     #                  1 day: 5m, 2-7 days: 1h, 8-60 days: 1d, 60+ days: 1wk
-    # Returns: List of {timestamp price} pairs
-    proc history {symbol {days 7} {interval ""}} {
-        if {$interval eq ""} {
-            return [uplevel #0 [list eval "stock::history $symbol $days"]]
-        } else {
-            return [uplevel #0 [list eval "stock::history $symbol $days $interval"]]
-        }
-    }
+    #=========================================================================
 
     # Generate ASCII art chart
     # Args: symbol - stock symbol (e.g., "AAPL")
@@ -58,11 +29,7 @@ namespace eval stock {
     #   stock::chart AAPL 30       # 30 days with daily data
     #   stock::chart AAPL 30 1h    # 30 days with hourly data
     proc chart {symbol {days 7} {interval ""}} {
-        if {$interval eq ""} {
-            return [uplevel #0 [list eval "stock::chart $symbol $days"]]
-        } else {
-            return [uplevel #0 [list eval "stock::chart $symbol $days $interval"]]
-        }
+        return [chart_from_data $symbol [history $symbol $days $interval]]
     }
 
     #=========================================================================
@@ -73,7 +40,6 @@ namespace eval stock {
     # Args: symbol - stock symbol
     #       history_data - list of {timestamp price} pairs
     # Returns: ASCII chart as a string
-    # Note: This is called from Rust with pre-fetched historical data
     proc chart_from_data {symbol history_data} {
         if {[llength $history_data] == 0} {
             error "No historical data available for $symbol"
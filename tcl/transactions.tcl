@@ -0,0 +1,183 @@
+# Transactions for sandbox evals
+#
+# Runs in the master interpreter. While a transaction is open, the first
+# change to each sandbox proc is journaled (proc_guard.tcl calls save_proc
+# before a proc is defined, renamed or deleted) and the tracked vars and
+# user namespaces are snapshotted, so a failed eval can be undone before the
+# state diff sees it. The journal and flags live here, out of reach of the
+# scripts being rolled back; the sandbox only gets `transaction`.
+
+namespace eval ::slopdrop::txn {
+    # Set while an eval runs transactionally
+    variable active 0
+    # Set for transactions the script may not end early (dry runs)
+    variable pinned 0
+}
+
+# Open a transaction (no-op if one is already open)
+proc ::slopdrop::txn::begin {{pin 0}} {
+    variable active
+    variable pinned
+    variable procs
+    variable vars
+    variable namespaces
+    variable tracking
+
+    if {$active} {
+        return
+    }
+    set active 1
+    set pinned $pin
+    array unset procs
+    array unset vars
+    set namespaces [interp eval sandbox ::slopdrop::user_namespaces]
+
+    # Values are shared Tcl_Objs, so the snapshot is cheap for scalars
+    array set vars [interp eval sandbox {apply {{} {
+        set snapshot [list]
+        foreach varname [::slopdrop::tracked_vars] {
+            if {[array exists ::$varname]} {
+                lappend snapshot $varname [list array [array get ::$varname]]
+            } elseif {[info exists ::$varname]} {
+                lappend snapshot $varname [list scalar [set ::$varname]]
+            }
+        }
+        return $snapshot
+    }}}]
+
+    # Changes made before the transaction opened still get committed
+    set tracking [interp eval sandbox {list $::slopdrop_modified_procs $::slopdrop_modified_vars}]
+}
+
+# Journal a sandbox proc's current definition (or its absence) before it changes
+proc ::slopdrop::txn::save_proc {name} {
+    variable active
+    variable procs
+
+    set name ::[string trimleft $name :]
+    if {!$active || [info exists procs($name)]} {
+        return
+    }
+
+    set definition [::slopdrop::sandbox_proc $name]
+    if {[llength $definition]} {
+        set procs($name) [list 1 {*}$definition]
+    } else {
+        set procs($name) [list 0]
+    }
+}
+
+# Undo every journaled change and close the transaction
+# Returns 1 if anything was rolled back, 0 if no transaction was open
+proc ::slopdrop::txn::rollback {} {
+    variable active
+    variable procs
+    variable vars
+    variable namespaces
+    variable tracking
+
+    if {!$active} {
+        return 0
+    }
+
+    foreach name [array names procs] {
+        lassign $procs($name) existed params body
+        if {$existed} {
+            catch {interp invokehidden sandbox proc $name $params $body}
+        } else {
+            catch {interp invokehidden sandbox rename $name {}}
+        }
+    }
+
+    interp eval sandbox [list apply {{vars namespaces tracking} {
+        # Drop vars created during the transaction, then put back the old
+        # values. Vars that have to be recreated lose their write trace, so
+        # they are forgotten here and re-traced by the next update_var_traces
+        global slopdrop_traced_vars
+        set recreated [list]
+        foreach varname [::slopdrop::tracked_vars] {
+            if {![dict exists $vars $varname]} {
+                unset -nocomplain ::$varname
+                lappend recreated $varname
+            }
+        }
+        foreach ns [lreverse [::slopdrop::user_namespaces]] {
+            if {$ns ni $namespaces} {
+                catch {namespace delete $ns}
+            }
+        }
+        foreach ns $namespaces {
+            namespace eval $ns {}
+        }
+        dict for {varname saved} $vars {
+            lassign $saved type value
+            if {$type eq "scalar" && [info exists ::$varname] && ![array exists ::$varname]} {
+                set ::$varname $value
+                continue
+            }
+            unset -nocomplain ::$varname
+            namespace eval ::[namespace qualifiers ::$varname] {}
+            if {$type eq "array"} {
+                array set ::$varname $value
+            } else {
+                set ::$varname $value
+            }
+            lappend recreated $varname
+        }
+        set traced [list]
+        foreach varname $slopdrop_traced_vars {
+            if {$varname ni $recreated} {
+                lappend traced $varname
+            }
+        }
+        set slopdrop_traced_vars $traced
+
+        lassign $tracking ::slopdrop_modified_procs ::slopdrop_modified_vars
+    }} [array get vars] $namespaces $tracking]
+
+    finish
+    return 1
+}
+
+# Close the transaction, keeping its changes
+proc ::slopdrop::txn::finish {} {
+    variable active
+    variable pinned
+    variable procs
+    variable vars
+    variable namespaces
+    variable tracking
+
+    set active 0
+    set pinned 0
+    array unset procs
+    array unset vars
+    set namespaces [list]
+    set tracking [list]
+}
+
+# User-facing switch: `transaction on` makes the rest of this eval roll back
+# if it fails, `transaction off` keeps what was done so far
+proc ::slopdrop::txn::transaction {{mode ""}} {
+    variable active
+    variable pinned
+
+    switch -- $mode {
+        "" {}
+        on {
+            begin
+        }
+        off {
+            if {$pinned} {
+                return -code error "transaction can't be turned off during a dry run"
+            }
+            finish
+        }
+        default {
+            return -code error "usage: transaction ?on|off?"
+        }
+    }
+    return $active
+}
+
+interp alias sandbox transaction {} ::slopdrop::txn::transaction
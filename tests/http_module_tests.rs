@@ -9,6 +9,18 @@ fn create_temp_state() -> (TempDir, PathBuf) {
     (temp_dir, state_path)
 }
 
+/// Call an internal httpx helper the way exposed commands run: in the master,
+/// with the caller set there (the helpers aren't reachable from the sandbox)
+fn call_httpx(interp: &SafeTclInterp, call: &str) -> Result<String, String> {
+    let script = format!("::slopdrop::sandbox_call {}", call);
+    interp
+        .interpreter()
+        .master()
+        .eval(script.as_str())
+        .map(|obj| obj.get_string())
+        .map_err(|e| format!("{:?}", e))
+}
+
 // =============================================================================
 // HTTP Module URL Validation Tests
 // =============================================================================
@@ -19,13 +31,13 @@ fn test_http_url_normalization() {
     let interp = SafeTclInterp::new(5000, &state_path, None, None, 1000).unwrap();
 
     // Test URL normalization - should add http:// if missing
-    let result = interp.eval("::httpx::normalize_url example.com").unwrap();
+    let result = call_httpx(&interp, "::httpx::normalize_url example.com").unwrap();
     assert_eq!(result.trim(), "http://example.com");
 
-    let result = interp.eval("::httpx::normalize_url http://example.com").unwrap();
+    let result = call_httpx(&interp, "::httpx::normalize_url http://example.com").unwrap();
     assert_eq!(result.trim(), "http://example.com");
 
-    let result = interp.eval("::httpx::normalize_url https://example.com").unwrap();
+    let result = call_httpx(&interp, "::httpx::normalize_url https://example.com").unwrap();
     assert_eq!(result.trim(), "https://example.com");
 }
 
@@ -35,14 +47,14 @@ fn test_http_url_validation_blocks_localhost() {
     let interp = SafeTclInterp::new(5000, &state_path, None, None, 1000).unwrap();
 
     // Should block localhost
-    let result = interp.eval("::httpx::validate_url http://localhost/test");
+    let result = call_httpx(&interp, "::httpx::validate_url http://localhost/test");
     assert!(result.is_err());
-    assert!(result.unwrap_err().to_string().contains("localhost"));
+    assert!(result.unwrap_err().contains("localhost"));
 
     // Should block 127.0.0.1
-    let result = interp.eval("::httpx::validate_url http://127.0.0.1/test");
+    let result = call_httpx(&interp, "::httpx::validate_url http://127.0.0.1/test");
     assert!(result.is_err());
-    assert!(result.unwrap_err().to_string().contains("localhost"));
+    assert!(result.unwrap_err().contains("localhost"));
 }
 
 #[test]
@@ -51,19 +63,19 @@ fn test_http_url_validation_blocks_private_ips() {
     let interp = SafeTclInterp::new(5000, &state_path, None, None, 1000).unwrap();
 
     // Should block 10.x.x.x
-    let result = interp.eval("::httpx::validate_url http://10.0.0.1/test");
+    let result = call_httpx(&interp, "::httpx::validate_url http://10.0.0.1/test");
     assert!(result.is_err());
-    assert!(result.unwrap_err().to_string().contains("private"));
+    assert!(result.unwrap_err().contains("private"));
 
     // Should block 192.168.x.x
-    let result = interp.eval("::httpx::validate_url http://192.168.1.1/test");
+    let result = call_httpx(&interp, "::httpx::validate_url http://192.168.1.1/test");
     assert!(result.is_err());
-    assert!(result.unwrap_err().to_string().contains("private"));
+    assert!(result.unwrap_err().contains("private"));
 
     // Should block 172.16-31.x.x
-    let result = interp.eval("::httpx::validate_url http://172.16.0.1/test");
+    let result = call_httpx(&interp, "::httpx::validate_url http://172.16.0.1/test");
     assert!(result.is_err());
-    assert!(result.unwrap_err().to_string().contains("private"));
+    assert!(result.unwrap_err().contains("private"));
 }
 
 #[test]
@@ -72,9 +84,9 @@ fn test_http_url_validation_blocks_link_local() {
     let interp = SafeTclInterp::new(5000, &state_path, None, None, 1000).unwrap();
 
     // Should block 169.254.x.x
-    let result = interp.eval("::httpx::validate_url http://169.254.1.1/test");
+    let result = call_httpx(&interp, "::httpx::validate_url http://169.254.1.1/test");
     assert!(result.is_err());
-    assert!(result.unwrap_err().to_string().contains("link-local"));
+    assert!(result.unwrap_err().contains("link-local"));
 }
 
 #[test]
@@ -83,10 +95,10 @@ fn test_http_url_validation_allows_public_urls() {
     let interp = SafeTclInterp::new(5000, &state_path, None, None, 1000).unwrap();
 
     // Should allow public URLs
-    let result = interp.eval("::httpx::validate_url http://example.com/test").unwrap();
+    let result = call_httpx(&interp, "::httpx::validate_url http://example.com/test").unwrap();
     assert_eq!(result.trim(), "1");
 
-    let result = interp.eval("::httpx::validate_url https://google.com").unwrap();
+    let result = call_httpx(&interp, "::httpx::validate_url https://google.com").unwrap();
     assert_eq!(result.trim(), "1");
 }
 
@@ -96,13 +108,12 @@ fn test_http_transfer_limit() {
     let interp = SafeTclInterp::new(5000, &state_path, None, None, 1000).unwrap();
 
     // Set up context
-    interp.eval("set ::nick testuser").unwrap();
-    interp.eval("set ::nick_channel #test").unwrap();
+    interp.set_caller("testuser", "user@host", "#test");
 
     // Try to exceed transfer limit in single request
-    let result = interp.eval("::httpx::check_limits 1000000");
+    let result = call_httpx(&interp, "::httpx::check_limits 1000000");
     assert!(result.is_err());
-    assert!(result.unwrap_err().to_string().contains("transfer limit"));
+    assert!(result.unwrap_err().contains("transfer limit"));
 }
 
 // =============================================================================
@@ -143,21 +154,27 @@ fn test_http_get_channel_and_user() {
     let interp = SafeTclInterp::new(5000, &state_path, None, None, 1000).unwrap();
 
     // Test without context set
-    let result = interp.eval("::httpx::get_channel").unwrap();
+    let result = call_httpx(&interp, "::httpx::get_channel").unwrap();
     assert_eq!(result.trim(), "");
 
-    let result = interp.eval("::httpx::get_user").unwrap();
+    let result = call_httpx(&interp, "::httpx::get_user").unwrap();
     assert_eq!(result.trim(), "unknown");
 
     // Set context
-    interp.eval("set ::nick testuser").unwrap();
-    interp.eval("set ::nick_channel #test").unwrap();
+    interp.set_caller("testuser", "user@host", "#test");
 
-    let result = interp.eval("::httpx::get_channel").unwrap();
+    let result = call_httpx(&interp, "::httpx::get_channel").unwrap();
     assert_eq!(result.trim(), "#test");
 
-    let result = interp.eval("::httpx::get_user").unwrap();
+    let result = call_httpx(&interp, "::httpx::get_user").unwrap();
+    assert_eq!(result.trim(), "testuser");
+
+    // Scripts can't bill their requests to someone else
+    interp.eval("set ::nick someone_else; set ::nick_channel #other").unwrap();
+    let result = call_httpx(&interp, "::httpx::get_user").unwrap();
     assert_eq!(result.trim(), "testuser");
+    let result = call_httpx(&interp, "::httpx::get_channel").unwrap();
+    assert_eq!(result.trim(), "#test");
 }
 
 #[test]
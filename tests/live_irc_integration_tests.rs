@@ -91,6 +91,10 @@ impl TestBot {
             blacklisted_users: vec![],
             memory_limit_mb: 0,
             max_recursion_depth: 1000,
+            max_eval_commands: 0,
+            worker_process: false,
+            worker_cpu_limit_secs: 0,
+            worker_max_open_files: 0,
             notify_self: false,
         };
 
@@ -99,6 +103,14 @@ impl TestBot {
             state_repo: None,
             max_output_lines: 10,
             ssh_key: None,
+            state_branch: None,
+            state_username: None,
+            state_token_file: None,
+            state_token_env: None,
+            state_credential_helper: false,
+            transactional_evals: false,
+            commit_coalescing: Default::default(),
+            sync_interval_secs: 0,
         };
 
         // Spawn TCL plugin
@@ -1189,7 +1201,7 @@ async fn test_live_httpx_namespace() {
 }
 
 #[tokio::test]
-async fn test_live_httpx_normalize_url() {
+async fn test_live_httpx_internals_hidden() {
     let _server = Lazy::force(&SHARED_SERVER);
     let test_id = get_unique_test_id();
     let channel = format!("#test{}", test_id);
@@ -1202,13 +1214,13 @@ async fn test_live_httpx_normalize_url() {
     let _bot = TestBot::start_with_channel(&state_path, &channel, &bot_nick).await.expect("Failed to start bot");
     let (client, mut stream) = create_test_client_with_channel(&client_nick, &channel).await.expect("Failed to connect");
 
-    // Test URL normalization
+    // Internal helpers stay in the master; only the http entry points are exposed
     client.send_privmsg(&channel, "tcl ::httpx::normalize_url example.com").expect("Failed to send");
 
     if let Some(response) = wait_for_response_from(&mut stream, 10, &channel, &bot_nick).await {
-        assert_eq!(response.trim(), "http://example.com");
+        assert!(response.contains("invalid command"), "Expected invalid command, got: {}", response);
     } else {
-        panic!("No response received for URL normalization");
+        panic!("No response received for hidden httpx helper");
     }
 
     let _ = fs::remove_dir_all(&state_path);
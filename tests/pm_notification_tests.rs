@@ -14,6 +14,10 @@ fn test_extract_admin_nicks_from_hostmasks() {
         blacklisted_users: vec![],
         memory_limit_mb: 0, // Disabled for tests - RLIMIT_AS affects entire process
         max_recursion_depth: 1000,
+        max_eval_commands: 0,
+        worker_process: false,
+        worker_cpu_limit_secs: 0,
+        worker_max_open_files: 0,
         notify_self: false,
     };
 
@@ -100,6 +104,10 @@ fn test_empty_admin_list() {
         blacklisted_users: vec![],
         memory_limit_mb: 0, // Disabled for tests - RLIMIT_AS affects entire process
         max_recursion_depth: 1000,
+        max_eval_commands: 0,
+        worker_process: false,
+        worker_cpu_limit_secs: 0,
+        worker_max_open_files: 0,
         notify_self: false,
     };
 
@@ -127,6 +135,10 @@ fn test_wildcard_only_patterns() {
         blacklisted_users: vec![],
         memory_limit_mb: 0, // Disabled for tests - RLIMIT_AS affects entire process
         max_recursion_depth: 1000,
+        max_eval_commands: 0,
+        worker_process: false,
+        worker_cpu_limit_secs: 0,
+        worker_max_open_files: 0,
         notify_self: false,
     };
 
@@ -155,6 +167,10 @@ fn test_complex_hostmask_patterns() {
         blacklisted_users: vec![],
         memory_limit_mb: 0, // Disabled for tests - RLIMIT_AS affects entire process
         max_recursion_depth: 1000,
+        max_eval_commands: 0,
+        worker_process: false,
+        worker_cpu_limit_secs: 0,
+        worker_max_open_files: 0,
         notify_self: false,
     };
 
@@ -185,6 +201,10 @@ fn test_duplicate_admin_nicks() {
         blacklisted_users: vec![],
         memory_limit_mb: 0, // Disabled for tests - RLIMIT_AS affects entire process
         max_recursion_depth: 1000,
+        max_eval_commands: 0,
+        worker_process: false,
+        worker_cpu_limit_secs: 0,
+        worker_max_open_files: 0,
         notify_self: false,
     };
 
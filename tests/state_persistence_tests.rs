@@ -1,8 +1,11 @@
-use slopdrop::state::{InterpreterState, StatePersistence, StateChanges, UserInfo};
+use slopdrop::state::{load_item, source_items, ConflictResolution, FsckIssue, InterpreterState, RemoteAuth, StatePersistence, StateChanges, UserInfo};
+use slopdrop::push_queue;
 use slopdrop::smeggdrop_commands;
+use slopdrop::state_bundle::{BundleFormat, StateBundle};
 use std::collections::HashSet;
 use std::fs;
 use std::path::PathBuf;
+use std::time::Duration;
 use tempfile::TempDir;
 use tcl::Interpreter;
 
@@ -365,23 +368,27 @@ fn test_user_info_to_signature() {
 
 #[test]
 fn test_state_changes_has_changes() {
-    let mut changes = StateChanges { new_procs: vec![], deleted_procs: vec![], new_vars: vec![], deleted_vars: vec![] };
+    let mut changes = StateChanges { new_procs: vec![], deleted_procs: vec![], new_vars: vec![], deleted_vars: vec![], new_namespaces: vec![], deleted_namespaces: vec![] };
     assert!(!changes.has_changes());
 
     changes.new_procs.push("test".to_string());
     assert!(changes.has_changes());
 
-    changes = StateChanges { new_procs: vec![], deleted_procs: vec![], new_vars: vec![], deleted_vars: vec![] };
+    changes = StateChanges { new_procs: vec![], deleted_procs: vec![], new_vars: vec![], deleted_vars: vec![], new_namespaces: vec![], deleted_namespaces: vec![] };
     changes.deleted_procs.push("test".to_string());
     assert!(changes.has_changes());
 
-    changes = StateChanges { new_procs: vec![], deleted_procs: vec![], new_vars: vec![], deleted_vars: vec![] };
+    changes = StateChanges { new_procs: vec![], deleted_procs: vec![], new_vars: vec![], deleted_vars: vec![], new_namespaces: vec![], deleted_namespaces: vec![] };
     changes.new_vars.push("test".to_string());
     assert!(changes.has_changes());
 
-    changes = StateChanges { new_procs: vec![], deleted_procs: vec![], new_vars: vec![], deleted_vars: vec![] };
+    changes = StateChanges { new_procs: vec![], deleted_procs: vec![], new_vars: vec![], deleted_vars: vec![], new_namespaces: vec![], deleted_namespaces: vec![] };
     changes.deleted_vars.push("test".to_string());
     assert!(changes.has_changes());
+
+    changes = StateChanges::default();
+    changes.new_namespaces.push("test".to_string());
+    assert!(changes.has_changes());
 }
 
 #[test]
@@ -1121,3 +1128,552 @@ fn test_multiple_array_elements_single_tracking() {
     assert_eq!(modified.iter().filter(|v| *v == "data").count(), 1,
                "Array should only appear once in modified list");
 }
+
+#[test]
+fn test_migrate_layout_reports_every_broken_item() {
+    let (_temp, state_path) = create_temp_state();
+    let interp = create_test_interp();
+
+    let persistence = StatePersistence::with_repo(state_path.clone(), None, None);
+    persistence.ensure_initialized().unwrap();
+    let user_info = UserInfo::new("testuser".to_string(), "testhost".to_string());
+
+    let before = InterpreterState::capture(&interp).unwrap();
+    interp.eval("proc greet {} { return hi }").unwrap();
+    interp.eval("set good 1; set bad 2").unwrap();
+    let after = InterpreterState::capture(&interp).unwrap();
+    let changes = before.diff(&after, &HashSet::new(), &HashSet::new());
+    persistence.save_changes(&interp, &changes, &user_info, "setup").unwrap();
+
+    // Lose the proc's content file and garble one var
+    let blob = |kind: &str, name: &str| {
+        let index = fs::read_to_string(state_path.join(kind).join("_index")).unwrap();
+        let line = index.lines().find(|line| line.starts_with(&format!("{} ", name))).unwrap();
+        state_path.join(kind).join(line.split_whitespace().nth(1).unwrap())
+    };
+    fs::remove_file(blob("procs", "greet")).unwrap();
+    fs::write(blob("vars", "bad"), "garbage").unwrap();
+
+    let scratch = create_test_interp();
+    let err = persistence.migrate_layout(&scratch).unwrap_err().to_string();
+    assert!(err.contains("proc greet"), "{}", err);
+    assert!(err.contains("var bad"), "{}", err);
+    assert!(!err.contains("var good"), "{}", err);
+
+    // Nothing was touched
+    assert!(!state_path.join("LAYOUT").exists());
+    assert!(state_path.join("vars/_index").exists());
+}
+
+#[test]
+fn test_migrate_layout_without_vars_dir() {
+    let (_temp, state_path) = create_temp_state();
+    let interp = create_test_interp();
+
+    let persistence = StatePersistence::with_repo(state_path.clone(), None, None);
+    persistence.ensure_initialized().unwrap();
+    let user_info = UserInfo::new("testuser".to_string(), "testhost".to_string());
+
+    let before = InterpreterState::capture(&interp).unwrap();
+    interp.eval("proc greet {} { return hi }").unwrap();
+    let after = InterpreterState::capture(&interp).unwrap();
+    let changes = before.diff(&after, &HashSet::new(), &HashSet::new());
+    persistence.save_changes(&interp, &changes, &user_info, "setup").unwrap();
+    let _ = fs::remove_dir_all(state_path.join("vars"));
+
+    let scratch = create_test_interp();
+    persistence.migrate_layout(&scratch).unwrap();
+    assert!(state_path.join("procs/greet.tcl").exists());
+}
+
+#[test]
+fn test_migrate_to_v2_layout() {
+    let (_temp, state_path) = create_temp_state();
+    let interp = create_test_interp();
+
+    let persistence = StatePersistence::with_repo(state_path.clone(), None, None);
+    persistence.ensure_initialized().unwrap();
+    let user_info = UserInfo::new("testuser".to_string(), "testhost".to_string());
+
+    // Some v1 state first
+    let before = InterpreterState::capture(&interp).unwrap();
+    interp.eval("proc greet {name} { return \"Hello, $name!\" }").unwrap();
+    interp.eval("array set scores {bob 20 alice 10}").unwrap();
+    let after = InterpreterState::capture(&interp).unwrap();
+    let changes = before.diff(&after, &HashSet::new(), &HashSet::new());
+    persistence.save_changes(&interp, &changes, &user_info, "setup").unwrap();
+
+    let scratch = create_test_interp();
+    let commit = persistence.migrate_layout(&scratch).unwrap();
+    assert_eq!(commit.author, "slopdrop");
+
+    assert_eq!(fs::read_to_string(state_path.join("LAYOUT")).unwrap().trim(), "2");
+    assert!(!state_path.join("procs/_index").exists());
+    assert!(fs::read_to_string(state_path.join("procs/greet.tcl")).unwrap().contains("Hello, $name!"));
+    assert_eq!(fs::read_to_string(state_path.join("vars/scores.array")).unwrap(), "alice 10\nbob 20");
+    assert!(persistence.migrate_layout(&scratch).is_err());
+
+    // Loading reads the new files
+    let items = source_items(state_path.as_path(), "vars").unwrap();
+    let fresh = create_test_interp();
+    load_item(&fresh, "vars", "scores", &items["scores"]).unwrap();
+    assert_eq!(fresh.eval("set scores(bob)").unwrap().get_string(), "20");
+
+    // Later saves use the new layout, and history is kept
+    let before = InterpreterState::capture(&interp).unwrap();
+    interp.eval("set config [dict create b 2 a 1]; unset scores").unwrap();
+    let after = InterpreterState::capture(&interp).unwrap();
+    let changes = before.diff(&after, &HashSet::new(), &HashSet::new());
+    persistence.save_changes(&interp, &changes, &user_info, "change").unwrap();
+
+    assert_eq!(fs::read_to_string(state_path.join("vars/config.dict")).unwrap(), "b 2\na 1");
+    assert!(!state_path.join("vars/scores.array").exists());
+    assert_eq!(persistence.get_history(10).unwrap().len(), 4);
+    assert_eq!(persistence.proc_history("greet", 10).unwrap().len(), 1);
+}
+
+#[test]
+fn test_fsck_finds_and_repairs_problems() {
+    let (_temp, state_path) = create_temp_state();
+    let interp = create_test_interp();
+
+    let persistence = StatePersistence::with_repo(state_path.clone(), None, None);
+    persistence.ensure_initialized().unwrap();
+    let user_info = UserInfo::new("testuser".to_string(), "testhost".to_string());
+
+    let before = InterpreterState::capture(&interp).unwrap();
+    interp.eval("proc greet {name} { return \"Hello, $name!\" }").unwrap();
+    interp.eval("set counter 1").unwrap();
+    let after = InterpreterState::capture(&interp).unwrap();
+    let changes = before.diff(&after, &HashSet::new(), &HashSet::new());
+    persistence.save_changes(&interp, &changes, &user_info, "setup").unwrap();
+
+    let report = persistence.fsck(&create_test_interp()).unwrap();
+    assert!(report.is_clean(), "{}", report.render());
+    assert_eq!((report.procs, report.vars), (1, 1));
+
+    // A dangling index entry, a var that can't be restored, and an orphan
+    let index = state_path.join("procs/_index");
+    let mut entries = fs::read_to_string(&index).unwrap();
+    entries.push_str("\nghost 0000000000000000000000000000000000000000");
+    fs::write(&index, entries).unwrap();
+    let index = state_path.join("vars/_index");
+    let mut entries = fs::read_to_string(&index).unwrap();
+    entries.push_str("\nbroken 1111111111111111111111111111111111111111");
+    fs::write(&index, entries).unwrap();
+    fs::write(state_path.join("vars/1111111111111111111111111111111111111111"), "array {a}").unwrap();
+    fs::write(state_path.join("procs/stray"), "{} {}").unwrap();
+
+    let report = persistence.fsck(&create_test_interp()).unwrap();
+    assert!(report.issues.iter().any(|issue| matches!(issue, FsckIssue::MissingBlob { name, .. } if name == "ghost")));
+    assert!(report.issues.iter().any(|issue| matches!(issue, FsckIssue::Broken { name, .. } if name == "broken")));
+    assert!(report.issues.iter().any(|issue| matches!(issue, FsckIssue::Orphan { path } if path == "procs/stray")));
+    assert!(report.render().contains("3 problems"));
+
+    let commit = persistence.fsck_repair(&report, &user_info).unwrap().unwrap();
+    assert_eq!(commit.author, "testuser");
+    assert!(!state_path.join("procs/stray").exists());
+
+    let report = persistence.fsck(&create_test_interp()).unwrap();
+    assert!(report.issues.iter().all(|issue| !issue.breaks_loading()), "{}", report.render());
+    assert_eq!((report.procs, report.vars), (1, 1));
+}
+
+#[test]
+fn test_export_import_bundle() {
+    let (_temp, state_path) = create_temp_state();
+    let persistence = StatePersistence::with_repo(state_path.clone(), None, None);
+    persistence.ensure_initialized().unwrap();
+    let user_info = UserInfo::new("alice".to_string(), "example.com".to_string());
+
+    let script = r#"
+        namespace eval ::cache {}
+        proc greet {name} { return "Hello, $name!" }
+        proc ::cache::get {key} { return $key }
+        set counter 1
+        array set scores {bob 20 alice 10}
+        set ::cache::hits 5
+    "#;
+    let bundle = StateBundle::parse(script, BundleFormat::Tcl).unwrap();
+    assert!(bundle.procs.contains_key("greet"));
+    assert!(bundle.procs.contains_key("::cache::get"));
+    assert_eq!(bundle.vars["scores"].value, "alice 10 bob 20");
+    assert!(bundle.vars.contains_key("cache::hits"));
+    assert!(bundle.namespaces.contains("cache"));
+
+    // Importing into an empty state is one commit by the importer
+    let (commit, changes) = persistence.import_bundle(&bundle, false, &user_info).unwrap();
+    assert_eq!(commit.unwrap().author, "alice");
+    assert_eq!(changes.new_procs.len(), 2);
+    assert_eq!(persistence.get_history(10).unwrap().len(), 2);
+
+    // Exporting gives the same bundle back, in either format
+    let exported = persistence.export_bundle().unwrap();
+    assert_eq!(exported, bundle);
+    let json = exported.render(BundleFormat::Json).unwrap();
+    assert_eq!(StateBundle::parse(&json, BundleFormat::Json).unwrap(), exported);
+    let tcl = exported.render(BundleFormat::Tcl).unwrap();
+    assert_eq!(StateBundle::parse(&tcl, BundleFormat::Tcl).unwrap(), exported);
+
+    // Merging what's already there changes nothing
+    let (commit, _) = persistence.import_bundle(&exported, false, &user_info).unwrap();
+    assert!(commit.is_none());
+
+    // Replacing deletes whatever the bundle leaves out
+    let mut smaller = exported.clone();
+    smaller.procs.retain(|name, _| name == "greet");
+    smaller.vars.clear();
+    smaller.namespaces.clear();
+    let (commit, changes) = persistence.import_bundle(&smaller, true, &user_info).unwrap();
+    assert!(commit.is_some());
+    assert_eq!(changes.deleted_procs, vec!["::cache::get".to_string()]);
+    assert_eq!(changes.deleted_namespaces, vec!["cache".to_string()]);
+    assert_eq!(persistence.export_bundle().unwrap(), smaller);
+}
+
+#[test]
+fn test_persist_off_makes_vars_ephemeral() {
+    let (_temp, state_path) = create_temp_state();
+    let interp = create_test_interp();
+    load_proc_tracking(&interp);
+
+    let persistence = StatePersistence::with_repo(state_path.clone(), None, None);
+    persistence.ensure_initialized().unwrap();
+    let user_info = UserInfo::new("alice".to_string(), "host".to_string());
+
+    let save = |code: &str| -> StateChanges {
+        let before = InterpreterState::capture(&interp).unwrap();
+        interp.eval(code).unwrap();
+        let after = InterpreterState::capture(&interp).unwrap();
+        let changes = before.diff(&after, &HashSet::new(), &HashSet::new());
+        persistence.save_changes(&interp, &changes, &user_info, code).unwrap();
+        changes
+    };
+    let persisted_vars = || -> Vec<String> { persistence.export_bundle().unwrap().vars.into_keys().collect() };
+
+    save("set scratch 1; set tmp_a 2; set keep 3");
+    assert_eq!(persisted_vars(), vec!["keep", "scratch", "tmp_a"]);
+
+    // Marking persisted vars ephemeral deletes them; the rules are persisted instead
+    let mut changes = save("persist off scratch; persist ephemeral -pattern tmp_*");
+    changes.deleted_vars.sort();
+    assert_eq!(changes.deleted_vars, vec!["scratch", "tmp_a"]);
+    assert_eq!(persisted_vars(), vec!["keep", "slopdrop_ephemeral"]);
+
+    // New matching vars and ephemeral namespaces are never persisted
+    let changes = save("set tmp_b 4; namespace eval ::scratchpad { variable x 1 }; persist off ::scratchpad");
+    assert!(!changes.new_vars.iter().any(|name| name == "tmp_b" || name == "scratchpad::x"));
+    assert!(changes.new_namespaces.is_empty());
+
+    let rules = interp.eval("persist list").unwrap().get_string();
+    assert!(rules.contains("pattern tmp_*"));
+    assert!(rules.contains("namespace scratchpad"));
+    assert!(rules.contains("var errorInfo (built in)"));
+    assert!(interp.eval("persist on nick").is_err());
+
+    // Persisting a var again saves it
+    let changes = save("persist on scratch");
+    assert!(changes.new_vars.contains(&"scratch".to_string()));
+}
+
+#[test]
+fn test_push_queue_retries_until_remote_is_reachable() {
+    let (temp, state_path) = create_temp_state();
+    let remote_path = temp.path().join("remote.git");
+    let remote_url = remote_path.to_string_lossy().into_owned();
+    StatePersistence::with_repo(state_path.clone(), None, None).ensure_initialized().unwrap();
+
+    let persistence = StatePersistence::with_repo(state_path.clone(), Some(remote_url.clone()), None);
+    let interp = create_test_interp();
+    let user_info = UserInfo::new("alice".to_string(), "host".to_string());
+
+    // The remote doesn't exist yet: the commit still goes through and the push waits
+    let before = InterpreterState::capture(&interp).unwrap();
+    interp.eval("set counter 1").unwrap();
+    let after = InterpreterState::capture(&interp).unwrap();
+    let changes = before.diff(&after, &HashSet::new(), &HashSet::new());
+    assert!(persistence.save_changes(&interp, &changes, &user_info, "set counter 1").unwrap().is_some());
+
+    let queue = push_queue::queue(&persistence);
+    assert!(queue.flush(Duration::from_secs(30)).is_err());
+    let status = queue.status();
+    assert_eq!(status.unpushed, 1);
+    assert!(status.last_error.is_some());
+    assert!(status.render().starts_with("1 commits unpushed, last error: "));
+
+    // Once it's there the retry pushes everything
+    git2::Repository::init_bare(&remote_path).unwrap();
+    queue.flush(Duration::from_secs(30)).unwrap();
+    assert_eq!(queue.status().unpushed, 0);
+
+    let local = git2::Repository::open(&state_path).unwrap();
+    let head = local.head().unwrap();
+    let remote = git2::Repository::open_bare(&remote_path).unwrap();
+    assert_eq!(remote.refname_to_id(head.name().unwrap()).unwrap(), head.target().unwrap());
+    assert_eq!(persistence.unpushed_commits().unwrap(), 0);
+
+    // A commit the queue never heard of (say, by a worker that died before
+    // pushing) is picked up on resume
+    let signature = git2::Signature::now("bob", "bob@host").unwrap();
+    let parent = head.peel_to_commit().unwrap();
+    let tree = parent.tree().unwrap();
+    local.commit(Some("HEAD"), &signature, &signature, "left behind", &tree, &[&parent]).unwrap();
+    assert_eq!(persistence.unpushed_commits().unwrap(), 1);
+    queue.resume();
+    assert_eq!(queue.status().unpushed, 1);
+    queue.flush(Duration::from_secs(30)).unwrap();
+    assert_eq!(persistence.unpushed_commits().unwrap(), 0);
+}
+
+/// Eval `code` and commit what it changed
+fn eval_and_save(persistence: &StatePersistence, interp: &Interpreter, user_info: &UserInfo, code: &str) {
+    let before = InterpreterState::capture(interp).unwrap();
+    interp.eval(code).unwrap();
+    let after = InterpreterState::capture(interp).unwrap();
+    let changes = before.diff(&after, &HashSet::new(), &HashSet::new());
+    persistence.save_changes(interp, &changes, user_info, code).unwrap();
+}
+
+#[test]
+fn test_sync_merges_changes_pushed_by_another_instance() {
+    let (temp, state_path) = create_temp_state();
+    let remote_path = temp.path().join("remote.git");
+    let remote_url = remote_path.to_string_lossy().into_owned();
+    git2::Repository::init_bare(&remote_path).unwrap();
+
+    // Instance A starts the state, instance B clones it
+    let ours = StatePersistence::with_repo(state_path.clone(), Some(remote_url.clone()), None);
+    ours.ensure_initialized().unwrap();
+    push_queue::queue(&ours).flush(Duration::from_secs(30)).unwrap();
+    let their_path = temp.path().join("other");
+    let theirs = StatePersistence::with_repo(their_path.clone(), Some(remote_url.clone()), None);
+    theirs.ensure_initialized().unwrap();
+
+    let our_interp = create_test_interp();
+    let their_interp = create_test_interp();
+    let alice = UserInfo::new("alice".to_string(), "host".to_string());
+    let bob = UserInfo::new("bob".to_string(), "host".to_string());
+
+    // B's new proc comes over and is loaded into A's interpreter
+    eval_and_save(&theirs, &their_interp, &bob, "proc greet {} { return hi }");
+    push_queue::queue(&theirs).flush(Duration::from_secs(30)).unwrap();
+
+    let outcome = ours.sync_with_remote(ConflictResolution::Report).unwrap();
+    assert_eq!(outcome.incoming, 1);
+    assert!(outcome.merge_commit.is_none());
+    assert_eq!(outcome.changes.new_procs, vec!["greet"]);
+    ours.reload_changes(&our_interp, &outcome.changes).unwrap();
+    assert_eq!(our_interp.eval("greet").unwrap().get_string(), "hi");
+    assert!(ours.sync_with_remote(ConflictResolution::Report).unwrap().render().starts_with("Already up to date"));
+
+    // Both set the same var: reported and left as ours until a side is
+    // picked, while the rest of the remote comes over
+    eval_and_save(&theirs, &their_interp, &bob, "set topic theirs");
+    eval_and_save(&theirs, &their_interp, &bob, "proc bye {} { return bye }");
+    push_queue::queue(&theirs).flush(Duration::from_secs(30)).unwrap();
+    eval_and_save(&ours, &our_interp, &alice, "set topic ours");
+
+    let outcome = ours.sync_with_remote(ConflictResolution::Report).unwrap();
+    assert_eq!(outcome.conflicts, vec!["var topic"]);
+    assert_eq!(outcome.changes.new_procs, vec!["bye"]);
+    assert!(outcome.changes.new_vars.is_empty());
+    assert!(outcome.render().contains("merged the rest"), "{}", outcome.render());
+    let partial = git2::Repository::open(&state_path).unwrap().head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(partial.parent_count(), 1);
+    ours.reload_changes(&our_interp, &outcome.changes).unwrap();
+    assert_eq!(our_interp.eval("bye").unwrap().get_string(), "bye");
+    assert_eq!(our_interp.eval("set topic").unwrap().get_string(), "ours");
+
+    // Nothing new to merge cleanly the second time round
+    let outcome = ours.sync_with_remote(ConflictResolution::Report).unwrap();
+    assert_eq!(outcome.conflicts, vec!["var topic"]);
+    assert!(!outcome.changes.has_changes());
+    assert!(outcome.merge_commit.is_none());
+
+    let outcome = ours.sync_with_remote(ConflictResolution::Theirs).unwrap();
+    assert!(outcome.conflicts.is_empty());
+    assert!(outcome.merge_commit.is_some());
+    assert_eq!(outcome.changes.new_vars, vec!["topic"]);
+    ours.reload_changes(&our_interp, &outcome.changes).unwrap();
+    assert_eq!(our_interp.eval("set topic").unwrap().get_string(), "theirs");
+}
+
+#[test]
+fn test_configured_branch_is_pushed_and_cloned() {
+    let (temp, state_path) = create_temp_state();
+    let remote_path = temp.path().join("remote.git");
+    let remote_url = remote_path.to_string_lossy().into_owned();
+    git2::Repository::init_bare(&remote_path).unwrap();
+
+    let branch = Some("bot-state".to_string());
+    let ours = StatePersistence::with_repo(state_path.clone(), Some(remote_url.clone()), None).with_branch(branch.clone());
+    ours.ensure_initialized().unwrap();
+    let interp = create_test_interp();
+    let user_info = UserInfo::new("alice".to_string(), "host".to_string());
+    eval_and_save(&ours, &interp, &user_info, "set counter 1");
+    push_queue::queue(&ours).flush(Duration::from_secs(30)).unwrap();
+
+    // Only the configured branch exists on the remote, no main/master guess
+    let remote = git2::Repository::open_bare(&remote_path).unwrap();
+    let local_head = git2::Repository::open(&state_path).unwrap().head().unwrap().target().unwrap();
+    assert_eq!(remote.refname_to_id("refs/heads/bot-state").unwrap(), local_head);
+    assert!(remote.find_reference("refs/heads/main").is_err());
+    assert!(remote.find_reference("refs/heads/master").is_err());
+
+    let clone_path = temp.path().join("clone");
+    StatePersistence::with_repo(clone_path.clone(), Some(remote_url), None)
+        .with_branch(branch)
+        .ensure_initialized()
+        .unwrap();
+    let clone = git2::Repository::open(&clone_path).unwrap();
+    assert_eq!(clone.head().unwrap().shorthand(), Some("bot-state"));
+    assert_eq!(clone.head().unwrap().target().unwrap(), local_head);
+}
+
+#[test]
+fn test_access_token_read_from_file_or_env() {
+    let (temp, _) = create_temp_state();
+    let token_file = temp.path().join("token");
+    fs::write(&token_file, "ghp_secret\n").unwrap();
+
+    let auth = RemoteAuth {
+        token_file: Some(token_file.clone()),
+        token_env: Some("SLOPDROP_TEST_UNSET_TOKEN".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(auth.token().unwrap().as_deref(), Some("ghp_secret"));
+
+    std::env::set_var("SLOPDROP_TEST_STATE_TOKEN", "env_secret");
+    let auth = RemoteAuth {
+        token_env: Some("SLOPDROP_TEST_STATE_TOKEN".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(auth.token().unwrap().as_deref(), Some("env_secret"));
+
+    let auth = RemoteAuth {
+        token_env: Some("SLOPDROP_TEST_UNSET_TOKEN".to_string()),
+        ..Default::default()
+    };
+    assert!(auth.token().is_err());
+    assert_eq!(RemoteAuth::default().token().unwrap(), None);
+
+    fs::write(&token_file, "  \n").unwrap();
+    let auth = RemoteAuth {
+        token_file: Some(token_file),
+        ..Default::default()
+    };
+    assert!(auth.token().is_err());
+}
+
+#[test]
+fn test_proc_owner_recorded_and_protection_enforced() {
+    let (_temp, state_path) = create_temp_state();
+    let interp = create_test_interp();
+    let persistence = StatePersistence::with_repo(state_path.clone(), None, None);
+    persistence.ensure_initialized().unwrap();
+    let alice = UserInfo::new("alice".to_string(), "~a@host".to_string());
+    let bob = UserInfo::new("bob".to_string(), "~b@host".to_string());
+
+    eval_and_save(&persistence, &interp, &alice, "proc greet {} { return hi }");
+    // Redefining someone else's unprotected proc keeps the original owner
+    eval_and_save(&persistence, &interp, &bob, "proc greet {} { return hello }");
+    assert_eq!(
+        fs::read_to_string(state_path.join("procs/_owners")).unwrap(),
+        "greet alice ~a@host"
+    );
+    assert!(persistence.proc_owners().denied("greet", &bob, false).is_none());
+
+    assert!(persistence.set_proc_protected("greet", true, &bob, false).is_err());
+    let commit = persistence.set_proc_protected("::greet", true, &alice, false).unwrap();
+    assert_eq!(commit.author, "alice");
+    assert!(persistence.set_proc_protected("greet", true, &alice, false).is_err());
+
+    let owners = persistence.proc_owners();
+    assert!(owners.get("greet").unwrap().protected);
+    assert!(owners.denied("greet", &bob, false).is_some());
+    assert!(owners.denied("greet", &bob, true).is_none());
+    assert!(owners.denied("greet", &alice, false).is_none());
+
+    // Drifting from its stored definition is noticed, however it happened
+    let procs = HashSet::from(["greet".to_string()]);
+    assert!(persistence.changed_protected_procs(&interp, &procs, &bob, false).unwrap().is_empty());
+    interp.eval("proc greet {} { return hacked }").unwrap();
+    assert_eq!(persistence.changed_protected_procs(&interp, &procs, &bob, false).unwrap(), vec!["greet"]);
+    assert!(persistence.changed_protected_procs(&interp, &procs, &alice, false).unwrap().is_empty());
+
+    // The owners file isn't mistaken for an orphaned proc
+    let report = persistence.fsck(&create_test_interp()).unwrap();
+    assert!(report.is_clean(), "{}", report.render());
+
+    // Deleting the proc forgets its owner
+    eval_and_save(&persistence, &interp, &alice, "rename greet {}");
+    assert!(persistence.proc_owners().get("greet").is_none());
+}
+
+#[test]
+fn test_unowned_proc_claimed_by_first_author_only() {
+    let (_temp, state_path) = create_temp_state();
+    let interp = create_test_interp();
+    let persistence = StatePersistence::with_repo(state_path.clone(), None, None);
+    persistence.ensure_initialized().unwrap();
+    let alice = UserInfo::new("alice".to_string(), "~a@host".to_string());
+
+    eval_and_save(&persistence, &interp, &alice, "proc greet {} { return hi }");
+    // As if greet was stored before owners were recorded
+    fs::write(state_path.join("procs/_owners"), "").unwrap();
+
+    // Taking alice's nick isn't enough to claim it
+    let impostor = UserInfo::new("alice".to_string(), "~m@elsewhere".to_string());
+    assert!(persistence.set_proc_protected("greet", true, &impostor, false).is_err());
+    persistence.set_proc_protected("greet", true, &alice, false).unwrap();
+    assert!(persistence.proc_owners().get("greet").unwrap().is(&alice));
+}
+
+#[test]
+fn test_reviewed_change_committed_as_submitter() {
+    let (_temp, state_path) = create_temp_state();
+    let interp = create_test_interp();
+    let persistence = StatePersistence::with_repo(state_path.clone(), None, None);
+    persistence.ensure_initialized().unwrap();
+    let alice = UserInfo::new("alice".to_string(), "~a@host".to_string());
+    let bob = UserInfo::new("bob".to_string(), "~b@host".to_string());
+    let admin = UserInfo::new("admin".to_string(), "~root@host".to_string());
+
+    eval_and_save(&persistence, &interp, &alice, "proc greet {} { return hi }");
+    persistence.set_proc_protected("greet", true, &alice, false).unwrap();
+
+    let code = "proc greet {} { return hello }";
+    let before = InterpreterState::capture(&interp).unwrap();
+    interp.eval(code).unwrap();
+    let after = InterpreterState::capture(&interp).unwrap();
+    let changes = before.diff(&after, &HashSet::new(), &HashSet::new());
+    let held = persistence.proc_owners().denied_changes(&changes, &bob, false);
+    assert_eq!(held.new_procs, vec!["greet"]);
+
+    let submission = StatePersistence::held_changes(&interp, &held).unwrap();
+    let review = persistence.submit_for_review(&submission, &bob, code).unwrap();
+    assert_eq!((review.id, review.author.as_str()), (1, "bob"));
+    assert_eq!(persistence.pending_reviews().unwrap().len(), 1);
+    let report = persistence.review_diff(1).unwrap();
+    assert!(report.diffs[0].contains("return hello"), "{}", report.diffs[0]);
+
+    let (commit, reloaded) = persistence.approve_review(1, &admin).unwrap();
+    assert_eq!(commit.author, "bob");
+    assert_eq!(reloaded.new_procs, vec!["greet"]);
+    let repo = git2::Repository::open(&state_path).unwrap();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.author().name(), Some("bob"));
+    assert_eq!(head.committer().name(), Some("admin"));
+    assert!(persistence.pending_reviews().unwrap().is_empty());
+    assert_eq!(persistence.proc_history("greet", 10).unwrap()[0].author, "bob");
+
+    // A change to a proc that moved on since can only be rejected
+    interp.eval("proc greet {} { return hey }").unwrap();
+    let submission = StatePersistence::held_changes(&interp, &held).unwrap();
+    let review = persistence.submit_for_review(&submission, &bob, "proc greet {} { return hey }").unwrap();
+    assert_eq!(review.id, 2);
+    eval_and_save(&persistence, &interp, &alice, "proc greet {} { return howdy }");
+    assert!(persistence.approve_review(2, &admin).is_err());
+    assert_eq!(persistence.reject_review(2).unwrap().nick, "bob");
+    assert!(persistence.reject_review(2).is_err());
+}
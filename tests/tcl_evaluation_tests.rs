@@ -99,6 +99,10 @@ async fn test_timeout_handling() {
         blacklisted_users: vec![],
         memory_limit_mb: 0, // Disabled for tests - RLIMIT_AS affects entire process
         max_recursion_depth: 1000,
+        max_eval_commands: 0,
+        worker_process: false,
+        worker_cpu_limit_secs: 0,
+        worker_max_open_files: 0,
         notify_self: false,
     };
 
@@ -106,6 +110,14 @@ async fn test_timeout_handling() {
         state_path: state_path.clone(),
         state_repo: None,
         ssh_key: None,
+        state_branch: None,
+        state_username: None,
+        state_token_file: None,
+        state_token_env: None,
+        state_credential_helper: false,
+        transactional_evals: false,
+        commit_coalescing: Default::default(),
+        sync_interval_secs: 0,
         max_output_lines: 10,
     };
 
@@ -139,6 +151,135 @@ async fn test_timeout_handling() {
     tcl_thread.shutdown();
 }
 
+#[tokio::test]
+async fn test_timeout_keeps_interpreter_state() {
+    let (_temp, state_path) = create_temp_state();
+
+    let security_config = SecurityConfig {
+        eval_timeout_ms: 500,
+        privileged_users: vec![],
+        blacklisted_users: vec![],
+        memory_limit_mb: 0, // Disabled for tests - RLIMIT_AS affects entire process
+        max_recursion_depth: 1000,
+        max_eval_commands: 0,
+        worker_process: false,
+        worker_cpu_limit_secs: 0,
+        worker_max_open_files: 0,
+        notify_self: false,
+    };
+
+    let tcl_config = TclConfig {
+        state_path: state_path.clone(),
+        state_repo: None,
+        ssh_key: None,
+        state_branch: None,
+        state_username: None,
+        state_token_file: None,
+        state_token_env: None,
+        state_credential_helper: false,
+        transactional_evals: false,
+        commit_coalescing: Default::default(),
+        sync_interval_secs: 0,
+        max_output_lines: 10,
+    };
+
+    let channel_members = Arc::new(RwLock::new(HashMap::new()));
+    let mut tcl_thread = TclThreadHandle::spawn(tcl_config, security_config, channel_members).unwrap();
+
+    // Variable that only lives in memory (system evals are not persisted)
+    tcl_thread.eval_simple("set ::unsaved_marker alive".to_string()).await.unwrap();
+
+    // Limit errors can't be swallowed by catch
+    let start = std::time::Instant::now();
+    let result = tcl_thread.eval(
+        "catch { while {1} {} }".to_string(),
+        false,
+        "testuser".to_string(),
+        "testhost".to_string(),
+        "#test".to_string(),
+    ).await.unwrap();
+    assert!(result.is_error);
+    assert!(result.output.contains("timed out"));
+    assert!(!result.output.contains("restarted"), "Thread should not restart: {}", result.output);
+    assert!(start.elapsed() < std::time::Duration::from_secs(3));
+
+    // Same interpreter is still running, so unsaved state survived
+    let output = tcl_thread.eval_simple("set ::unsaved_marker".to_string()).await.unwrap();
+    assert_eq!(output, "alive");
+
+    tcl_thread.shutdown();
+}
+
+#[tokio::test]
+async fn test_command_limit() {
+    let (_temp, state_path) = create_temp_state();
+
+    let security_config = SecurityConfig {
+        eval_timeout_ms: 5000,
+        privileged_users: vec![],
+        blacklisted_users: vec![],
+        memory_limit_mb: 0, // Disabled for tests - RLIMIT_AS affects entire process
+        max_recursion_depth: 1000,
+        max_eval_commands: 10000,
+        worker_process: false,
+        worker_cpu_limit_secs: 0,
+        worker_max_open_files: 0,
+        notify_self: false,
+    };
+
+    let tcl_config = TclConfig {
+        state_path: state_path.clone(),
+        state_repo: None,
+        ssh_key: None,
+        state_branch: None,
+        state_username: None,
+        state_token_file: None,
+        state_token_env: None,
+        state_credential_helper: false,
+        transactional_evals: false,
+        commit_coalescing: Default::default(),
+        sync_interval_secs: 0,
+        max_output_lines: 10,
+    };
+
+    let channel_members = Arc::new(RwLock::new(HashMap::new()));
+    let mut tcl_thread = TclThreadHandle::spawn(tcl_config, security_config, channel_members).unwrap();
+
+    let result = tcl_thread.eval(
+        "while {1} { incr x }".to_string(),
+        false,
+        "testuser".to_string(),
+        "testhost".to_string(),
+        "#test".to_string(),
+    ).await.unwrap();
+    assert!(result.is_error);
+    assert!(result.output.contains("command limit"), "Unexpected output: {}", result.output);
+
+    // A script raising the limit's error text itself is a plain error
+    let result = tcl_thread.eval(
+        "error {command count limit exceeded}".to_string(),
+        false,
+        "testuser".to_string(),
+        "testhost".to_string(),
+        "#test".to_string(),
+    ).await.unwrap();
+    assert!(result.is_error);
+    assert!(!result.output.contains("max 10000 commands"), "Unexpected output: {}", result.output);
+
+    // Small scripts still run normally afterwards
+    let result = tcl_thread.eval(
+        "expr {6 * 7}".to_string(),
+        false,
+        "testuser".to_string(),
+        "testhost".to_string(),
+        "#test".to_string(),
+    ).await.unwrap();
+    assert!(!result.is_error);
+    assert_eq!(result.output, "42");
+
+    tcl_thread.shutdown();
+}
+
 #[test]
 fn test_variable_persistence() {
     let (_temp, state_path) = create_temp_state();
@@ -0,0 +1,132 @@
+#![cfg(unix)]
+
+use slopdrop::config::{SecurityConfig, TclConfig};
+use slopdrop::tcl_process::TclProcessHandle;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tempfile::TempDir;
+
+/// Helper to create a temporary state directory
+fn create_temp_state() -> (TempDir, PathBuf) {
+    let temp_dir = TempDir::new().unwrap();
+    let state_path = temp_dir.path().join("state");
+    (temp_dir, state_path)
+}
+
+/// Helper to spawn a worker process running the slopdrop binary under test
+fn spawn_worker(state_path: PathBuf, channel_members: slopdrop::types::ChannelMembers) -> TclProcessHandle {
+    let security_config = SecurityConfig {
+        eval_timeout_ms: 500,
+        privileged_users: vec![],
+        blacklisted_users: vec![],
+        memory_limit_mb: 0,
+        max_recursion_depth: 1000,
+        max_eval_commands: 0,
+        worker_process: true,
+        worker_cpu_limit_secs: 0,
+        worker_max_open_files: 0,
+        notify_self: false,
+    };
+
+    let tcl_config = TclConfig {
+        state_path,
+        state_repo: None,
+        ssh_key: None,
+        state_branch: None,
+        state_username: None,
+        state_token_file: None,
+        state_token_env: None,
+        state_credential_helper: false,
+        transactional_evals: false,
+        commit_coalescing: Default::default(),
+        sync_interval_secs: 0,
+        max_output_lines: 10,
+    };
+
+    TclProcessHandle::spawn_with_program(
+        PathBuf::from(env!("CARGO_BIN_EXE_slopdrop")),
+        tcl_config,
+        security_config,
+        channel_members,
+    ).unwrap()
+}
+
+#[tokio::test]
+async fn test_process_worker_eval() {
+    let (_temp, state_path) = create_temp_state();
+    let mut worker = spawn_worker(state_path, Arc::new(RwLock::new(HashMap::new())));
+
+    let result = worker.eval(
+        "expr {1 + 1}".to_string(),
+        false,
+        "testuser".to_string(),
+        "testhost".to_string(),
+        "#test".to_string(),
+    ).await.unwrap();
+
+    assert!(!result.is_error);
+    assert_eq!(result.output, "2");
+
+    worker.shutdown();
+}
+
+#[tokio::test]
+async fn test_process_worker_persists_and_survives_timeout() {
+    let (_temp, state_path) = create_temp_state();
+    let mut worker = spawn_worker(state_path, Arc::new(RwLock::new(HashMap::new())));
+
+    let result = worker.eval(
+        "proc proc_worker_test {} { return ok }".to_string(),
+        false,
+        "testuser".to_string(),
+        "testhost".to_string(),
+        "#test".to_string(),
+    ).await.unwrap();
+    assert!(!result.is_error);
+    assert!(result.commit_info.is_some(), "Commit info should cross the process boundary");
+
+    let result = worker.eval(
+        "while {1} {}".to_string(),
+        false,
+        "testuser".to_string(),
+        "testhost".to_string(),
+        "#test".to_string(),
+    ).await.unwrap();
+    assert!(result.is_error);
+    assert!(result.output.contains("timed out"));
+
+    // Worker is still usable afterwards
+    let output = worker.eval_simple("proc_worker_test".to_string()).await.unwrap();
+    assert_eq!(output, "ok");
+
+    worker.shutdown();
+}
+
+#[tokio::test]
+async fn test_process_worker_sees_channel_members() {
+    let (_temp, state_path) = create_temp_state();
+
+    let channel_members = Arc::new(RwLock::new(HashMap::new()));
+    let mut worker = spawn_worker(state_path, channel_members.clone());
+
+    // Members joining after the worker started are sent along with the next eval
+    channel_members.write().unwrap().insert(
+        "#test".to_string(),
+        HashSet::from(["alice".to_string(), "bob".to_string()]),
+    );
+
+    let result = worker.eval(
+        "chanlist #test".to_string(),
+        false,
+        "testuser".to_string(),
+        "testhost".to_string(),
+        "#test".to_string(),
+    ).await.unwrap();
+
+    assert!(!result.is_error);
+    assert!(result.output.contains("alice"), "Should contain alice: {}", result.output);
+    assert!(result.output.contains("bob"), "Should contain bob: {}", result.output);
+
+    worker.shutdown();
+}
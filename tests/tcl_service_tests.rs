@@ -1,6 +1,8 @@
-use slopdrop::config::{SecurityConfig, TclConfig};
-use slopdrop::tcl_service::{EvalContext, TclService};
+use slopdrop::config::{CoalesceConfig, CoalesceMode, SecurityConfig, TclConfig};
+use slopdrop::tcl_service::{AdminNotice, EvalContext, TclService};
+use slopdrop::tcl_thread::EvalMode;
 use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 use tempfile::TempDir;
@@ -26,12 +28,22 @@ fn create_test_service(state_path: PathBuf) -> TclService {
 
 /// Helper function to create a test TclService with custom channel members
 fn create_test_service_with_members(state_path: PathBuf, channel_members: Arc<RwLock<HashMap<String, HashSet<String>>>>) -> TclService {
+    let (security_config, tcl_config) = test_configs(state_path);
+    TclService::new(security_config, tcl_config, channel_members).unwrap()
+}
+
+/// Helper function to create the configs test services use
+fn test_configs(state_path: PathBuf) -> (SecurityConfig, TclConfig) {
     let security_config = SecurityConfig {
         eval_timeout_ms: 5000,
         privileged_users: vec!["admin!*@*".to_string(), "alice!*@*.example.com".to_string()],
         blacklisted_users: vec![],
         memory_limit_mb: 0, // Disabled for tests - RLIMIT_AS affects entire process
         max_recursion_depth: 1000,
+        max_eval_commands: 0,
+        worker_process: false,
+        worker_cpu_limit_secs: 0,
+        worker_max_open_files: 0,
         notify_self: false,
     };
 
@@ -39,10 +51,18 @@ fn create_test_service_with_members(state_path: PathBuf, channel_members: Arc<Rw
         state_path,
         state_repo: None,
         ssh_key: None,
+        state_branch: None,
+        state_username: None,
+        state_token_file: None,
+        state_token_env: None,
+        state_credential_helper: false,
+        transactional_evals: false,
+        commit_coalescing: Default::default(),
+        sync_interval_secs: 0,
         max_output_lines: 5,  // Small for testing pagination
     };
 
-    TclService::new(security_config, tcl_config, channel_members).unwrap()
+    (security_config, tcl_config)
 }
 
 #[tokio::test]
@@ -360,6 +380,7 @@ async fn test_rollback() {
     // Rollback to previous commit
     let rollback_msg = service.rollback(&commit_hash).await.unwrap();
     assert!(rollback_msg.contains("Rolled back"));
+    assert!(rollback_msg.contains("+var: x"), "{}", rollback_msg);
 
     // Verify old value is restored
     let response = service.eval("set x", ctx.clone()).await.unwrap();
@@ -368,6 +389,288 @@ async fn test_rollback() {
     service.shutdown();
 }
 
+#[tokio::test]
+async fn test_rollback_command_reloads_interpreter() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let ctx = EvalContext::new("admin".to_string(), "user@localhost".to_string())
+        .with_admin(true);
+
+    service.eval("set rb_var 1", ctx.clone()).await.unwrap();
+    let target = service.history(1).await.unwrap()[0].commit_id.clone();
+    service.eval("set rb_var 2; proc rb_proc {} {}", ctx.clone()).await.unwrap();
+
+    // Abbreviated hashes work, and the reply lists what changed
+    let response = service
+        .eval(&format!("rollback {}", &target[..8]), ctx.clone())
+        .await
+        .unwrap();
+    assert!(!response.is_error, "{:?}", response.output);
+    assert!(response.output[0].contains("-proc: rb_proc"), "{}", response.output[0]);
+    assert!(response.output[0].contains("+var: rb_var"), "{}", response.output[0]);
+    assert!(response.commit_info.is_none());
+
+    // The running interpreter matches the rolled back state, and the reload
+    // isn't committed again by the next eval
+    let response = service
+        .eval("list $rb_var [llength [info procs rb_proc]]", ctx.clone())
+        .await
+        .unwrap();
+    assert_eq!(response.output[0], "1 0");
+    assert_eq!(service.history(1).await.unwrap()[0].commit_id, target);
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_revert_single_commit() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let ctx = EvalContext::new("admin".to_string(), "user@localhost".to_string())
+        .with_admin(true);
+
+    service.eval("set rv_var 1", ctx.clone()).await.unwrap();
+    service.eval("proc rv_proc {} { return hi }", ctx.clone()).await.unwrap();
+    let proc_commit = service.history(1).await.unwrap()[0].commit_id.clone();
+    service.eval("set rv_var 2", ctx.clone()).await.unwrap();
+    let var_commit = service.history(1).await.unwrap()[0].commit_id.clone();
+    service.eval("set rv_var 3", ctx.clone()).await.unwrap();
+
+    // Only the proc commit is undone; later commits stay
+    let message = service.revert(&proc_commit, &ctx).await.unwrap();
+    assert!(message.contains("-proc: rv_proc"), "{}", message);
+
+    let response = service
+        .eval("list $rv_var [llength [info procs rv_proc]]", ctx.clone())
+        .await
+        .unwrap();
+    assert_eq!(response.output[0], "3 0");
+
+    let history = service.history(2).await.unwrap();
+    assert!(history[0].message.starts_with("Revert"), "{}", history[0].message);
+    assert_eq!(history[0].author, "admin");
+
+    // rv_var was changed again after var_commit, so that revert conflicts
+    let err = service.revert(&var_commit, &ctx).await.unwrap_err();
+    assert!(err.to_string().contains("var rv_var"), "{}", err);
+    assert_eq!(service.history(1).await.unwrap()[0].commit_id, history[0].commit_id);
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_undo_own_change() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let alice = EvalContext::new("alice".to_string(), "alice@host".to_string());
+    let bob = EvalContext::new("bob".to_string(), "bob@host".to_string());
+
+    service.eval("proc undo_proc {} { return 1 }", alice.clone()).await.unwrap();
+    service.eval("set undo_var 1", bob.clone()).await.unwrap();
+
+    // Alice's last change is undone even though Bob committed after her
+    let response = service.eval("undo", alice.clone()).await.unwrap();
+    assert!(!response.is_error, "{:?}", response.output);
+    assert!(response.output[0].contains("-proc: undo_proc"), "{}", response.output[0]);
+    let response = service
+        .eval("list $undo_var [llength [info procs undo_proc]]", bob.clone())
+        .await
+        .unwrap();
+    assert_eq!(response.output[0], "1 0");
+
+    // Only admins can undo someone else's change
+    let response = service.eval("undo alice", bob.clone()).await.unwrap();
+    assert!(response.is_error);
+    assert!(response.output[0].contains("admin"), "{}", response.output[0]);
+
+    // Refused once someone else has touched the same proc
+    service.eval("proc undo_proc {} { return 2 }", alice.clone()).await.unwrap();
+    service.eval("proc undo_proc {} { return 3 }", bob.clone()).await.unwrap();
+    let response = service.eval("undo", alice.clone()).await.unwrap();
+    assert!(response.is_error);
+    assert!(response.output[0].contains("proc undo_proc"), "{}", response.output[0]);
+    let response = service.eval("undo_proc", alice.clone()).await.unwrap();
+    assert_eq!(response.output[0], "3");
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_repeated_undo_walks_back() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let alice = EvalContext::new("alice".to_string(), "alice@host".to_string());
+
+    service.eval("proc walk_proc {} { return 1 }", alice.clone()).await.unwrap();
+    service.eval("set walk_var 1", alice.clone()).await.unwrap();
+
+    let response = service.eval("undo", alice.clone()).await.unwrap();
+    assert!(response.output[0].contains("-var: walk_var"), "{}", response.output[0]);
+
+    // The second undo skips the first one's revert commit instead of undoing it
+    let response = service.eval("undo", alice.clone()).await.unwrap();
+    assert!(!response.is_error, "{:?}", response.output);
+    assert!(response.output[0].contains("-proc: walk_proc"), "{}", response.output[0]);
+    let response = service
+        .eval("list [info exists walk_var] [llength [info procs walk_proc]]", alice.clone())
+        .await
+        .unwrap();
+    assert_eq!(response.output[0], "0 0");
+
+    // Nothing of Alice's is left to undo
+    let response = service.eval("undo", alice.clone()).await.unwrap();
+    assert!(response.is_error);
+    assert!(response.output[0].contains("no changes by alice"), "{}", response.output[0]);
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_undo_commits_held_var_changes_first() {
+    let (_temp, state_path) = create_temp_state();
+    let (security_config, mut tcl_config) = test_configs(state_path);
+    tcl_config.commit_coalescing = CoalesceConfig {
+        mode: CoalesceMode::Batch,
+        interval_secs: 3600,
+        vars: vec!["held_*".to_string()],
+    };
+    let mut service = TclService::new(security_config, tcl_config, Arc::new(RwLock::new(HashMap::new()))).unwrap();
+
+    let alice = EvalContext::new("alice".to_string(), "alice@host".to_string());
+
+    service.eval("proc held_proc {} { return 1 }", alice.clone()).await.unwrap();
+    let response = service.eval("set held_var 1", alice.clone()).await.unwrap();
+    assert!(response.commit_info.is_none());
+
+    // The held var is committed before the undo looks for Alice's newest change
+    let response = service.eval("undo", alice.clone()).await.unwrap();
+    assert!(!response.is_error, "{:?}", response.output);
+    assert!(response.output[0].contains("-var: held_var"), "{}", response.output[0]);
+    let response = service
+        .eval("list [info exists held_var] [llength [info procs held_proc]]", alice.clone())
+        .await
+        .unwrap();
+    assert_eq!(response.output[0], "0 1");
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_proc_history_diff_and_blame() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let alice = EvalContext::new("alice".to_string(), "alice@host".to_string());
+    let bob = EvalContext::new("bob".to_string(), "bob@host".to_string());
+
+    service
+        .eval("proc hist_proc {} {\n    set a 1\n    return $a\n}", alice.clone())
+        .await
+        .unwrap();
+    service.eval("set hist_var 1", bob.clone()).await.unwrap();
+    service
+        .eval("proc hist_proc {} {\n    set a 2\n    return $a\n}", bob.clone())
+        .await
+        .unwrap();
+
+    // Only commits touching the proc are listed, newest first
+    let response = service
+        .eval("lmap entry [history proc hist_proc] {lindex $entry 2}", alice.clone())
+        .await
+        .unwrap();
+    assert_eq!(response.output[0], "bob alice");
+
+    // Default diff is the most recent change
+    let response = service.eval("diff hist_proc", alice.clone()).await.unwrap();
+    assert!(!response.is_error, "{:?}", response.output);
+    let diff = response.output.join("\n");
+    assert!(diff.contains("-    set a 1"), "{}", diff);
+    assert!(diff.contains("+    set a 2"), "{}", diff);
+
+    // The changed line belongs to bob, the rest to alice
+    let response = service
+        .eval("join [lmap line [blame hist_proc] {lindex $line 1}] ,", alice.clone())
+        .await
+        .unwrap();
+    assert_eq!(response.output[0], "alice,bob,alice,alice");
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_eval_at_past_commit() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let ctx = EvalContext::new("admin".to_string(), "user@localhost".to_string())
+        .with_admin(true);
+
+    service.eval("set at_var 1", ctx.clone()).await.unwrap();
+    let past = service.history(1).await.unwrap()[0].commit_id.clone();
+    service.eval("set at_var 2; proc at_proc {} {}", ctx.clone()).await.unwrap();
+    let head = service.history(1).await.unwrap()[0].commit_id.clone();
+
+    let at = EvalMode::At(past[..8].to_string());
+    let response = service
+        .eval_with_mode("list $at_var [llength [info procs at_proc]]", ctx.clone(), at.clone())
+        .await
+        .unwrap();
+    assert_eq!(response.output[0], "1 0");
+
+    // Changes made in the past stay there
+    let response = service
+        .eval_with_mode("set at_var 5; proc at_other {} {}", ctx.clone(), at)
+        .await
+        .unwrap();
+    assert!(response.commit_info.is_none());
+    let response = service
+        .eval("list $at_var [llength [info procs at_other]]", ctx.clone())
+        .await
+        .unwrap();
+    assert_eq!(response.output[0], "2 0");
+    assert_eq!(service.history(1).await.unwrap()[0].commit_id, head);
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_namespace_vars_persist() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path.clone());
+    let ctx = EvalContext::new("alice".to_string(), "alice@host".to_string());
+
+    service
+        .eval("namespace eval ::counters { variable hits 3 }; cache put scores alice 10", ctx.clone())
+        .await
+        .unwrap();
+    service.shutdown();
+
+    let namespaces = fs::read_to_string(state_path.join("vars/_namespaces")).unwrap();
+    assert!(namespaces.lines().any(|ns| ns == "counters"), "{}", namespaces);
+
+    // A fresh interpreter on the same state gets both back
+    let mut service = create_test_service(state_path.clone());
+    let response = service
+        .eval("list $::counters::hits [cache get scores alice]", ctx.clone())
+        .await
+        .unwrap();
+    assert!(!response.is_error, "{:?}", response.output);
+    assert_eq!(response.output[0], "3 10");
+
+    // Deleting the namespace deletes its vars from the state
+    service.eval("namespace delete ::counters", ctx).await.unwrap();
+    let index = fs::read_to_string(state_path.join("vars/_index")).unwrap();
+    assert!(!index.contains("counters::hits"), "{}", index);
+    let namespaces = fs::read_to_string(state_path.join("vars/_namespaces")).unwrap();
+    assert!(!namespaces.lines().any(|ns| ns == "counters"), "{}", namespaces);
+
+    service.shutdown();
+}
+
 #[tokio::test]
 async fn test_state_persistence_across_evals() {
     let (_temp, state_path) = create_temp_state();
@@ -521,3 +824,300 @@ async fn test_chanlist_command() {
 
     service.shutdown();
 }
+
+#[tokio::test]
+async fn test_native_commands_compose() {
+    let (_temp, state_path) = create_temp_state();
+
+    let channel_members = Arc::new(RwLock::new(HashMap::new()));
+    channel_members.write().unwrap().insert("#test".to_string(), HashSet::from([
+        "bob".to_string(),
+        "alice".to_string(),
+    ]));
+
+    let mut service = create_test_service_with_members(state_path, channel_members);
+
+    let ctx = EvalContext::new("testuser".to_string(), "testhost".to_string())
+        .with_channel("#test".to_string());
+
+    // chanlist is a real command, so it works inside other commands
+    let response = service
+        .eval("set n 0; foreach nick [chanlist #test] {incr n}; set n", ctx.clone())
+        .await
+        .unwrap();
+    assert!(!response.is_error, "{:?}", response.output);
+    assert_eq!(response.output[0], "2");
+
+    let response = service.eval("lindex [chanlist #test] 0", ctx.clone()).await.unwrap();
+    assert_eq!(response.output[0], "alice");
+
+    // history returns a list of {hash date author message} entries
+    service.eval("set native_history_var 1", ctx.clone()).await.unwrap();
+    let response = service
+        .eval("proc last_author {} {lindex [history 1] 0 2}; last_author", ctx.clone())
+        .await
+        .unwrap();
+    assert!(!response.is_error, "{:?}", response.output);
+    assert_eq!(response.output[0], "testuser");
+
+    // rollback respects the caller's privileges even when called indirectly
+    let response = service
+        .eval("catch {rollback HEAD} msg; set msg", ctx)
+        .await
+        .unwrap();
+    assert!(response.output[0].contains("requires admin"), "{:?}", response.output);
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_native_commands_follow_config_reload() {
+    let (_temp, state_path) = create_temp_state();
+    let (_new_temp, new_state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let ctx = EvalContext::new("testuser".to_string(), "testhost".to_string());
+    for i in 0..4 {
+        service.eval(&format!("set reload_var{} 1", i), ctx.clone()).await.unwrap();
+    }
+
+    // Point the service at a fresh state directory
+    let (security_config, tcl_config) = test_configs(new_state_path);
+    service.update_config(tcl_config, security_config).unwrap();
+    service.eval("set reload_var_new 1", ctx.clone()).await.unwrap();
+
+    // history reads the new repository, like the service does
+    let response = service.eval("llength [history 100]", ctx).await.unwrap();
+    assert!(!response.is_error, "{:?}", response.output);
+    let expected = service.history(100).await.unwrap().len();
+    assert_eq!(response.output[0], expected.to_string());
+    assert!(expected < 4, "history still reads the old repository");
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_puts_output_returned() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let ctx = EvalContext::new("testuser".to_string(), "testhost".to_string());
+
+    let response = service
+        .eval("foreach i {1 2} {puts \"line $i\"}; return done", ctx.clone())
+        .await
+        .unwrap();
+    assert!(!response.is_error);
+    assert_eq!(response.output, vec!["line 1", "line 2", "done"]);
+
+    // Output printed before an error is kept
+    let response = service.eval("puts before; error boom", ctx).await.unwrap();
+    assert!(response.is_error);
+    assert_eq!(response.output[0], "before");
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_structured_eval_result() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let ctx = EvalContext::new("testuser".to_string(), "testhost".to_string());
+
+    let response = service
+        .eval("puts hi; set x 0; for {set i 0} {$i < 100} {incr i} {incr x}; set x", ctx.clone())
+        .await
+        .unwrap();
+    assert!(!response.is_error);
+    assert_eq!(response.return_value, "100");
+    assert_eq!(response.captured_output, "hi\n");
+    assert!(response.error_code.is_none());
+    assert!(!response.restarted);
+    let stats = response.stats.unwrap();
+    assert!(stats.commands >= 100, "{:?}", stats);
+
+    // Errors carry TCL's errorCode and the full errorInfo
+    let response = service.eval("no_such_command_here", ctx).await.unwrap();
+    assert!(response.is_error);
+    assert_eq!(response.error_code.as_deref(), Some("TCL LOOKUP COMMAND no_such_command_here"));
+    assert!(response.error_info.unwrap().contains("while executing"));
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_failed_transaction_rolls_back() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let ctx = EvalContext::new("testuser".to_string(), "testhost".to_string());
+
+    let response = service
+        .eval("proc txn_keep {} { return old }; set txn_var old", ctx.clone())
+        .await
+        .unwrap();
+    assert!(!response.is_error);
+
+    // Everything after `transaction on` is undone when the script fails
+    let response = service
+        .eval(
+            "transaction on; proc txn_keep {} { return new }; proc txn_new {} {}; set txn_var new; set txn_created 1; rename txn_keep {}; error boom",
+            ctx.clone(),
+        )
+        .await
+        .unwrap();
+    assert!(response.is_error);
+    assert!(response.rolled_back);
+    assert!(response.commit_info.is_none());
+
+    let response = service
+        .eval("list [txn_keep] $txn_var [info exists txn_created] [llength [info procs txn_new]]", ctx.clone())
+        .await
+        .unwrap();
+    assert_eq!(response.output[0], "old old 0 0");
+
+    // Successful transactions keep their changes
+    let response = service.eval("transaction on; set txn_var kept", ctx.clone()).await.unwrap();
+    assert!(!response.rolled_back);
+    assert!(response.commit_info.is_some());
+    let response = service.eval("set txn_var", ctx).await.unwrap();
+    assert_eq!(response.output[0], "kept");
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_dry_run_keeps_nothing() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let ctx = EvalContext::new("testuser".to_string(), "testhost".to_string());
+
+    let response = service
+        .eval("proc dry_greet {} { return old }", ctx.clone())
+        .await
+        .unwrap();
+    assert!(response.commit_info.is_some());
+    let commits_before = service.history(100).await.unwrap().len();
+
+    let response = service
+        .dry_run("proc dry_greet {} { return new }; set dry_var 1; transaction off", ctx.clone())
+        .await
+        .unwrap();
+    assert!(response.commit_info.is_none());
+    let report = response.dry_run.expect("dry run report");
+    assert_eq!(report.changes_summary, "+proc: dry_greet | +var: dry_var");
+    assert_eq!(report.files_changed, 2);
+    let proc_diff = report.diffs.iter().find(|d| d.contains("procs/dry_greet")).unwrap();
+    assert!(proc_diff.contains("-{} { return old }"), "{}", proc_diff);
+    assert!(proc_diff.contains("+{} { return new }"), "{}", proc_diff);
+
+    // Neither the interpreter nor the repository kept anything
+    let response = service
+        .eval("list [dry_greet] [info exists dry_var]", ctx)
+        .await
+        .unwrap();
+    assert_eq!(response.output[0], "old 0");
+    assert_eq!(service.history(100).await.unwrap().len(), commits_before);
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_dry_run_journal_out_of_script_reach() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let ctx = EvalContext::new("testuser".to_string(), "testhost".to_string());
+
+    service
+        .eval("proc dry_greet {} { return old }", ctx.clone())
+        .await
+        .unwrap();
+
+    // The transaction flags and journal live in the master, so a script
+    // can't unpin or close its own dry run
+    let response = service
+        .dry_run(
+            "set ::slopdrop::txn::pinned 0; set ::slopdrop::txn::active 0; catch {::slopdrop::txn::finish}; proc dry_greet {} { return new }",
+            ctx.clone(),
+        )
+        .await
+        .unwrap();
+    assert!(!response.is_error);
+
+    let response = service.eval("list [dry_greet] [transaction]", ctx).await.unwrap();
+    assert_eq!(response.output[0], "old 0");
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_protected_proc_refused_up_front() {
+    let (_temp, state_path) = create_temp_state();
+    let mut service = create_test_service(state_path);
+
+    let alice = EvalContext::new("alice".to_string(), "~a@host".to_string());
+    let bob = EvalContext::new("bob".to_string(), "~b@host".to_string());
+
+    service.eval("proc greet {} { return hi }", alice.clone()).await.unwrap();
+    let response = service.eval("protect greet", alice.clone()).await.unwrap();
+    assert!(!response.is_error, "{:?}", response.output);
+
+    // Redefining, renaming or deleting it fails before anything changes,
+    // including through the names proc_tracking.tcl builds on, and the
+    // change is held for review instead
+    for code in [
+        "proc greet {} { return hacked }",
+        "rename greet {}",
+        "rename greet gone",
+        "proc other {} {}; rename other greet",
+        "::slopdrop::_original_proc greet {} { return hacked }",
+        "::slopdrop::_original_rename greet {}",
+        r#"proc greet {} "return \{""#,
+    ] {
+        let response = service.eval(code, bob.clone()).await.unwrap();
+        assert!(response.is_error, "{}", code);
+        assert!(response.output[0].contains("greet is protected by alice"), "{}: {:?}", code, response.output);
+        assert!(response.review.is_some(), "{}", code);
+    }
+    let response = service.eval("greet", bob).await.unwrap();
+    assert_eq!(response.output[0], "hi");
+    let admin = EvalContext::new("admin".to_string(), "~root@host".to_string()).with_admin(true);
+    let response = service.eval("llength [review list]", admin).await.unwrap();
+    assert_eq!(response.output[0], "7");
+
+    // Admins hear about each one, whichever frontend it came from
+    let notices = service.take_admin_notices();
+    assert_eq!(notices.iter().filter(|notice| matches!(notice, AdminNotice::Review(_))).count(), 7);
+
+    // The owner still can
+    let response = service.eval("proc greet {} { return hello }; greet", alice).await.unwrap();
+    assert_eq!(response.output[0], "hello");
+
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn test_shared_service_visible_across_frontends() {
+    let (_temp, state_path) = create_temp_state();
+    let service = std::sync::Arc::new(tokio::sync::Mutex::new(create_test_service(state_path)));
+
+    // One "frontend" (e.g. IRC) defines a proc
+    let irc_service = service.clone();
+    let irc_ctx = EvalContext::new("alice".to_string(), "alice@irc".to_string())
+        .with_channel("#test".to_string());
+    let response = irc_service.lock().await
+        .eval("proc shared_greet {} { return shared }", irc_ctx).await.unwrap();
+    assert!(!response.is_error);
+
+    // Another "frontend" (e.g. web) sees it immediately without a restart
+    let web_service = service.clone();
+    let web_ctx = EvalContext::new("web".to_string(), "web".to_string());
+    let response = web_service.lock().await.eval("shared_greet", web_ctx).await.unwrap();
+    assert!(!response.is_error);
+    assert_eq!(response.output[0], "shared");
+
+    service.lock().await.shutdown();
+}
@@ -39,12 +39,24 @@ async fn create_test_app_state(state_path: std::path::PathBuf) -> AppState {
         blacklisted_users: vec![],
         memory_limit_mb: 0, // Disabled for tests - RLIMIT_AS affects entire process
         max_recursion_depth: 1000,
+        max_eval_commands: 0,
+        worker_process: false,
+        worker_cpu_limit_secs: 0,
+        worker_max_open_files: 0,
     };
 
     let tcl_config = TclConfig {
         state_path,
         state_repo: None,
         ssh_key: None,
+        state_branch: None,
+        state_username: None,
+        state_token_file: None,
+        state_token_env: None,
+        state_credential_helper: false,
+        transactional_evals: false,
+        commit_coalescing: Default::default(),
+        sync_interval_secs: 0,
         max_output_lines: 10,
     };
 
@@ -117,6 +129,9 @@ async fn test_eval_endpoint_basic() {
     assert_eq!(json["is_error"], false);
     assert_eq!(json["output"][0], "2");
     assert_eq!(json["more_available"], false);
+    assert_eq!(json["return_value"], "2");
+    assert_eq!(json["restarted"], false);
+    assert!(json["stats"]["commands"].as_u64().unwrap() > 0);
 }
 
 #[cfg(feature = "frontend-web")]
@@ -408,6 +423,50 @@ async fn test_rollback_endpoint() {
     assert!(json["message"].as_str().unwrap().contains("Rolled back"));
 }
 
+#[cfg(feature = "frontend-web")]
+#[tokio::test]
+async fn test_proc_history_endpoint() {
+    let (_temp, state_path) = create_temp_state();
+    let app_state = create_test_app_state(state_path).await;
+
+    for code in ["proc web_proc {} { return 1 }", "set other 1", "proc web_proc {} { return 2 }"] {
+        let request_body = serde_json::json!({ "code": code, "is_admin": true });
+        let _ = create_router(app_state.clone())
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/eval")
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_vec(&request_body).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+    }
+
+    let response = create_router(app_state)
+        .oneshot(
+            Request::builder()
+                .uri("/api/procs/web_proc/history")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    // Only the two commits that touched web_proc are listed
+    let versions = json.as_array().unwrap();
+    assert_eq!(versions.len(), 2, "{:?}", json);
+    assert!(versions.iter().all(|v| v["content_hash"].is_string()));
+}
+
 #[cfg(feature = "frontend-web")]
 #[tokio::test]
 async fn test_root_endpoint_returns_html() {